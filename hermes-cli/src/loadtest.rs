@@ -0,0 +1,255 @@
+//! `hermes-cli loadtest`: concurrent lnurlp callbacks, and optionally their
+//! settlement, against a hermes connected to a devimint federation,
+//! reporting latency percentiles of each step.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+use reqwest::Client;
+use serde_json::Value;
+use tokio::{process::Command, task::JoinSet};
+
+/// How often a settling invoice's verify url is polled.
+const VERIFY_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Args)]
+pub struct LoadtestArgs {
+    /// User whose lightning address is paid
+    #[arg(long)]
+    username: String,
+    /// Callbacks in flight at once
+    #[arg(long, default_value_t = 10)]
+    concurrency: usize,
+    /// Callbacks in total
+    #[arg(long, default_value_t = 100)]
+    requests: usize,
+    /// Amount of each invoice
+    #[arg(long, default_value_t = 1000)]
+    amount_msats: u64,
+    /// Also pay every invoice and wait for hermes to see it settle
+    #[arg(long)]
+    settle: bool,
+    /// Command paying the invoice given as its last argument, by default
+    /// `$FM_LIGHTNING_CLI pay`, the CLN node of a devimint shell
+    #[arg(long)]
+    pay_command: Option<String>,
+    /// How long a paid invoice may take to show as settled
+    #[arg(long, default_value_t = 60)]
+    settle_timeout_secs: u64,
+    /// Fail if the callback's p99 latency exceeds this
+    #[arg(long)]
+    max_p99_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct Timings {
+    lnurlp: Vec<Duration>,
+    callback: Vec<Duration>,
+    pay: Vec<Duration>,
+    settle: Vec<Duration>,
+    errors: Vec<String>,
+}
+
+impl Timings {
+    fn extend(&mut self, other: Timings) {
+        self.lnurlp.extend(other.lnurlp);
+        self.callback.extend(other.callback);
+        self.pay.extend(other.pay);
+        self.settle.extend(other.settle);
+        self.errors.extend(other.errors);
+    }
+}
+
+pub async fn run(url: String, args: LoadtestArgs) -> Result<()> {
+    if args.concurrency == 0 || args.requests == 0 {
+        return Err(anyhow!(
+            "--concurrency and --requests must be greater than 0"
+        ));
+    }
+    let pay_command = match args.pay_command.clone() {
+        _ if !args.settle => None,
+        Some(command) => Some(command),
+        None => match std::env::var("FM_LIGHTNING_CLI") {
+            Ok(cli) => Some(format!("{cli} pay")),
+            Err(_) => {
+                return Err(anyhow!(
+                    "--settle needs --pay-command, or FM_LIGHTNING_CLI from a devimint shell"
+                ))
+            }
+        },
+    };
+
+    let http = Client::new();
+    let lnurlp = format!(
+        "{}/.well-known/lnurlp/{}",
+        url.trim_end_matches('/'),
+        args.username
+    );
+
+    let started = Instant::now();
+    let mut workers = JoinSet::new();
+    for worker in 0..args.concurrency {
+        // spread the requests evenly, the first workers take the remainder
+        let count = args.requests / args.concurrency
+            + usize::from(worker < args.requests % args.concurrency);
+        let http = http.clone();
+        let lnurlp = lnurlp.clone();
+        let pay_command = pay_command.clone();
+        let amount_msats = args.amount_msats;
+        let settle_timeout = Duration::from_secs(args.settle_timeout_secs);
+        workers.spawn(async move {
+            let mut timings = Timings::default();
+            for _ in 0..count {
+                if let Err(e) = zap(
+                    &http,
+                    &lnurlp,
+                    amount_msats,
+                    pay_command.as_deref(),
+                    settle_timeout,
+                    &mut timings,
+                )
+                .await
+                {
+                    timings.errors.push(format!("{e:#}"));
+                }
+            }
+            timings
+        });
+    }
+
+    let mut timings = Timings::default();
+    while let Some(worker) = workers.join_next().await {
+        timings.extend(worker?);
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{} callbacks in {:.1}s ({:.1}/s), {} failed",
+        args.requests,
+        elapsed.as_secs_f64(),
+        timings.callback.len() as f64 / elapsed.as_secs_f64(),
+        timings.errors.len()
+    );
+    report("lnurlp", &mut timings.lnurlp);
+    report("callback", &mut timings.callback);
+    if args.settle {
+        report("pay", &mut timings.pay);
+        report("settle", &mut timings.settle);
+    }
+    for error in timings.errors.iter().take(10) {
+        eprintln!("error: {error}");
+    }
+
+    if !timings.errors.is_empty() {
+        return Err(anyhow!(
+            "{} of {} failed",
+            timings.errors.len(),
+            args.requests
+        ));
+    }
+    if let Some(max) = args.max_p99_ms {
+        let p99 = percentile(&timings.callback, 99.0);
+        if p99 > Duration::from_millis(max) {
+            return Err(anyhow!(
+                "callback p99 of {}ms exceeds {max}ms",
+                p99.as_millis()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// One payment: the pay request, its callback and, with `pay_command`,
+/// paying the invoice and polling verify until it settled.
+async fn zap(
+    http: &Client,
+    lnurlp: &str,
+    amount_msats: u64,
+    pay_command: Option<&str>,
+    settle_timeout: Duration,
+    timings: &mut Timings,
+) -> Result<()> {
+    let start = Instant::now();
+    let pay_request = get_json(http.get(lnurlp)).await?;
+    timings.lnurlp.push(start.elapsed());
+    let callback = pay_request["callback"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Pay request has no callback"))?;
+
+    let start = Instant::now();
+    let invoice = get_json(
+        http.get(callback)
+            .query(&[("amount", amount_msats.to_string())]),
+    )
+    .await?;
+    timings.callback.push(start.elapsed());
+
+    let Some(pay_command) = pay_command else {
+        return Ok(());
+    };
+    let pr = invoice["pr"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Callback returned no invoice"))?;
+    let verify = invoice["verify"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Callback returned no verify url"))?;
+
+    let start = Instant::now();
+    let mut parts = pay_command.split_whitespace();
+    let program = parts.next().ok_or_else(|| anyhow!("Empty pay command"))?;
+    let output = Command::new(program).args(parts).arg(pr).output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Paying failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    timings.pay.push(start.elapsed());
+
+    loop {
+        if get_json(http.get(verify)).await?["settled"] == Value::Bool(true) {
+            timings.settle.push(start.elapsed());
+            return Ok(());
+        }
+        if start.elapsed() > settle_timeout {
+            return Err(anyhow!("Invoice didn't settle in time"));
+        }
+        tokio::time::sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value> {
+    let res = request.send().await?;
+    let status = res.status();
+    let body: Value = res.json().await?;
+    if !status.is_success() || body["status"] == "ERROR" {
+        return Err(anyhow!("{status}: {body}"));
+    }
+
+    Ok(body)
+}
+
+fn report(step: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort();
+    println!(
+        "{step:>8}: p50 {:>6}ms  p90 {:>6}ms  p99 {:>6}ms  max {:>6}ms",
+        percentile(latencies, 50.0).as_millis(),
+        percentile(latencies, 90.0).as_millis(),
+        percentile(latencies, 99.0).as_millis(),
+        latencies[latencies.len() - 1].as_millis(),
+    );
+}
+
+/// Nearest rank percentile of sorted `latencies`.
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
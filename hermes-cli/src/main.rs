@@ -0,0 +1,293 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::{Client, RequestBuilder, Response};
+use serde_json::{json, Value};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+mod loadtest;
+
+/// Administrative client for a running hermes server
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Base url of the hermes server
+    #[arg(long, env = "HERMES_URL", default_value = "http://localhost:3000")]
+    url: String,
+
+    /// One of the server's ADMIN_API_KEYS, needed for everything but `loadtest`
+    #[arg(long, env = "HERMES_ADMIN_KEY")]
+    admin_key: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List registered users
+    Users,
+    /// List connected federations
+    Federations,
+    /// Join a new federation
+    JoinFederation { invite_code: String },
+    /// List each federation's lightning gateways
+    Gateways,
+    /// List invoices
+    Invoices {
+        #[arg(long)]
+        state: Option<String>,
+        #[arg(long)]
+        username: Option<String>,
+        #[arg(long)]
+        federation_id: Option<String>,
+        #[arg(long)]
+        limit: Option<i64>,
+        #[arg(long)]
+        offset: Option<i64>,
+    },
+    /// List audit log entries
+    Audit {
+        #[arg(long)]
+        actor: Option<String>,
+        #[arg(long)]
+        action: Option<String>,
+        #[arg(long)]
+        target: Option<String>,
+        #[arg(long)]
+        limit: Option<i64>,
+        #[arg(long)]
+        offset: Option<i64>,
+    },
+    /// List payouts that failed after their invoice settled
+    DeadLetters {
+        /// Include dead letters that were already replayed
+        #[arg(long)]
+        include_resolved: bool,
+    },
+    /// Retry a dead letter
+    Replay { id: i32 },
+    /// Download an encrypted backup of every table to a file
+    Backup {
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Download every user or invoice to a file
+    Export {
+        #[arg(value_enum)]
+        table: ExportTable,
+        #[arg(long, short)]
+        output: PathBuf,
+        /// `json` or `csv`
+        #[arg(long)]
+        format: Option<String>,
+        /// Only invoices in this state
+        #[arg(long)]
+        state: Option<String>,
+        /// Only this user's invoices
+        #[arg(long)]
+        username: Option<String>,
+        /// Only invoices in this federation
+        #[arg(long)]
+        federation_id: Option<String>,
+    },
+    /// Send concurrent lnurlp callbacks, and optionally pay them, reporting
+    /// latency percentiles
+    Loadtest(loadtest::LoadtestArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportTable {
+    Users,
+    Invoices,
+}
+
+struct AdminClient {
+    http: Client,
+    url: String,
+    admin_key: String,
+}
+
+impl AdminClient {
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.http
+            .get(format!("{}/admin{}", self.url.trim_end_matches('/'), path))
+            .bearer_auth(&self.admin_key)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http
+            .post(format!("{}/admin{}", self.url.trim_end_matches('/'), path))
+            .bearer_auth(&self.admin_key)
+    }
+}
+
+async fn send(request: RequestBuilder) -> Result<Value> {
+    let res = request.send().await?;
+    let status = res.status();
+    let body: Value = res.json().await?;
+    if !status.is_success() {
+        return Err(anyhow!("{status}: {body}"));
+    }
+
+    Ok(body)
+}
+
+/// Writes the response body to `output` as it arrives, so a large backup or
+/// export is never held in memory. A download that fails partway removes the
+/// file rather than leave a truncated one behind.
+async fn download(request: RequestBuilder, output: &Path) -> Result<Value> {
+    let mut res = request.send().await?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await?;
+        return Err(anyhow!("{status}: {body}"));
+    }
+
+    let mut file = File::create(output).await?;
+    let bytes = match write_body(&mut res, &mut file).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            drop(file);
+            let _ = tokio::fs::remove_file(output).await;
+            return Err(e);
+        }
+    };
+
+    Ok(json!({ "output": output.display().to_string(), "bytes": bytes }))
+}
+
+async fn write_body(res: &mut Response, file: &mut File) -> Result<u64> {
+    let mut bytes = 0;
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk).await?;
+        bytes += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    Ok(bytes)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Command::Loadtest(args) = cli.command {
+        return loadtest::run(cli.url, args).await;
+    }
+    let client = AdminClient {
+        http: Client::new(),
+        url: cli.url,
+        admin_key: cli
+            .admin_key
+            .ok_or_else(|| anyhow!("--admin-key or HERMES_ADMIN_KEY is required"))?,
+    };
+
+    let body = match cli.command {
+        Command::Users => send(client.get("/users")).await?,
+        Command::Federations => send(client.get("/federations")).await?,
+        Command::Gateways => send(client.get("/federations/gateways")).await?,
+        Command::JoinFederation { invite_code } => {
+            send(
+                client
+                    .post("/federations")
+                    .json(&json!({ "inviteCode": invite_code })),
+            )
+            .await?
+        }
+        Command::Invoices {
+            state,
+            username,
+            federation_id,
+            limit,
+            offset,
+        } => {
+            let mut query = vec![];
+            if let Some(state) = state {
+                query.push(("state", state));
+            }
+            if let Some(username) = username {
+                query.push(("username", username));
+            }
+            if let Some(federation_id) = federation_id {
+                query.push(("federation_id", federation_id));
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+            if let Some(offset) = offset {
+                query.push(("offset", offset.to_string()));
+            }
+            send(client.get("/invoices").query(&query)).await?
+        }
+        Command::Audit {
+            actor,
+            action,
+            target,
+            limit,
+            offset,
+        } => {
+            let mut query = vec![];
+            if let Some(actor) = actor {
+                query.push(("actor", actor));
+            }
+            if let Some(action) = action {
+                query.push(("action", action));
+            }
+            if let Some(target) = target {
+                query.push(("target", target));
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+            if let Some(offset) = offset {
+                query.push(("offset", offset.to_string()));
+            }
+            send(client.get("/audit").query(&query)).await?
+        }
+        Command::DeadLetters { include_resolved } => {
+            send(
+                client
+                    .get("/dead-letters")
+                    .query(&[("include_resolved", include_resolved)]),
+            )
+            .await?
+        }
+        Command::Replay { id } => send(client.post(&format!("/dead-letters/{id}/replay"))).await?,
+        Command::Backup { output } => download(client.get("/backup"), &output).await?,
+        Command::Export {
+            table,
+            output,
+            format,
+            state,
+            username,
+            federation_id,
+        } => {
+            let mut query = vec![];
+            if let Some(format) = format {
+                query.push(("format", format));
+            }
+            let path = match table {
+                ExportTable::Users => "/export/users",
+                ExportTable::Invoices => {
+                    if let Some(state) = state {
+                        query.push(("state", state));
+                    }
+                    if let Some(username) = username {
+                        query.push(("username", username));
+                    }
+                    if let Some(federation_id) = federation_id {
+                        query.push(("federation_id", federation_id));
+                    }
+                    "/export/invoices"
+                }
+            };
+            download(client.get(path).query(&query), &output).await?
+        }
+        Command::Loadtest(_) => unreachable!("handled above"),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&body)?);
+
+    Ok(())
+}
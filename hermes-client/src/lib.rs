@@ -0,0 +1,269 @@
+//! Typed async client for the public hermes api.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), hermes_client::Error> {
+//! use hermes_client::{types::LnurlCallbackParams, HermesClient};
+//!
+//! let client = HermesClient::new("https://hermes.example.com".parse().unwrap());
+//! let pay = client.well_known("alice").await?;
+//! let invoice = client
+//!     .callback(
+//!         "alice",
+//!         &LnurlCallbackParams {
+//!             amount: pay.min_sendable,
+//!             ..Default::default()
+//!         },
+//!     )
+//!     .await?;
+//! if let Some(verify) = &invoice.verify {
+//!     let status = client.verify_url(verify).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use reqwest::{header::AUTHORIZATION, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+pub mod types;
+
+use types::{
+    ErrorResponse, InvoiceLookup, InvoiceStatusParams, InvoiceStatusResponse, LnurlCallbackParams,
+    LnurlCallbackResponse, LnurlResponse, LnurlVerifyResponse, LnurlWellKnownResponse,
+    Nip05Attestation, PairParams, PairResponse, RegisterParams, RotateParams, Status,
+    UserWellKnown, XmppChallengeParams,
+};
+
+/// Header that makes retried callbacks return the invoice already issued.
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+#[derive(Debug)]
+pub enum Error {
+    /// The server rejected the request, branch on `error.code`
+    Api {
+        status: StatusCode,
+        error: ErrorResponse,
+    },
+    /// The request failed or the response could not be decoded
+    Http(reqwest::Error),
+    /// The base url cannot have paths joined to it
+    Url(url::ParseError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Api { status, error } => {
+                write!(f, "{status} {}: {}", error.code, error.reason)
+            }
+            Error::Http(e) => write!(f, "{e}"),
+            Error::Url(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Api { .. } => None,
+            Error::Http(e) => Some(e),
+            Error::Url(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Url(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+pub struct HermesClient {
+    http: reqwest::Client,
+    url: Url,
+}
+
+impl HermesClient {
+    pub fn new(url: Url) -> Self {
+        Self::with_client(url, reqwest::Client::new())
+    }
+
+    /// Uses a preconfigured `reqwest` client, e.g. with timeouts or a proxy.
+    pub fn with_client(url: Url, http: reqwest::Client) -> Self {
+        Self { http, url }
+    }
+
+    /// `GET /.well-known/lnurlp/:username`
+    pub async fn well_known(&self, username: &str) -> Result<LnurlWellKnownResponse> {
+        let url = self.endpoint(&format!(".well-known/lnurlp/{username}"))?;
+        send(self.http.get(url)).await
+    }
+
+    /// `GET /lnurlp/:username/lnurl`, the LNURL and `lightning:` uri to
+    /// render as a QR code or deep link
+    pub async fn lnurl(&self, username: &str) -> Result<LnurlResponse> {
+        let url = self.endpoint(&format!("lnurlp/{username}/lnurl"))?;
+        send(self.http.get(url)).await
+    }
+
+    /// `GET /lnurlp/:username/callback`
+    pub async fn callback(
+        &self,
+        username: &str,
+        params: &LnurlCallbackParams,
+    ) -> Result<LnurlCallbackResponse> {
+        let url = self.endpoint(&format!("lnurlp/{username}/callback"))?;
+        send(self.http.get(url).query(params)).await
+    }
+
+    /// Like [`Self::callback`], but retrying with the same `idempotency_key`
+    /// returns the invoice that was already issued instead of a new one.
+    pub async fn callback_idempotent(
+        &self,
+        username: &str,
+        params: &LnurlCallbackParams,
+        idempotency_key: &str,
+    ) -> Result<LnurlCallbackResponse> {
+        let url = self.endpoint(&format!("lnurlp/{username}/callback"))?;
+        send(
+            self.http
+                .get(url)
+                .query(params)
+                .header(IDEMPOTENCY_KEY, idempotency_key),
+        )
+        .await
+    }
+
+    /// `GET /lnurlp/:username/verify/:op_id/:token`
+    pub async fn verify(
+        &self,
+        username: &str,
+        op_id: &str,
+        token: &str,
+    ) -> Result<LnurlVerifyResponse> {
+        let url = self.endpoint(&format!("lnurlp/{username}/verify/{op_id}/{token}"))?;
+        send(self.http.get(url)).await
+    }
+
+    /// Follows the `verify` url of a callback response.
+    pub async fn verify_url(&self, verify: &Url) -> Result<LnurlVerifyResponse> {
+        send(self.http.get(verify.clone())).await
+    }
+
+    /// `POST /register`
+    pub async fn register(&self, params: &RegisterParams) -> Result<bool> {
+        let url = self.endpoint("register")?;
+        send(self.http.post(url).json(params)).await
+    }
+
+    /// `POST /register/xmpp`, sends a code to `jid` to register with.
+    pub async fn xmpp_challenge(&self, jid: &str) -> Result<bool> {
+        let url = self.endpoint("register/xmpp")?;
+        let params = XmppChallengeParams {
+            jid: jid.to_string(),
+        };
+        send(self.http.post(url).json(&params)).await
+    }
+
+    /// `POST /wallet/pair`. `authorization` is the NIP-98 header value,
+    /// `Nostr <base64 event>`, signed by the wallet's key for this url, with
+    /// a `payload` tag of the hex SHA-256 of `serde_json::to_vec(params)`.
+    /// Each event is only accepted once.
+    pub async fn pair(&self, params: &PairParams, authorization: &str) -> Result<PairResponse> {
+        let url = self.endpoint("wallet/pair")?;
+        send(
+            self.http
+                .post(url)
+                .header(AUTHORIZATION, authorization)
+                .json(params),
+        )
+        .await
+    }
+
+    /// `POST /wallet/rotate`, with a NIP-98 header like `pair`.
+    pub async fn rotate(&self, params: &RotateParams, authorization: &str) -> Result<PairResponse> {
+        let url = self.endpoint("wallet/rotate")?;
+        send(
+            self.http
+                .post(url)
+                .header(AUTHORIZATION, authorization)
+                .json(params),
+        )
+        .await
+    }
+
+    /// `GET /.well-known/nostr.json?name=`
+    pub async fn nip05(&self, name: &str) -> Result<UserWellKnown> {
+        let url = self.endpoint(".well-known/nostr.json")?;
+        send(self.http.get(url).query(&[("name", name)])).await
+    }
+
+    /// `GET /nip05/verify`, whether `nip05` is `pubkey`'s identity.
+    pub async fn verify_nip05(&self, nip05: &str, pubkey: &str) -> Result<Nip05Attestation> {
+        let url = self.endpoint("nip05/verify")?;
+        send(
+            self.http
+                .get(url)
+                .query(&[("nip05", nip05), ("pubkey", pubkey)]),
+        )
+        .await
+    }
+
+    /// `GET /invoices/lookup?bolt11=`
+    pub async fn lookup_bolt11(&self, bolt11: &str) -> Result<InvoiceLookup> {
+        let url = self.endpoint("invoices/lookup")?;
+        send(self.http.get(url).query(&[("bolt11", bolt11)])).await
+    }
+
+    /// `GET /invoices/lookup?paymentHash=`
+    pub async fn lookup_payment_hash(&self, payment_hash: &str) -> Result<InvoiceLookup> {
+        let url = self.endpoint("invoices/lookup")?;
+        send(self.http.get(url).query(&[("paymentHash", payment_hash)])).await
+    }
+
+    /// `POST /invoices/status`, at most 100 invoices at once.
+    pub async fn invoice_status(
+        &self,
+        params: &InvoiceStatusParams,
+    ) -> Result<InvoiceStatusResponse> {
+        let url = self.endpoint("invoices/status")?;
+        send(self.http.post(url).json(params)).await
+    }
+
+    /// `GET /status`
+    pub async fn status(&self) -> Result<Status> {
+        let url = self.endpoint("status")?;
+        send(self.http.get(url)).await
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        let mut base = self.url.clone();
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(base.join(path)?)
+    }
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let res = request.send().await?;
+    let status = res.status();
+    if !status.is_success() {
+        let error = res.json().await?;
+        return Err(Error::Api { status, error });
+    }
+
+    Ok(res.json().await?)
+}
@@ -0,0 +1,291 @@
+//! Request and response bodies of the public api. These mirror the structs in
+//! the server's handlers, with fedimint and nostr types replaced by their
+//! string encodings so consumers don't need those dependencies.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// LUD-06 pay request for a lightning address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlWellKnownResponse {
+    pub callback: Url,
+    /// In millisatoshis
+    pub max_sendable: u64,
+    /// In millisatoshis
+    pub min_sendable: u64,
+    pub metadata: String,
+    pub comment_allowed: Option<i32>,
+    pub tag: String,
+    pub status: String,
+    /// Hex x-only key that signs zap receipts
+    pub nostr_pubkey: Option<String>,
+    pub allows_nostr: bool,
+    /// LUD-18 payer identity fields that may be sent as `payerdata`, as
+    /// `{"name": {"mandatory": false}, ...}`
+    #[serde(default)]
+    pub payer_data: Option<serde_json::Value>,
+}
+
+/// Ways of pointing a wallet at a user's pay request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlResponse {
+    /// LUD-01 bech32 LNURL, uppercase
+    pub lnurl: String,
+    /// `lightning:` followed by the LNURL
+    pub uri: String,
+    /// LUD-17 `lnurlp://` uri
+    pub lud17: String,
+    pub lightning_address: String,
+}
+
+/// Parameters for requesting an invoice from a user's callback.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlCallbackParams {
+    /// In millisatoshis
+    pub amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proofofpayer: Option<String>,
+    /// LUD-18 payer identity, as json
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payerdata: Option<String>,
+    /// A signed zap request event, as json
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nostr: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlCallbackSuccessAction {
+    pub tag: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlCallbackResponse {
+    pub status: String,
+    pub reason: Option<String>,
+    /// BOLT11 invoice
+    pub pr: String,
+    /// Not given for users whose invoices come from their own node
+    pub verify: Option<Url>,
+    pub success_action: Option<LnurlCallbackSuccessAction>,
+    pub routes: Option<Vec<String>>,
+}
+
+/// LUD-21 payment status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlVerifyResponse {
+    pub status: String,
+    pub settled: bool,
+    /// Hex preimage, `None` until the invoice settled
+    pub preimage: Option<String>,
+    pub pr: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmType {
+    Nostr,
+    Xmpp,
+}
+
+/// How a user receives the ecash for a paid invoice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteFormat {
+    #[default]
+    Fedimint,
+    Cashu,
+    Link,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterParams {
+    /// Hex x-only nostr key
+    pub pubkey: String,
+    pub name: String,
+    pub dm_type: DmType,
+    #[serde(default)]
+    pub note_format: NoteFormat,
+    pub federation_id: String,
+    /// Deliver receipts together on the server's payout schedule
+    #[serde(default)]
+    pub batch_payouts: bool,
+    /// Hold receipts as a balance claimed with an api key
+    #[serde(default)]
+    pub custodial: bool,
+    /// Lightning address or LNURL of your own node to forward payments to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_to: Option<String>,
+    /// Seconds to redeem sent notes in before the server reclaims them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes_expiry_secs: Option<u32>,
+    /// Nostr relays, or the single XMPP chat server. The server's defaults
+    /// are used if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relays: Option<Vec<String>>,
+    /// XMPP address to DM, required for XMPP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jid: Option<String>,
+    /// The code `xmpp_challenge` had sent to `jid`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jid_code: Option<String>,
+    /// XMPP groupchat to post a notice of every payment in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_room: Option<String>,
+    /// DM types to try in order when `dm_type` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_dm_types: Vec<DmType>,
+    /// Hex pubkey of an NWC wallet to send `payment_received` notifications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nwc_pubkey: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XmppChallengeParams {
+    pub jid: String,
+}
+
+/// NIP-05 names and relays, keyed by hex pubkey.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserWellKnown {
+    pub names: HashMap<String, String>,
+    pub relays: HashMap<String, Vec<String>>,
+}
+
+/// Whether a NIP-05 identity belongs to a pubkey, as checked by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Nip05Attestation {
+    pub nip05: String,
+    pub pubkey: String,
+    pub valid: bool,
+    /// The same as a nostr event signed by the server's `nostr_pubkey`
+    pub attestation: serde_json::Value,
+}
+
+/// An invoice found by `HermesClient::lookup_bolt11` or
+/// `HermesClient::lookup_payment_hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceLookup {
+    pub payment_hash: String,
+    /// In millisatoshis
+    pub amount: i64,
+    /// `Pending`, `Settled`, `Cancelled` or `Expired`
+    pub state: String,
+    pub settled: bool,
+    /// Only returned when looking up by bolt11
+    pub verify: Option<Url>,
+}
+
+/// Request of `HermesClient::invoice_status`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatusParams {
+    pub operations: Vec<VerifyOperation>,
+    pub payment_hashes: Vec<String>,
+}
+
+/// The operation id and token at the end of a verify url.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyOperation {
+    pub op_id: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatusResponse {
+    /// Unknown invoices are left out
+    pub invoices: Vec<InvoiceStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatus {
+    /// Only returned for invoices asked for by operation
+    pub op_id: Option<String>,
+    pub payment_hash: String,
+    /// In millisatoshis
+    pub amount: i64,
+    /// `Pending`, `Settled`, `Cancelled` or `Expired`
+    pub state: String,
+    pub settled: bool,
+    /// Only returned for settled invoices asked for by operation
+    pub preimage: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairParams {
+    pub federation_id: String,
+    /// Assigned from the pubkey if not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Receives a signed webhook for every settled payment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_url: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relays: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotateParams {
+    /// Keeps the current push url if not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_url: Option<Url>,
+}
+
+/// Credentials of a paired wallet, only returned once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairResponse {
+    pub name: String,
+    pub lightning_address: String,
+    pub federation_id: String,
+    pub api_key: String,
+    /// HMAC key of the `BTCPay-Sig` header on pushes
+    pub push_secret: Option<String>,
+}
+
+/// What a server supports, for feature detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub version: String,
+    pub domain: String,
+    pub luds: Vec<u16>,
+    pub nips: Vec<u16>,
+    pub registration_open: bool,
+    pub note_formats: Vec<NoteFormat>,
+    pub dm_types: Vec<DmType>,
+    /// Only listed if the operator publishes them
+    #[serde(default)]
+    pub federations: Option<Vec<String>>,
+}
+
+/// The body of every error response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub reason: String,
+    /// Stable machine readable code, e.g. `USER_NOT_FOUND`
+    pub code: String,
+    pub request_id: Option<String>,
+    /// Free alternatives for a `NAME_TAKEN` registration
+    #[serde(default)]
+    pub suggestions: Option<Vec<String>>,
+}
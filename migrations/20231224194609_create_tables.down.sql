@@ -0,0 +1,5 @@
+DROP TABLE zaps;
+DROP TABLE invoice;
+DROP TABLE app_user_relays;
+DROP TABLE relay;
+DROP TABLE app_user;
@@ -1,10 +1,9 @@
--- Up
 CREATE TABLE app_user (
     id SERIAL PRIMARY KEY,
     pubkey VARCHAR(64) NOT NULL,
     name VARCHAR(20) NOT NULL,
     dm_type VARCHAR(5) NOT NULL,
-    federation_id VARCHAR(64) NOT NULL,
+    federation_id VARCHAR(64) NOT NULL
 );
 CREATE TABLE relay (
     id SERIAL PRIMARY KEY,
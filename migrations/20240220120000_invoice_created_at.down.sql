@@ -0,0 +1,3 @@
+DROP INDEX invoice_created_at_idx;
+DROP INDEX invoice_state_idx;
+ALTER TABLE invoice DROP COLUMN created_at;
@@ -0,0 +1,3 @@
+ALTER TABLE invoice ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+CREATE INDEX invoice_state_idx ON invoice (state);
+CREATE INDEX invoice_created_at_idx ON invoice (created_at);
@@ -0,0 +1 @@
+ALTER TABLE invoice ADD COLUMN request_id VARCHAR(64);
@@ -0,0 +1,2 @@
+DROP INDEX invoice_idempotency_key_idx;
+ALTER TABLE invoice DROP COLUMN idempotency_key;
@@ -0,0 +1,3 @@
+ALTER TABLE invoice ADD COLUMN idempotency_key VARCHAR(255);
+CREATE UNIQUE INDEX invoice_idempotency_key_idx ON invoice (app_user_id, idempotency_key)
+    WHERE idempotency_key IS NOT NULL;
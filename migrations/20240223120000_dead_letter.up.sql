@@ -0,0 +1,12 @@
+CREATE TABLE dead_letter (
+    id SERIAL PRIMARY KEY,
+    invoice_id INTEGER NOT NULL references invoice(id),
+    channel VARCHAR(32) NOT NULL,
+    operation_id VARCHAR(64),
+    notes TEXT,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+CREATE INDEX dead_letter_unresolved_idx ON dead_letter (created_at) WHERE resolved_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE app_user ADD COLUMN note_format VARCHAR(16) NOT NULL DEFAULT 'fedimint';
@@ -0,0 +1,4 @@
+ALTER TABLE app_user DROP COLUMN api_key_hash;
+
+DROP INDEX invoice_payment_hash_idx;
+ALTER TABLE invoice DROP COLUMN payment_hash;
@@ -0,0 +1,4 @@
+ALTER TABLE invoice ADD COLUMN payment_hash VARCHAR(64);
+CREATE INDEX invoice_payment_hash_idx ON invoice (app_user_id, payment_hash);
+
+ALTER TABLE app_user ADD COLUMN api_key_hash VARCHAR(64) UNIQUE;
@@ -0,0 +1,8 @@
+CREATE TABLE webhook (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL references app_user(id),
+    url TEXT NOT NULL,
+    secret VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX webhook_app_user_id_idx ON webhook (app_user_id);
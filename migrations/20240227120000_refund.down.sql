@@ -0,0 +1,3 @@
+DROP TABLE refund;
+
+ALTER TABLE invoice DROP COLUMN payer_pubkey;
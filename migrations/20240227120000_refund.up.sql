@@ -0,0 +1,15 @@
+ALTER TABLE invoice ADD COLUMN payer_pubkey VARCHAR(66);
+
+CREATE TABLE refund (
+    id SERIAL PRIMARY KEY,
+    invoice_id INTEGER NOT NULL UNIQUE references invoice(id),
+    payer_pubkey VARCHAR(66) NOT NULL,
+    amount BIGINT NOT NULL,
+    reason VARCHAR(32) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    claimed_at TIMESTAMPTZ,
+    voided_at TIMESTAMPTZ,
+    bolt11 TEXT,
+    operation_id VARCHAR(64)
+);
+CREATE INDEX refund_payer_pubkey_idx ON refund (payer_pubkey) WHERE claimed_at IS NULL;
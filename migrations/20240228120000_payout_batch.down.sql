@@ -0,0 +1,5 @@
+ALTER TABLE invoice DROP COLUMN payout_batch_id;
+
+DROP TABLE payout_batch;
+
+ALTER TABLE app_user DROP COLUMN batch_payouts;
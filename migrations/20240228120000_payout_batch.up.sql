@@ -0,0 +1,18 @@
+ALTER TABLE app_user ADD COLUMN batch_payouts BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE payout_batch (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL references app_user(id),
+    federation_id VARCHAR(64) NOT NULL,
+    amount BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sealed_at TIMESTAMPTZ,
+    operation_id VARCHAR(64),
+    notes TEXT,
+    delivered_at TIMESTAMPTZ,
+    error TEXT
+);
+-- receipts are added to the user's single open batch until it is sealed for payout
+CREATE UNIQUE INDEX payout_batch_open_idx ON payout_batch (app_user_id, federation_id) WHERE sealed_at IS NULL;
+
+ALTER TABLE invoice ADD COLUMN payout_batch_id INTEGER references payout_batch(id);
@@ -0,0 +1,5 @@
+DROP TABLE withdrawal;
+
+DROP TABLE balance;
+
+ALTER TABLE app_user DROP COLUMN custodial;
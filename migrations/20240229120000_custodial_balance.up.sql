@@ -0,0 +1,21 @@
+ALTER TABLE app_user ADD COLUMN custodial BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE balance (
+    app_user_id INTEGER NOT NULL references app_user(id),
+    federation_id VARCHAR(64) NOT NULL,
+    amount BIGINT NOT NULL CHECK (amount >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (app_user_id, federation_id)
+);
+
+CREATE TABLE withdrawal (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL references app_user(id),
+    federation_id VARCHAR(64) NOT NULL,
+    amount BIGINT NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    operation_id VARCHAR(64) NOT NULL,
+    bolt11 TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX withdrawal_app_user_id_idx ON withdrawal (app_user_id);
@@ -0,0 +1,13 @@
+CREATE TABLE ecash_payout (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL references app_user(id),
+    invoice_id INTEGER references invoice(id),
+    payout_batch_id INTEGER references payout_batch(id),
+    operation_id VARCHAR(64) NOT NULL,
+    amount BIGINT NOT NULL,
+    note_format VARCHAR(16) NOT NULL,
+    notes TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    claimed_at TIMESTAMPTZ
+);
+CREATE INDEX ecash_payout_unclaimed_idx ON ecash_payout (app_user_id) WHERE claimed_at IS NULL;
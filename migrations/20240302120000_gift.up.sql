@@ -0,0 +1,11 @@
+CREATE TABLE gift (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL references app_user(id),
+    invoice_id INTEGER NOT NULL UNIQUE references invoice(id),
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    operation_id VARCHAR(64) NOT NULL,
+    amount BIGINT NOT NULL,
+    notes TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    claimed_at TIMESTAMPTZ
+);
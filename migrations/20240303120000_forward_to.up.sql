@@ -0,0 +1 @@
+ALTER TABLE app_user ADD COLUMN forward_to TEXT;
@@ -0,0 +1 @@
+ALTER TABLE app_user ADD COLUMN notes_expiry_secs INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE ecash_payout DROP COLUMN reissued_at;
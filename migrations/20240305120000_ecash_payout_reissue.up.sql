@@ -0,0 +1 @@
+ALTER TABLE ecash_payout ADD COLUMN reissued_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+DROP INDEX invoice_app_user_id_created_at_idx;
+
+DROP TABLE receive_limit;
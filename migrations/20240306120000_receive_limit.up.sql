@@ -0,0 +1,9 @@
+CREATE TABLE receive_limit (
+    app_user_id INTEGER PRIMARY KEY references app_user(id),
+    daily_msats BIGINT,
+    weekly_msats BIGINT,
+    exempt BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX invoice_app_user_id_created_at_idx ON invoice (app_user_id, created_at);
@@ -0,0 +1,7 @@
+CREATE TABLE ip_ban (
+    ip VARCHAR(45) PRIMARY KEY,
+    reason TEXT NOT NULL,
+    expires_at TIMESTAMPTZ,
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS refund_payer_lookup_idx;
+ALTER TABLE refund DROP COLUMN payer_lookup;
@@ -0,0 +1,4 @@
+-- payer keys are encrypted with a random nonce, so refunds are looked up by
+-- a keyed hash of the key instead
+ALTER TABLE refund ADD COLUMN payer_lookup TEXT;
+CREATE INDEX refund_payer_lookup_idx ON refund (payer_lookup) WHERE claimed_at IS NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE federation_stats (
+    federation_id TEXT NOT NULL,
+    day DATE NOT NULL,
+    settled_count BIGINT NOT NULL DEFAULT 0,
+    settled_msats BIGINT NOT NULL DEFAULT 0,
+    gateway_fee_msats BIGINT NOT NULL DEFAULT 0,
+    notes_issued_count BIGINT NOT NULL DEFAULT 0,
+    notes_issued_msats BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (federation_id, day)
+);
+
+CREATE INDEX federation_stats_day_idx ON federation_stats (day);
@@ -0,0 +1,4 @@
+DROP INDEX dead_letter_created_at_idx;
+DROP INDEX app_user_created_at_idx;
+
+ALTER TABLE app_user DROP COLUMN created_at;
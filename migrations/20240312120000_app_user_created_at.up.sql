@@ -0,0 +1,12 @@
+-- users registered before this have no registration time unless the audit log has it
+ALTER TABLE app_user ADD COLUMN created_at TIMESTAMPTZ;
+ALTER TABLE app_user ALTER COLUMN created_at SET DEFAULT NOW();
+UPDATE app_user SET created_at = registered.created_at
+    FROM (
+        SELECT target, MIN(created_at) AS created_at FROM audit_log
+            WHERE action = 'user.register' GROUP BY target
+    ) registered
+    WHERE registered.target = app_user.name;
+
+CREATE INDEX app_user_created_at_idx ON app_user (created_at);
+CREATE INDEX dead_letter_created_at_idx ON dead_letter (created_at);
@@ -0,0 +1,3 @@
+DROP INDEX invoice_events_invoice_id_idx;
+
+DROP TABLE invoice_events;
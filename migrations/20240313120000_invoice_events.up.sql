@@ -0,0 +1,13 @@
+CREATE TABLE invoice_events (
+    id BIGSERIAL PRIMARY KEY,
+    invoice_id INTEGER NOT NULL references invoice(id),
+    event VARCHAR(32) NOT NULL,
+    from_state INTEGER,
+    to_state INTEGER,
+    source VARCHAR(16) NOT NULL,
+    detail TEXT,
+    request_id VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX invoice_events_invoice_id_idx ON invoice_events (invoice_id, created_at);
@@ -0,0 +1,2 @@
+ALTER TABLE invoice DROP COLUMN deleted_at;
+ALTER TABLE app_user DROP COLUMN deleted_at;
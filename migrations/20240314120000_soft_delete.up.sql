@@ -0,0 +1,2 @@
+ALTER TABLE app_user ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE invoice ADD COLUMN deleted_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+DROP INDEX invoice_bolt11_idx;
+DROP INDEX invoice_payment_hash_lookup_idx;
@@ -0,0 +1,2 @@
+CREATE INDEX invoice_payment_hash_lookup_idx ON invoice (payment_hash);
+CREATE INDEX invoice_bolt11_idx ON invoice USING HASH (bolt11);
@@ -0,0 +1,4 @@
+ALTER TABLE invoice DROP COLUMN gateway_fee_msats;
+ALTER TABLE invoice DROP COLUMN gateway_id;
+ALTER TABLE invoice DROP COLUMN settled_at;
+ALTER TABLE invoice DROP COLUMN preimage;
@@ -0,0 +1,4 @@
+ALTER TABLE invoice ADD COLUMN preimage VARCHAR(64);
+ALTER TABLE invoice ADD COLUMN settled_at TIMESTAMPTZ;
+ALTER TABLE invoice ADD COLUMN gateway_id VARCHAR(66);
+ALTER TABLE invoice ADD COLUMN gateway_fee_msats BIGINT;
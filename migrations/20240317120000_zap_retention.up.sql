@@ -0,0 +1,7 @@
+CREATE TABLE zaps_archive
+(
+    id          INTEGER     NOT NULL PRIMARY KEY references invoice (id),
+    request     TEXT        NOT NULL,
+    event_id    VARCHAR(64),
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,9 @@
+-- fails while history points at archived invoices, move them back first
+ALTER TABLE zaps_archive ADD CONSTRAINT zaps_archive_id_fkey FOREIGN KEY (id) REFERENCES invoice (id);
+ALTER TABLE gift ADD CONSTRAINT gift_invoice_id_fkey FOREIGN KEY (invoice_id) REFERENCES invoice (id);
+ALTER TABLE ecash_payout ADD CONSTRAINT ecash_payout_invoice_id_fkey FOREIGN KEY (invoice_id) REFERENCES invoice (id);
+ALTER TABLE refund ADD CONSTRAINT refund_invoice_id_fkey FOREIGN KEY (invoice_id) REFERENCES invoice (id);
+ALTER TABLE dead_letter ADD CONSTRAINT dead_letter_invoice_id_fkey FOREIGN KEY (invoice_id) REFERENCES invoice (id);
+ALTER TABLE invoice_events ADD CONSTRAINT invoice_events_invoice_id_fkey FOREIGN KEY (invoice_id) REFERENCES invoice (id);
+
+DROP TABLE invoice_archive;
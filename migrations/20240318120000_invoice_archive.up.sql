@@ -0,0 +1,15 @@
+-- finished invoices moved out of the hot table, columns added to invoice
+-- need adding here too
+CREATE TABLE invoice_archive (
+    LIKE invoice INCLUDING DEFAULTS,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id)
+);
+
+-- history may point at an archived invoice
+ALTER TABLE invoice_events DROP CONSTRAINT invoice_events_invoice_id_fkey;
+ALTER TABLE dead_letter DROP CONSTRAINT dead_letter_invoice_id_fkey;
+ALTER TABLE refund DROP CONSTRAINT refund_invoice_id_fkey;
+ALTER TABLE ecash_payout DROP CONSTRAINT ecash_payout_invoice_id_fkey;
+ALTER TABLE gift DROP CONSTRAINT gift_invoice_id_fkey;
+ALTER TABLE zaps_archive DROP CONSTRAINT zaps_archive_id_fkey;
@@ -0,0 +1,2 @@
+ALTER TABLE invoice_archive DROP COLUMN payer_data;
+ALTER TABLE invoice DROP COLUMN payer_data;
@@ -0,0 +1,2 @@
+ALTER TABLE invoice ADD COLUMN payer_data TEXT;
+ALTER TABLE invoice_archive ADD COLUMN payer_data TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE zaps_archive DROP COLUMN receipt;
+ALTER TABLE zaps DROP COLUMN receipt;
@@ -0,0 +1,2 @@
+ALTER TABLE zaps ADD COLUMN receipt TEXT;
+ALTER TABLE zaps_archive ADD COLUMN receipt TEXT;
@@ -0,0 +1 @@
+DROP INDEX app_user_name_lower_idx;
@@ -0,0 +1,3 @@
+-- fails if two names only differ in case, rename one of them first. Names of
+-- deleted users stay taken.
+CREATE UNIQUE INDEX app_user_name_lower_idx ON app_user (lower(name));
@@ -0,0 +1,2 @@
+DROP TABLE xmpp_challenge;
+ALTER TABLE app_user DROP COLUMN jid;
@@ -0,0 +1,8 @@
+-- users registered before this keep getting DMs at name@XMPP_CHAT_SERVER
+ALTER TABLE app_user ADD COLUMN jid VARCHAR(255);
+CREATE TABLE xmpp_challenge (
+    jid VARCHAR(255) PRIMARY KEY,
+    code_hash VARCHAR(64) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1 @@
+DROP TABLE xmpp_outbox;
@@ -0,0 +1,11 @@
+CREATE TABLE xmpp_outbox (
+    id SERIAL PRIMARY KEY,
+    app_user_id INTEGER NOT NULL REFERENCES app_user(id),
+    jid VARCHAR(255) NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ,
+    -- xmpp, or nostr if the recipient never came online
+    channel VARCHAR(16)
+);
+CREATE INDEX xmpp_outbox_pending_idx ON xmpp_outbox (created_at) WHERE sent_at IS NULL;
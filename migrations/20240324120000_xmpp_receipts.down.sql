@@ -0,0 +1,3 @@
+ALTER TABLE xmpp_outbox DROP COLUMN received_at;
+ALTER TABLE xmpp_outbox DROP COLUMN invoice_id;
+ALTER TABLE xmpp_outbox DROP COLUMN operation_id;
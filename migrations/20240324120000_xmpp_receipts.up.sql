@@ -0,0 +1,5 @@
+-- the payout a message carries, invoice_id is unset for payout batches
+ALTER TABLE xmpp_outbox ADD COLUMN operation_id VARCHAR(64);
+ALTER TABLE xmpp_outbox ADD COLUMN invoice_id INTEGER;
+-- when the recipient's client sent a XEP-0184 receipt
+ALTER TABLE xmpp_outbox ADD COLUMN received_at TIMESTAMPTZ;
@@ -0,0 +1 @@
+ALTER TABLE app_user ADD COLUMN notify_room VARCHAR(255);
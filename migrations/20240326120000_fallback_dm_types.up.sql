@@ -0,0 +1,2 @@
+-- comma separated dm types tried in order after dm_type fails
+ALTER TABLE app_user ADD COLUMN fallback_dm_types VARCHAR(64);
@@ -0,0 +1 @@
+ALTER TABLE app_user ADD COLUMN nwc_pubkey VARCHAR(64);
@@ -0,0 +1,6 @@
+-- display names from kind-0 profiles, keyed by pubkey so users sharing one share it
+CREATE TABLE nostr_profile (
+    pubkey VARCHAR(64) PRIMARY KEY,
+    display_name VARCHAR(255),
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
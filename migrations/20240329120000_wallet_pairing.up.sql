@@ -0,0 +1,12 @@
+-- the user each wallet key was paired with, a key pairs once while its user lives
+CREATE TABLE wallet_pairing (
+    pubkey VARCHAR(64) PRIMARY KEY,
+    app_user_id INTEGER NOT NULL REFERENCES app_user(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- keys that already have a user count as paired with their oldest one
+INSERT INTO wallet_pairing (pubkey, app_user_id)
+SELECT DISTINCT ON (pubkey) pubkey, id FROM app_user
+WHERE deleted_at IS NULL
+ORDER BY pubkey, id;
@@ -0,0 +1,2 @@
+ALTER TABLE ecash_payout DROP COLUMN reissue_msats;
+ALTER TABLE ecash_payout DROP COLUMN reissue_federation_id;
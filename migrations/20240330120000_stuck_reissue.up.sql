@@ -0,0 +1,3 @@
+-- a reissue whose funds left the source federation but never reached the user
+ALTER TABLE ecash_payout ADD COLUMN reissue_federation_id VARCHAR(64);
+ALTER TABLE ecash_payout ADD COLUMN reissue_msats BIGINT;
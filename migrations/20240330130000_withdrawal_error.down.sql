@@ -0,0 +1 @@
+ALTER TABLE withdrawal DROP COLUMN error;
@@ -0,0 +1,2 @@
+-- set when it's unknown whether a lightning withdrawal went out
+ALTER TABLE withdrawal ADD COLUMN error TEXT;
@@ -0,0 +1,6 @@
+-- invoices handed out with a verify url from before the url carried a token,
+-- still answered on the old url until they expire
+CREATE TABLE legacy_verify (
+    invoice_id INTEGER PRIMARY KEY REFERENCES invoice(id) ON DELETE CASCADE
+);
+INSERT INTO legacy_verify (invoice_id) SELECT id FROM invoice WHERE state = 0;
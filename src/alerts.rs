@@ -0,0 +1,179 @@
+//! Tells the operator when something needs attention, as a nostr DM to
+//! `ALERT_NPUB` and/or a POST to `ALERT_WEBHOOK_URL`. Every error logged with
+//! `alert = true` is sent, and the `alert_checks` job logs such errors for
+//! conditions nothing else notices: an unreachable database or federation,
+//! dead letters piling up and federations without a gateway.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{
+    field::{Field, Visit},
+    warn, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::{config::CONFIG, state::AppState, utils::http_client_builder};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const QUEUE_SIZE: usize = 100;
+
+static ALERTS: OnceLock<broadcast::Sender<Alert>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+struct Alert {
+    /// Alerts with the same key are only sent once per `ALERT_REPEAT_SECS`
+    key: String,
+    message: String,
+}
+
+/// Whether an alert destination is configured.
+pub fn enabled() -> bool {
+    CONFIG.alert_npub.is_some() || CONFIG.alert_webhook_url.is_some()
+}
+
+fn sender() -> &'static broadcast::Sender<Alert> {
+    ALERTS.get_or_init(|| broadcast::channel(QUEUE_SIZE).0)
+}
+
+/// Turns error events with `alert = true` into alerts, keyed by where they
+/// were logged and the `federation_id` field if they have one.
+pub struct AlertLayer;
+
+impl<S: Subscriber> Layer<S> for AlertLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut fields = AlertFields::default();
+        event.record(&mut fields);
+        if !fields.alert {
+            return;
+        }
+
+        let mut key = event.metadata().name().to_string();
+        if let Some(federation_id) = fields.federation_id {
+            key.push_str(&format!(" {federation_id}"));
+        }
+        // nobody listening yet, e.g. during startup, only drops the alert
+        let _ = sender().send(Alert {
+            key,
+            message: fields.message,
+        });
+    }
+}
+
+#[derive(Default)]
+struct AlertFields {
+    alert: bool,
+    message: String,
+    federation_id: Option<String>,
+}
+
+impl Visit for AlertFields {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "alert" {
+            self.alert = value;
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "federation_id" => self.federation_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            "federation_id" => self.federation_id = Some(format!("{value:?}")),
+            _ => {}
+        }
+    }
+}
+
+/// Sends alerts until shutdown, skipping repeats of an alert sent within
+/// `ALERT_REPEAT_SECS`.
+pub async fn dispatch(state: AppState) -> Result<()> {
+    let mut alerts = sender().subscribe();
+    let http = http_client_builder()?.timeout(DELIVERY_TIMEOUT).build()?;
+    let mut last_sent = HashMap::<String, Instant>::new();
+
+    loop {
+        let alert = tokio::select! {
+            _ = state.shutdown.cancelled() => return Ok(()),
+            alert = alerts.recv() => alert,
+        };
+
+        match alert {
+            Ok(alert) => {
+                let now = Instant::now();
+                last_sent.retain(|_, sent| now.duration_since(*sent) < CONFIG.alert_repeat);
+                if last_sent.contains_key(&alert.key) {
+                    metrics::counter!("alerts_total", "result" => "repeat").increment(1);
+                    continue;
+                }
+                last_sent.insert(alert.key.clone(), now);
+                send(&state, &http, &alert).await;
+            }
+            Err(RecvError::Lagged(missed)) => warn!("Alert dispatcher missed {missed} alert(s)"),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Failures are only logged, without `alert` so they don't loop back here.
+async fn send(state: &AppState, http: &reqwest::Client, alert: &Alert) {
+    let message = format!("hermes {}: {}", CONFIG.domain, alert.message);
+
+    if let Some(npub) = CONFIG.alert_npub {
+        match state
+            .nostr
+            .send_direct_msg(npub, message.clone(), None)
+            .await
+        {
+            Ok(_) => metrics::counter!("alerts_total", "result" => "sent").increment(1),
+            Err(e) => {
+                metrics::counter!("alerts_total", "result" => "failed").increment(1);
+                warn!("Could not send alert over nostr: {e}");
+            }
+        }
+    }
+
+    if let Some(url) = CONFIG.alert_webhook_url.as_ref() {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        // `text` so Slack and Mattermost incoming webhooks show it as is
+        let body = json!({
+            "text": message,
+            "key": alert.key,
+            "domain": CONFIG.domain,
+            "timestamp": timestamp,
+        });
+        let sent = http
+            .post(url.clone())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        match sent {
+            Ok(_) => metrics::counter!("alerts_total", "result" => "sent").increment(1),
+            Err(e) => {
+                metrics::counter!("alerts_total", "result" => "failed").increment(1);
+                warn!("Could not post alert to ALERT_WEBHOOK_URL: {e}");
+            }
+        }
+    }
+}
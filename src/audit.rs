@@ -0,0 +1,49 @@
+//! Append-only trail of security relevant actions: registrations, admin
+//! operations and setting changes. Listed with `GET /admin/audit`.
+
+use serde_json::Value;
+use tracing::error;
+
+use crate::{
+    model::{
+        audit_log::{AuditEntryForCreate, AuditLogBmc},
+        ModelManager,
+    },
+    router::middleware::{current_admin_key_id, current_request_id},
+};
+
+/// The admin making the current request, by the id of their key.
+pub fn admin_actor() -> String {
+    format!(
+        "admin:{}",
+        current_admin_key_id().unwrap_or_else(|| "unknown".to_string())
+    )
+}
+
+pub fn user_actor(pubkey: &str) -> String {
+    format!("user:{pubkey}")
+}
+
+/// Records an action that already happened, tagged with the current request
+/// id. Failing to record doesn't undo or fail the action, it raises an alert.
+pub async fn record(
+    mm: &ModelManager,
+    actor: String,
+    action: &str,
+    target: Option<&str>,
+    details: Value,
+) {
+    let entry = AuditEntryForCreate {
+        actor,
+        action: action.to_string(),
+        target: target.map(|t| t.to_string()),
+        details,
+        request_id: current_request_id(),
+    };
+    if let Err(e) = AuditLogBmc::create(mm, entry).await {
+        error!(
+            alert = true,
+            "Could not record {action} in the audit log: {e:#}"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, KeyInit, OsRng},
+    XChaCha20Poly1305,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use object_store::path::Path;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::{
+    config::CONFIG,
+    export::{self, Encryptor, ExportFormat, ExportStream},
+    model::{export::ExportBmc, ModelManager},
+};
+
+/// The XChaCha20-Poly1305 nonce less the 5 bytes STREAM keeps for its
+/// counter and last segment flag.
+const NONCE_SIZE: usize = 19;
+
+/// Exports every table and encrypts it with `BACKUP_KEY` as it's written.
+/// The archive is the 19 byte nonce followed by the JSON export, encrypted
+/// with XChaCha20-Poly1305 in the STREAM construction (big endian 32 bit
+/// counter): segments of `export::CHUNK_SIZE` bytes, each followed by its
+/// 16 byte tag, the last one shorter.
+pub fn create_backup(mm: &ModelManager) -> Result<ExportStream> {
+    let key = CONFIG
+        .backup_key
+        .as_deref()
+        .ok_or_else(|| anyhow!("BACKUP_KEY is not configured"))?;
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let encryptor = Encryptor::from_aead(XChaCha20Poly1305::new(key.into()), &nonce.into());
+
+    let mm = mm.clone();
+    let archive = export::spawn(ExportFormat::Json, Some(encryptor), |mut out| async move {
+        ExportBmc::snapshot(&mm, &mut out).await?;
+        out.finish().await
+    });
+
+    Ok(
+        stream::once(async move { Ok(Bytes::copy_from_slice(&nonce)) })
+            .chain(archive)
+            .boxed(),
+    )
+}
+
+pub fn backup_file_name() -> String {
+    let now = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    format!("hermes-backup-{now}.bin")
+}
+
+/// Writes a new backup to `BACKUP_URL`, uploaded in parts as it's written.
+pub async fn upload_backup(mm: &ModelManager) -> Result<()> {
+    let url = CONFIG
+        .backup_url
+        .as_ref()
+        .ok_or_else(|| anyhow!("BACKUP_URL is not configured"))?;
+    // credentials come from the usual AWS_*/GOOGLE_* environment variables
+    let (store, prefix) = object_store::parse_url_opts(url, std::env::vars())?;
+
+    let mut archive = create_backup(mm)?;
+    let location = Path::from(format!("{}/{}", prefix, backup_file_name()));
+    let (upload_id, mut upload) = store.put_multipart(&location).await?;
+    let uploaded = async {
+        while let Some(chunk) = archive.try_next().await? {
+            upload.write_all(&chunk).await?;
+        }
+        upload.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    if let Err(e) = uploaded {
+        // don't leave a truncated backup, or its parts, behind
+        let _ = store.abort_multipart(&location, &upload_id).await;
+        return Err(e);
+    }
+
+    info!("Uploaded backup to {location}");
+    Ok(())
+}
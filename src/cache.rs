@@ -0,0 +1,82 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{model::app_user::AppUser, router::handlers::nostr::AppUserRelays};
+
+/// A small in-process cache keyed by username. Entries expire after `ttl` and
+/// are invalidated explicitly when the user they describe changes.
+pub struct TtlCache<V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let entries = self.entries.lock().expect("cache lock poisoned");
+        entries
+            .get(key)
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, v)| v.clone())
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        entries.insert(key.to_string(), (Instant::now(), value));
+    }
+
+    pub fn invalidate(&self, key: &str) {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        entries.remove(key);
+    }
+
+    /// Drops expired entries so names that are never looked up again don't linger.
+    pub fn retain_fresh(&self) {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+    }
+}
+
+/// Caches the user lookups behind `/.well-known/lnurlp/:username` and
+/// `/.well-known/nostr.json`, which are hit far more often than users change,
+/// and the callback's, which only briefly since it acts on the settings.
+pub struct UserCache {
+    pub app_users: TtlCache<AppUser>,
+    pub nip05: TtlCache<AppUserRelays>,
+    pub callback: TtlCache<AppUserRelays>,
+}
+
+impl UserCache {
+    pub fn new(ttl: Duration, callback_ttl: Duration) -> Self {
+        Self {
+            app_users: TtlCache::new(ttl),
+            nip05: TtlCache::new(ttl),
+            callback: TtlCache::new(callback_ttl),
+        }
+    }
+
+    /// Forgets everything cached for a username, call whenever that user changes.
+    pub fn invalidate(&self, name: &str) {
+        self.app_users.invalidate(name);
+        self.nip05.invalidate(name);
+        self.callback.invalidate(name);
+    }
+
+    pub fn retain_fresh(&self) {
+        self.app_users.retain_fresh();
+        self.nip05.retain_fresh();
+        self.callback.retain_fresh();
+    }
+}
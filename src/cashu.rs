@@ -0,0 +1,266 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use lightning_invoice::Bolt11Invoice;
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::prelude::rand::rngs::OsRng;
+use nostr::prelude::rand::RngCore;
+use nostr::secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+use url::Url;
+
+use crate::{
+    config::CONFIG, federations::pay_bolt11, model::ModelManager, utils::http_client_builder,
+};
+
+const DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+/// Never reserve less than this for the lightning payment into the mint.
+const MIN_FEE_RESERVE_MSATS: u64 = 2_000;
+
+/// A minimal Cashu wallet (NUT-00, 01, 04) used to turn received funds into a
+/// Cashu token for recipients that don't understand fedimint notes.
+pub struct CashuMint {
+    url: Url,
+    http: reqwest::Client,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MintQuote {
+    pub quote: String,
+    pub request: String,
+}
+
+#[derive(Deserialize)]
+struct KeysResponse {
+    keysets: Vec<Keyset>,
+}
+
+#[derive(Deserialize)]
+struct Keyset {
+    id: String,
+    unit: String,
+    keys: BTreeMap<u64, String>,
+}
+
+#[derive(Deserialize)]
+struct MintResponse {
+    signatures: Vec<BlindSignature>,
+}
+
+#[derive(Deserialize)]
+struct BlindSignature {
+    amount: u64,
+    #[serde(rename = "C_")]
+    c: String,
+}
+
+#[derive(Serialize)]
+struct Proof {
+    amount: u64,
+    id: String,
+    secret: String,
+    #[serde(rename = "C")]
+    c: String,
+}
+
+struct BlindedOutput {
+    amount: u64,
+    secret: String,
+    blinding_factor: SecretKey,
+    blinded: PublicKey,
+}
+
+impl CashuMint {
+    pub fn from_config() -> Result<Self> {
+        let url = CONFIG
+            .cashu_mint_url
+            .clone()
+            .ok_or_else(|| anyhow!("CASHU_MINT_URL is not configured"))?;
+        Ok(Self {
+            url,
+            http: http_client_builder()?.build()?,
+        })
+    }
+
+    /// Sats to mint for a received amount, keeping a reserve for the
+    /// lightning fees of paying the mint.
+    pub fn quote_amount(amount_msats: u64) -> u64 {
+        let reserve =
+            (amount_msats * CONFIG.cashu_fee_reserve_ppm / 1_000_000).max(MIN_FEE_RESERVE_MSATS);
+        amount_msats.saturating_sub(reserve) / 1_000
+    }
+
+    #[instrument(skip(self))]
+    pub async fn request_quote(&self, sats: u64) -> Result<MintQuote> {
+        let quote = self
+            .http
+            .post(self.url.join("v1/mint/quote/bolt11")?)
+            .json(&json!({ "amount": sats, "unit": "sat" }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(quote)
+    }
+
+    /// Mints tokens for a paid quote and serializes them as a `cashuA` token.
+    #[instrument(skip(self))]
+    pub async fn mint_token(&self, quote: &str, sats: u64) -> Result<String> {
+        let keyset = self.active_keyset().await?;
+        let outputs = split_amount(sats)
+            .into_iter()
+            .map(blind_output)
+            .collect::<Result<Vec<_>>>()?;
+
+        let response: MintResponse = self
+            .http
+            .post(self.url.join("v1/mint/bolt11")?)
+            .json(&json!({
+                "quote": quote,
+                "outputs": outputs.iter().map(|o| json!({
+                    "amount": o.amount,
+                    "id": keyset.id,
+                    "B_": o.blinded.to_string(),
+                })).collect::<Vec<_>>(),
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if response.signatures.len() != outputs.len() {
+            bail!(
+                "Mint returned {} signatures for {} outputs",
+                response.signatures.len(),
+                outputs.len()
+            );
+        }
+
+        let secp = Secp256k1::new();
+        let proofs = outputs
+            .into_iter()
+            .zip(response.signatures)
+            .map(|(output, signature)| {
+                let mint_key = keyset
+                    .keys
+                    .get(&signature.amount)
+                    .ok_or_else(|| anyhow!("Mint has no key for amount {}", signature.amount))?;
+                let mint_key = PublicKey::from_str(mint_key)?;
+                let blinded_sig = PublicKey::from_str(&signature.c)?;
+
+                // C = C_ - rK
+                let r_k = mint_key.mul_tweak(&secp, &Scalar::from(output.blinding_factor))?;
+                let c = blinded_sig.combine(&r_k.negate(&secp))?;
+
+                Ok(Proof {
+                    amount: output.amount,
+                    id: keyset.id.clone(),
+                    secret: output.secret,
+                    c: c.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let token = json!({
+            "token": [{ "mint": self.url.as_str(), "proofs": proofs }],
+            "unit": "sat",
+        });
+        Ok(format!("cashuA{}", URL_SAFE.encode(token.to_string())))
+    }
+
+    async fn active_keyset(&self) -> Result<Keyset> {
+        let keys: KeysResponse = self
+            .http
+            .get(self.url.join("v1/keys")?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        keys.keysets
+            .into_iter()
+            .find(|k| k.unit == "sat")
+            .ok_or_else(|| anyhow!("Mint has no sat keyset"))
+    }
+}
+
+/// Pays a mint quote for `sats` with the federation's ecash, waiting for the
+/// payment to complete. The mint picks the invoice, so it's checked against
+/// the quote first.
+#[instrument(skip_all, fields(quote = %quote.quote))]
+pub async fn pay_quote(
+    mm: &ModelManager,
+    client: &ClientArc,
+    quote: &MintQuote,
+    sats: u64,
+) -> Result<OperationId> {
+    let invoice = Bolt11Invoice::from_str(&quote.request)?;
+    check_quote_invoice(&invoice, sats)?;
+    pay_bolt11(mm, client, invoice).await
+}
+
+fn check_quote_invoice(invoice: &Bolt11Invoice, sats: u64) -> Result<()> {
+    let expected = sats * 1_000;
+    if invoice.amount_milli_satoshis() != Some(expected) {
+        bail!(
+            "Mint quote invoice is for {:?} msats, expected {expected}",
+            invoice.amount_milli_satoshis()
+        );
+    }
+    if invoice.is_expired() {
+        bail!("Mint quote invoice is expired");
+    }
+    Ok(())
+}
+
+/// Splits an amount into the powers of two the mint signs for.
+fn split_amount(sats: u64) -> Vec<u64> {
+    (0..64)
+        .map(|bit| 1u64 << bit)
+        .filter(|value| sats & value != 0)
+        .collect()
+}
+
+fn blind_output(amount: u64) -> Result<BlindedOutput> {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret = hex::encode(secret);
+
+    let mut r = [0u8; 32];
+    OsRng.fill_bytes(&mut r);
+    let blinding_factor = SecretKey::from_slice(&r)?;
+
+    // B_ = Y + rG
+    let secp = Secp256k1::new();
+    let y = hash_to_curve(secret.as_bytes())?;
+    let blinded = y.combine(&PublicKey::from_secret_key(&secp, &blinding_factor))?;
+
+    Ok(BlindedOutput {
+        amount,
+        secret,
+        blinding_factor,
+        blinded,
+    })
+}
+
+/// NUT-00 hash_to_curve
+fn hash_to_curve(message: &[u8]) -> Result<PublicKey> {
+    let msg_hash = Sha256::hash(&[DOMAIN_SEPARATOR, message].concat());
+    for counter in 0u32..u16::MAX as u32 {
+        let hash = Sha256::hash(&[msg_hash.as_ref(), &counter.to_le_bytes()].concat());
+        if let Ok(point) = PublicKey::from_slice(&[&[0x02], hash.as_ref()].concat()) {
+            return Ok(point);
+        }
+    }
+    bail!("No valid point found for message")
+}
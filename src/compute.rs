@@ -0,0 +1,23 @@
+//! CPU-bound work like signing events and serializing ecash, run on tokio's
+//! blocking threads so bursts of callbacks don't hold up the async workers.
+//! At most `COMPUTE_THREADS` jobs run at once, the rest wait their turn.
+
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use tokio::sync::Semaphore;
+
+use crate::config::CONFIG;
+
+static PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+/// Runs `f` on a blocking thread once one of the pool's slots is free.
+pub async fn run<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let permits = PERMITS.get_or_init(|| Semaphore::new(CONFIG.compute_threads));
+    let _permit = permits.acquire().await?;
+    Ok(tokio::task::spawn_blocking(f).await?)
+}
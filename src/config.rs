@@ -1,19 +1,46 @@
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwap;
 use fedimint_client::derivable_secret::DerivableSecret;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_core::api::InviteCode;
+use ipnet::IpNet;
 use nostr::hashes::hex::FromHex;
 use nostr::key::FromSkStr;
+use nostr::nips::nip19::FromBech32;
+use nostr::secp256k1::XOnlyPublicKey;
 use nostr::Keys;
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
 use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
 use tracing::info;
+use url::Url;
+use zeroize::Zeroizing;
+
+use crate::subscriptions::OverflowPolicy;
+use crate::telemetry::LogFormat;
+
+/// Settings fetched from Vault by `load_vault_secrets`, keyed by their upper
+/// case name. Kept out of the environment, which child processes inherit.
+static VAULT_SECRETS: OnceLock<HashMap<String, Zeroizing<String>>> = OnceLock::new();
 
 lazy_static::lazy_static! {
     pub static ref CONFIG: Config =
-        Config::from_env().expect("Failed to load config from environment");
+        Config::load().unwrap_or_else(|e| panic!("Failed to load config: {e}"));
+    /// Settings that can change without a restart, see `AppState::reload_config`
+    pub static ref RUNTIME_CONFIG: ArcSwap<RuntimeConfig> = ArcSwap::from_pointee(
+        RuntimeConfig::load().unwrap_or_else(|e| panic!("Failed to load config: {e}"))
+    );
 }
 
+const DEFAULT_CONFIG_FILE: &str = "hermes.toml";
+
 pub struct Config {
     pub domain: String,
     pub port: u16,
@@ -21,43 +48,388 @@ pub struct Config {
     pub root_secret: DerivableSecret,
     pub fm_db_path: PathBuf,
     pub pg_db: String,
+    /// Read replica for lookups that tolerate replication lag
+    pub pg_db_replica: Option<String>,
+    pub db_max_connections: u32,
+    pub db_acquire_timeout: Duration,
+    pub db_statement_timeout: Option<Duration>,
+    pub db_slow_query_threshold: Duration,
+    pub auto_migrate: bool,
     pub nostr_sk: Keys,
-    pub default_relay: String,
+    /// How often users' kind-0 profiles are fetched again for their
+    /// display names
+    pub profile_refresh: Duration,
     pub xmpp_username: String,
-    pub xmpp_password: String,
+    pub xmpp_password: Zeroizing<String>,
     pub xmpp_chat_server: String,
+    /// Connect as a XEP-0114 component on this domain instead of as
+    /// `xmpp_username`
+    pub xmpp_component_domain: Option<String>,
+    pub xmpp_component_secret: Option<Zeroizing<String>>,
+    pub xmpp_component_host: String,
+    pub xmpp_component_port: u16,
+    /// How long XMPP DMs wait for their recipient to come online before
+    /// going out as a nostr DM
+    pub xmpp_presence_timeout: Duration,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_sample_ratio: f64,
+    pub log_format: LogFormat,
+    pub sentry_dsn: Option<String>,
+    pub sentry_environment: Option<String>,
+    pub sentry_sample_rate: f32,
+    pub admin_api_keys: Vec<String>,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    pub shutdown_timeout: Duration,
+    pub cache_ttl: Duration,
+    /// How long the callback reuses a user's settings
+    pub callback_cache_ttl: Duration,
+    pub subscription_max_active: usize,
+    /// Signing and serialization jobs run at once, see `compute`
+    pub compute_threads: usize,
+    /// Batch invoice and event inserts, see `write_buffer`
+    pub write_buffer: bool,
+    pub write_buffer_max_rows: usize,
+    pub write_buffer_flush: Duration,
+    pub subscription_max_queued: usize,
+    pub subscription_overflow: OverflowPolicy,
+    /// Resident memory above which streams of expired invoices are stopped
+    pub subscription_max_memory_mb: Option<u64>,
+    pub grpc_port: Option<u16>,
+    /// Address the gRPC admin API listens on, loopback unless the operator
+    /// puts it elsewhere
+    pub grpc_bind: IpAddr,
+    pub acme_domains: Vec<String>,
+    pub acme_contacts: Vec<String>,
+    pub acme_cache_dir: Option<PathBuf>,
+    pub acme_production: bool,
+    pub tls_port: u16,
+    pub backup_key: Option<Zeroizing<[u8; 32]>>,
+    pub backup_url: Option<Url>,
+    pub db_encryption_key: Option<Zeroizing<[u8; 32]>>,
+    pub cashu_mint_url: Option<Url>,
+    pub cashu_fee_reserve_ppm: u64,
+    pub notes_expiry: Duration,
+    pub payout_batch_max_age: Duration,
+    pub payout_batch_threshold_msats: Option<u64>,
+    pub socks_proxy: Option<SocketAddr>,
+    /// Connect to XMPP directly even though `socks_proxy` is set
+    pub xmpp_without_proxy: bool,
+    pub onion_domain: Option<String>,
+    pub public_url: Option<Url>,
+    pub trust_forwarded_headers: bool,
+    pub trusted_proxies: Vec<IpNet>,
+    pub ip_ban_threshold: u32,
+    pub ip_ban_half_life: Duration,
+    pub ip_ban_duration: Duration,
+    pub comment_allowed: u16,
+    pub comment_deny_patterns: Vec<Regex>,
+    pub comment_moderation_url: Option<Url>,
+    pub alert_npub: Option<XOnlyPublicKey>,
+    pub alert_webhook_url: Option<Url>,
+    pub alert_repeat: Duration,
+    pub alert_dead_letter_threshold: i64,
+    pub status_show_federations: bool,
+    /// Zaps of finished invoices older than this many days are pruned, kept
+    /// forever if unset
+    pub zap_retention_days: Option<i32>,
+    /// Move pruned zaps to `zaps_archive` instead of deleting them
+    pub zap_archive: bool,
+    /// Finished invoices older than this many days are archived, kept in the
+    /// invoice table forever if unset
+    pub invoice_archive_days: Option<i32>,
+    /// Where archived invoices are written as CSV files instead of
+    /// `invoice_archive`
+    pub invoice_archive_url: Option<Url>,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
+    /// Loads config from built in defaults, then the TOML file at `CONFIG_FILE`
+    /// (default `hermes.toml`), then the environment. Every missing or invalid
+    /// field is reported at once.
+    pub fn load() -> Result<Self> {
         dotenv::dotenv().ok();
 
-        let domain = env::var("DOMAIN").unwrap_or("localhost".to_string());
+        let mut l = Loader::new()?;
+
+        let domain = l.or_default("DOMAIN", "localhost".to_string());
+        let port = l.or_default("PORT", 3000u16);
+        let fm_db_path = l.required::<PathBuf>("FM_DB_PATH");
+        let invite_code = l.required::<InviteCode>("FEDERATION_INVITE_CODE");
+        let root_secret = l.required_with("SECRET_KEY", create_root_secret);
+        let pg_db = l.required::<String>("DATABASE_URL");
+        let pg_db_replica = l.optional::<String>("DATABASE_REPLICA_URL");
+        let db_max_connections = l.or_default("DB_MAX_CONNECTIONS", 5u32);
+        l.check(
+            "DB_MAX_CONNECTIONS",
+            db_max_connections > 0,
+            "must be greater than 0",
+        );
+        let db_acquire_timeout =
+            Duration::from_secs(l.or_default("DB_ACQUIRE_TIMEOUT_SECS", 30u64));
+        let db_statement_timeout = l
+            .optional::<u64>("DB_STATEMENT_TIMEOUT_MS")
+            .map(Duration::from_millis);
+        let db_slow_query_threshold =
+            Duration::from_millis(l.or_default("DB_SLOW_QUERY_MS", 500u64));
+        let auto_migrate = l.or_default("AUTO_MIGRATE", false);
+        let nostr_sk = l.required_with("NOSTR_SK", |s| {
+            Keys::from_sk_str(s).map_err(|e| e.to_string())
+        });
+        let profile_refresh =
+            Duration::from_secs(l.or_default("PROFILE_REFRESH_SECS", 24 * 60 * 60u64));
+
+        // e.g. hermes.example.com, the server must route it to a component
+        let xmpp_component_domain = l.optional::<String>("XMPP_COMPONENT_DOMAIN");
+        // a component doesn't log in as a user
+        let (xmpp_username, xmpp_password, xmpp_component_secret) =
+            if xmpp_component_domain.is_some() {
+                (
+                    Some(l.or_default("XMPP_USERNAME", String::new())),
+                    Some(l.parse("XMPP_PASSWORD", secret).unwrap_or_default()),
+                    l.required_with("XMPP_COMPONENT_SECRET", secret),
+                )
+            } else {
+                (
+                    l.required::<String>("XMPP_USERNAME"),
+                    l.required_with("XMPP_PASSWORD", secret),
+                    None,
+                )
+            };
+        let xmpp_component_host = l.or_default("XMPP_COMPONENT_HOST", "localhost".to_string());
+        let xmpp_component_port = l.or_default("XMPP_COMPONENT_PORT", 5347u16);
+        let xmpp_chat_server = l.or_default("XMPP_CHAT_SERVER", String::new());
+        let xmpp_presence_timeout =
+            Duration::from_secs(l.or_default("XMPP_PRESENCE_TIMEOUT_SECS", 10 * 60u64));
+
+        let otlp_endpoint = l.optional::<String>("OTLP_ENDPOINT");
+        let otlp_sample_ratio = l.or_default("OTLP_SAMPLE_RATIO", 1.0f64);
+        l.check(
+            "OTLP_SAMPLE_RATIO",
+            (0.0..=1.0).contains(&otlp_sample_ratio),
+            "must be between 0 and 1",
+        );
+        let log_format = l.or_default("LOG_FORMAT", LogFormat::Text);
+        // panics, errors logged at error level and failed requests are reported here
+        let sentry_dsn = l.optional::<String>("SENTRY_DSN");
+        let sentry_environment = l.optional::<String>("SENTRY_ENVIRONMENT");
+        let sentry_sample_rate = l.or_default("SENTRY_SAMPLE_RATE", 1.0f32);
+        l.check(
+            "SENTRY_SAMPLE_RATE",
+            (0.0..=1.0).contains(&sentry_sample_rate),
+            "must be between 0 and 1",
+        );
+
+        // comma separated so a new key can be added before the old one is removed
+        let admin_api_keys = l.list("ADMIN_API_KEYS", "");
+
+        let cors_allowed_origins = l.list("CORS_ALLOWED_ORIGINS", "*");
+        let cors_allowed_headers = l.list("CORS_ALLOWED_HEADERS", "content-type");
+
+        let shutdown_timeout = Duration::from_secs(l.or_default("SHUTDOWN_TIMEOUT_SECS", 30u64));
+
+        // zero disables caching of well-known lookups
+        let cache_ttl = Duration::from_secs(l.or_default("CACHE_TTL_SECS", 60u64));
+        let callback_cache_ttl = Duration::from_secs(l.or_default("CALLBACK_CACHE_TTL_SECS", 5u64));
+
+        let subscription_max_active = l.or_default("SUBSCRIPTION_MAX_ACTIVE", 1_000usize);
+        l.check(
+            "SUBSCRIPTION_MAX_ACTIVE",
+            subscription_max_active > 0,
+            "must be greater than 0",
+        );
+        let subscription_max_queued = l.or_default("SUBSCRIPTION_MAX_QUEUED", 1_000usize);
+        let compute_threads = l.or_default(
+            "COMPUTE_THREADS",
+            std::thread::available_parallelism().map_or(4, |n| n.get()),
+        );
+        l.check(
+            "COMPUTE_THREADS",
+            compute_threads > 0,
+            "must be greater than 0",
+        );
+        let subscription_overflow = l.or_default("SUBSCRIPTION_OVERFLOW", OverflowPolicy::Queue);
+        let subscription_max_memory_mb = l.optional::<u64>("SUBSCRIPTION_MAX_MEMORY_MB");
+
+        let write_buffer = l.or_default("WRITE_BUFFER", false);
+        let write_buffer_max_rows = l.or_default("WRITE_BUFFER_MAX_ROWS", 500usize);
+        l.check(
+            "WRITE_BUFFER_MAX_ROWS",
+            write_buffer_max_rows > 0,
+            "must be greater than 0",
+        );
+        let write_buffer_flush =
+            Duration::from_millis(l.or_default("WRITE_BUFFER_FLUSH_MS", 200u64));
+        l.check(
+            "WRITE_BUFFER_FLUSH_MS",
+            !write_buffer_flush.is_zero(),
+            "must be greater than 0",
+        );
 
-        let port = env::var("PORT").unwrap_or("3000".to_string());
-        let port = u16::from_str(&port).expect("Invalid port");
+        let grpc_port = l.optional::<u16>("GRPC_PORT");
+        // the gRPC server has no TLS of its own, so it stays local by default
+        let grpc_bind = l.or_default("GRPC_BIND", IpAddr::from([127, 0, 0, 1]));
 
-        let fm_db_path = env::var("FM_DB_PATH").expect("FM_DB_PATH must be set");
-        let fm_db_path = PathBuf::from_str(&fm_db_path).expect("Invalid fm db path");
+        // built in TLS is only enabled when domains are configured
+        let acme_domains = l.list("ACME_DOMAINS", "");
+        let acme_contacts = l.list("ACME_CONTACTS", "");
+        let acme_cache_dir = l.optional::<PathBuf>("ACME_CACHE_DIR");
+        let acme_production = l.or_default("ACME_PRODUCTION", false);
+        let tls_port = l.or_default("TLS_PORT", 443u16);
 
-        let invite_code =
-            env::var("FEDERATION_INVITE_CODE").expect("FEDERATION_INVITE_CODE must be set");
-        let invite_code = InviteCode::from_str(&invite_code).expect("Invalid invite code");
+        let backup_key = l.parse("BACKUP_KEY", |s| {
+            FromHex::from_hex(s)
+                .map(Zeroizing::new)
+                .map_err(|_| "expected 32 bytes of hex".to_string())
+        });
+        // e.g. s3://bucket/prefix or file:///var/backups/hermes
+        let backup_url = l.optional::<Url>("BACKUP_URL");
+        l.check(
+            "BACKUP_KEY",
+            backup_url.is_none() || backup_key.is_some(),
+            "must be set when BACKUP_URL is",
+        );
+        // encrypts notes and payer data in the database, see model::sealed
+        let db_encryption_key = l.parse("DB_ENCRYPTION_KEY", |s| {
+            FromHex::from_hex(s)
+                .map(Zeroizing::new)
+                .map_err(|_| "expected 32 bytes of hex".to_string())
+        });
 
-        let secret = env::var("SECRET_KEY").expect("SECRET_KEY must be set");
-        let root_secret = create_root_secret(secret);
+        // lets users opt into receiving Cashu tokens minted here instead of fedimint notes
+        let cashu_mint_url = l.optional::<Url>("CASHU_MINT_URL");
+        let cashu_fee_reserve_ppm = l.or_default("CASHU_FEE_RESERVE_PPM", 10_000u64);
 
-        let pg_db = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        // unredeemed notes go back to us after this, users can override it
+        let notes_expiry = Duration::from_secs(l.or_default("NOTES_EXPIRY_SECS", 604_800u64));
+        l.check(
+            "NOTES_EXPIRY_SECS",
+            !notes_expiry.is_zero(),
+            "must be greater than 0",
+        );
 
-        let nostr_sk = env::var("NOSTR_SK").expect("NOSTR_SK must be set");
-        let nostr_sk = Keys::from_sk_str(&nostr_sk).expect("Invalid NOSTR_SK");
+        // users with batched payouts get paid once their oldest receipt is this old,
+        // or sooner once the batch reaches the threshold
+        let payout_batch_max_age =
+            Duration::from_secs(l.or_default("PAYOUT_BATCH_MAX_AGE_SECS", 24 * 60 * 60u64));
+        let payout_batch_threshold_msats = l.optional::<u64>("PAYOUT_BATCH_THRESHOLD_MSATS");
 
-        let default_relay =
-            env::var("DEFAULT_NOSTR_RELAY").expect("DEFAULT_NOSTR_RELAY must be set");
+        // e.g. 127.0.0.1:9050 for a local Tor daemon
+        let socks_proxy = l.optional::<SocketAddr>("SOCKS_PROXY");
+        // tokio-xmpp can't connect through a proxy, so XMPP stays off unless the
+        // operator accepts it revealing the server's address
+        let xmpp_without_proxy = l.or_default("XMPP_WITHOUT_PROXY", false);
+        // hidden service address, used in urls handed to clients that connect through it
+        let onion_domain = l.optional::<String>("ONION_DOMAIN");
+        l.check(
+            "ONION_DOMAIN",
+            onion_domain.iter().all(|d| d.ends_with(".onion")),
+            "must be a .onion address",
+        );
 
-        let xmpp_username = env::var("XMPP_USERNAME").expect("XMPP_USERNAME must be set");
-        let xmpp_password = env::var("XMPP_PASSWORD").expect("XMPP_PASSWORD must be set");
-        let xmpp_chat_server = env::var("XMPP_CHAT_SERVER").expect("XMPP_CHAT_SERVER must be set");
+        // base of the urls we hand out, e.g. https://hermes.example.com when behind a proxy
+        let public_url = l.optional::<Url>("PUBLIC_URL");
+        // only enable behind a reverse proxy that sets these headers itself
+        let trust_forwarded_headers = l.or_default("TRUST_FORWARDED_HEADERS", false);
+        // addresses or CIDR ranges of proxies whose forwarded headers are believed
+        let mut trusted_proxies = vec![];
+        for proxy in l.list("TRUSTED_PROXIES", "") {
+            match proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+            {
+                Ok(net) => trusted_proxies.push(net),
+                Err(_) => l.check(
+                    "TRUSTED_PROXIES",
+                    false,
+                    &format!("has an invalid address: {proxy}"),
+                ),
+            }
+        }
+
+        // ips are banned once their abuse score reaches the threshold, zero disables it
+        let ip_ban_threshold = l.or_default("IP_BAN_THRESHOLD", 100u32);
+        let ip_ban_half_life =
+            Duration::from_secs(l.or_default("IP_BAN_HALF_LIFE_SECS", 10 * 60u64));
+        l.check(
+            "IP_BAN_HALF_LIFE_SECS",
+            !ip_ban_half_life.is_zero(),
+            "must be greater than 0",
+        );
+        let ip_ban_duration = Duration::from_secs(l.or_default("IP_BAN_DURATION_SECS", 60 * 60u64));
+        // LUD-12 comment length advertised to wallets, zero turns comments off
+        let comment_allowed = l.or_default("COMMENT_ALLOWED", 255u16);
+        // case insensitive regexes, comments matching any are rejected
+        let mut comment_deny_patterns = vec![];
+        for pattern in l.list("COMMENT_DENY_PATTERNS", "") {
+            match RegexBuilder::new(&pattern).case_insensitive(true).build() {
+                Ok(regex) => comment_deny_patterns.push(regex),
+                Err(e) => l.check(
+                    "COMMENT_DENY_PATTERNS",
+                    false,
+                    &format!("has an invalid pattern: {e}"),
+                ),
+            }
+        }
+        // POSTed {"comment", "username"}, answers {"allow", "comment"?}
+        let comment_moderation_url = l.optional::<Url>("COMMENT_MODERATION_URL");
+
+        // the operator is sent alerts as nostr DMs to this key, and/or POSTed to the url
+        let alert_npub = l.parse("ALERT_NPUB", |s| {
+            XOnlyPublicKey::from_bech32(s)
+                .or_else(|_| XOnlyPublicKey::from_str(s))
+                .map_err(|_| "expected an npub or hex public key".to_string())
+        });
+        let alert_webhook_url = l.optional::<Url>("ALERT_WEBHOOK_URL");
+        // the same alert isn't sent again until this has passed
+        let alert_repeat = Duration::from_secs(l.or_default("ALERT_REPEAT_SECS", 60 * 60u64));
+        let alert_dead_letter_threshold = l.or_default("ALERT_DEAD_LETTER_THRESHOLD", 10i64);
+        l.check(
+            "ALERT_DEAD_LETTER_THRESHOLD",
+            alert_dead_letter_threshold > 0,
+            "must be greater than 0",
+        );
+        // federation ids are only listed on /status if the operator opts in
+        let status_show_federations = l.or_default("STATUS_SHOW_FEDERATIONS", false);
+        let zap_retention_days: Option<i32> = l.optional("ZAP_RETENTION_DAYS");
+        l.check(
+            "ZAP_RETENTION_DAYS",
+            zap_retention_days.map_or(true, |days| days > 0),
+            "must be greater than 0",
+        );
+        let zap_archive = l.or_default("ZAP_ARCHIVE", false);
+        let invoice_archive_days: Option<i32> = l.optional("INVOICE_ARCHIVE_DAYS");
+        l.check(
+            "INVOICE_ARCHIVE_DAYS",
+            invoice_archive_days.map_or(true, |days| days > 0),
+            "must be greater than 0",
+        );
+        // e.g. s3://bucket/invoices or file:///var/archive/hermes
+        let invoice_archive_url = l.optional::<Url>("INVOICE_ARCHIVE_URL");
+
+        let (
+            Some(fm_db_path),
+            Some(invite_code),
+            Some(root_secret),
+            Some(pg_db),
+            Some(nostr_sk),
+            Some(xmpp_username),
+            Some(xmpp_password),
+        ) = (
+            fm_db_path,
+            invite_code,
+            root_secret,
+            pg_db,
+            nostr_sk,
+            xmpp_username,
+            xmpp_password,
+        )
+        else {
+            return Err(l.error());
+        };
+        if !l.errors.is_empty() {
+            return Err(l.error());
+        }
 
         info!("Loaded config");
 
@@ -68,16 +440,457 @@ impl Config {
             root_secret,
             fm_db_path,
             pg_db,
+            pg_db_replica,
+            db_max_connections,
+            db_acquire_timeout,
+            db_statement_timeout,
+            db_slow_query_threshold,
+            auto_migrate,
             nostr_sk,
-            default_relay,
+            profile_refresh,
             xmpp_username,
             xmpp_password,
             xmpp_chat_server,
+            xmpp_component_domain,
+            xmpp_component_secret,
+            xmpp_component_host,
+            xmpp_component_port,
+            xmpp_presence_timeout,
+            otlp_endpoint,
+            otlp_sample_ratio,
+            log_format,
+            sentry_dsn,
+            sentry_environment,
+            sentry_sample_rate,
+            admin_api_keys,
+            cors_allowed_origins,
+            cors_allowed_headers,
+            shutdown_timeout,
+            cache_ttl,
+            callback_cache_ttl,
+            subscription_max_active,
+            compute_threads,
+            write_buffer,
+            write_buffer_max_rows,
+            write_buffer_flush,
+            subscription_max_queued,
+            subscription_overflow,
+            subscription_max_memory_mb,
+            grpc_port,
+            grpc_bind,
+            acme_domains,
+            acme_contacts,
+            acme_cache_dir,
+            acme_production,
+            tls_port,
+            backup_key,
+            backup_url,
+            db_encryption_key,
+            cashu_mint_url,
+            cashu_fee_reserve_ppm,
+            notes_expiry,
+            payout_batch_max_age,
+            payout_batch_threshold_msats,
+            socks_proxy,
+            xmpp_without_proxy,
+            onion_domain,
+            public_url,
+            trust_forwarded_headers,
+            trusted_proxies,
+            ip_ban_threshold,
+            ip_ban_half_life,
+            ip_ban_duration,
+            comment_allowed,
+            comment_deny_patterns,
+            comment_moderation_url,
+            alert_npub,
+            alert_webhook_url,
+            alert_repeat,
+            alert_dead_letter_threshold,
+            status_show_federations,
+            zap_retention_days,
+            zap_archive,
+            invoice_archive_days,
+            invoice_archive_url,
+        })
+    }
+}
+
+/// Settings re-read on SIGHUP or `POST /admin/reload`. Environment variables
+/// still take precedence, so change these in the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub min_sendable_msats: u64,
+    pub max_sendable_msats: u64,
+    pub nostr_relays: Vec<String>,
+    pub rate_limit_ip_per_minute: u32,
+    pub rate_limit_username_per_minute: u32,
+    /// Zaps allowed per minute from one zap request key or payer key
+    pub rate_limit_zap_sender_per_minute: u32,
+    /// Smallest zap accepted, `min_sendable_msats` applies if unset
+    pub min_zap_msats: Option<u64>,
+    /// Unpaid invoices allowed at once in a federation, unlimited if unset
+    pub max_pending_invoices_per_federation: Option<u64>,
+    /// Unpaid invoices allowed at once for a user, unlimited if unset
+    pub max_pending_invoices_per_user: Option<u64>,
+    /// Most a user can be invoiced for in a rolling day, unlimited if unset
+    pub daily_receive_limit_msats: Option<u64>,
+    /// Most a user can be invoiced for in a rolling week, unlimited if unset
+    pub weekly_receive_limit_msats: Option<u64>,
+    /// Same as the above for all users together
+    pub instance_daily_receive_limit_msats: Option<u64>,
+    pub instance_weekly_receive_limit_msats: Option<u64>,
+    /// Whether `POST /register` accepts new users
+    pub registration_open: bool,
+}
+
+impl RuntimeConfig {
+    pub fn load() -> Result<Self> {
+        let mut l = Loader::new()?;
+
+        let min_sendable_msats = l.or_default("MIN_SENDABLE_MSATS", 1_000u64);
+        let max_sendable_msats = l.or_default("MAX_SENDABLE_MSATS", 100_000u64);
+        l.check(
+            "MAX_SENDABLE_MSATS",
+            max_sendable_msats >= min_sendable_msats,
+            "must be at least MIN_SENDABLE_MSATS",
+        );
+
+        let default_relay = l.raw("DEFAULT_NOSTR_RELAY").unwrap_or_default();
+        let nostr_relays = l.list("NOSTR_RELAYS", &default_relay);
+        l.check(
+            "NOSTR_RELAYS",
+            !nostr_relays.is_empty(),
+            "or DEFAULT_NOSTR_RELAY must be set",
+        );
+
+        let rate_limit_ip_per_minute = l.or_default("RATE_LIMIT_IP_PER_MINUTE", 60u32);
+        l.check(
+            "RATE_LIMIT_IP_PER_MINUTE",
+            rate_limit_ip_per_minute > 0,
+            "must be greater than 0",
+        );
+        let rate_limit_username_per_minute = l.or_default("RATE_LIMIT_USERNAME_PER_MINUTE", 120u32);
+        l.check(
+            "RATE_LIMIT_USERNAME_PER_MINUTE",
+            rate_limit_username_per_minute > 0,
+            "must be greater than 0",
+        );
+        let rate_limit_zap_sender_per_minute =
+            l.or_default("RATE_LIMIT_ZAP_SENDER_PER_MINUTE", 10u32);
+        l.check(
+            "RATE_LIMIT_ZAP_SENDER_PER_MINUTE",
+            rate_limit_zap_sender_per_minute > 0,
+            "must be greater than 0",
+        );
+        let min_zap_msats: Option<u64> = l.optional("MIN_ZAP_MSATS");
+
+        let max_pending_invoices_per_federation: Option<u64> =
+            l.optional("MAX_PENDING_INVOICES_PER_FEDERATION");
+        l.check(
+            "MAX_PENDING_INVOICES_PER_FEDERATION",
+            max_pending_invoices_per_federation != Some(0),
+            "must be greater than 0",
+        );
+        let max_pending_invoices_per_user: Option<u64> =
+            l.optional("MAX_PENDING_INVOICES_PER_USER");
+        l.check(
+            "MAX_PENDING_INVOICES_PER_USER",
+            max_pending_invoices_per_user != Some(0),
+            "must be greater than 0",
+        );
+
+        let daily_receive_limit_msats = l.optional("DAILY_RECEIVE_LIMIT_MSATS");
+        let weekly_receive_limit_msats = l.optional("WEEKLY_RECEIVE_LIMIT_MSATS");
+        let instance_daily_receive_limit_msats = l.optional("INSTANCE_DAILY_RECEIVE_LIMIT_MSATS");
+        let instance_weekly_receive_limit_msats = l.optional("INSTANCE_WEEKLY_RECEIVE_LIMIT_MSATS");
+        let registration_open = l.or_default("REGISTRATION_OPEN", true);
+
+        if !l.errors.is_empty() {
+            return Err(l.error());
+        }
+
+        Ok(Self {
+            min_sendable_msats,
+            max_sendable_msats,
+            nostr_relays,
+            rate_limit_ip_per_minute,
+            rate_limit_username_per_minute,
+            rate_limit_zap_sender_per_minute,
+            min_zap_msats,
+            max_pending_invoices_per_federation,
+            max_pending_invoices_per_user,
+            daily_receive_limit_msats,
+            weekly_receive_limit_msats,
+            instance_daily_receive_limit_msats,
+            instance_weekly_receive_limit_msats,
+            registration_open,
+        })
+    }
+}
+
+/// Fetches secrets from a HashiCorp Vault KV v2 secret at `VAULT_SECRET_PATH`
+/// if `VAULT_ADDR` is set, before `CONFIG` is loaded. Each key of the secret
+/// is read as the setting of the same upper case name, e.g. `nostr_sk`, in
+/// place of the environment and config file.
+pub async fn load_vault_secrets() -> Result<()> {
+    dotenv::dotenv().ok();
+
+    let mut l = Loader::new()?;
+    let addr = l.optional::<Url>("VAULT_ADDR");
+    if !l.errors.is_empty() {
+        return Err(l.error());
+    }
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+    let token = l.required_with("VAULT_TOKEN", secret);
+    let path = l.or_default("VAULT_SECRET_PATH", "secret/data/hermes".to_string());
+    let namespace = l.optional::<String>("VAULT_NAMESPACE");
+    let Some(token) = token else {
+        return Err(l.error());
+    };
+    if !l.errors.is_empty() {
+        return Err(l.error());
+    }
+
+    let url = addr.join(&format!("v1/{}", path.trim_start_matches('/')))?;
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .get(url)
+        .header("X-Vault-Token", token.as_str());
+    if let Some(namespace) = namespace {
+        request = request.header("X-Vault-Namespace", namespace);
+    }
+    let response = request
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| anyhow!("Could not read secrets from Vault: {e}"))?;
+    let body = Zeroizing::new(response.text().await?);
+
+    let mut kv: serde_json::Value = serde_json::from_str(&body)?;
+    let data = kv["data"]["data"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Vault secret at {path} is not a KV v2 secret"))?;
+    let mut secrets = HashMap::with_capacity(data.len());
+    for (key, value) in data.iter_mut() {
+        let key = key.to_uppercase();
+        let serde_json::Value::String(value) = value.take() else {
+            return Err(anyhow!("Vault secret {key} is not a string"));
+        };
+        secrets.insert(key, Zeroizing::new(value));
+    }
+    VAULT_SECRETS
+        .set(secrets)
+        .map_err(|_| anyhow!("Vault secrets were already loaded"))?;
+
+    Ok(())
+}
+
+/// Per job scheduler settings, see `Scheduler::register`.
+#[derive(Debug, Clone, Copy)]
+pub struct JobConfig {
+    pub enabled: bool,
+    pub interval: Duration,
+    pub jitter: Duration,
+}
+
+impl JobConfig {
+    pub fn load(name: &str, default_interval: Duration) -> Result<Self> {
+        let mut l = Loader::new()?;
+        let prefix = format!("JOB_{}", name.to_uppercase());
+
+        let enabled = l.or_default(&format!("{prefix}_ENABLED"), true);
+        let interval = l.or_default(
+            &format!("{prefix}_INTERVAL_SECS"),
+            default_interval.as_secs(),
+        );
+        l.check(
+            &format!("{prefix}_INTERVAL_SECS"),
+            interval > 0,
+            "must be greater than 0",
+        );
+        // spread runs out a bit so multiple instances don't fire in lockstep
+        let jitter = l.or_default(&format!("{prefix}_JITTER_SECS"), interval / 10);
+
+        if !l.errors.is_empty() {
+            return Err(l.error());
+        }
+
+        Ok(Self {
+            enabled,
+            interval: Duration::from_secs(interval),
+            jitter: Duration::from_secs(jitter),
         })
     }
 }
 
-fn create_root_secret(secret: String) -> DerivableSecret {
-    let secret_bytes: [u8; 64] = FromHex::from_hex(&secret).expect("Invalid hex string");
-    PlainRootSecretStrategy::to_root_secret(&secret_bytes)
+/// Looks up each setting in the secrets fetched from Vault first, then the
+/// environment, then the config file, collecting errors instead of failing
+/// on the first one.
+struct Loader {
+    file: toml::Table,
+    vault: Option<&'static HashMap<String, Zeroizing<String>>>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn new() -> Result<Self> {
+        let path = env::var("CONFIG_FILE").ok();
+        let file = match fs::read_to_string(path.as_deref().unwrap_or(DEFAULT_CONFIG_FILE)) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| anyhow!("Invalid config file: {e}"))?
+            }
+            // the default file is optional, an explicitly configured one is not
+            Err(_) if path.is_none() => toml::Table::new(),
+            Err(e) => return Err(anyhow!("Could not read config file: {e}")),
+        };
+
+        Ok(Self {
+            file,
+            vault: VAULT_SECRETS.get(),
+            errors: vec![],
+        })
+    }
+
+    /// Env vars use the upper case name, file keys the lower case one. A
+    /// `<KEY>_FILE` variable or `<key>_file` entry instead names a file
+    /// holding the value, e.g. a docker secret. Values are wiped from memory
+    /// once parsed.
+    fn raw(&mut self, key: &str) -> Option<Zeroizing<String>> {
+        let file_key = format!("{key}_FILE");
+        let path = self.value(&file_key);
+        if let Some(value) = self.value(key) {
+            if path.is_some() {
+                self.errors
+                    .push(format!("{key} and {file_key} can't both be set"));
+            }
+            return Some(value);
+        }
+
+        let path = path?;
+        match fs::read_to_string(path.as_str()) {
+            Ok(contents) => {
+                let contents = Zeroizing::new(contents);
+                let value = contents.trim_end_matches(['\r', '\n']);
+                if value.is_empty() {
+                    self.errors
+                        .push(format!("{file_key} points to an empty file"));
+                    return None;
+                }
+                Some(Zeroizing::new(value.to_string()))
+            }
+            Err(e) => {
+                self.errors
+                    .push(format!("{file_key} could not be read: {e}"));
+                None
+            }
+        }
+    }
+
+    fn value(&self, key: &str) -> Option<Zeroizing<String>> {
+        if let Some(value) = self.vault.and_then(|vault| vault.get(key)) {
+            return Some(value.clone());
+        }
+        let value = env::var(key).ok().or_else(|| {
+            self.file.get(&key.to_lowercase()).map(|value| match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Array(items) => items
+                    .iter()
+                    .map(|i| i.as_str().map(|s| s.to_string()).unwrap_or(i.to_string()))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                other => other.to_string(),
+            })
+        })?;
+        Some(Zeroizing::new(value))
+    }
+
+    fn parse<T>(&mut self, key: &str, f: impl FnOnce(&str) -> Result<T, String>) -> Option<T> {
+        let raw = self.raw(key)?;
+        match f(&raw) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{key} is invalid: {e}"));
+                None
+            }
+        }
+    }
+
+    fn required_with<T>(
+        &mut self,
+        key: &str,
+        f: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        if self.value(key).is_none() && self.value(&format!("{key}_FILE")).is_none() {
+            self.errors.push(format!("{key} must be set"));
+            return None;
+        }
+        self.parse(key, f)
+    }
+
+    fn required<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.required_with(key, |s| T::from_str(s).map_err(|e| e.to_string()))
+    }
+
+    fn optional<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(key, |s| T::from_str(s).map_err(|e| e.to_string()))
+    }
+
+    fn or_default<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(key).unwrap_or(default)
+    }
+
+    fn list(&mut self, key: &str, default: &str) -> Vec<String> {
+        match self.raw(key) {
+            Some(raw) => split_list(&raw),
+            None => split_list(default),
+        }
+    }
+
+    fn check(&mut self, key: &str, valid: bool, reason: &str) {
+        if !valid {
+            self.errors.push(format!("{key} {reason}"));
+        }
+    }
+
+    fn error(&self) -> anyhow::Error {
+        anyhow!("\n  - {}", self.errors.join("\n  - "))
+    }
+}
+
+fn create_root_secret(secret: &str) -> Result<DerivableSecret, String> {
+    let secret_bytes: Zeroizing<[u8; 64]> = FromHex::from_hex(secret)
+        .map(Zeroizing::new)
+        .map_err(|_| "expected 64 bytes of hex".to_string())?;
+    Ok(PlainRootSecretStrategy::to_root_secret(&secret_bytes))
+}
+
+/// A secret kept as is, wiped from memory when dropped.
+fn secret(s: &str) -> Result<Zeroizing<String>, String> {
+    if s.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    Ok(Zeroizing::new(s.to_string()))
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
 }
@@ -1,11 +1,100 @@
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::router::{handlers::lnurlp::LnurlStatus, middleware::current_request_id};
+
+/// Stable error codes so integrators can branch on failures without parsing `reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Banned,
+    NotFound,
+    UserNotFound,
+    InvoiceNotFound,
+    FederationUnavailable,
+    AmountTooLow,
+    AmountTooHigh,
+    InvalidNostrEvent,
+    CommentRejected,
+    InvalidDmType,
+    RegistrationFailed,
+    RegistrationClosed,
+    NameTaken,
+    AlreadyPaired,
+    IdempotencyKeyReused,
+    RefundUnavailable,
+    ReissueUnavailable,
+    InsufficientBalance,
+    ReceiveLimitExceeded,
+    GiftUnavailable,
+    NodeUnavailable,
+    RateLimited,
+    TooManyPendingInvoices,
+    Overloaded,
+    ShuttingDown,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::FederationUnavailable
+            | ErrorCode::AmountTooLow
+            | ErrorCode::AmountTooHigh
+            | ErrorCode::InvalidNostrEvent
+            | ErrorCode::CommentRejected
+            | ErrorCode::InvalidDmType
+            | ErrorCode::RegistrationFailed
+            | ErrorCode::InsufficientBalance => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::ReceiveLimitExceeded | ErrorCode::Banned | ErrorCode::RegistrationClosed => {
+                StatusCode::FORBIDDEN
+            }
+            ErrorCode::NotFound | ErrorCode::UserNotFound | ErrorCode::InvoiceNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ErrorCode::NameTaken
+            | ErrorCode::AlreadyPaired
+            | ErrorCode::IdempotencyKeyReused
+            | ErrorCode::RefundUnavailable
+            | ErrorCode::ReissueUnavailable => StatusCode::CONFLICT,
+            ErrorCode::GiftUnavailable => StatusCode::GONE,
+            ErrorCode::NodeUnavailable => StatusCode::BAD_GATEWAY,
+            ErrorCode::RateLimited | ErrorCode::TooManyPendingInvoices => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ErrorCode::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => ErrorCode::BadRequest,
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::ShuttingDown,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
 
 pub struct AppError {
     pub error: anyhow::Error,
     pub status: StatusCode,
+    pub code: ErrorCode,
+    /// Alternatives to what was asked for, e.g. free names for `NameTaken`
+    pub suggestions: Option<Vec<String>>,
 }
 
 impl AppError {
@@ -13,14 +102,65 @@ impl AppError {
         Self {
             error: error.into(),
             status,
+            code: ErrorCode::from_status(status),
+            suggestions: None,
         }
     }
+
+    pub fn from_code(code: ErrorCode, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            status: code.status(),
+            code,
+            suggestions: None,
+        }
+    }
+
+    pub fn with_suggestions(mut self, suggestions: Vec<String>) -> Self {
+        self.suggestions = Some(suggestions);
+        self
+    }
+}
+
+/// Follows the LUD-06 error shape with an added machine readable `code`.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub status: LnurlStatus,
+    pub reason: String,
+    pub code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestions: Option<Vec<String>>,
 }
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (self.status, format!("Something went wrong: {}", self.error)).into_response()
+        // a no-op unless SENTRY_DSN is set
+        if self.status.is_server_error() {
+            sentry::with_scope(
+                |scope| {
+                    scope.set_tag("code", format!("{:?}", self.code));
+                    if let Some(request_id) = current_request_id() {
+                        scope.set_tag("request_id", request_id);
+                    }
+                },
+                || sentry::integrations::anyhow::capture_anyhow(&self.error),
+            );
+        }
+
+        let body = ErrorResponse {
+            status: LnurlStatus::Error,
+            reason: format!("Something went wrong: {}", self.error),
+            code: self.code,
+            request_id: current_request_id(),
+            suggestions: self.suggestions,
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        // lets middleware see why a request failed, e.g. for ip reputation
+        response.extensions_mut().insert(self.code);
+        response
     }
 }
 
@@ -32,6 +172,8 @@ where
         Self {
             error: err.into(),
             status: StatusCode::INTERNAL_SERVER_ERROR, // default status code
+            code: ErrorCode::Internal,
+            suggestions: None,
         }
     }
 }
@@ -0,0 +1,42 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::model::invoice_state::InvoiceState;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceUpdate {
+    pub operation_id: String,
+    pub username: String,
+    pub state: InvoiceState,
+}
+
+/// Fan-out of invoice state transitions to push subscribers (websocket, sse).
+#[derive(Clone)]
+pub struct InvoiceEvents {
+    sender: broadcast::Sender<InvoiceUpdate>,
+}
+
+impl InvoiceEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, update: InvoiceUpdate) {
+        // an error only means nobody is listening
+        let _ = self.sender.send(update);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<InvoiceUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for InvoiceEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
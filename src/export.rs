@@ -0,0 +1,250 @@
+//! Exports written to the response as rows are read, so a large instance
+//! never holds a whole table, or a whole backup, in memory.
+
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chacha20poly1305::{aead::stream::EncryptorBE32, XChaCha20Poly1305};
+use futures::{channel::mpsc, stream::BoxStream, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+
+use crate::model::invoice::Invoice;
+
+/// Bytes buffered before they're handed on, also the plaintext size of each
+/// encrypted backup segment.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks that may wait for a slow client before reading more rows.
+const QUEUED_CHUNKS: usize = 4;
+
+/// Chunks of an export as they're written, ending with an error if it failed
+/// partway.
+pub type ExportStream = BoxStream<'static, Result<Bytes>>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Invoice columns of CSV exports and archive files. Payer keys, comments
+/// and payer data are left out, they'd be stored unencrypted.
+pub const INVOICE_CSV_HEADER: [&str; 17] = [
+    "id",
+    "federation_id",
+    "op_id",
+    "app_user_id",
+    "bolt11",
+    "payment_hash",
+    "amount",
+    "state",
+    "request_id",
+    "idempotency_key",
+    "payout_batch_id",
+    "preimage",
+    "gateway_id",
+    "gateway_fee_msats",
+    "created_at",
+    "settled_at",
+    "deleted_at",
+];
+
+pub fn invoice_csv_record(
+    invoice: &Invoice,
+    created_at: OffsetDateTime,
+    settled_at: Option<OffsetDateTime>,
+    deleted_at: Option<OffsetDateTime>,
+) -> Result<[String; 17]> {
+    Ok([
+        invoice.id.to_string(),
+        invoice.federation_id.clone(),
+        invoice.op_id.clone(),
+        invoice.app_user_id.to_string(),
+        invoice.bolt11.clone(),
+        invoice.payment_hash.clone().unwrap_or_default(),
+        invoice.amount.to_string(),
+        invoice.state.event_name().to_string(),
+        invoice.request_id.clone().unwrap_or_default(),
+        invoice.idempotency_key.clone().unwrap_or_default(),
+        optional(invoice.payout_batch_id),
+        invoice.preimage.clone().unwrap_or_default(),
+        invoice.gateway_id.clone().unwrap_or_default(),
+        optional(invoice.gateway_fee_msats),
+        created_at.format(&Rfc3339)?,
+        optional_time(settled_at)?,
+        optional_time(deleted_at)?,
+    ])
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn optional_time(value: Option<OffsetDateTime>) -> Result<String> {
+    Ok(value
+        .map(|t| t.format(&Rfc3339))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// Runs `f` in the background, streaming what it writes. The stream ends
+/// with an error if `f` fails, so a truncated export can't pass for a
+/// complete one.
+pub fn spawn<F, Fut>(format: ExportFormat, encryptor: Option<Encryptor>, f: F) -> ExportStream
+where
+    F: FnOnce(ExportWriter) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    let (mut tx, rx) = mpsc::channel(QUEUED_CHUNKS);
+    let writer = ExportWriter {
+        format,
+        buf: Vec::with_capacity(CHUNK_SIZE),
+        rows: 0,
+        encryptor,
+        tx: tx.clone(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = f(writer).await {
+            warn!("Export failed: {e:#}");
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    rx.boxed()
+}
+
+/// Encrypts a backup in segments of `CHUNK_SIZE` with the STREAM
+/// construction, so it can be decrypted as it's read, too.
+pub type Encryptor = EncryptorBE32<XChaCha20Poly1305>;
+
+/// Serializes rows into chunks for an `ExportStream`.
+pub struct ExportWriter {
+    format: ExportFormat,
+    buf: Vec<u8>,
+    /// Rows written to the current array or CSV file
+    rows: usize,
+    encryptor: Option<Encryptor>,
+    tx: mpsc::Sender<Result<Bytes>>,
+}
+
+impl ExportWriter {
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Writes `bytes` as they are, for the structure around JSON arrays.
+    pub async fn raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.buf.extend_from_slice(bytes);
+        self.flush_full().await
+    }
+
+    /// Starts a list of rows, only marked in JSON.
+    pub async fn begin_array(&mut self) -> Result<()> {
+        self.rows = 0;
+        match self.format {
+            ExportFormat::Json => self.raw(b"[").await,
+            ExportFormat::Csv => Ok(()),
+        }
+    }
+
+    pub async fn end_array(&mut self) -> Result<()> {
+        match self.format {
+            ExportFormat::Json => self.raw(b"]").await,
+            ExportFormat::Csv => Ok(()),
+        }
+    }
+
+    /// Writes a row as an array element, or as a CSV record with a header
+    /// taken from its field names before the first one.
+    pub async fn row<T: Serialize>(&mut self, row: &T) -> Result<()> {
+        match self.format {
+            ExportFormat::Json => {
+                if self.rows > 0 {
+                    self.buf.push(b',');
+                }
+                serde_json::to_writer(&mut self.buf, row)?;
+            }
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(self.rows == 0)
+                    .from_writer(&mut self.buf);
+                writer.serialize(row)?;
+                writer.flush()?;
+            }
+        }
+        self.rows += 1;
+        self.flush_full().await
+    }
+
+    /// Writes a CSV record, with `header` before the first one.
+    pub async fn record<I, T>(&mut self, header: &[&str], record: I) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let mut writer = csv::Writer::from_writer(&mut self.buf);
+        if self.rows == 0 {
+            writer.write_record(header)?;
+        }
+        writer.write_record(record)?;
+        writer.flush()?;
+        drop(writer);
+        self.rows += 1;
+        self.flush_full().await
+    }
+
+    /// Sends what's left, ending the export.
+    pub async fn finish(mut self) -> Result<()> {
+        let rest = std::mem::take(&mut self.buf);
+        let chunk = match self.encryptor.take() {
+            Some(encryptor) => encryptor
+                .encrypt_last(rest.as_slice())
+                .map_err(|e| anyhow!("Could not encrypt export: {e}"))?,
+            None if rest.is_empty() => return Ok(()),
+            None => rest,
+        };
+        self.send(chunk).await
+    }
+
+    async fn flush_full(&mut self) -> Result<()> {
+        while self.buf.len() >= CHUNK_SIZE {
+            let rest = self.buf.split_off(CHUNK_SIZE);
+            let full = std::mem::replace(&mut self.buf, rest);
+            let chunk = match self.encryptor.as_mut() {
+                Some(encryptor) => encryptor
+                    .encrypt_next(full.as_slice())
+                    .map_err(|e| anyhow!("Could not encrypt export: {e}"))?,
+                None => full,
+            };
+            self.send(chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, chunk: Vec<u8>) -> Result<()> {
+        self.tx
+            .send(Ok(Bytes::from(chunk)))
+            .await
+            .map_err(|_| anyhow!("Export was cancelled"))
+    }
+}
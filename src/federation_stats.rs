@@ -0,0 +1,61 @@
+//! Settled volume, gateway fees and note issuance per federation, kept per
+//! day in the database for `GET /admin/federations/stats` and as Prometheus
+//! counters labelled by federation.
+
+use tracing::warn;
+
+use crate::model::{
+    federation_stats::{FederationStatsBmc, FederationStatsDelta},
+    ModelManager,
+};
+
+/// An invoice to a user was paid.
+pub async fn settled(mm: &ModelManager, federation_id: &str, amount_msats: u64) {
+    let label = federation_id.to_string();
+    metrics::counter!("federation_settled_total", "federation_id" => label.clone()).increment(1);
+    metrics::counter!("federation_settled_msats_total", "federation_id" => label)
+        .increment(amount_msats);
+
+    let delta = FederationStatsDelta {
+        settled_count: 1,
+        settled_msats: amount_msats as i64,
+        ..Default::default()
+    };
+    record(mm, federation_id, delta).await;
+}
+
+/// A gateway was paid `fee_msats` to pay a lightning invoice for us.
+pub async fn gateway_fee(mm: &ModelManager, federation_id: &str, fee_msats: u64) {
+    let label = federation_id.to_string();
+    metrics::counter!("federation_gateway_fees_msats_total", "federation_id" => label)
+        .increment(fee_msats);
+
+    let delta = FederationStatsDelta {
+        gateway_fee_msats: fee_msats as i64,
+        ..Default::default()
+    };
+    record(mm, federation_id, delta).await;
+}
+
+/// Ecash worth `amount_msats` was spent out of the federation's client.
+pub async fn notes_issued(mm: &ModelManager, federation_id: &str, amount_msats: u64) {
+    let label = federation_id.to_string();
+    metrics::counter!("federation_notes_issued_total", "federation_id" => label.clone())
+        .increment(1);
+    metrics::counter!("federation_notes_issued_msats_total", "federation_id" => label)
+        .increment(amount_msats);
+
+    let delta = FederationStatsDelta {
+        notes_issued_count: 1,
+        notes_issued_msats: amount_msats as i64,
+        ..Default::default()
+    };
+    record(mm, federation_id, delta).await;
+}
+
+/// Failing to record only loses a data point, the payment itself went through.
+async fn record(mm: &ModelManager, federation_id: &str, delta: FederationStatsDelta) {
+    if let Err(e) = FederationStatsBmc::record(mm, federation_id, delta).await {
+        warn!("Could not record stats for federation {federation_id}: {e:#}");
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Result};
+use arc_swap::ArcSwap;
+use fedimint_client::ClientArc;
+use fedimint_core::{
+    api::InviteCode,
+    bitcoin_hashes::{sha256, Hash},
+    config::FederationId,
+    core::OperationId,
+    Amount,
+};
+use fedimint_ln_client::{
+    api::LnFederationApi, InternalPayState, LightningClientModule, LnPayState, LnReceiveState,
+    OutgoingLightningPayment, PayType,
+};
+use fedimint_ln_common::contracts::{ContractId, DecryptedPreimage};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use multimint::MultiMint;
+use tokio::sync::Mutex;
+use tracing::{error, info, info_span, instrument, Instrument};
+
+use crate::{federation_stats, model::ModelManager};
+
+/// Read-mostly view of the multimint clients. Lookups load a snapshot without
+/// locking; joining a federation goes through multimint and then publishes a
+/// new snapshot.
+#[derive(Clone)]
+pub struct FederationRegistry {
+    fm: Arc<Mutex<MultiMint>>,
+    clients: Arc<ArcSwap<HashMap<FederationId, ClientArc>>>,
+}
+
+impl FederationRegistry {
+    pub async fn new(fm: MultiMint) -> Self {
+        let clients = fm.clients.lock().await.clone();
+        Self {
+            fm: Arc::new(Mutex::new(fm)),
+            clients: Arc::new(ArcSwap::from_pointee(clients)),
+        }
+    }
+
+    pub fn get(&self, federation_id: &FederationId) -> Option<ClientArc> {
+        self.clients.load().get(federation_id).cloned()
+    }
+
+    pub fn contains(&self, federation_id: &FederationId) -> bool {
+        self.clients.load().contains_key(federation_id)
+    }
+
+    pub fn ids(&self) -> Vec<FederationId> {
+        self.clients.load().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.load().is_empty()
+    }
+
+    /// Joins a federation, serialized so concurrent joins don't race on the
+    /// multimint database.
+    pub async fn join(&self, invite_code: InviteCode) -> Result<()> {
+        let mut fm = self.fm.lock().await;
+        fm.register_new(invite_code, None).await?;
+        self.clients
+            .store(Arc::new(fm.clients.lock().await.clone()));
+        Ok(())
+    }
+
+    /// Drops every client, which shuts down their fedimint task groups.
+    pub async fn clear(&self) {
+        let fm = self.fm.lock().await;
+        fm.clients.lock().await.clear();
+        self.clients.store(Arc::new(HashMap::new()));
+    }
+}
+
+/// Held back for the lightning fees when paying out of the federation.
+const FEE_RESERVE_PPM: u64 = 10_000;
+const MIN_FEE_RESERVE_MSATS: u64 = 2_000;
+
+/// The most the lightning fees of paying `amount_msats` out are expected to
+/// be.
+pub fn fee_reserve(amount_msats: u64) -> u64 {
+    (amount_msats * FEE_RESERVE_PPM / 1_000_000).max(MIN_FEE_RESERVE_MSATS)
+}
+
+/// The error of a payment that certainly left nothing behind in the
+/// federation: it was never funded, canceled or refunded. Any other payment
+/// error leaves open whether the money went out, so what it paid for must not
+/// be given back.
+#[derive(Debug)]
+pub struct NothingSent(String);
+
+impl fmt::Display for NothingSent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NothingSent {}
+
+/// Context of any other payment error, naming the operation to check for
+/// whether the money went out.
+#[derive(Debug)]
+pub struct UnresolvedPayment(pub OperationId);
+
+impl fmt::Display for UnresolvedPayment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Payment {} is unresolved", self.0)
+    }
+}
+
+/// Whether a payment error is a `NothingSent`.
+pub fn nothing_sent(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<NothingSent>().is_some()
+}
+
+/// Pays a bolt11 invoice with a federation's ecash, waiting for the payment
+/// to complete. The gateway fee of a successful payment is recorded in the
+/// federation's stats. Failures that certainly moved nothing are
+/// `NothingSent`, the others carry an `UnresolvedPayment`.
+#[instrument(skip_all, fields(payment_hash = %invoice.payment_hash()))]
+pub async fn pay_bolt11(
+    mm: &ModelManager,
+    client: &ClientArc,
+    invoice: Bolt11Invoice,
+) -> Result<OperationId> {
+    let ln = client.get_first_module::<LightningClientModule>();
+    let OutgoingLightningPayment {
+        payment_type, fee, ..
+    } = ln
+        .pay_bolt11_invoice(invoice, ())
+        .instrument(info_span!("pay_bolt11_invoice"))
+        .await
+        .map_err(|e| NothingSent(format!("Funding the payment failed: {e:#}")))?;
+    let federation_id = client.federation_id().to_string();
+
+    let (PayType::Lightning(op_id) | PayType::Internal(op_id)) = payment_type;
+    let paid = async {
+        match payment_type {
+            PayType::Lightning(op_id) => {
+                let mut updates = ln.subscribe_ln_pay(op_id).await?.into_stream();
+                while let Some(state) = updates.next().await {
+                    match state {
+                        LnPayState::Success { .. } => {
+                            federation_stats::gateway_fee(mm, &federation_id, fee.msats).await;
+                            return Ok(op_id);
+                        }
+                        // the gateway took the payment but couldn't route it
+                        LnPayState::Refunded { .. } => {
+                            error!(
+                                alert = true,
+                                federation_id = %federation_id,
+                                "Gateway failed to pay out of federation {federation_id}, \
+                                    it may be low on liquidity"
+                            );
+                            bail!(NothingSent(format!("Payment {op_id} failed: {state:?}")))
+                        }
+                        LnPayState::Canceled => {
+                            bail!(NothingSent(format!("Payment {op_id} failed: {state:?}")))
+                        }
+                        LnPayState::UnexpectedError { .. } => {
+                            bail!("Payment {op_id} failed: {state:?}")
+                        }
+                        _ => info!("Paying invoice: {state:?}"),
+                    }
+                }
+                bail!("Payment updates for {op_id} ended")
+            }
+            PayType::Internal(op_id) => {
+                let mut updates = ln.subscribe_internal_pay(op_id).await?.into_stream();
+                while let Some(state) = updates.next().await {
+                    match state {
+                        InternalPayState::Preimage(_) => return Ok(op_id),
+                        InternalPayState::Funding => {}
+                        InternalPayState::FundingFailed { .. }
+                        | InternalPayState::RefundSuccess { .. } => {
+                            bail!(NothingSent(format!("Payment {op_id} failed: {state:?}")))
+                        }
+                        _ => bail!("Payment {op_id} failed: {state:?}"),
+                    }
+                }
+                bail!("Payment updates for {op_id} ended")
+            }
+        }
+    };
+    paid.await.map_err(|e| {
+        if nothing_sent(&e) {
+            e
+        } else {
+            e.context(UnresolvedPayment(op_id))
+        }
+    })
+}
+
+/// The preimage of an invoice the federation's client received, once the
+/// federation has decrypted it for the gateway. `None` while it hasn't or if
+/// the gateway handed over an invalid one.
+#[instrument(skip(client))]
+pub async fn receive_preimage(client: &ClientArc, payment_hash: &str) -> Result<Option<String>> {
+    let contract_id = ContractId::from_str(payment_hash)?;
+    let contract = client.api().get_incoming_contract(contract_id).await?;
+
+    match contract.contract.decrypted_preimage {
+        DecryptedPreimage::Some(key) => {
+            let preimage = sha256::Hash::hash(&key.0);
+            Ok(Some(preimage.to_string()))
+        }
+        DecryptedPreimage::Pending | DecryptedPreimage::Invalid => Ok(None),
+    }
+}
+
+/// Moves `amount_msats` of ecash from one federation's client to another's
+/// over lightning, less the fee reserve, returning what arrived. Failures
+/// before anything left `from` are `NothingSent`.
+#[instrument(skip_all, fields(amount = amount_msats))]
+pub async fn transfer(
+    mm: &ModelManager,
+    from: &ClientArc,
+    to: &ClientArc,
+    amount_msats: u64,
+) -> Result<u64> {
+    let received = amount_msats.saturating_sub(fee_reserve(amount_msats));
+    if received == 0 {
+        bail!(NothingSent(format!(
+            "{amount_msats} msats don't cover the lightning fees"
+        )));
+    }
+
+    let ln = to.get_first_module::<LightningClientModule>();
+    let not_sent = |e: anyhow::Error| NothingSent(format!("Preparing the transfer failed: {e:#}"));
+    let (op_id, invoice) = ln
+        .create_bolt11_invoice(
+            Amount::from_msats(received),
+            "hermes transfer".to_string(),
+            None,
+            (),
+        )
+        .await
+        .map_err(not_sent)?;
+    let mut updates = ln
+        .subscribe_ln_receive(op_id)
+        .await
+        .map_err(not_sent)?
+        .into_stream();
+
+    pay_bolt11(mm, from, invoice).await?;
+
+    while let Some(state) = updates.next().await {
+        match state {
+            LnReceiveState::Claimed => return Ok(received),
+            LnReceiveState::Canceled { reason } => bail!("Receive {op_id} canceled: {reason:?}"),
+            _ => info!("Receiving transfer: {state:?}"),
+        }
+    }
+    bail!("Receive updates for {op_id} ended")
+}
@@ -0,0 +1,138 @@
+//! The lightning gateways of each federation, looked up in the background so
+//! creating an invoice never waits on gateway discovery.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use fedimint_core::config::FederationId;
+use fedimint_ln_client::LightningClientModule;
+use fedimint_ln_common::LightningGateway;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::state::AppState;
+
+/// How long a federation gets to name its gateways.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A federation's gateways as of the last refresh.
+#[derive(Debug, Clone)]
+pub struct GatewaySet {
+    /// The one new invoices are routed through
+    pub active: Option<LightningGateway>,
+    pub gateways: Vec<LightningGateway>,
+    pub refreshed_at: OffsetDateTime,
+}
+
+/// A gateway as listed by the admin API.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewayInfo {
+    pub gateway_id: String,
+    pub api: String,
+    pub base_fee_msats: u32,
+    pub fee_ppm: u32,
+    pub active: bool,
+}
+
+/// Read-mostly map of the gateway sets, swapped whole on refresh like the
+/// federation clients.
+#[derive(Clone, Default)]
+pub struct GatewayCache {
+    sets: Arc<ArcSwap<HashMap<FederationId, GatewaySet>>>,
+}
+
+impl GatewayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The gateway invoices of the federation go through, `None` until it
+    /// was first refreshed or if it has none.
+    pub fn active(&self, federation_id: &FederationId) -> Option<LightningGateway> {
+        self.sets.load().get(federation_id)?.active.clone()
+    }
+
+    pub fn get(&self, federation_id: &FederationId) -> Option<GatewaySet> {
+        self.sets.load().get(federation_id).cloned()
+    }
+
+    pub fn all(&self) -> Arc<HashMap<FederationId, GatewaySet>> {
+        self.sets.load_full()
+    }
+
+    fn store(&self, federation_id: FederationId, set: GatewaySet) {
+        self.sets.rcu(|sets| {
+            let mut sets = HashMap::clone(sets);
+            sets.insert(federation_id, set.clone());
+            sets
+        });
+    }
+}
+
+impl GatewaySet {
+    pub fn info(&self) -> Vec<GatewayInfo> {
+        let active_id = self.active.as_ref().map(|g| g.gateway_id);
+        self.gateways
+            .iter()
+            .map(|g| GatewayInfo {
+                gateway_id: g.gateway_id.to_string(),
+                api: g.api.to_string(),
+                base_fee_msats: g.fees.base_msat,
+                fee_ppm: g.fees.proportional_millionths,
+                active: Some(g.gateway_id) == active_id,
+            })
+            .collect()
+    }
+}
+
+/// Looks up every federation's gateways and picks the active one, so the
+/// client has it at hand when an invoice is created. A federation that
+/// fails keeps the gateways it had.
+pub async fn refresh_gateways(state: AppState) -> Result<()> {
+    for federation_id in state.federations.ids() {
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+        let Some(client) = state.federations.get(&federation_id) else {
+            continue;
+        };
+        let ln = client.get_first_module::<LightningClientModule>();
+
+        let refreshed = tokio::time::timeout(REFRESH_TIMEOUT, async {
+            let active = ln.select_active_gateway().await.ok();
+            let gateways: Vec<LightningGateway> = ln
+                .list_gateways()
+                .await
+                .into_iter()
+                .map(|announcement| announcement.info)
+                .collect();
+            (active, gateways)
+        })
+        .await;
+        let Ok((active, gateways)) = refreshed else {
+            warn!("Refreshing the gateways of federation {federation_id} timed out");
+            continue;
+        };
+
+        if active.is_none() {
+            warn!("Federation {federation_id} has no active gateway");
+        } else if state.gateways.active(&federation_id).map(|g| g.gateway_id)
+            != active.as_ref().map(|g| g.gateway_id)
+        {
+            info!("Federation {federation_id} routes invoices through a new gateway");
+        }
+        state.gateways.store(
+            federation_id,
+            GatewaySet {
+                active,
+                gateways,
+                refreshed_at: OffsetDateTime::now_utc(),
+            },
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use time::format_description::well_known::Rfc3339;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::{
+    config::CONFIG,
+    error::AppError,
+    model::{
+        app_user::{AppUser, AppUserBmc},
+        app_user_relays::AppUserRelaysBmc,
+        invoice::InvoiceBmc,
+        nostr_profile::NostrProfileBmc,
+        receive_limit::{self, ReceiveLimitForSet},
+    },
+    profiles,
+    router::{
+        handlers::{
+            admin::{
+                federations::{self, JoinFederationParams},
+                limits, users,
+            },
+            NameOrPubkey,
+        },
+        middleware::{as_admin, is_admin_key},
+    },
+    state::AppState,
+};
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::*;
+
+pub mod proto {
+    tonic::include_proto!("hermes.admin");
+}
+
+pub struct AdminService {
+    state: AppState,
+}
+
+impl AdminService {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+/// Serves the gRPC admin API until shutdown is signalled.
+pub async fn serve(state: AppState, port: u16) -> Result<()> {
+    let addr = SocketAddr::new(CONFIG.grpc_bind, port);
+    let shutdown = state.shutdown.clone();
+    let service = AdminServer::with_interceptor(AdminService::new(state), check_auth);
+
+    if !addr.ip().is_loopback() {
+        warn!("gRPC admin is served without TLS on {addr}, keep it on a private network");
+    }
+    info!("gRPC admin listening on {}", addr);
+    Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(addr, shutdown.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+fn check_auth(request: Request<()>) -> Result<Request<()>, Status> {
+    if is_admin_key(bearer(&request)) {
+        Ok(request)
+    } else {
+        Err(Status::unauthenticated("Invalid admin api key"))
+    }
+}
+
+fn bearer<T>(request: &Request<T>) -> &str {
+    request
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+fn internal(e: anyhow::Error) -> Status {
+    Status::internal(e.to_string())
+}
+
+/// The status matching the code the `/admin` route answers with.
+fn app_status(e: AppError) -> Status {
+    let message = e.error.to_string();
+    match e.status {
+        StatusCode::BAD_REQUEST => Status::invalid_argument(message),
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::CONFLICT => Status::failed_precondition(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn user_message(user: AppUser) -> User {
+    User {
+        id: user.id,
+        pubkey: user.pubkey,
+        name: user.name,
+        dm_type: user.dm_type,
+        federation_id: user.federation_id,
+        relays: vec![],
+        display_name: String::new(),
+    }
+}
+
+fn limit_message(limit: receive_limit::ReceiveLimit) -> ReceiveLimit {
+    ReceiveLimit {
+        app_user_id: limit.app_user_id,
+        daily_msats: limit.daily_msats,
+        weekly_msats: limit.weekly_msats,
+        exempt: limit.exempt,
+        updated_at: limit.updated_at.format(&Rfc3339).unwrap_or_default(),
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn list_federations(
+        &self,
+        _request: Request<ListFederationsRequest>,
+    ) -> Result<Response<ListFederationsResponse>, Status> {
+        let federation_ids = self
+            .state
+            .federations
+            .ids()
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        Ok(Response::new(ListFederationsResponse { federation_ids }))
+    }
+
+    async fn list_users(
+        &self,
+        _request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let users = AppUserBmc::list(&self.state.mm, false)
+            .await
+            .map_err(internal)?;
+        let pubkeys: Vec<String> = users.iter().map(|u| u.pubkey.clone()).collect();
+        let display_names = NostrProfileBmc::get_many(&self.state.mm, &pubkeys)
+            .await
+            .map_err(internal)?;
+        let users = users
+            .into_iter()
+            .map(|u| User {
+                id: u.id,
+                display_name: display_names.get(&u.pubkey).cloned().unwrap_or_default(),
+                pubkey: u.pubkey,
+                name: u.name,
+                dm_type: u.dm_type,
+                federation_id: u.federation_id,
+                relays: vec![],
+            })
+            .collect();
+
+        Ok(Response::new(ListUsersResponse { users }))
+    }
+
+    async fn get_user(&self, request: Request<GetUserRequest>) -> Result<Response<User>, Status> {
+        let name = request.into_inner().name;
+        let user = AppUserRelaysBmc::get_by(&self.state.mm, NameOrPubkey::Name, &name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        let display_name =
+            profiles::display_name(&self.state.nostr, &self.state.mm, &user.pubkey).await;
+
+        Ok(Response::new(User {
+            id: user.app_user_id,
+            display_name: display_name.unwrap_or_default(),
+            pubkey: user.pubkey,
+            name: user.name,
+            dm_type: user.dm_type,
+            federation_id: user.federation_id,
+            relays: user.relays,
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<GetStatsRequest>,
+    ) -> Result<Response<Stats>, Status> {
+        let users = AppUserBmc::list(&self.state.mm, false)
+            .await
+            .map_err(internal)?
+            .len() as i64;
+        let federations = self.state.federations.len() as i64;
+        let invoices = InvoiceBmc::totals_by_state(&self.state.mm)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|(state, count, amount_msats)| InvoiceTotals {
+                state: format!("{state:?}"),
+                count,
+                amount_msats,
+            })
+            .collect();
+
+        Ok(Response::new(Stats {
+            users,
+            federations,
+            invoices,
+        }))
+    }
+
+    // the calls below go through the `/admin` handlers, so they're checked
+    // and audited the same way
+
+    async fn join_federation(
+        &self,
+        request: Request<JoinFederationRequest>,
+    ) -> Result<Response<ListFederationsResponse>, Status> {
+        let key = bearer(&request).to_string();
+        let params = JoinFederationParams {
+            invite_code: request.into_inner().invite_code,
+        };
+        let Json(federation_ids) = as_admin(
+            &key,
+            federations::handle_join_federation(State(self.state.clone()), Json(params)),
+        )
+        .await
+        .map_err(app_status)?;
+
+        Ok(Response::new(ListFederationsResponse { federation_ids }))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        let key = bearer(&request).to_string();
+        let name = request.into_inner().name;
+        as_admin(
+            &key,
+            users::handle_delete_user(Path(name), State(self.state.clone())),
+        )
+        .await
+        .map_err(app_status)?;
+
+        Ok(Response::new(DeleteUserResponse {}))
+    }
+
+    async fn restore_user(
+        &self,
+        request: Request<RestoreUserRequest>,
+    ) -> Result<Response<User>, Status> {
+        let key = bearer(&request).to_string();
+        let name = request.into_inner().name;
+        let Json(restored) = as_admin(
+            &key,
+            users::handle_restore_user(Path(name), State(self.state.clone())),
+        )
+        .await
+        .map_err(app_status)?;
+
+        Ok(Response::new(user_message(restored)))
+    }
+
+    async fn get_receive_limit(
+        &self,
+        request: Request<GetReceiveLimitRequest>,
+    ) -> Result<Response<ReceiveLimit>, Status> {
+        let name = request.into_inner().name;
+        let Json(limit) = limits::handle_get_receive_limit(Path(name), State(self.state.clone()))
+            .await
+            .map_err(app_status)?;
+
+        Ok(Response::new(limit_message(limit)))
+    }
+
+    async fn set_receive_limit(
+        &self,
+        request: Request<SetReceiveLimitRequest>,
+    ) -> Result<Response<ReceiveLimit>, Status> {
+        let key = bearer(&request).to_string();
+        let request = request.into_inner();
+        let params = ReceiveLimitForSet {
+            daily_msats: request.daily_msats,
+            weekly_msats: request.weekly_msats,
+            exempt: request.exempt,
+        };
+        let Json(limit) = as_admin(
+            &key,
+            limits::handle_set_receive_limit(
+                Path(request.name),
+                State(self.state.clone()),
+                Json(params),
+            ),
+        )
+        .await
+        .map_err(app_status)?;
+
+        Ok(Response::new(limit_message(limit)))
+    }
+
+    async fn delete_receive_limit(
+        &self,
+        request: Request<DeleteReceiveLimitRequest>,
+    ) -> Result<Response<DeleteReceiveLimitResponse>, Status> {
+        let key = bearer(&request).to_string();
+        let name = request.into_inner().name;
+        as_admin(
+            &key,
+            limits::handle_delete_receive_limit(Path(name), State(self.state.clone())),
+        )
+        .await
+        .map_err(app_status)?;
+
+        Ok(Response::new(DeleteReceiveLimitResponse {}))
+    }
+}
@@ -0,0 +1,52 @@
+//! Settlement steps of invoices, recorded in `invoice_events` next to the
+//! state transitions `InvoiceBmc::transition` records, for
+//! `GET /admin/invoices/:id/events`.
+
+use std::future::Future;
+
+use tracing::warn;
+
+use crate::{
+    model::{
+        invoice_event::{InvoiceEventBmc, InvoiceEventForCreate, InvoiceEventSource},
+        ModelManager,
+    },
+    router::middleware::current_request_id,
+    write_buffer,
+};
+
+tokio::task_local! {
+    static SOURCE: InvoiceEventSource;
+}
+
+/// Runs `f` with the steps it records attributed to `source`.
+pub async fn with_source<F: Future>(source: InvoiceEventSource, f: F) -> F::Output {
+    SOURCE.scope(source, f).await
+}
+
+/// Records `event` for an invoice, attributed to the enclosing `with_source`
+/// or else the callback. Failing to record only loses history, the step
+/// itself went through. With the write buffer on, it is only queued.
+pub async fn record(mm: &ModelManager, invoice_id: i32, event: &str, detail: Option<String>) {
+    let event_c = InvoiceEventForCreate {
+        invoice_id,
+        event: event.to_string(),
+        source: SOURCE
+            .try_with(|source| *source)
+            .unwrap_or(InvoiceEventSource::Callback),
+        detail,
+        request_id: current_request_id(),
+    };
+    let event_c = if write_buffer::enabled() {
+        match write_buffer::push_event(event_c) {
+            Ok(()) => return,
+            // the buffer is full, so write through rather than drop it
+            Err(event_c) => event_c,
+        }
+    } else {
+        event_c
+    };
+    if let Err(e) = InvoiceEventBmc::create(mm, event_c).await {
+        warn!("Could not record {event} for invoice {invoice_id}: {e:#}");
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use fedimint_core::api::IGlobalFederationApi;
+use fedimint_ln_client::LightningClientModule;
+use tracing::error;
+
+use crate::{config::CONFIG, model::dead_letter::DeadLetterBmc, state::AppState};
+
+const FEDERATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Logs an alert for every problem found, `alerts::dispatch` takes care of
+/// not repeating them too often.
+pub async fn check_alerts(state: AppState) -> Result<()> {
+    if let Err(e) = state.mm.ping().await {
+        error!(alert = true, "Database is unreachable: {e:#}");
+    } else {
+        let open = DeadLetterBmc::count_open(&state.mm).await?;
+        if open >= CONFIG.alert_dead_letter_threshold {
+            error!(
+                alert = true,
+                "{open} notification(s) are dead lettered, see GET /admin/dead-letters"
+            );
+        }
+    }
+
+    for federation_id in state.federations.ids() {
+        let Some(client) = state.federations.get(&federation_id) else {
+            continue;
+        };
+
+        let reachable = tokio::time::timeout(
+            FEDERATION_TIMEOUT,
+            client.api().fetch_consensus_block_count(),
+        )
+        .await;
+        match reachable {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!(
+                alert = true,
+                federation_id = %federation_id,
+                "Federation {federation_id} is unreachable: {e}"
+            ),
+            Err(_) => error!(
+                alert = true,
+                federation_id = %federation_id,
+                "Federation {federation_id} did not answer within {}s",
+                FEDERATION_TIMEOUT.as_secs()
+            ),
+        }
+
+        let ln = client.get_first_module::<LightningClientModule>();
+        if ln.list_gateways().await.is_empty() {
+            error!(
+                alert = true,
+                federation_id = %federation_id,
+                "Federation {federation_id} has no lightning gateway, invoices can't be paid"
+            );
+        }
+    }
+
+    Ok(())
+}
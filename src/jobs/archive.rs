@@ -0,0 +1,81 @@
+use anyhow::Result;
+use object_store::{path::Path, ObjectStore};
+use tracing::info;
+
+use crate::{
+    config::CONFIG,
+    export::{invoice_csv_record, INVOICE_CSV_HEADER},
+    model::invoice_archive::{ArchivedInvoice, InvoiceArchiveBmc},
+    state::AppState,
+};
+
+/// Invoices archived per statement, and per file with `INVOICE_ARCHIVE_URL`.
+const BATCH_SIZE: i64 = 1000;
+
+/// Moves finished invoices past `INVOICE_ARCHIVE_DAYS` out of the invoice
+/// table, into `invoice_archive` or CSV files at `INVOICE_ARCHIVE_URL`.
+pub async fn archive_invoices(state: AppState) -> Result<()> {
+    let Some(days) = CONFIG.invoice_archive_days else {
+        return Ok(());
+    };
+    let store = match CONFIG.invoice_archive_url.as_ref() {
+        // credentials come from the usual AWS_*/GOOGLE_* environment variables
+        Some(url) => Some(object_store::parse_url_opts(url, std::env::vars())?),
+        None => None,
+    };
+
+    let mut archived = 0;
+    loop {
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+        let batch = match &store {
+            Some((store, prefix)) => {
+                InvoiceArchiveBmc::export(&state.mm, days, BATCH_SIZE, |invoices| {
+                    write_csv(store.as_ref(), prefix, invoices)
+                })
+                .await? as u64
+            }
+            None => InvoiceArchiveBmc::archive(&state.mm, days, BATCH_SIZE).await?,
+        };
+        archived += batch;
+        if batch < BATCH_SIZE as u64 {
+            break;
+        }
+    }
+
+    metrics::counter!("invoices_archived_total").increment(archived);
+    if archived > 0 {
+        info!("Archived {archived} invoice(s) older than {days} days");
+    }
+
+    Ok(())
+}
+
+/// Writes a batch as `invoices-<first id>-<last id>.csv` under `prefix`.
+async fn write_csv(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    invoices: Vec<ArchivedInvoice>,
+) -> Result<()> {
+    let first = invoices.first().map(|i| i.invoice.id).unwrap_or_default();
+    let last = invoices.last().map(|i| i.invoice.id).unwrap_or_default();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(INVOICE_CSV_HEADER)?;
+    for archived in invoices {
+        writer.write_record(invoice_csv_record(
+            &archived.invoice,
+            archived.created_at,
+            archived.settled_at,
+            archived.deleted_at,
+        )?)?;
+    }
+    let data = writer.into_inner()?;
+
+    let location = Path::from(format!("{prefix}/invoices-{first}-{last}.csv"));
+    store.put(&location, data.into()).await?;
+
+    info!("Wrote archived invoices to {location}");
+    Ok(())
+}
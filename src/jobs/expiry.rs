@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use lightning_invoice::Bolt11Invoice;
+use tracing::{info, warn};
+
+use crate::{
+    events::InvoiceUpdate,
+    model::{
+        app_user::AppUserBmc, invoice::InvoiceBmc, invoice_event::InvoiceEventSource,
+        invoice_state::InvoiceState,
+    },
+    state::AppState,
+};
+
+use super::reconcile::payment_arrived;
+
+/// Marks pending invoices past their BOLT11 expiry as expired and stops
+/// watching them, otherwise abandoned invoices stay pending forever. An
+/// invoice the gateway already paid is left pending for its claim, or for
+/// the reconciler to settle.
+pub async fn expire_invoices(state: AppState) -> Result<()> {
+    let pending = InvoiceBmc::get_pending(&state.mm).await?;
+
+    let mut expired = 0;
+    for invoice in pending {
+        let bolt11 = match Bolt11Invoice::from_str(&invoice.bolt11) {
+            Ok(bolt11) => bolt11,
+            Err(e) => {
+                warn!("Invoice {} has an unparsable bolt11: {e}", invoice.id);
+                continue;
+            }
+        };
+        if !bolt11.is_expired() {
+            continue;
+        }
+        match payment_arrived(&state, &invoice).await {
+            Ok(false) => {}
+            Ok(true) => {
+                info!(
+                    "Invoice {} expired after being paid, waiting for its claim",
+                    invoice.id
+                );
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "Could not check invoice {} before expiring it: {e:#}",
+                    invoice.id
+                );
+                continue;
+            }
+        }
+
+        // a payment may have landed since we listed, only expire if still pending
+        let Some(invoice) = InvoiceBmc::transition(
+            &state.mm,
+            invoice.id,
+            InvoiceState::Pending,
+            InvoiceState::Expired,
+            InvoiceEventSource::Sweeper,
+        )
+        .await?
+        else {
+            continue;
+        };
+        state.subscriptions.cancel(invoice.id);
+
+        let user = AppUserBmc::get(&state.mm, invoice.app_user_id).await?;
+        state.invoice_events.publish(InvoiceUpdate {
+            operation_id: invoice.op_id,
+            username: user.name,
+            state: invoice.state,
+        });
+        expired += 1;
+    }
+
+    metrics::counter!("invoices_expired_total").increment(expired);
+    if expired > 0 {
+        info!("Expired {expired} invoice(s)");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{alerts, backup, config::CONFIG, gateways, profiles, state::AppState};
+
+mod alert_checks;
+mod archive;
+mod expiry;
+mod payouts;
+mod reconcile;
+mod retention;
+mod xmpp_outbox;
+
+/// Registers every background job with the scheduler.
+pub fn register_all(state: &AppState) -> Result<()> {
+    // forget rate limit buckets that have fully refilled
+    state.scheduler.register(
+        state,
+        "rate_limit_cleanup",
+        Duration::from_secs(60),
+        |state| async move {
+            state.rate_limiter.retain_recent();
+            Ok(())
+        },
+    )?;
+
+    // forget abuse scores that decayed away and expired bans
+    state.scheduler.register(
+        state,
+        "ip_reputation_cleanup",
+        Duration::from_secs(60),
+        |state| async move {
+            state.reputation.retain_recent();
+            Ok(())
+        },
+    )?;
+
+    state.scheduler.register(
+        state,
+        "cache_cleanup",
+        Duration::from_secs(60),
+        |state| async move {
+            state.cache.retain_fresh();
+            Ok(())
+        },
+    )?;
+
+    state.scheduler.register(
+        state,
+        "db_pool_metrics",
+        Duration::from_secs(15),
+        |state| async move {
+            state.mm.record_pool_metrics();
+            Ok(())
+        },
+    )?;
+
+    // stream numbers, and room made under SUBSCRIPTION_MAX_MEMORY_MB
+    state.scheduler.register(
+        state,
+        "subscription_trim",
+        Duration::from_secs(15),
+        |state| async move {
+            state.subscriptions.trim();
+            Ok(())
+        },
+    )?;
+
+    state.scheduler.register(
+        state,
+        "expire_invoices",
+        Duration::from_secs(60),
+        expiry::expire_invoices,
+    )?;
+
+    state.scheduler.register(
+        state,
+        "reconcile_invoices",
+        Duration::from_secs(5 * 60),
+        reconcile::reconcile_invoices,
+    )?;
+
+    state.scheduler.register(
+        state,
+        "payout_batches",
+        Duration::from_secs(60),
+        payouts::deliver_payout_batches,
+    )?;
+
+    state.scheduler.register(
+        state,
+        "xmpp_outbox",
+        Duration::from_secs(15),
+        xmpp_outbox::deliver_xmpp_outbox,
+    )?;
+
+    state.scheduler.register(
+        state,
+        "gateway_refresh",
+        Duration::from_secs(60),
+        gateways::refresh_gateways,
+    )?;
+
+    state.scheduler.register(
+        state,
+        "profile_refresh",
+        Duration::from_secs(60),
+        profiles::refresh_profiles,
+    )?;
+
+    if CONFIG.zap_retention_days.is_some() {
+        state.scheduler.register(
+            state,
+            "zap_retention",
+            Duration::from_secs(60 * 60),
+            retention::prune_zaps,
+        )?;
+    }
+
+    if CONFIG.invoice_archive_days.is_some() {
+        state.scheduler.register(
+            state,
+            "invoice_archive",
+            Duration::from_secs(60 * 60),
+            archive::archive_invoices,
+        )?;
+    }
+
+    if alerts::enabled() {
+        state.scheduler.register(
+            state,
+            "alert_checks",
+            Duration::from_secs(60),
+            alert_checks::check_alerts,
+        )?;
+    }
+
+    if CONFIG.backup_url.is_some() {
+        state.scheduler.register(
+            state,
+            "backup",
+            Duration::from_secs(24 * 60 * 60),
+            |state| async move { backup::upload_backup(&state.mm).await },
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use fedimint_core::{config::FederationId, Amount};
+use fedimint_mint_client::{MintClientModule, OOBNotes};
+use time::OffsetDateTime;
+use tracing::{error, info, instrument};
+
+use crate::{
+    config::CONFIG,
+    federation_stats,
+    model::{
+        app_user_relays::AppUserRelaysBmc,
+        ecash_payout::EcashPayoutForCreate,
+        payout_batch::{PayoutBatch, PayoutBatchBmc},
+    },
+    router::handlers::{
+        lnurlp::callback::{notes_expiry, record_payout, send_payout, Payout},
+        NoteFormat,
+    },
+    state::AppState,
+};
+
+/// Pays out the batches of users with batched payouts that are old or large
+/// enough, and retries batches whose payout failed.
+pub async fn deliver_payout_batches(state: AppState) -> Result<()> {
+    let started_before = OffsetDateTime::now_utc() - CONFIG.payout_batch_max_age;
+    let threshold = CONFIG.payout_batch_threshold_msats.map(|t| t as i64);
+    let batches = PayoutBatchBmc::list_due(&state.mm, threshold, started_before).await?;
+
+    let mut delivered = 0;
+    for batch in batches {
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+
+        match deliver(&state, &batch).await {
+            Ok(()) => delivered += 1,
+            Err(e) => {
+                error!(
+                    alert = true,
+                    "Payout batch {} failed, retrying next run: {e:#}", batch.id
+                );
+                PayoutBatchBmc::record_failure(&state.mm, batch.id, &format!("{e:#}")).await?;
+            }
+        }
+    }
+
+    metrics::counter!("payout_batches_delivered_total").increment(delivered);
+    if delivered > 0 {
+        info!("Delivered {delivered} payout batch(es)");
+    }
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(batch_id = batch.id, federation_id = %batch.federation_id))]
+async fn deliver(state: &AppState, batch: &PayoutBatch) -> Result<()> {
+    let batch = PayoutBatchBmc::seal(&state.mm, batch.id).await?;
+    let userrelays = AppUserRelaysBmc::get_by_id(&state.mm, batch.app_user_id).await?;
+    let amount = batch.amount as u64;
+
+    // notes spent on an earlier run are sent again rather than spent twice
+    let (operation_id, notes) = match (batch.operation_id, batch.notes) {
+        (Some(operation_id), Some(notes)) => (operation_id.parse()?, notes.parse::<OOBNotes>()?),
+        _ => {
+            let client = FederationId::from_str(&batch.federation_id)
+                .ok()
+                .and_then(|id| state.federations.get(&id))
+                .ok_or_else(|| anyhow!("federation {} is not connected", batch.federation_id))?;
+            let mint = client.get_first_module::<MintClientModule>();
+            let (operation_id, notes) = mint
+                .spend_notes(
+                    Amount::from_msats(amount),
+                    notes_expiry(userrelays.notes_expiry_secs),
+                    (),
+                )
+                .await?;
+            federation_stats::notes_issued(&state.mm, &batch.federation_id, amount).await;
+            PayoutBatchBmc::set_notes(
+                &state.mm,
+                batch.id,
+                &operation_id.to_string(),
+                notes.to_string().into(),
+            )
+            .await?;
+            record_payout(
+                &state.mm,
+                EcashPayoutForCreate {
+                    app_user_id: batch.app_user_id,
+                    invoice_id: None,
+                    payout_batch_id: Some(batch.id),
+                    operation_id: operation_id.to_string(),
+                    amount: batch.amount,
+                    note_format: NoteFormat::Fedimint.to_string(),
+                    notes: notes.to_string().into(),
+                },
+            )
+            .await;
+            (operation_id, notes)
+        }
+    };
+
+    send_payout(
+        &state.nostr,
+        &state.mm,
+        &userrelays,
+        operation_id,
+        amount,
+        &Payout::Fedimint(notes),
+        None,
+    )
+    .await?;
+    PayoutBatchBmc::mark_delivered(&state.mm, batch.id).await
+}
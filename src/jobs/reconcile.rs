@@ -0,0 +1,133 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::Result;
+use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_core::config::FederationId;
+use fedimint_ln_client::{LightningClientModule, LnReceiveState};
+use futures::StreamExt;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{
+    model::{
+        app_user_relays::AppUserRelaysBmc,
+        invoice::{Invoice, InvoiceBmc, InvoiceFilter},
+        invoice_event::InvoiceEventSource,
+        invoice_state::InvoiceState,
+    },
+    router::handlers::lnurlp::callback::finish_invoice,
+    state::AppState,
+};
+
+/// Invoices younger than this are still being watched by their subscription.
+const MIN_AGE: time::Duration = time::Duration::minutes(5);
+/// How long to replay an operation's updates before assuming it is still open.
+const OPERATION_TIMEOUT: Duration = Duration::from_secs(2);
+const BATCH_SIZE: i64 = 500;
+
+/// Cross-checks pending invoices against the fedimint operation log and
+/// finishes the ones whose operation already completed, e.g. because the
+/// subscription that should have noticed was lost.
+pub async fn reconcile_invoices(state: AppState) -> Result<()> {
+    let filter = InvoiceFilter {
+        state: Some(InvoiceState::Pending),
+        to: Some(OffsetDateTime::now_utc() - MIN_AGE),
+        ..Default::default()
+    };
+    let (invoices, _) = InvoiceBmc::list(&state.mm, &filter, BATCH_SIZE, 0).await?;
+
+    let mut reconciled = 0;
+    for invoice in invoices.into_iter().map(|i| i.invoice) {
+        let Some(client) = FederationId::from_str(&invoice.federation_id)
+            .ok()
+            .and_then(|id| state.federations.get(&id))
+        else {
+            continue;
+        };
+
+        let ln = client.get_first_module::<LightningClientModule>();
+        let Ok(subscription) = ln.subscribe_ln_receive(invoice.op_id.parse()?).await else {
+            continue;
+        };
+        let Some(last_state) = operation_outcome(subscription).await else {
+            continue;
+        };
+
+        let final_state = match last_state {
+            LnReceiveState::Claimed => InvoiceState::Settled,
+            LnReceiveState::Canceled { .. } => InvoiceState::Cancelled,
+            _ => continue,
+        };
+
+        warn!(
+            "Invoice {} missed its {:?} update, reconciling",
+            invoice.id, final_state
+        );
+        let userrelays = AppUserRelaysBmc::get_by_id(&state.mm, invoice.app_user_id).await?;
+        finish_invoice(
+            &state,
+            &client,
+            invoice.id,
+            &userrelays,
+            final_state,
+            InvoiceEventSource::Reconciler,
+        )
+        .await?;
+        reconciled += 1;
+    }
+
+    metrics::counter!("invoices_reconciled_total").increment(reconciled);
+    if reconciled > 0 {
+        info!("Reconciled {reconciled} invoice(s)");
+    }
+
+    Ok(())
+}
+
+/// The final state of an operation, or `None` if it is still in progress.
+async fn operation_outcome(
+    subscription: UpdateStreamOrOutcome<LnReceiveState>,
+) -> Option<LnReceiveState> {
+    let mut stream = subscription.into_stream();
+    let mut last = None;
+    let drained = tokio::time::timeout(OPERATION_TIMEOUT, async {
+        while let Some(op_state) = stream.next().await {
+            last = Some(op_state);
+        }
+    })
+    .await;
+
+    drained.ok().and(last)
+}
+
+/// Whether the gateway already paid into an invoice's contract, so it must
+/// not be expired with its bolt11: the claim can still land after that, and
+/// only a pending invoice gets settled. An invoice whose federation isn't
+/// loaded can't be paid anymore.
+pub(super) async fn payment_arrived(state: &AppState, invoice: &Invoice) -> Result<bool> {
+    let Some(client) = FederationId::from_str(&invoice.federation_id)
+        .ok()
+        .and_then(|id| state.federations.get(&id))
+    else {
+        return Ok(false);
+    };
+
+    let ln = client.get_first_module::<LightningClientModule>();
+    let mut stream = ln
+        .subscribe_ln_receive(invoice.op_id.parse()?)
+        .await?
+        .into_stream();
+    let mut arrived = false;
+    // an open operation replays what it has and then waits, that's enough
+    let _ = tokio::time::timeout(OPERATION_TIMEOUT, async {
+        while let Some(op_state) = stream.next().await {
+            arrived |= matches!(
+                op_state,
+                LnReceiveState::Funded | LnReceiveState::AwaitingFunds | LnReceiveState::Claimed
+            );
+        }
+    })
+    .await;
+
+    Ok(arrived)
+}
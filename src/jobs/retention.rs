@@ -0,0 +1,40 @@
+use anyhow::Result;
+use tracing::info;
+
+use crate::{config::CONFIG, model::zap::ZapBmc, state::AppState};
+
+/// Zaps removed per statement, so a large backlog doesn't hold locks on the
+/// table for long.
+const BATCH_SIZE: i64 = 1000;
+
+/// Prunes zaps past `ZAP_RETENTION_DAYS`, or archives them with
+/// `ZAP_ARCHIVE`, keeping the zaps table small on busy instances.
+pub async fn prune_zaps(state: AppState) -> Result<()> {
+    let Some(days) = CONFIG.zap_retention_days else {
+        return Ok(());
+    };
+
+    let mut pruned = 0;
+    loop {
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+        let batch = ZapBmc::prune(&state.mm, days, CONFIG.zap_archive, BATCH_SIZE).await?;
+        pruned += batch;
+        if batch < BATCH_SIZE as u64 {
+            break;
+        }
+    }
+
+    metrics::counter!("zaps_pruned_total").increment(pruned);
+    if pruned > 0 {
+        let verb = if CONFIG.zap_archive {
+            "Archived"
+        } else {
+            "Pruned"
+        };
+        info!("{verb} {pruned} zap(s) older than {days} days");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use nostr::secp256k1::XOnlyPublicKey;
+use time::OffsetDateTime;
+use tracing::{error, info};
+
+use crate::{
+    config::CONFIG,
+    model::xmpp_outbox::{XmppOutboxBmc, XmppOutboxMessage, NOSTR_CHANNEL, XMPP_CHANNEL},
+    state::AppState,
+    xmpp_client,
+};
+
+/// Messages looked at per run.
+const BATCH_SIZE: i64 = 1000;
+
+/// Sends queued XMPP DMs whose recipient came online, and sends those that
+/// waited longer than `XMPP_PRESENCE_TIMEOUT_SECS` as a nostr DM instead.
+pub async fn deliver_xmpp_outbox(state: AppState) -> Result<()> {
+    let pending = XmppOutboxBmc::list_pending(&state.mm, BATCH_SIZE).await?;
+    let timed_out = OffsetDateTime::now_utc() - CONFIG.xmpp_presence_timeout;
+
+    for message in pending {
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+        let channel = if xmpp_client::is_online(&message.jid) {
+            XMPP_CHANNEL
+        } else if message.created_at < timed_out {
+            NOSTR_CHANNEL
+        } else {
+            continue;
+        };
+        // one failure shouldn't hold up the rest, it is tried again next run
+        if let Err(e) = deliver(&state, &message, channel).await {
+            error!(
+                "Delivering XMPP outbox message {} failed: {e:#}",
+                message.id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn deliver(state: &AppState, message: &XmppOutboxMessage, channel: &str) -> Result<()> {
+    if channel == XMPP_CHANNEL {
+        xmpp_client::send_message(&message.jid, &message.message, Some(message.id))?;
+    } else {
+        info!(
+            "{} stayed offline, sending outbox message {} over nostr",
+            message.jid, message.id
+        );
+        state
+            .nostr
+            .send_direct_msg(
+                XOnlyPublicKey::from_str(&message.pubkey)?,
+                message.message.to_string(),
+                None,
+            )
+            .await?;
+    }
+    metrics::counter!("xmpp_outbox_sent_total", "channel" => channel.to_string()).increment(1);
+    XmppOutboxBmc::mark_sent(&state.mm, message.id, channel).await
+}
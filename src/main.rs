@@ -1,50 +1,274 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use fedimint_core::config::FederationId;
-use fedimint_ln_client::LightningClientModule;
 use itertools::Itertools;
-use tracing::{error, info};
+use tokio::signal;
+use tracing::{error, info, info_span, warn, Instrument};
 
+mod alerts;
+mod audit;
+mod backup;
+mod cache;
+mod cashu;
+mod compute;
 mod config;
 mod error;
+mod events;
+mod export;
+mod federation_stats;
+mod federations;
+mod gateways;
+mod grpc;
+mod invoice_log;
+mod jobs;
 mod model;
+mod nwc;
+mod profiles;
+mod rate_limit;
+mod reputation;
 mod router;
+mod scheduler;
 mod state;
+mod subscriptions;
+mod supervisor;
+mod telemetry;
+mod tls;
 
 mod utils;
+mod webhooks;
+mod write_buffer;
+mod xmpp_client;
 use state::AppState;
 
 use crate::config::CONFIG;
 use crate::model::app_user_relays::AppUserRelaysBmc;
 use crate::model::invoice::InvoiceBmc;
+use crate::model::{sealed, ModelManager};
 use crate::router::handlers::lnurlp::callback::spawn_invoice_subscription;
+use crate::supervisor::{spawn_supervised, RestartPolicy};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    // must run before anything reads CONFIG
+    config::load_vault_secrets().await?;
+    let _error_reporting = telemetry::init_error_reporting();
+    telemetry::init_tracing()?;
+    telemetry::init_metrics()?;
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(|a| a.as_str()) == Some("migrate") {
+        return migrate(args.get(1).map(|a| a.as_str())).await;
+    }
+    if args.first().map(|a| a.as_str()) == Some("encrypt-columns") {
+        return encrypt_columns().await;
+    }
+
+    if CONFIG.socks_proxy.is_some() {
+        warn!("SOCKS_PROXY does not apply to federation connections, they connect directly");
+    }
 
     let state = AppState::new().await?;
 
     let app = router::create_router(state.clone()).await?;
 
-    // spawn a task to check for previous pending invoices
+    jobs::register_all(&state)?;
+
+    // reload runtime config on SIGHUP
+    let reload_state = state.clone();
     tokio::spawn(async move {
-        if let Err(e) = handle_pending_invoices(state).await {
-            error!("Error handling pending invoices: {e}")
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .expect("failed to install SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            match reload_state.reload_config().await {
+                Ok(_) => {
+                    audit::record(
+                        &reload_state.mm,
+                        "system:sighup".to_string(),
+                        "config.reload",
+                        None,
+                        serde_json::json!({}),
+                    )
+                    .await
+                }
+                Err(e) => error!("Error reloading config: {e}"),
+            }
         }
     });
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", CONFIG.domain, CONFIG.port))
+    // spawn a task to check for previous pending invoices, not restarted since
+    // that would subscribe twice, the reconciliation job picks up any it missed
+    let pending_state = state.clone();
+    spawn_supervised(
+        &state.tasks,
+        "resume_pending_invoices",
+        RestartPolicy::Never,
+        move || handle_pending_invoices(pending_state.clone()),
+    );
+
+    // best effort, wallets still get notifications without it
+    let nwc_nostr = state.nostr.clone();
+    spawn_supervised(&state.tasks, "nwc_info", RestartPolicy::Never, move || {
+        nwc::publish_info(nwc_nostr.clone())
+    });
+
+    let webhook_state = state.clone();
+    spawn_supervised(
+        &state.tasks,
+        "webhook_dispatcher",
+        RestartPolicy::OnFailure {
+            max_restarts: 5,
+            backoff: Duration::from_secs(1),
+        },
+        move || webhooks::dispatch(webhook_state.clone()),
+    );
+
+    if CONFIG.write_buffer {
+        let buffer_state = state.clone();
+        spawn_supervised(
+            &state.tasks,
+            "write_buffer",
+            RestartPolicy::OnFailure {
+                max_restarts: 5,
+                backoff: Duration::from_secs(1),
+            },
+            move || write_buffer::run(buffer_state.clone()),
+        );
+    }
+
+    if xmpp_client::enabled() {
+        let xmpp_state = state.clone();
+        spawn_supervised(
+            &state.tasks,
+            "xmpp_connection",
+            RestartPolicy::OnFailure {
+                max_restarts: 5,
+                backoff: Duration::from_secs(1),
+            },
+            move || xmpp_client::run(xmpp_state.clone()),
+        );
+    } else {
+        warn!("XMPP is off, it can't connect through SOCKS_PROXY. Set XMPP_WITHOUT_PROXY to connect directly");
+    }
+
+    if alerts::enabled() {
+        let alert_state = state.clone();
+        spawn_supervised(
+            &state.tasks,
+            "alert_dispatcher",
+            RestartPolicy::OnFailure {
+                max_restarts: 5,
+                backoff: Duration::from_secs(1),
+            },
+            move || alerts::dispatch(alert_state.clone()),
+        );
+    }
+
+    if let Some(grpc_port) = CONFIG.grpc_port {
+        let grpc_state = state.clone();
+        spawn_supervised(
+            &state.tasks,
+            "grpc_server",
+            RestartPolicy::OnFailure {
+                max_restarts: 5,
+                backoff: Duration::from_secs(1),
+            },
+            move || grpc::serve(grpc_state.clone(), grpc_port),
+        );
+    }
+
+    if CONFIG.acme_domains.is_empty() {
+        let listener = tokio::net::TcpListener::bind(format!("{}:{}", CONFIG.domain, CONFIG.port))
+            .await
+            .unwrap();
+        info!("Listening on {}", CONFIG.port);
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal(state.clone()))
         .await
         .unwrap();
-    info!("Listening on {}", CONFIG.port);
-    axum::serve(listener, app).await.unwrap();
+    } else {
+        tokio::spawn(shutdown_signal(state.clone()));
+        tls::serve(app, state.shutdown.clone()).await?;
+    }
+
+    drain_subscriptions(state).await;
+    telemetry::shutdown_tracing();
+
+    Ok(())
+}
+
+/// `hermes migrate [up|down|status]`
+async fn migrate(command: Option<&str>) -> Result<()> {
+    let mm = ModelManager::new().await?;
+    match command.unwrap_or("status") {
+        "up" => mm.migrate_up().await?,
+        "down" => mm.migrate_down().await?,
+        "status" => info!("{:?}", mm.schema_status().await?),
+        other => return Err(anyhow!("Unknown migrate command: {other}")),
+    }
 
     Ok(())
 }
 
+/// `hermes encrypt-columns`, run once after setting `DB_ENCRYPTION_KEY`
+async fn encrypt_columns() -> Result<()> {
+    let mm = ModelManager::new().await?;
+    let encrypted = sealed::encrypt_existing(&mm).await?;
+    info!("Encrypted {encrypted} stored value(s)");
+
+    Ok(())
+}
+
+/// Waits for SIGINT or SIGTERM and then tells background tasks to stop
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received, no longer accepting requests");
+    state.shutdown.cancel();
+}
+
+/// Waits (bounded) for invoice subscriptions to stop. Invoices still pending
+/// stay pending in the db and are resubscribed on the next start.
+async fn drain_subscriptions(state: AppState) {
+    state.tasks.close();
+    info!("Waiting for {} background task(s)", state.tasks.len());
+    if tokio::time::timeout(CONFIG.shutdown_timeout, state.tasks.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            "Timed out draining background tasks, {} still running",
+            state.tasks.len()
+        );
+    }
+
+    // dropping the clients shuts down their fedimint task groups
+    state.federations.clear().await;
+    info!("Shutdown complete");
+}
+
 /// Starts subscription for all pending invoices from previous run
 async fn handle_pending_invoices(state: AppState) -> Result<()> {
     let invoices = InvoiceBmc::get_pending(&state.mm).await?;
@@ -60,24 +284,20 @@ async fn handle_pending_invoices(state: AppState) -> Result<()> {
     for (federation_id, invoices) in invoices_by_federation {
         // Get the corresponding multimint client for the federation_id
         if let Ok(federation_id) = FederationId::from_str(&federation_id) {
-            if let Some(client) = state.fm.clients.lock().await.get(&federation_id) {
-                let ln = client.get_first_module::<LightningClientModule>();
+            if state.federations.contains(&federation_id) {
                 for invoice in invoices {
-                    // Create subscription to operation if it exists
-                    if let Ok(subscription) = ln
-                        .subscribe_ln_receive(invoice.op_id.parse().expect("invalid op_id"))
-                        .await
-                    {
-                        let nip05relays =
-                            AppUserRelaysBmc::get_by_id(&state.mm, invoice.app_user_id).await?;
-                        spawn_invoice_subscription(
-                            state.clone(),
-                            invoice.id,
-                            nip05relays.clone(),
-                            subscription,
-                        )
-                        .await;
-                    }
+                    let nip05relays =
+                        AppUserRelaysBmc::get_by_id(&state.mm, invoice.app_user_id).await?;
+                    spawn_invoice_subscription(
+                        state.clone(),
+                        invoice.id,
+                        invoice.op_id.parse()?,
+                        subscriptions::bolt11_expiry(&invoice.bolt11),
+                        nip05relays,
+                        state.subscriptions.admit_existing(),
+                    )
+                    .instrument(info_span!("resume", request_id = ?invoice.request_id))
+                    .await;
                 }
             }
         }
@@ -1,15 +1,17 @@
 #![allow(dead_code)]
-use crate::router::handlers::NameOrPubkey;
+use crate::{export::ExportWriter, router::handlers::NameOrPubkey};
 
 use super::{
     base::{self, DbBmc},
-    ModelManager,
+    ModelManager, Tx,
 };
 use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
 use serde::Serialize;
 use sqlb::Fields;
 use sqlb::HasFields;
-use sqlx::FromRow;
+use sqlx::{Executor, FromRow, Postgres};
+use tracing::instrument;
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct AppUser {
@@ -17,7 +19,21 @@ pub struct AppUser {
     pub pubkey: String,
     pub name: String,
     pub dm_type: String,
+    pub note_format: String,
     pub federation_id: String,
+    pub batch_payouts: bool,
+    pub custodial: bool,
+    pub forward_to: Option<String>,
+    pub notes_expiry_secs: Option<i32>,
+    /// Verified XMPP address, unset for nostr users and XMPP users registered
+    /// before addresses were verified
+    pub jid: Option<String>,
+    /// XMPP groupchat settlement notices are posted to
+    pub notify_room: Option<String>,
+    /// Comma separated dm types tried in order when `dm_type` fails
+    pub fallback_dm_types: Option<String>,
+    /// Hex pubkey of the user's NWC wallet, sent NIP-47 notifications
+    pub nwc_pubkey: Option<String>,
 }
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
@@ -25,7 +41,21 @@ pub struct AppUserForCreate {
     pub pubkey: String,
     pub name: String,
     pub dm_type: String,
+    pub note_format: String,
     pub federation_id: String,
+    pub batch_payouts: bool,
+    pub custodial: bool,
+    pub forward_to: Option<String>,
+    pub notes_expiry_secs: Option<i32>,
+    /// Verified XMPP address, unset for nostr users and XMPP users registered
+    /// before addresses were verified
+    pub jid: Option<String>,
+    /// XMPP groupchat settlement notices are posted to
+    pub notify_room: Option<String>,
+    /// Comma separated dm types tried in order when `dm_type` fails
+    pub fallback_dm_types: Option<String>,
+    /// Hex pubkey of the user's NWC wallet, sent NIP-47 notifications
+    pub nwc_pubkey: Option<String>,
 }
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
@@ -33,9 +63,20 @@ pub struct AppUserForUpdate {
     pub pubkey: Option<String>,
     pub name: Option<String>,
     pub dm_type: Option<String>,
+    pub note_format: Option<String>,
     pub federation_id: Option<String>,
+    pub batch_payouts: Option<bool>,
+    pub custodial: Option<bool>,
+    pub forward_to: Option<String>,
+    pub notes_expiry_secs: Option<i32>,
 }
 
+/// Unique index on `lower(name)`, see the unique_username migration.
+const NAME_INDEX: &str = "app_user_name_lower_idx";
+
+/// Users are soft deleted: lookups by name, pubkey or api key and listings
+/// skip them, but their invoices, zaps and payouts keep pointing at them and
+/// `get` by id still finds them for settling what's in flight.
 pub struct AppUserBmc;
 
 impl DbBmc for AppUserBmc {
@@ -51,37 +92,202 @@ impl AppUserBmc {
         base::get::<Self, _>(mm, id).await
     }
 
+    #[instrument(skip(mm, col))]
     pub async fn get_by(mm: &ModelManager, col: NameOrPubkey, val: &str) -> Result<AppUser> {
         let column_name = match col {
             NameOrPubkey::Name => "name",
             NameOrPubkey::Pubkey => "pubkey",
         };
 
-        let user: AppUser = sqlb::select()
-            .table(Self::TABLE)
-            .columns(AppUser::field_names())
-            .and_where(column_name, "=", val)
-            .fetch_optional(mm.db())
+        let query = format!(
+            "SELECT {} FROM {} WHERE {column_name} = $1 AND deleted_at IS NULL",
+            AppUser::field_names().join(", "),
+            Self::TABLE
+        );
+        let user: AppUser = mm
+            .read_or_primary(|db| sqlx::query_as(&query).bind(val).fetch_optional(db))
             .await?
-            .ok_or(anyhow!(
-                "User not found in table '{}', {}: {}",
-                Self::TABLE,
-                column_name,
-                val
-            ))?;
+            .ok_or_else(|| {
+                anyhow::Error::new(sqlx::Error::RowNotFound).context(format!(
+                    "User not found in table '{}', {}: {}",
+                    Self::TABLE,
+                    column_name,
+                    val
+                ))
+            })?;
+
+        Ok(user)
+    }
+
+    /// The pubkey of the live user with this name, if there is one.
+    #[instrument(skip(mm))]
+    pub async fn find_pubkey(mm: &ModelManager, name: &str) -> Result<Option<String>> {
+        let query = format!(
+            "SELECT pubkey FROM {} WHERE name = $1 AND deleted_at IS NULL",
+            Self::TABLE
+        );
+        let pubkey = mm
+            .read_or_primary(|db| sqlx::query_scalar(&query).bind(name).fetch_optional(db))
+            .await?;
+
+        Ok(pubkey)
+    }
+
+    /// Looks a user up by the sha256 hash of their LNbits api key.
+    #[instrument(skip_all)]
+    pub async fn get_by_api_key_hash(mm: &ModelManager, hash: &str) -> Result<Option<AppUser>> {
+        let user = sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE api_key_hash = $1 AND deleted_at IS NULL",
+            AppUser::field_names().join(", "),
+            Self::TABLE
+        ))
+        .bind(hash)
+        .fetch_optional(mm.db())
+        .await?;
 
         Ok(user)
     }
 
-    pub async fn list(mm: &ModelManager) -> Result<Vec<AppUser>> {
-        base::list::<Self, _>(mm).await
+    /// Replaces the user's api key hash, revoking any previous key.
+    #[instrument(skip(mm, hash))]
+    pub async fn set_api_key_hash(mm: &ModelManager, id: i32, hash: &str) -> Result<()> {
+        Self::update_api_key_hash(mm.db(), id, hash).await
+    }
+
+    /// `set_api_key_hash` as part of a transaction.
+    #[instrument(skip(tx, hash))]
+    pub async fn set_api_key_hash_in(tx: &mut Tx, id: i32, hash: &str) -> Result<()> {
+        Self::update_api_key_hash(&mut **tx, id, hash).await
+    }
+
+    async fn update_api_key_hash<'e, X>(db: X, id: i32, hash: &str) -> Result<()>
+    where
+        X: Executor<'e, Database = Postgres>,
+    {
+        let count = sqlx::query(&format!(
+            "UPDATE {} SET api_key_hash = $2 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(hash)
+        .execute(db)
+        .await?
+        .rows_affected();
+
+        if count == 0 {
+            return Err(anyhow!(
+                "Entity not found in table '{}', id: {}",
+                Self::TABLE,
+                id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Which of `names` are already taken, compared case-insensitively and
+    /// returned lowercased. Deleted users keep their names. Reads the primary
+    /// since a replica could hand out a name that was just registered.
+    #[instrument(skip(mm))]
+    pub async fn taken_names(mm: &ModelManager, names: &[String]) -> Result<Vec<String>> {
+        let lowered: Vec<String> = names.iter().map(|n| n.to_lowercase()).collect();
+        let taken = sqlx::query_scalar(&format!(
+            "SELECT lower(name) FROM {} WHERE lower(name) = ANY($1)",
+            Self::TABLE
+        ))
+        .bind(lowered)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(taken)
+    }
+
+    /// Whether a model error came from the case-insensitive unique name
+    /// index, i.e. someone registered the name first.
+    pub fn is_name_conflict(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<sqlx::Error>()
+            .and_then(|e| e.as_database_error())
+            .and_then(|e| e.constraint())
+            == Some(NAME_INDEX)
+    }
+
+    /// Live users, or only the deleted ones.
+    #[instrument(skip(mm))]
+    pub async fn list(mm: &ModelManager, deleted: bool) -> Result<Vec<AppUser>> {
+        let users = sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE (deleted_at IS NOT NULL) = $1 ORDER BY id",
+            AppUser::field_names().join(", "),
+            Self::TABLE
+        ))
+        .bind(deleted)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Writes every live user to `out` as they're read, as a JSON array or
+    /// CSV file.
+    #[instrument(skip_all)]
+    pub async fn export(mm: &ModelManager, out: &mut ExportWriter) -> Result<()> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE deleted_at IS NULL ORDER BY id",
+            AppUser::field_names().join(", "),
+            Self::TABLE
+        );
+        let mut users = sqlx::query_as::<_, AppUser>(&query).fetch(mm.db_read());
+        out.begin_array().await?;
+        while let Some(user) = users.try_next().await? {
+            out.row(&user).await?;
+        }
+        out.end_array().await
+    }
+
+    /// A deleted user by name, for restoring them.
+    #[instrument(skip(mm))]
+    pub async fn get_deleted_by_name(mm: &ModelManager, name: &str) -> Result<Option<AppUser>> {
+        let user = sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE name = $1 AND deleted_at IS NOT NULL",
+            AppUser::field_names().join(", "),
+            Self::TABLE
+        ))
+        .bind(name)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(user)
     }
 
     pub async fn update(mm: &ModelManager, id: i32, user_u: AppUserForUpdate) -> Result<()> {
         base::update::<Self, _>(mm, id, user_u).await
     }
 
-    pub async fn delete(mm: &ModelManager, id: i32) -> Result<()> {
-        base::delete::<Self>(mm, id).await
+    /// Soft deletes a user. Their name stays taken until they are restored,
+    /// so nobody else can pick up payments meant for them. Returns `false` if
+    /// they were already deleted.
+    #[instrument(skip(mm))]
+    pub async fn delete(mm: &ModelManager, id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Undoes `delete`. Returns `false` if the user wasn't deleted.
+    #[instrument(skip(mm))]
+    pub async fn restore(mm: &ModelManager, id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
     }
 }
@@ -4,7 +4,8 @@ use super::{
     app_user::{AppUser, AppUserBmc, AppUserForCreate},
     base::{self, DbBmc},
     relay::{RelayBmc, RelayForCreate},
-    ModelManager,
+    wallet_pairing::WalletPairingBmc,
+    ModelManager, Tx,
 };
 
 use anyhow::Result;
@@ -12,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use sqlb::Fields;
 use sqlb::HasFields;
 use sqlx::FromRow;
+use tracing::instrument;
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct AppUserRelay {
@@ -24,7 +26,16 @@ pub struct AppUserRelaysForCreate {
     pub pubkey: String,
     pub name: String,
     pub dm_type: String,
+    pub note_format: String,
     pub federation_id: String,
+    pub batch_payouts: bool,
+    pub custodial: bool,
+    pub forward_to: Option<String>,
+    pub notes_expiry_secs: Option<i32>,
+    pub jid: Option<String>,
+    pub notify_room: Option<String>,
+    pub fallback_dm_types: Vec<String>,
+    pub nwc_pubkey: Option<String>,
     pub relays: Vec<String>,
 }
 
@@ -33,7 +44,12 @@ pub struct AppUserRelaysForUpdate {
     pub pubkey: Option<String>,
     pub name: Option<String>,
     pub dm_type: Option<String>,
+    pub note_format: Option<String>,
     pub federation_id: Option<String>,
+    pub batch_payouts: Option<bool>,
+    pub custodial: Option<bool>,
+    pub forward_to: Option<String>,
+    pub notes_expiry_secs: Option<i32>,
     pub relays: Option<Vec<String>>,
 }
 
@@ -44,33 +60,64 @@ impl DbBmc for AppUserRelaysBmc {
 }
 
 impl AppUserRelaysBmc {
+    /// Creates a user and their relays. The key's first user is the one a
+    /// wallet pairing with it gets, so it can't pair a second one.
+    #[instrument(skip_all)]
     pub async fn register(
         mm: &ModelManager,
         app_user_relays_c: AppUserRelaysForCreate,
     ) -> Result<()> {
-        let tx = mm.db().begin().await?;
+        let pubkey = app_user_relays_c.pubkey.clone();
+        let mut tx = mm.begin().await?;
+        let user_id = Self::register_in(&mut tx, app_user_relays_c).await?;
+        // false for a key that already has a user, which keeps it
+        WalletPairingBmc::create_in(&mut tx, &pubkey, user_id).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Creates a user and their relays in `tx`, returning the user's id.
+    pub async fn register_in(
+        tx: &mut Tx,
+        app_user_relays_c: AppUserRelaysForCreate,
+    ) -> Result<i32> {
         let user_c = AppUserForCreate {
             pubkey: app_user_relays_c.pubkey,
             name: app_user_relays_c.name,
             dm_type: app_user_relays_c.dm_type,
+            note_format: app_user_relays_c.note_format,
             federation_id: app_user_relays_c.federation_id,
+            batch_payouts: app_user_relays_c.batch_payouts,
+            custodial: app_user_relays_c.custodial,
+            forward_to: app_user_relays_c.forward_to,
+            notes_expiry_secs: app_user_relays_c.notes_expiry_secs,
+            jid: app_user_relays_c.jid,
+            notify_room: app_user_relays_c.notify_room,
+            fallback_dm_types: (!app_user_relays_c.fallback_dm_types.is_empty())
+                .then(|| app_user_relays_c.fallback_dm_types.join(",")),
+            nwc_pubkey: app_user_relays_c.nwc_pubkey,
         };
-        let user_id = base::create::<Self, _>(mm, user_c).await?;
+        let user_id = base::create_in::<AppUserBmc, _>(tx, user_c).await?;
 
         for relay in app_user_relays_c.relays {
             let relay_c = RelayForCreate { relay };
-            let relay_id = base::create::<Self, _>(mm, relay_c).await?;
-            let userrelay = AppUserRelay {
-                app_user_id: user_id,
-                relay_id,
-            };
-            base::create::<Self, _>(mm, userrelay).await?;
+            let relay_id = base::create_in::<RelayBmc, _>(tx, relay_c).await?;
+            // no id column to return, so not through base::create_in
+            sqlx::query(&format!(
+                "INSERT INTO {} (app_user_id, relay_id) VALUES ($1, $2)",
+                Self::TABLE
+            ))
+            .bind(user_id)
+            .bind(relay_id)
+            .execute(&mut **tx)
+            .await?;
         }
-        tx.commit().await?;
 
-        Ok(())
+        Ok(user_id)
     }
 
+    #[instrument(skip(mm))]
     pub async fn get_by_id(mm: &ModelManager, id: i32) -> Result<AppUserRelays> {
         let db = mm.db();
 
@@ -94,7 +141,19 @@ impl AppUserRelaysBmc {
             pubkey: user.pubkey,
             name: user.name,
             dm_type: user.dm_type,
+            note_format: user.note_format,
             federation_id: user.federation_id,
+            batch_payouts: user.batch_payouts,
+            custodial: user.custodial,
+            forward_to: user.forward_to,
+            notes_expiry_secs: user.notes_expiry_secs,
+            jid: user.jid,
+            notify_room: user.notify_room,
+            fallback_dm_types: user
+                .fallback_dm_types
+                .map(|types| types.split(',').map(|t| t.to_string()).collect())
+                .unwrap_or_default(),
+            nwc_pubkey: user.nwc_pubkey,
             relays: relays
                 .into_iter()
                 .map(|relay| relay.relay.to_string())
@@ -104,6 +163,7 @@ impl AppUserRelaysBmc {
         Ok(userrelays)
     }
 
+    #[instrument(skip(mm, field))]
     pub async fn get_by(
         mm: &ModelManager,
         field: NameOrPubkey,
@@ -131,7 +191,19 @@ impl AppUserRelaysBmc {
             pubkey: user.pubkey,
             name: user.name,
             dm_type: user.dm_type,
+            note_format: user.note_format,
             federation_id: user.federation_id,
+            batch_payouts: user.batch_payouts,
+            custodial: user.custodial,
+            forward_to: user.forward_to,
+            notes_expiry_secs: user.notes_expiry_secs,
+            jid: user.jid,
+            notify_room: user.notify_room,
+            fallback_dm_types: user
+                .fallback_dm_types
+                .map(|types| types.split(',').map(|t| t.to_string()).collect())
+                .unwrap_or_default(),
+            nwc_pubkey: user.nwc_pubkey,
             relays: relays
                 .into_iter()
                 .map(|relay| relay.relay.to_string())
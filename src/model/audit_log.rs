@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "id, actor, action, target, details, request_id, created_at";
+
+/// A security relevant action, see `crate::audit`. Entries can't be changed
+/// or deleted once written.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Who did it, e.g. `admin:<key id>` or `user:<pubkey>`
+    pub actor: String,
+    pub action: String,
+    /// What it was done to, e.g. a username
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+    pub request_id: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntryForCreate {
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub from: Option<OffsetDateTime>,
+    pub to: Option<OffsetDateTime>,
+}
+
+impl AuditFilter {
+    fn push_where(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        qb.push(" WHERE TRUE");
+        if let Some(actor) = self.actor.clone() {
+            qb.push(" AND actor = ").push_bind(actor);
+        }
+        if let Some(action) = self.action.clone() {
+            qb.push(" AND action = ").push_bind(action);
+        }
+        if let Some(target) = self.target.clone() {
+            qb.push(" AND target = ").push_bind(target);
+        }
+        if let Some(from) = self.from {
+            qb.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = self.to {
+            qb.push(" AND created_at < ").push_bind(to);
+        }
+    }
+}
+
+pub struct AuditLogBmc;
+
+impl DbBmc for AuditLogBmc {
+    const TABLE: &'static str = "audit_log";
+}
+
+impl AuditLogBmc {
+    #[instrument(skip(mm, entry), fields(action = %entry.action))]
+    pub async fn create(mm: &ModelManager, entry: AuditEntryForCreate) -> Result<i64> {
+        let (id,) = sqlx::query_as(&format!(
+            "INSERT INTO {} (actor, action, target, details, request_id) \
+                VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            Self::TABLE
+        ))
+        .bind(entry.actor)
+        .bind(entry.action)
+        .bind(entry.target)
+        .bind(entry.details)
+        .bind(entry.request_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Entries matching the filter, newest first, along with the total match count
+    #[instrument(skip(mm))]
+    pub async fn list(
+        mm: &ModelManager,
+        filter: &AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<AuditEntry>, i64)> {
+        let mut qb = QueryBuilder::new(format!("SELECT {COLUMNS} FROM {}", Self::TABLE));
+        filter.push_where(&mut qb);
+        qb.push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+        let entries = qb.build_query_as::<AuditEntry>().fetch_all(mm.db()).await?;
+
+        let mut qb = QueryBuilder::new(format!("SELECT COUNT(*) FROM {}", Self::TABLE));
+        filter.push_where(&mut qb);
+        let (total,) = qb.build_query_as::<(i64,)>().fetch_one(mm.db()).await?;
+
+        Ok((entries, total))
+    }
+}
@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+/// Funds hermes holds for a custodial user in one federation.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Balance {
+    pub app_user_id: i32,
+    pub federation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+pub struct BalanceBmc;
+
+impl DbBmc for BalanceBmc {
+    const TABLE: &'static str = "balance";
+}
+
+impl BalanceBmc {
+    #[instrument(skip(mm))]
+    pub async fn credit(
+        mm: &ModelManager,
+        app_user_id: i32,
+        federation_id: &str,
+        amount: i64,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {0} (app_user_id, federation_id, amount) VALUES ($1, $2, $3) \
+                ON CONFLICT (app_user_id, federation_id) \
+                DO UPDATE SET amount = {0}.amount + EXCLUDED.amount, updated_at = NOW()",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(federation_id)
+        .bind(amount)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Takes `amount` out of a balance, or returns false if it doesn't hold
+    /// that much.
+    #[instrument(skip(mm))]
+    pub async fn debit(
+        mm: &ModelManager,
+        app_user_id: i32,
+        federation_id: &str,
+        amount: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET amount = amount - $3, updated_at = NOW() \
+                WHERE app_user_id = $1 AND federation_id = $2 AND amount >= $3",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(federation_id)
+        .bind(amount)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The user's balance in a federation, zero if they never received there.
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, app_user_id: i32, federation_id: &str) -> Result<i64> {
+        let amount: Option<(i64,)> = sqlx::query_as(&format!(
+            "SELECT amount FROM {} WHERE app_user_id = $1 AND federation_id = $2",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(federation_id)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(amount.map(|(amount,)| amount).unwrap_or_default())
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn list_for_user(mm: &ModelManager, app_user_id: i32) -> Result<Vec<Balance>> {
+        let rows = sqlx::query_as(&format!(
+            "SELECT app_user_id, federation_id, amount, updated_at FROM {} \
+                WHERE app_user_id = $1 ORDER BY federation_id",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows)
+    }
+}
@@ -1,20 +1,39 @@
 #![allow(dead_code)]
-use crate::model::ModelManager;
+use crate::model::{ModelManager, Tx};
 use anyhow::{anyhow, Result};
 use sqlb::HasFields;
-use sqlx::{postgres::PgRow, FromRow};
+use sqlx::{postgres::PgRow, Executor, FromRow, Postgres};
+use tracing::instrument;
 
 pub trait DbBmc {
     const TABLE: &'static str;
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn create<MC, E>(mm: &ModelManager, data: E) -> Result<i32>
 where
     MC: DbBmc,
     E: HasFields,
 {
-    let db = mm.db();
+    insert::<MC, _, _>(mm.db(), data).await
+}
+
+/// `create` as part of a transaction from `ModelManager::begin`.
+#[instrument(skip_all, fields(table = MC::TABLE))]
+pub async fn create_in<MC, E>(tx: &mut Tx, data: E) -> Result<i32>
+where
+    MC: DbBmc,
+    E: HasFields,
+{
+    insert::<MC, _, _>(&mut **tx, data).await
+}
 
+async fn insert<'e, MC, E, X>(db: X, data: E) -> Result<i32>
+where
+    MC: DbBmc,
+    E: HasFields,
+    X: Executor<'e, Database = Postgres>,
+{
     let fields = data.not_none_fields();
     let (id,) = sqlb::insert()
         .table(MC::TABLE)
@@ -26,6 +45,7 @@ where
     Ok(id)
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn get<MC, E>(mm: &ModelManager, id: i32) -> Result<E>
 where
     MC: DbBmc,
@@ -49,6 +69,7 @@ where
     Ok(entity)
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn get_many<MC, E>(mm: &ModelManager, ids: &[i32]) -> Result<Vec<E>>
 where
     MC: DbBmc,
@@ -63,6 +84,7 @@ where
     Ok(entities)
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn list<MC, E>(mm: &ModelManager) -> Result<Vec<E>>
 where
     MC: DbBmc,
@@ -80,6 +102,7 @@ where
     Ok(entities)
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn update<MC, E>(mm: &ModelManager, id: i32, data: E) -> Result<()>
 where
     MC: DbBmc,
@@ -106,6 +129,7 @@ where
     }
 }
 
+#[instrument(skip_all, fields(table = MC::TABLE))]
 pub async fn delete<MC>(mm: &ModelManager, id: i32) -> Result<()>
 where
     MC: DbBmc,
@@ -0,0 +1,144 @@
+#![allow(dead_code)]
+use super::{
+    base::{self, DbBmc},
+    sealed::Sealed,
+    ModelManager,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlb::Fields;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str =
+    "id, invoice_id, channel, operation_id, notes, error, attempts, created_at, resolved_at";
+
+/// A settlement step that failed after the invoice was already marked settled,
+/// kept with enough context to replay it.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DeadLetter {
+    pub id: i32,
+    pub invoice_id: i32,
+    /// Which step failed: `spend_notes`, the user's dm type, or `zap`
+    pub channel: String,
+    pub operation_id: Option<String>,
+    pub notes: Option<Sealed>,
+    pub error: String,
+    pub attempts: i32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub resolved_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct DeadLetterForCreate {
+    pub invoice_id: i32,
+    pub channel: String,
+    pub operation_id: Option<String>,
+    pub notes: Option<Sealed>,
+    pub error: String,
+}
+
+pub struct DeadLetterBmc;
+
+impl DbBmc for DeadLetterBmc {
+    const TABLE: &'static str = "dead_letter";
+}
+
+impl DeadLetterBmc {
+    pub async fn create(mm: &ModelManager, dl_c: DeadLetterForCreate) -> Result<i32> {
+        base::create::<Self, _>(mm, dl_c).await
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, id: i32) -> Result<DeadLetter> {
+        sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(anyhow!(
+            "Entity not found in table '{}', id: {}",
+            Self::TABLE,
+            id
+        ))
+    }
+
+    /// Oldest first, optionally including entries that were already replayed.
+    #[instrument(skip(mm))]
+    pub async fn list(mm: &ModelManager, include_resolved: bool) -> Result<Vec<DeadLetter>> {
+        let rows = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE $1 OR resolved_at IS NULL ORDER BY created_at",
+            Self::TABLE
+        ))
+        .bind(include_resolved)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// How many entries are waiting to be replayed.
+    #[instrument(skip(mm))]
+    pub async fn count_open(mm: &ModelManager) -> Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM {} WHERE resolved_at IS NULL",
+            Self::TABLE
+        ))
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn mark_resolved(mm: &ModelManager, id: i32) -> Result<DeadLetter> {
+        sqlx::query(&format!(
+            "UPDATE {} SET resolved_at = NOW(), attempts = attempts + 1 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+        Self::get(mm, id).await
+    }
+
+    /// Resolves an invoice's open entries for a channel, e.g. once the payer
+    /// was refunded instead.
+    #[instrument(skip(mm))]
+    pub async fn resolve_for_invoice(
+        mm: &ModelManager,
+        invoice_id: i32,
+        channel: &str,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET resolved_at = NOW() \
+                WHERE invoice_id = $1 AND channel = $2 AND resolved_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(invoice_id)
+        .bind(channel)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records another failed replay.
+    #[instrument(skip(mm))]
+    pub async fn record_failure(mm: &ModelManager, id: i32, error: &str) -> Result<DeadLetter> {
+        sqlx::query(&format!(
+            "UPDATE {} SET error = $2, attempts = attempts + 1 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(error)
+        .execute(mm.db())
+        .await?;
+        Self::get(mm, id).await
+    }
+}
@@ -0,0 +1,196 @@
+#![allow(dead_code)]
+use super::{
+    base::{self, DbBmc},
+    sealed::Sealed,
+    ModelManager,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::Fields;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "id, app_user_id, invoice_id, payout_batch_id, operation_id, amount, \
+    note_format, notes, created_at, claimed_at, reissued_at, reissue_federation_id, reissue_msats";
+/// `COLUMNS` qualified for updates joined with `app_user`.
+const RETURNING: &str = "ecash_payout.id, app_user_id, invoice_id, payout_batch_id, \
+    operation_id, amount, ecash_payout.note_format, notes, created_at, claimed_at, reissued_at, \
+    reissue_federation_id, reissue_msats";
+
+/// Ecash spent for a user, kept so a wallet that missed the DM can still
+/// fetch it.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct EcashPayout {
+    pub id: i32,
+    pub app_user_id: i32,
+    pub invoice_id: Option<i32>,
+    pub payout_batch_id: Option<i32>,
+    pub operation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    pub note_format: String,
+    pub notes: Sealed,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub claimed_at: Option<OffsetDateTime>,
+    /// Set once the reclaimed notes were reissued, possibly in another
+    /// federation
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub reissued_at: Option<OffsetDateTime>,
+    /// Federation a reissue moved the funds towards before failing, left for
+    /// an operator
+    pub reissue_federation_id: Option<String>,
+    /// What arrived there, in millisatoshis, or `None` if it's unknown
+    /// whether the transfer went through
+    pub reissue_msats: Option<i64>,
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct EcashPayoutForCreate {
+    pub app_user_id: i32,
+    pub invoice_id: Option<i32>,
+    pub payout_batch_id: Option<i32>,
+    pub operation_id: String,
+    pub amount: i64,
+    pub note_format: String,
+    pub notes: Sealed,
+}
+
+pub struct EcashPayoutBmc;
+
+impl DbBmc for EcashPayoutBmc {
+    const TABLE: &'static str = "ecash_payout";
+}
+
+impl EcashPayoutBmc {
+    pub async fn create(mm: &ModelManager, payout_c: EcashPayoutForCreate) -> Result<i32> {
+        base::create::<Self, _>(mm, payout_c).await
+    }
+
+    /// Marks every unclaimed payout for the users registered with `pubkey` as
+    /// claimed and returns them, oldest first. Payouts older than the user's
+    /// notes expiry, or `default_expiry_secs`, were reclaimed and are skipped.
+    #[instrument(skip(mm))]
+    pub async fn claim_all(
+        mm: &ModelManager,
+        pubkey: &str,
+        default_expiry_secs: i64,
+    ) -> Result<Vec<EcashPayout>> {
+        let mut rows: Vec<EcashPayout> = sqlx::query_as(&format!(
+            "UPDATE {0} SET claimed_at = NOW() FROM app_user \
+                WHERE app_user.id = {0}.app_user_id AND app_user.pubkey = $1 \
+                AND {0}.claimed_at IS NULL AND {0}.created_at > NOW() \
+                - make_interval(secs => COALESCE(app_user.notes_expiry_secs, $2)) \
+                RETURNING {RETURNING}",
+            Self::TABLE
+        ))
+        .bind(pubkey)
+        .bind(default_expiry_secs)
+        .fetch_all(mm.db())
+        .await?;
+        rows.sort_by_key(|payout| payout.created_at);
+
+        Ok(rows)
+    }
+
+    /// A payout by its spend operation, if it went to a user registered with
+    /// `pubkey`.
+    #[instrument(skip(mm))]
+    pub async fn get_for_pubkey(
+        mm: &ModelManager,
+        pubkey: &str,
+        operation_id: &str,
+    ) -> Result<Option<EcashPayout>> {
+        let payout = sqlx::query_as(&format!(
+            "SELECT {RETURNING} FROM {0} JOIN app_user ON app_user.id = {0}.app_user_id \
+                WHERE app_user.pubkey = $1 AND {0}.operation_id = $2",
+            Self::TABLE
+        ))
+        .bind(pubkey)
+        .bind(operation_id)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(payout)
+    }
+
+    /// Marks a payout reissued, or returns false if it already was.
+    #[instrument(skip(mm))]
+    pub async fn start_reissue(mm: &ModelManager, id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET reissued_at = NOW() WHERE id = $1 AND reissued_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// A payout by its spend operation.
+    #[instrument(skip(mm))]
+    pub async fn get_by_operation_id(mm: &ModelManager, operation_id: &str) -> Result<EcashPayout> {
+        let payout = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE operation_id = $1",
+            Self::TABLE
+        ))
+        .bind(operation_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(payout)
+    }
+
+    /// Records a reissue whose funds left the source federation without
+    /// reaching the user, it stays marked reissued so it isn't paid twice.
+    #[instrument(skip(mm))]
+    pub async fn mark_reissue_stuck(
+        mm: &ModelManager,
+        id: i32,
+        federation_id: &str,
+        received_msats: Option<i64>,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET reissue_federation_id = $2, reissue_msats = $3 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(federation_id)
+        .bind(received_msats)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Clears a stuck reissue once it was finished.
+    #[instrument(skip(mm))]
+    pub async fn clear_reissue_stuck(mm: &ModelManager, id: i32) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET reissue_federation_id = NULL, reissue_msats = NULL WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Lets a payout be reissued again after an attempt that moved no funds.
+    #[instrument(skip(mm))]
+    pub async fn undo_reissue(mm: &ModelManager, id: i32) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET reissued_at = NULL WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlb::HasFields;
+use sqlx::{postgres::PgRow, FromRow};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::instrument;
+
+use super::{
+    app_user::AppUser,
+    app_user_relays::AppUserRelay,
+    invoice::{Invoice, InvoiceWithTimestamp},
+    relay::Relay,
+    zap::Zap,
+    ModelManager, Tx,
+};
+use crate::export::ExportWriter;
+
+pub struct ExportBmc;
+
+impl ExportBmc {
+    /// Writes every table as one JSON object, read in one repeatable read
+    /// transaction so the export is consistent. Rows are written as they're
+    /// read, used for backups.
+    #[instrument(skip_all)]
+    pub async fn snapshot(mm: &ModelManager, out: &mut ExportWriter) -> Result<()> {
+        let mut tx = mm.db().begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let created_at = serde_json::to_string(&OffsetDateTime::now_utc().format(&Rfc3339)?)?;
+        out.raw(format!("{{\"created_at\":{created_at},\"app_users\":").as_bytes())
+            .await?;
+        write_table::<AppUser>(
+            &mut tx,
+            &select_all(AppUser::field_names(), "app_user"),
+            out,
+        )
+        .await?;
+        out.raw(b",\"relays\":").await?;
+        write_table::<Relay>(&mut tx, &select_all(Relay::field_names(), "relay"), out).await?;
+        out.raw(b",\"app_user_relays\":").await?;
+        write_table::<AppUserRelay>(
+            &mut tx,
+            &select_all(AppUserRelay::field_names(), "app_user_relays"),
+            out,
+        )
+        .await?;
+        out.raw(b",\"invoices\":").await?;
+        write_table::<InvoiceWithTimestamp>(
+            &mut tx,
+            &format!(
+                "SELECT {}, created_at, settled_at FROM invoice ORDER BY id",
+                Invoice::field_names().join(", ")
+            ),
+            out,
+        )
+        .await?;
+        out.raw(b",\"zaps\":").await?;
+        write_table::<Zap>(&mut tx, &select_all(Zap::field_names(), "zaps"), out).await?;
+        out.raw(b"}").await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Writes the rows of `sql` as a JSON array.
+async fn write_table<T>(tx: &mut Tx, sql: &str, out: &mut ExportWriter) -> Result<()>
+where
+    T: for<'r> FromRow<'r, PgRow> + Serialize + Send + Unpin,
+{
+    out.begin_array().await?;
+    let mut rows = sqlx::query_as::<_, T>(sql).fetch(&mut **tx);
+    while let Some(row) = rows.try_next().await? {
+        out.row(&row).await?;
+    }
+    out.end_array().await
+}
+
+fn select_all(fields: &[&str], table: &str) -> String {
+    format!("SELECT {} FROM {}", fields.join(", "), table)
+}
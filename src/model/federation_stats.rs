@@ -0,0 +1,95 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::FromRow;
+use tracing::instrument;
+
+const COLUMNS: &str = "federation_id, to_char(day, 'YYYY-MM-DD') AS day, settled_count, \
+    settled_msats, gateway_fee_msats, notes_issued_count, notes_issued_msats";
+
+/// What went through a federation on one day (UTC), in millisatoshis.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FederationStats {
+    pub federation_id: String,
+    /// `YYYY-MM-DD`
+    pub day: String,
+    /// Invoices paid to users
+    pub settled_count: i64,
+    pub settled_msats: i64,
+    /// Lightning fees paid to gateways for refunds, withdrawals and transfers
+    pub gateway_fee_msats: i64,
+    /// Ecash spent out of the federation's client to users
+    pub notes_issued_count: i64,
+    pub notes_issued_msats: i64,
+}
+
+/// Added to today's row of a federation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FederationStatsDelta {
+    pub settled_count: i64,
+    pub settled_msats: i64,
+    pub gateway_fee_msats: i64,
+    pub notes_issued_count: i64,
+    pub notes_issued_msats: i64,
+}
+
+pub struct FederationStatsBmc;
+
+impl DbBmc for FederationStatsBmc {
+    const TABLE: &'static str = "federation_stats";
+}
+
+impl FederationStatsBmc {
+    #[instrument(skip(mm))]
+    pub async fn record(
+        mm: &ModelManager,
+        federation_id: &str,
+        delta: FederationStatsDelta,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {0} (federation_id, day, settled_count, settled_msats, \
+                gateway_fee_msats, notes_issued_count, notes_issued_msats) \
+                VALUES ($1, (NOW() AT TIME ZONE 'UTC')::date, $2, $3, $4, $5, $6) \
+                ON CONFLICT (federation_id, day) DO UPDATE SET \
+                settled_count = {0}.settled_count + EXCLUDED.settled_count, \
+                settled_msats = {0}.settled_msats + EXCLUDED.settled_msats, \
+                gateway_fee_msats = {0}.gateway_fee_msats + EXCLUDED.gateway_fee_msats, \
+                notes_issued_count = {0}.notes_issued_count + EXCLUDED.notes_issued_count, \
+                notes_issued_msats = {0}.notes_issued_msats + EXCLUDED.notes_issued_msats",
+            Self::TABLE
+        ))
+        .bind(federation_id)
+        .bind(delta.settled_count)
+        .bind(delta.settled_msats)
+        .bind(delta.gateway_fee_msats)
+        .bind(delta.notes_issued_count)
+        .bind(delta.notes_issued_msats)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// The last `days` days of stats, newest first, for one federation or all.
+    #[instrument(skip(mm))]
+    pub async fn list(
+        mm: &ModelManager,
+        federation_id: Option<&str>,
+        days: i32,
+    ) -> Result<Vec<FederationStats>> {
+        let stats = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE ($1::TEXT IS NULL OR federation_id = $1) \
+                AND day > (NOW() AT TIME ZONE 'UTC')::date - $2 \
+                ORDER BY day DESC, federation_id",
+            Self::TABLE
+        ))
+        .bind(federation_id)
+        .bind(days)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(stats)
+    }
+}
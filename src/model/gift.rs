@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+use super::{
+    base::{self, DbBmc},
+    sealed::Sealed,
+    ModelManager,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::Fields;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str =
+    "id, app_user_id, invoice_id, token_hash, operation_id, amount, notes, created_at, claimed_at";
+/// `COLUMNS` qualified for updates joined with `app_user`.
+const RETURNING: &str = "gift.id, app_user_id, invoice_id, token_hash, operation_id, amount, \
+    notes, created_at, claimed_at";
+
+/// Ecash for a paid invoice waiting behind a one-time claim link. Only the
+/// sha256 hash of the link's token is kept.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Gift {
+    pub id: i32,
+    pub app_user_id: i32,
+    pub invoice_id: i32,
+    pub token_hash: String,
+    pub operation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    pub notes: Sealed,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub claimed_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct GiftForCreate {
+    pub app_user_id: i32,
+    pub invoice_id: i32,
+    pub token_hash: String,
+    pub operation_id: String,
+    pub amount: i64,
+    pub notes: Sealed,
+}
+
+pub struct GiftBmc;
+
+impl DbBmc for GiftBmc {
+    const TABLE: &'static str = "gift";
+}
+
+impl GiftBmc {
+    pub async fn create(mm: &ModelManager, gift_c: GiftForCreate) -> Result<i32> {
+        base::create::<Self, _>(mm, gift_c).await
+    }
+
+    /// Marks the gift for a token hash claimed and returns it, or `None` if
+    /// there is no such gift, it was already claimed, or its notes expired
+    /// after the user's notes expiry, or `default_expiry_secs`.
+    #[instrument(skip_all)]
+    pub async fn claim(
+        mm: &ModelManager,
+        token_hash: &str,
+        default_expiry_secs: i64,
+    ) -> Result<Option<Gift>> {
+        let gift = sqlx::query_as(&format!(
+            "UPDATE {0} SET claimed_at = NOW() FROM app_user \
+                WHERE app_user.id = {0}.app_user_id AND token_hash = $1 \
+                AND claimed_at IS NULL AND created_at > NOW() \
+                - make_interval(secs => COALESCE(app_user.notes_expiry_secs, $2)) \
+                RETURNING {RETURNING}",
+            Self::TABLE
+        ))
+        .bind(token_hash)
+        .bind(default_expiry_secs)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(gift)
+    }
+}
@@ -1,13 +1,23 @@
 #![allow(dead_code)]
 use super::{
     base::{self, DbBmc},
-    ModelManager,
+    ModelManager, Tx,
+};
+use crate::export::{invoice_csv_record, ExportFormat, ExportWriter, INVOICE_CSV_HEADER};
+use crate::model::{
+    invoice_archive::{ArchivedInvoice, InvoiceArchiveBmc},
+    invoice_event::{InvoiceEventBmc, InvoiceEventSource},
+    invoice_state::InvoiceState,
+    sealed::Sealed,
+    zap::{Zap, ZapBmc},
 };
-use crate::model::invoice_state::InvoiceState;
 use anyhow::{anyhow, Result};
-use serde::Serialize;
-use sqlb::{Fields, HasFields};
-use sqlx::FromRow;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlb::Fields;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct Invoice {
@@ -16,8 +26,83 @@ pub struct Invoice {
     pub op_id: String,
     pub app_user_id: i32,
     pub bolt11: String,
+    pub payment_hash: Option<String>,
     pub amount: i64,
     pub state: InvoiceState,
+    pub request_id: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub payer_pubkey: Option<Sealed>,
+    /// The batch a settled invoice is paid out in, for users with batched payouts
+    pub payout_batch_id: Option<i32>,
+    /// LUD-12 comment from the payer, already moderated
+    pub comment: Option<Sealed>,
+    /// LUD-18 payer identity as json, only the fields the pay request asks for
+    pub payer_data: Option<Sealed>,
+    /// Hex preimage, known once the invoice settled
+    pub preimage: Option<String>,
+    /// Gateway the invoice was routed through
+    pub gateway_id: Option<String>,
+    /// What the gateway charges for routing the invoice, in millisatoshis
+    pub gateway_fee_msats: Option<i64>,
+}
+
+/// Invoice along with its creation and settlement times, used for listings.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct InvoiceWithTimestamp {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub invoice: Invoice,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub settled_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InvoiceFilter {
+    pub state: Option<InvoiceState>,
+    pub federation_id: Option<String>,
+    pub app_user_id: Option<i32>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    /// Only soft deleted invoices instead of live ones
+    #[serde(default)]
+    pub deleted: bool,
+    /// Search `invoice_archive` instead of the invoice table
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl InvoiceFilter {
+    fn table(&self) -> &'static str {
+        if self.archived {
+            InvoiceArchiveBmc::TABLE
+        } else {
+            InvoiceBmc::TABLE
+        }
+    }
+
+    fn push_where(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        qb.push(" WHERE (deleted_at IS NOT NULL) = ")
+            .push_bind(self.deleted);
+        if let Some(state) = self.state {
+            qb.push(" AND state = ").push_bind(state);
+        }
+        if let Some(federation_id) = self.federation_id.clone() {
+            qb.push(" AND federation_id = ").push_bind(federation_id);
+        }
+        if let Some(app_user_id) = self.app_user_id {
+            qb.push(" AND app_user_id = ").push_bind(app_user_id);
+        }
+        if let Some(from) = self.from {
+            qb.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = self.to {
+            qb.push(" AND created_at < ").push_bind(to);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
@@ -26,7 +111,15 @@ pub struct InvoiceForCreate {
     pub federation_id: String,
     pub app_user_id: i32,
     pub bolt11: String,
+    pub payment_hash: Option<String>,
     pub amount: i64,
+    pub request_id: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub payer_pubkey: Option<Sealed>,
+    pub comment: Option<Sealed>,
+    pub payer_data: Option<Sealed>,
+    pub gateway_id: Option<String>,
+    pub gateway_fee_msats: Option<i64>,
 }
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
@@ -45,22 +138,181 @@ impl InvoiceBmc {
         base::create::<Self, _>(mm, inv_c).await
     }
 
+    pub async fn create_in(tx: &mut Tx, inv_c: InvoiceForCreate) -> Result<i32> {
+        base::create_in::<Self, _>(tx, inv_c).await
+    }
+
+    /// Creates an invoice along with its zap request, if it is for a zap, so
+    /// neither is left without the other. Runs in a savepoint, so a failure
+    /// leaves the rest of `tx` usable.
+    #[instrument(skip_all)]
+    pub async fn create_with_zap_in(
+        tx: &mut Tx,
+        inv_c: InvoiceForCreate,
+        zap_request: Option<String>,
+    ) -> Result<i32> {
+        sqlx::query("SAVEPOINT create_invoice")
+            .execute(&mut **tx)
+            .await?;
+        let created = async {
+            let id = Self::create_in(tx, inv_c).await?;
+            if let Some(request) = zap_request {
+                ZapBmc::create_in(
+                    tx,
+                    Zap {
+                        id,
+                        request: request.into(),
+                        event_id: None,
+                        receipt: None,
+                    },
+                )
+                .await?;
+            }
+            Ok::<_, anyhow::Error>(id)
+        }
+        .await;
+        let release = match created {
+            Ok(_) => "RELEASE SAVEPOINT create_invoice",
+            Err(_) => "ROLLBACK TO SAVEPOINT create_invoice",
+        };
+        sqlx::query(release).execute(&mut **tx).await?;
+
+        created
+    }
+
     pub async fn get(mm: &ModelManager, id: i32) -> Result<Invoice> {
         base::get::<Self, _>(mm, id).await
     }
 
+    #[instrument(skip(mm))]
     pub async fn get_by_op_id(mm: &ModelManager, op_id: &str) -> Result<Invoice> {
-        let inv: Invoice = sqlb::select()
+        let query = format!(
+            "SELECT {} FROM {} WHERE op_id = $1 AND deleted_at IS NULL",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        );
+        let inv: Invoice = mm
+            .read_or_primary(|db| sqlx::query_as(&query).bind(op_id).fetch_optional(db))
+            .await?
+            .ok_or(anyhow!("No invoice found with op_id: {}", op_id))?;
+        Ok(inv)
+    }
+
+    /// Get an invoice whose verify url predates verify tokens, while it's
+    /// still within a day, the invoice expiry, and hasn't expired or been
+    /// cancelled
+    #[instrument(skip(mm))]
+    pub async fn get_legacy_verify(mm: &ModelManager, op_id: &str) -> Result<Option<Invoice>> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE op_id = $1 AND deleted_at IS NULL AND state IN ($2, $3) \
+             AND created_at > now() - interval '1 day' \
+             AND id IN (SELECT invoice_id FROM legacy_verify)",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        );
+        let inv = mm
+            .read_or_primary(|db| {
+                sqlx::query_as(&query)
+                    .bind(op_id)
+                    .bind(InvoiceState::Pending)
+                    .bind(InvoiceState::Settled)
+                    .fetch_optional(db)
+            })
+            .await?;
+        Ok(inv)
+    }
+
+    /// Get the invoice a user's callback created for an idempotency key, if any
+    #[instrument(skip(mm))]
+    pub async fn get_by_idempotency_key(
+        mm: &ModelManager,
+        app_user_id: i32,
+        key: &str,
+    ) -> Result<Option<Invoice>> {
+        let inv = sqlb::select()
             .table(Self::TABLE)
             .columns(Invoice::field_names())
-            .and_where("op_id", "=", op_id)
+            .and_where("app_user_id", "=", app_user_id)
+            .and_where("idempotency_key", "=", key)
             .fetch_optional(mm.db())
-            .await?
-            .ok_or(anyhow!("No invoice found with op_id: {}", op_id))?;
+            .await?;
         Ok(inv)
     }
 
+    /// Get one of a user's invoices by its payment hash
+    #[instrument(skip(mm))]
+    pub async fn get_by_payment_hash(
+        mm: &ModelManager,
+        app_user_id: i32,
+        payment_hash: &str,
+    ) -> Result<Option<Invoice>> {
+        let inv = sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE app_user_id = $1 AND payment_hash = $2 \
+                AND deleted_at IS NULL",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(payment_hash)
+        .fetch_optional(mm.db())
+        .await?;
+        Ok(inv)
+    }
+
+    /// Any user's invoice by its payment hash, for wallets and support who
+    /// only have the invoice
+    #[instrument(skip(mm))]
+    pub async fn find_by_payment_hash(
+        mm: &ModelManager,
+        payment_hash: &str,
+    ) -> Result<Option<Invoice>> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE payment_hash = $1 AND deleted_at IS NULL LIMIT 1",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        );
+        mm.read_or_primary(|db| sqlx::query_as(&query).bind(payment_hash).fetch_optional(db))
+            .await
+    }
+
+    /// The invoices with any of `op_ids` or `payment_hashes`, in no
+    /// particular order, for wallets syncing many at once. Reads the primary
+    /// since some could be missing from a replica.
+    #[instrument(skip_all, fields(op_ids = op_ids.len(), payment_hashes = payment_hashes.len()))]
+    pub async fn find_many(
+        mm: &ModelManager,
+        op_ids: &[String],
+        payment_hashes: &[String],
+    ) -> Result<Vec<Invoice>> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE (op_id = ANY($1) OR payment_hash = ANY($2)) \
+                AND deleted_at IS NULL",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        );
+        let invoices = sqlx::query_as(&query)
+            .bind(op_ids)
+            .bind(payment_hashes)
+            .fetch_all(mm.db())
+            .await?;
+        Ok(invoices)
+    }
+
+    /// Any user's invoice by its BOLT11 string, as hermes encoded it. Also
+    /// finds invoices from before payment hashes were stored.
+    #[instrument(skip(mm))]
+    pub async fn find_by_bolt11(mm: &ModelManager, bolt11: &str) -> Result<Option<Invoice>> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE bolt11 = $1 AND deleted_at IS NULL",
+            Invoice::field_names().join(", "),
+            Self::TABLE
+        );
+        mm.read_or_primary(|db| sqlx::query_as(&query).bind(bolt11).fetch_optional(db))
+            .await
+    }
+
     /// Get all pending invoices
+    #[instrument(skip(mm))]
     pub async fn get_pending(mm: &ModelManager) -> Result<Vec<Invoice>> {
         let rows = sqlb::select()
             .table(Self::TABLE)
@@ -72,13 +324,192 @@ impl InvoiceBmc {
         Ok(rows)
     }
 
+    /// Pending invoices in a federation and pending invoices of a user
+    #[instrument(skip(mm))]
+    pub async fn count_pending(
+        mm: &ModelManager,
+        federation_id: &str,
+        app_user_id: i32,
+    ) -> Result<(i64, i64)> {
+        let counts = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FILTER (WHERE federation_id = $1), \
+                COUNT(*) FILTER (WHERE app_user_id = $2) FROM {} WHERE state = $3",
+            Self::TABLE
+        ))
+        .bind(federation_id)
+        .bind(app_user_id)
+        .bind(InvoiceState::Pending)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// List invoices matching the filter, newest first, along with the total match count
+    #[instrument(skip(mm))]
+    pub async fn list(
+        mm: &ModelManager,
+        filter: &InvoiceFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<InvoiceWithTimestamp>, i64)> {
+        let mut qb = QueryBuilder::new(format!(
+            "SELECT {}, created_at, settled_at FROM {}",
+            Invoice::field_names().join(", "),
+            filter.table()
+        ));
+        filter.push_where(&mut qb);
+        qb.push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+        let invoices = qb
+            .build_query_as::<InvoiceWithTimestamp>()
+            .fetch_all(mm.db_read())
+            .await?;
+
+        let mut qb = QueryBuilder::new(format!("SELECT COUNT(*) FROM {}", filter.table()));
+        filter.push_where(&mut qb);
+        let (total,) = qb
+            .build_query_as::<(i64,)>()
+            .fetch_one(mm.db_read())
+            .await?;
+
+        Ok((invoices, total))
+    }
+
+    /// Writes every invoice matching the filter to `out` as they're read,
+    /// oldest first, as a JSON array or CSV file.
+    #[instrument(skip(mm, out))]
+    pub async fn export(
+        mm: &ModelManager,
+        filter: &InvoiceFilter,
+        out: &mut ExportWriter,
+    ) -> Result<()> {
+        let mut qb = QueryBuilder::new(format!(
+            "SELECT {}, created_at, settled_at, deleted_at FROM {}",
+            Invoice::field_names().join(", "),
+            filter.table()
+        ));
+        filter.push_where(&mut qb);
+        qb.push(" ORDER BY id");
+        let mut invoices = qb.build_query_as::<ArchivedInvoice>().fetch(mm.db_read());
+        out.begin_array().await?;
+        while let Some(row) = invoices.try_next().await? {
+            match out.format() {
+                ExportFormat::Json => out.row(&row).await?,
+                ExportFormat::Csv => {
+                    let record = invoice_csv_record(
+                        &row.invoice,
+                        row.created_at,
+                        row.settled_at,
+                        row.deleted_at,
+                    )?;
+                    out.record(&INVOICE_CSV_HEADER, record).await?
+                }
+            }
+        }
+        out.end_array().await
+    }
+
+    /// Count and total amount of invoices in each state
+    #[instrument(skip(mm))]
+    pub async fn totals_by_state(mm: &ModelManager) -> Result<Vec<(InvoiceState, i64, i64)>> {
+        let query = format!(
+            "SELECT state, COUNT(*), COALESCE(SUM(amount), 0)::BIGINT FROM {} GROUP BY state",
+            Self::TABLE
+        );
+        let rows = sqlx::query_as(&query).fetch_all(mm.db_read()).await?;
+
+        Ok(rows)
+    }
+
     pub async fn set_state(mm: &ModelManager, id: i32, state: InvoiceState) -> Result<Invoice> {
         let inv_u = InvoiceForUpdate { state };
         base::update::<Self, _>(mm, id, inv_u).await?;
         Self::get(mm, id).await
     }
 
-    pub async fn delete(mm: &ModelManager, id: i32) -> Result<()> {
-        base::delete::<Self>(mm, id).await
+    /// Moves an invoice from one state to another, recording the transition
+    /// in the same statement and stamping `settled_at` when it settles. Returns `None` if the invoice was no longer in
+    /// `from`, so concurrent callers finish it once.
+    #[instrument(skip(mm))]
+    pub async fn transition(
+        mm: &ModelManager,
+        id: i32,
+        from: InvoiceState,
+        to: InvoiceState,
+        source: InvoiceEventSource,
+    ) -> Result<Option<Invoice>> {
+        let moved: Option<(i32,)> = sqlx::query_as(&format!(
+            "WITH moved AS ( \
+                UPDATE {} SET state = $3, \
+                    settled_at = CASE WHEN $3 = $6 THEN NOW() ELSE settled_at END \
+                WHERE id = $1 AND state = $2 RETURNING id \
+            ) \
+            INSERT INTO {} (invoice_id, event, from_state, to_state, source) \
+                SELECT id, $4, $2, $3, $5 FROM moved RETURNING invoice_id",
+            Self::TABLE,
+            InvoiceEventBmc::TABLE
+        ))
+        .bind(id)
+        .bind(from)
+        .bind(to)
+        .bind(to.event_name())
+        .bind(source.to_string())
+        .bind(InvoiceState::Settled)
+        .fetch_optional(mm.db())
+        .await?;
+
+        if moved.is_none() {
+            return Ok(None);
+        }
+        Self::get(mm, id).await.map(Some)
+    }
+
+    /// Stores the preimage of a settled invoice
+    #[instrument(skip(mm))]
+    pub async fn set_preimage(mm: &ModelManager, id: i32, preimage: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET preimage = $2 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(preimage)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Soft deletes an invoice, hiding it from lookups by operation id or
+    /// payment hash and from listings. Its zap, payouts and events are kept,
+    /// and a pending one is still settled if paid. Returns `false` if it was
+    /// already deleted.
+    #[instrument(skip(mm))]
+    pub async fn delete(mm: &ModelManager, id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Undoes `delete`. Returns `false` if the invoice wasn't deleted.
+    #[instrument(skip(mm))]
+    pub async fn restore(mm: &ModelManager, id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "UPDATE {} SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
     }
 }
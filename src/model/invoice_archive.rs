@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+use super::{
+    base::DbBmc,
+    invoice::{Invoice, InvoiceBmc},
+    invoice_state::InvoiceState,
+    ModelManager,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::HasFields;
+use sqlx::FromRow;
+use std::future::Future;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+/// Finished invoices created more than `$2` days ago that nothing still
+/// waits on: no zap left in `zaps` (zap retention moves those out), no open
+/// dead letter, refund, gift, ecash payout or undelivered payout batch.
+const ARCHIVABLE: &str = "state <> $1 AND created_at < NOW() - make_interval(days => $2) \
+    AND NOT EXISTS (SELECT 1 FROM zaps z WHERE z.id = invoice.id) \
+    AND NOT EXISTS (SELECT 1 FROM dead_letter d \
+        WHERE d.invoice_id = invoice.id AND d.resolved_at IS NULL) \
+    AND NOT EXISTS (SELECT 1 FROM refund r \
+        WHERE r.invoice_id = invoice.id AND r.claimed_at IS NULL AND r.voided_at IS NULL) \
+    AND NOT EXISTS (SELECT 1 FROM gift g \
+        WHERE g.invoice_id = invoice.id AND g.claimed_at IS NULL) \
+    AND NOT EXISTS (SELECT 1 FROM ecash_payout e \
+        WHERE e.invoice_id = invoice.id AND e.claimed_at IS NULL) \
+    AND (payout_batch_id IS NULL OR EXISTS (SELECT 1 FROM payout_batch b \
+        WHERE b.id = invoice.payout_batch_id AND b.delivered_at IS NOT NULL))";
+
+/// An invoice with every column, as it leaves the invoice table.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct ArchivedInvoice {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub invoice: Invoice,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub settled_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub deleted_at: Option<OffsetDateTime>,
+}
+
+pub struct InvoiceArchiveBmc;
+
+impl DbBmc for InvoiceArchiveBmc {
+    const TABLE: &'static str = "invoice_archive";
+}
+
+impl InvoiceArchiveBmc {
+    fn columns() -> String {
+        format!(
+            "{}, created_at, settled_at, deleted_at",
+            Invoice::field_names().join(", ")
+        )
+    }
+
+    /// Moves up to `limit` archivable invoices older than `days` days into
+    /// `invoice_archive`. Returns how many were moved.
+    #[instrument(skip(mm))]
+    pub async fn archive(mm: &ModelManager, days: i32, limit: i64) -> Result<u64> {
+        let result = sqlx::query(&format!(
+            "WITH moved AS ( \
+                DELETE FROM {invoice} WHERE id IN ( \
+                    SELECT id FROM {invoice} WHERE {ARCHIVABLE} ORDER BY id LIMIT $3 \
+                ) RETURNING {columns} \
+            ) \
+            INSERT INTO {archive} ({columns}) SELECT {columns} FROM moved",
+            invoice = InvoiceBmc::TABLE,
+            archive = Self::TABLE,
+            columns = Self::columns(),
+        ))
+        .bind(InvoiceState::Pending)
+        .bind(days)
+        .bind(limit)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Removes up to `limit` archivable invoices older than `days` days from
+    /// the database, handing them to `write` first. They're only removed if
+    /// `write` succeeds. Returns how many were removed.
+    #[instrument(skip(mm, write))]
+    pub async fn export<F, Fut>(mm: &ModelManager, days: i32, limit: i64, write: F) -> Result<usize>
+    where
+        F: FnOnce(Vec<ArchivedInvoice>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut tx = mm.db().begin().await?;
+
+        let invoices: Vec<ArchivedInvoice> = sqlx::query_as(&format!(
+            "DELETE FROM {invoice} WHERE id IN ( \
+                SELECT id FROM {invoice} WHERE {ARCHIVABLE} ORDER BY id LIMIT $3 \
+            ) RETURNING {columns}",
+            invoice = InvoiceBmc::TABLE,
+            columns = Self::columns(),
+        ))
+        .bind(InvoiceState::Pending)
+        .bind(days)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let count = invoices.len();
+        if count > 0 {
+            write(invoices).await?;
+        }
+        tx.commit().await?;
+
+        Ok(count)
+    }
+}
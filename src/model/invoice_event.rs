@@ -0,0 +1,131 @@
+#![allow(dead_code)]
+use std::fmt;
+
+use super::{base::DbBmc, invoice_state::InvoiceState, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str =
+    "id, invoice_id, event, from_state, to_state, source, detail, request_id, created_at";
+
+/// What moved an invoice along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceEventSource {
+    /// The LNURL callback creating it
+    Callback,
+    /// The federation subscription watching it
+    Subscription,
+    /// The job expiring unpaid invoices
+    Sweeper,
+    /// The job catching up on missed subscription updates
+    Reconciler,
+    /// An operator, e.g. replaying a dead letter
+    Admin,
+}
+
+impl fmt::Display for InvoiceEventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            InvoiceEventSource::Callback => "callback",
+            InvoiceEventSource::Subscription => "subscription",
+            InvoiceEventSource::Sweeper => "sweeper",
+            InvoiceEventSource::Reconciler => "reconciler",
+            InvoiceEventSource::Admin => "admin",
+        };
+        f.write_str(source)
+    }
+}
+
+/// A step in an invoice's life: a state transition, with `from_state` and
+/// `to_state`, or a settlement step like `delivered` or `dead_lettered`.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceEvent {
+    pub id: i64,
+    pub invoice_id: i32,
+    pub event: String,
+    pub from_state: Option<InvoiceState>,
+    pub to_state: Option<InvoiceState>,
+    pub source: String,
+    pub detail: Option<String>,
+    pub request_id: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvoiceEventForCreate {
+    pub invoice_id: i32,
+    pub event: String,
+    pub source: InvoiceEventSource,
+    pub detail: Option<String>,
+    pub request_id: Option<String>,
+}
+
+pub struct InvoiceEventBmc;
+
+impl DbBmc for InvoiceEventBmc {
+    const TABLE: &'static str = "invoice_events";
+}
+
+impl InvoiceEventBmc {
+    /// Records a step. State transitions are recorded by
+    /// `InvoiceBmc::transition` along with the transition itself.
+    #[instrument(skip(mm))]
+    pub async fn create(mm: &ModelManager, event_c: InvoiceEventForCreate) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (invoice_id, event, source, detail, request_id) \
+                VALUES ($1, $2, $3, $4, $5)",
+            Self::TABLE
+        ))
+        .bind(event_c.invoice_id)
+        .bind(event_c.event)
+        .bind(event_c.source.to_string())
+        .bind(event_c.detail)
+        .bind(event_c.request_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records several steps with one statement, for the write buffer.
+    #[instrument(skip_all, fields(count = events.len()))]
+    pub async fn create_many(mm: &ModelManager, events: Vec<InvoiceEventForCreate>) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut qb = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (invoice_id, event, source, detail, request_id) ",
+            Self::TABLE
+        ));
+        qb.push_values(events, |mut row, event_c| {
+            row.push_bind(event_c.invoice_id)
+                .push_bind(event_c.event)
+                .push_bind(event_c.source.to_string())
+                .push_bind(event_c.detail)
+                .push_bind(event_c.request_id);
+        });
+        qb.build().execute(mm.db()).await?;
+
+        Ok(())
+    }
+
+    /// Oldest first.
+    #[instrument(skip(mm))]
+    pub async fn list_for_invoice(mm: &ModelManager, invoice_id: i32) -> Result<Vec<InvoiceEvent>> {
+        let events = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE invoice_id = $1 ORDER BY created_at, id",
+            Self::TABLE
+        ))
+        .bind(invoice_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(events)
+    }
+}
@@ -1,15 +1,35 @@
 use serde::{Deserialize, Serialize};
 use sqlb::bindable;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type, ToSchema)]
 #[repr(i32)]
 pub enum InvoiceState {
     /// The invoice is pending payment.
     Pending = 0,
     /// The invoice has been paid and settled.
     Settled = 1,
-    /// The invoice has been cancelled or expired.
+    /// The invoice has been cancelled.
     Cancelled = 2,
+    /// The invoice expired before it was paid.
+    Expired = 3,
+}
+
+impl InvoiceState {
+    /// Whether the invoice can no longer change state.
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, InvoiceState::Pending)
+    }
+
+    /// How moving into this state is named in `invoice_events`.
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            InvoiceState::Pending => "pending",
+            InvoiceState::Settled => "settled",
+            InvoiceState::Cancelled => "cancelled",
+            InvoiceState::Expired => "expired",
+        }
+    }
 }
 
 bindable!(InvoiceState);
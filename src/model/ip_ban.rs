@@ -0,0 +1,92 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "ip, reason, expires_at, created_by, created_at";
+
+/// An ip turned away from the public endpoints, see `crate::reputation`.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpBan {
+    pub ip: String,
+    pub reason: String,
+    /// Never if unset
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    /// The audit log actor that banned it
+    pub created_by: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+impl IpBan {
+    pub fn is_active(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at > OffsetDateTime::now_utc(),
+            None => true,
+        }
+    }
+}
+
+pub struct IpBanBmc;
+
+impl DbBmc for IpBanBmc {
+    const TABLE: &'static str = "ip_ban";
+}
+
+impl IpBanBmc {
+    /// Bans an ip, replacing any earlier ban of it.
+    #[instrument(skip(mm))]
+    pub async fn upsert(
+        mm: &ModelManager,
+        ip: &str,
+        reason: &str,
+        expires_at: Option<OffsetDateTime>,
+        created_by: &str,
+    ) -> Result<IpBan> {
+        let ban = sqlx::query_as(&format!(
+            "INSERT INTO {} (ip, reason, expires_at, created_by) VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (ip) DO UPDATE SET reason = EXCLUDED.reason, \
+                expires_at = EXCLUDED.expires_at, created_by = EXCLUDED.created_by, \
+                created_at = NOW() RETURNING {COLUMNS}",
+            Self::TABLE
+        ))
+        .bind(ip)
+        .bind(reason)
+        .bind(expires_at)
+        .bind(created_by)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(ban)
+    }
+
+    /// Bans that haven't expired, newest first.
+    #[instrument(skip(mm))]
+    pub async fn list_active(mm: &ModelManager) -> Result<Vec<IpBan>> {
+        let rows = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE expires_at IS NULL OR expires_at > NOW() \
+                ORDER BY created_at DESC",
+            Self::TABLE
+        ))
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Lifts a ban, returning false if the ip wasn't banned.
+    #[instrument(skip(mm))]
+    pub async fn delete(mm: &ModelManager, ip: &str) -> Result<bool> {
+        let result = sqlx::query(&format!("DELETE FROM {} WHERE ip = $1", Self::TABLE))
+            .bind(ip)
+            .execute(mm.db())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
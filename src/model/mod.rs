@@ -1,26 +1,118 @@
 pub mod app_user;
 pub mod app_user_relays;
+pub mod audit_log;
+pub mod balance;
 mod base;
+pub mod dead_letter;
+pub mod ecash_payout;
+pub mod export;
+pub mod federation_stats;
+pub mod gift;
 pub mod invoice;
+pub mod invoice_archive;
+pub mod invoice_event;
 pub mod invoice_state;
+pub mod ip_ban;
+pub mod nostr_profile;
+pub mod payout_batch;
+pub mod receive_limit;
+pub mod refund;
 pub mod relay;
-mod store;
+pub mod sealed;
+pub mod stats;
+pub mod store;
+pub mod user_activity;
+pub mod wallet_pairing;
+pub mod webhook;
+pub mod withdrawal;
+pub mod xmpp_challenge;
+pub mod xmpp_outbox;
 pub mod zap;
 
-use crate::model::store::{new_db_pool, Db};
+use crate::model::store::{
+    migrations::{self, SchemaStatus},
+    new_db_pool, new_replica_pool, Db,
+};
 use anyhow::Result;
+use std::future::Future;
+
+/// Whether a model error was caused by a unique constraint, e.g. a concurrent insert.
+pub fn is_unique_violation(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+/// Whether a model error means the row doesn't exist, as opposed to the
+/// database failing.
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::RowNotFound)
+    )
+}
+
+/// A transaction on the primary from `ModelManager::begin`. Dropping it
+/// without committing rolls it back.
+pub type Tx = sqlx::Transaction<'static, sqlx::Postgres>;
 
 #[derive(Clone, Debug)]
 pub struct ModelManager {
     db: Db,
+    /// Serves hot path lookups when `DATABASE_REPLICA_URL` is set
+    replica: Option<Db>,
 }
 
 impl ModelManager {
     /// Constructor
     pub async fn new() -> Result<Self> {
         let db = new_db_pool().await?;
+        let replica = new_replica_pool().await?;
+
+        Ok(ModelManager { db, replica })
+    }
+
+    pub async fn schema_status(&self) -> Result<SchemaStatus> {
+        migrations::status(&self.db).await
+    }
 
-        Ok(ModelManager { db })
+    pub async fn migrate_up(&self) -> Result<()> {
+        migrations::up(&self.db).await
+    }
+
+    pub async fn migrate_down(&self) -> Result<()> {
+        migrations::down(&self.db).await
+    }
+
+    /// Fails if the schema doesn't match the embedded migrations.
+    pub async fn ensure_schema(&self, auto_migrate: bool) -> Result<()> {
+        migrations::ensure_current(&self.db, auto_migrate).await
+    }
+
+    /// Starts a transaction for writes that must land together, passed to the
+    /// `*_in` Bmc functions.
+    pub async fn begin(&self) -> Result<Tx> {
+        Ok(self.db.begin().await?)
+    }
+
+    /// Checks that the database, and the replica if there is one, are reachable.
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        if let Some(replica) = &self.replica {
+            sqlx::query("SELECT 1").execute(replica).await?;
+        }
+        Ok(())
+    }
+
+    /// Records connection pool usage so saturation shows up before requests time out.
+    pub fn record_pool_metrics(&self) {
+        let size = self.db.size();
+        let idle = self.db.num_idle() as u32;
+        metrics::gauge!("db_pool_connections").set(size as f64);
+        metrics::gauge!("db_pool_idle_connections").set(idle as f64);
+        metrics::gauge!("db_pool_in_use_connections").set(size.saturating_sub(idle) as f64);
+        metrics::gauge!("db_pool_max_connections")
+            .set(self.db.options().get_max_connections() as f64);
     }
 
     /// Returns the sqlx db pool reference.
@@ -28,4 +120,27 @@ impl ModelManager {
     pub(in crate::model) fn db(&self) -> &Db {
         &self.db
     }
+
+    /// The replica if there is one, else the primary. Only for reads that
+    /// don't mind being a little behind, like reports.
+    pub(in crate::model) fn db_read(&self) -> &Db {
+        self.replica.as_ref().unwrap_or(&self.db)
+    }
+
+    /// Runs a lookup on the replica, retrying it on the primary if nothing
+    /// was found since the row may not have replicated yet.
+    pub(in crate::model) async fn read_or_primary<'a, T, F, Fut>(
+        &'a self,
+        query: F,
+    ) -> Result<Option<T>>
+    where
+        F: Fn(&'a Db) -> Fut,
+        Fut: Future<Output = sqlx::Result<Option<T>>>,
+    {
+        let found = query(self.db_read()).await?;
+        if found.is_some() || self.replica.is_none() {
+            return Ok(found);
+        }
+        Ok(query(&self.db).await?)
+    }
 }
@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use tracing::instrument;
+
+/// What hermes knows of a user's kind-0 profile, only the name to show for
+/// them. Kept for pubkeys without a profile too, so they aren't looked up
+/// on every notification.
+pub struct NostrProfileBmc;
+
+impl DbBmc for NostrProfileBmc {
+    const TABLE: &'static str = "nostr_profile";
+}
+
+impl NostrProfileBmc {
+    /// Stores the display name fetched for `pubkey`, `None` if their profile
+    /// has none.
+    #[instrument(skip(mm))]
+    pub async fn upsert(mm: &ModelManager, pubkey: &str, display_name: Option<&str>) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (pubkey, display_name) VALUES ($1, $2) \
+                ON CONFLICT (pubkey) DO UPDATE SET display_name = EXCLUDED.display_name, \
+                fetched_at = NOW()",
+            Self::TABLE
+        ))
+        .bind(pubkey)
+        .bind(display_name)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// The stored display name of `pubkey`, the outer `None` if it was never
+    /// fetched.
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, pubkey: &str) -> Result<Option<Option<String>>> {
+        let display_name = mm
+            .read_or_primary(|db| {
+                sqlx::query_scalar(&format!(
+                    "SELECT display_name FROM {} WHERE pubkey = $1",
+                    Self::TABLE
+                ))
+                .bind(pubkey)
+                .fetch_optional(db)
+            })
+            .await?;
+
+        Ok(display_name)
+    }
+
+    /// Display names of the given pubkeys, those without one are left out.
+    #[instrument(skip_all)]
+    pub async fn get_many(
+        mm: &ModelManager,
+        pubkeys: &[String],
+    ) -> Result<HashMap<String, String>> {
+        let names: Vec<(String, String)> = sqlx::query_as(&format!(
+            "SELECT pubkey, display_name FROM {} \
+                WHERE pubkey = ANY($1) AND display_name IS NOT NULL",
+            Self::TABLE
+        ))
+        .bind(pubkeys)
+        .fetch_all(mm.db_read())
+        .await?;
+
+        Ok(names.into_iter().collect())
+    }
+
+    /// Pubkeys of live users whose profile was never fetched or not in the
+    /// last `max_age_secs`, least recently fetched first.
+    #[instrument(skip(mm))]
+    pub async fn list_stale(
+        mm: &ModelManager,
+        max_age_secs: i64,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        let pubkeys = sqlx::query_scalar(&format!(
+            "SELECT users.pubkey FROM \
+                (SELECT DISTINCT pubkey FROM app_user WHERE deleted_at IS NULL) users \
+                LEFT JOIN {0} ON {0}.pubkey = users.pubkey \
+                WHERE {0}.fetched_at IS NULL \
+                OR {0}.fetched_at < NOW() - make_interval(secs => $1) \
+                ORDER BY {0}.fetched_at NULLS FIRST LIMIT $2",
+            Self::TABLE
+        ))
+        .bind(max_age_secs)
+        .bind(limit)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(pubkeys)
+    }
+}
@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, sealed::Sealed, ModelManager};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "id, app_user_id, federation_id, amount, created_at, sealed_at, \
+    operation_id, notes, delivered_at, error";
+
+/// Settled receipts of a user with batched payouts, paid out as one set of
+/// notes once the batch is old or large enough.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct PayoutBatch {
+    pub id: i32,
+    pub app_user_id: i32,
+    pub federation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Set once no more receipts are added and the batch is being paid out
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub sealed_at: Option<OffsetDateTime>,
+    pub operation_id: Option<String>,
+    pub notes: Option<Sealed>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub delivered_at: Option<OffsetDateTime>,
+    /// Why the last payout attempt failed
+    pub error: Option<String>,
+}
+
+pub struct PayoutBatchBmc;
+
+impl DbBmc for PayoutBatchBmc {
+    const TABLE: &'static str = "payout_batch";
+}
+
+impl PayoutBatchBmc {
+    /// Adds a settled invoice to the user's open batch, starting one if
+    /// needed, and returns the batch.
+    #[instrument(skip(mm))]
+    pub async fn add_invoice(
+        mm: &ModelManager,
+        app_user_id: i32,
+        federation_id: &str,
+        invoice_id: i32,
+        amount: i64,
+    ) -> Result<PayoutBatch> {
+        let mut tx = mm.db().begin().await?;
+
+        let batch: PayoutBatch = sqlx::query_as(&format!(
+            "INSERT INTO {0} (app_user_id, federation_id, amount) VALUES ($1, $2, $3) \
+                ON CONFLICT (app_user_id, federation_id) WHERE sealed_at IS NULL \
+                DO UPDATE SET amount = {0}.amount + EXCLUDED.amount RETURNING {COLUMNS}",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(federation_id)
+        .bind(amount)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE invoice SET payout_batch_id = $2 WHERE id = $1")
+            .bind(invoice_id)
+            .bind(batch.id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(batch)
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, id: i32) -> Result<PayoutBatch> {
+        sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(anyhow!(
+            "Entity not found in table '{}', id: {}",
+            Self::TABLE,
+            id
+        ))
+    }
+
+    /// Open batches that reached `threshold` or were started before
+    /// `started_before`, plus sealed batches that weren't delivered yet,
+    /// oldest first.
+    #[instrument(skip(mm))]
+    pub async fn list_due(
+        mm: &ModelManager,
+        threshold: Option<i64>,
+        started_before: OffsetDateTime,
+    ) -> Result<Vec<PayoutBatch>> {
+        let rows = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE delivered_at IS NULL AND (sealed_at IS NOT NULL \
+                OR amount >= $1 OR created_at < $2) ORDER BY created_at",
+            Self::TABLE
+        ))
+        .bind(threshold)
+        .bind(started_before)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Stops adding receipts to a batch so its amount can be spent. Sealing
+    /// an already sealed batch returns it unchanged.
+    #[instrument(skip(mm))]
+    pub async fn seal(mm: &ModelManager, id: i32) -> Result<PayoutBatch> {
+        sqlx::query(&format!(
+            "UPDATE {} SET sealed_at = NOW() WHERE id = $1 AND sealed_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+        Self::get(mm, id).await
+    }
+
+    /// Records the notes spent for a sealed batch, before they are sent.
+    #[instrument(skip(mm, notes))]
+    pub async fn set_notes(
+        mm: &ModelManager,
+        id: i32,
+        operation_id: &str,
+        notes: Sealed,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET operation_id = $2, notes = $3 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(operation_id)
+        .bind(notes)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn mark_delivered(mm: &ModelManager, id: i32) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET delivered_at = NOW(), error = NULL WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed payout attempt, retried on the next run.
+    #[instrument(skip(mm))]
+    pub async fn record_failure(mm: &ModelManager, id: i32, error: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET error = $2 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(error)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, invoice_state::InvoiceState, ModelManager};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "app_user_id, daily_msats, weekly_msats, exempt, updated_at";
+
+/// An operator's override of the configured receive limits for one user.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiveLimit {
+    pub app_user_id: i32,
+    /// Replaces `DAILY_RECEIVE_LIMIT_MSATS` when set
+    pub daily_msats: Option<i64>,
+    /// Replaces `WEEKLY_RECEIVE_LIMIT_MSATS` when set
+    pub weekly_msats: Option<i64>,
+    /// Lifts the user's limits entirely, instance limits still apply
+    pub exempt: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiveLimitForSet {
+    pub daily_msats: Option<i64>,
+    pub weekly_msats: Option<i64>,
+    #[serde(default)]
+    pub exempt: bool,
+}
+
+/// Amounts invoiced in the last day and week, by a user and on the whole
+/// instance, in millisatoshis.
+#[derive(Debug, Clone, Copy, FromRow)]
+pub struct ReceivedVolume {
+    pub user_daily: i64,
+    pub user_weekly: i64,
+    pub instance_daily: i64,
+    pub instance_weekly: i64,
+}
+
+pub struct ReceiveLimitBmc;
+
+impl DbBmc for ReceiveLimitBmc {
+    const TABLE: &'static str = "receive_limit";
+}
+
+impl ReceiveLimitBmc {
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, app_user_id: i32) -> Result<Option<ReceiveLimit>> {
+        let limit = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE app_user_id = $1",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(limit)
+    }
+
+    /// Creates or replaces the user's override.
+    #[instrument(skip(mm))]
+    pub async fn set(
+        mm: &ModelManager,
+        app_user_id: i32,
+        limit: ReceiveLimitForSet,
+    ) -> Result<ReceiveLimit> {
+        let limit = sqlx::query_as(&format!(
+            "INSERT INTO {} (app_user_id, daily_msats, weekly_msats, exempt) \
+                VALUES ($1, $2, $3, $4) ON CONFLICT (app_user_id) DO UPDATE SET \
+                daily_msats = EXCLUDED.daily_msats, weekly_msats = EXCLUDED.weekly_msats, \
+                exempt = EXCLUDED.exempt, updated_at = NOW() RETURNING {COLUMNS}",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(limit.daily_msats)
+        .bind(limit.weekly_msats)
+        .bind(limit.exempt)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(limit)
+    }
+
+    /// Removes the user's override, returning false if there was none.
+    #[instrument(skip(mm))]
+    pub async fn delete(mm: &ModelManager, app_user_id: i32) -> Result<bool> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE app_user_id = $1",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// What was invoiced recently, counting pending invoices so a burst of
+    /// callbacks can't get past the limits before any of them are paid.
+    #[instrument(skip(mm))]
+    pub async fn received_volume(mm: &ModelManager, app_user_id: i32) -> Result<ReceivedVolume> {
+        let volume = sqlx::query_as(
+            "SELECT \
+                COALESCE(SUM(amount) FILTER (WHERE app_user_id = $1 \
+                    AND created_at > NOW() - INTERVAL '1 day'), 0)::BIGINT AS user_daily, \
+                COALESCE(SUM(amount) FILTER (WHERE app_user_id = $1), 0)::BIGINT AS user_weekly, \
+                COALESCE(SUM(amount) FILTER (WHERE created_at > NOW() - INTERVAL '1 day'), 0)::BIGINT \
+                    AS instance_daily, \
+                COALESCE(SUM(amount), 0)::BIGINT AS instance_weekly \
+                FROM invoice WHERE state IN ($2, $3) AND created_at > NOW() - INTERVAL '7 days'",
+        )
+        .bind(app_user_id)
+        .bind(InvoiceState::Pending)
+        .bind(InvoiceState::Settled)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(volume)
+    }
+}
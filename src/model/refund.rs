@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+use super::{
+    base::DbBmc,
+    sealed::{blind_index, Sealed},
+    ModelManager,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+const COLUMNS: &str = "id, invoice_id, payer_pubkey, amount, reason, created_at, claimed_at, \
+    voided_at, bolt11, operation_id";
+
+/// Funds hermes holds for a payment it couldn't deliver, claimable by the
+/// holder of the payer key given in the callback.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Refund {
+    pub id: i32,
+    pub invoice_id: i32,
+    pub payer_pubkey: Sealed,
+    /// In millisatoshis
+    pub amount: i64,
+    pub reason: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub claimed_at: Option<OffsetDateTime>,
+    /// Set when the payment was delivered after all, e.g. by a replay
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub voided_at: Option<OffsetDateTime>,
+    /// The payer's invoice the refund was paid to
+    pub bolt11: Option<String>,
+    pub operation_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefundForCreate {
+    pub invoice_id: i32,
+    pub payer_pubkey: String,
+    pub amount: i64,
+    pub reason: String,
+}
+
+pub struct RefundBmc;
+
+impl DbBmc for RefundBmc {
+    const TABLE: &'static str = "refund";
+}
+
+impl RefundBmc {
+    /// Makes an invoice refundable, reopening a refund that was voided by a
+    /// delivery attempt that then failed again.
+    #[instrument(skip(mm))]
+    pub async fn open(mm: &ModelManager, refund_c: RefundForCreate) -> Result<()> {
+        let payer_lookup = blind_index(&refund_c.payer_pubkey);
+        sqlx::query(&format!(
+            "INSERT INTO {0} (invoice_id, payer_pubkey, payer_lookup, amount, reason) \
+                VALUES ($1, $2, $3, $4, $5) ON CONFLICT (invoice_id) DO UPDATE SET voided_at = NULL \
+                WHERE {0}.claimed_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(refund_c.invoice_id)
+        .bind(Sealed::from(refund_c.payer_pubkey))
+        .bind(payer_lookup)
+        .bind(refund_c.amount)
+        .bind(refund_c.reason)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, id: i32) -> Result<Refund> {
+        sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?
+        .ok_or(anyhow!(
+            "Entity not found in table '{}', id: {}",
+            Self::TABLE,
+            id
+        ))
+    }
+
+    /// Refunds a payer can still claim, oldest first. Encrypted keys are
+    /// matched by their blind index, ones stored before encryption directly.
+    #[instrument(skip(mm))]
+    pub async fn list_claimable(mm: &ModelManager, payer_pubkey: &str) -> Result<Vec<Refund>> {
+        let rows = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {} WHERE (payer_lookup = $1 OR payer_pubkey = $2) \
+                AND claimed_at IS NULL AND voided_at IS NULL ORDER BY created_at",
+            Self::TABLE
+        ))
+        .bind(blind_index(payer_pubkey))
+        .bind(payer_pubkey)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Marks a refund claimed for `bolt11`, or returns `None` if it was
+    /// already claimed or voided.
+    #[instrument(skip(mm))]
+    pub async fn claim(mm: &ModelManager, id: i32, bolt11: &str) -> Result<Option<Refund>> {
+        let refund = sqlx::query_as(&format!(
+            "UPDATE {} SET claimed_at = NOW(), bolt11 = $2 \
+                WHERE id = $1 AND claimed_at IS NULL AND voided_at IS NULL RETURNING {COLUMNS}",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(bolt11)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(refund)
+    }
+
+    /// Reopens a claim whose payment failed.
+    #[instrument(skip(mm))]
+    pub async fn unclaim(mm: &ModelManager, id: i32) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET claimed_at = NULL, bolt11 = NULL WHERE id = $1 AND operation_id IS NULL",
+            Self::TABLE
+        ))
+        .bind(id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn set_operation_id(mm: &ModelManager, id: i32, operation_id: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET operation_id = $2 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(operation_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Withdraws an invoice's unclaimed refund before delivering the payment
+    /// after all. Returns false if the payer already claimed it.
+    #[instrument(skip(mm))]
+    pub async fn void(mm: &ModelManager, invoice_id: i32) -> Result<bool> {
+        sqlx::query(&format!(
+            "UPDATE {} SET voided_at = NOW() \
+                WHERE invoice_id = $1 AND claimed_at IS NULL AND voided_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(invoice_id)
+        .execute(mm.db())
+        .await?;
+
+        let (claimed,): (bool,) = sqlx::query_as(&format!(
+            "SELECT EXISTS (SELECT 1 FROM {} WHERE invoice_id = $1 AND claimed_at IS NOT NULL)",
+            Self::TABLE
+        ))
+        .bind(invoice_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(!claimed)
+    }
+}
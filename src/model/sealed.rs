@@ -0,0 +1,266 @@
+//! Text columns encrypted with `DB_ENCRYPTION_KEY`, so a leaked database
+//! doesn't hand out spendable ecash or who paid whom.
+//!
+//! Values are encrypted when bound to a query and decrypted when read back,
+//! the rest of the code only ever sees plaintext. Rows written before a key
+//! was configured are stored as plaintext and still read fine, `hermes
+//! encrypt-columns` encrypts them in place.
+
+use std::{fmt, ops::Deref};
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use nostr::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::{Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+use sqlb::bindable;
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
+
+use super::ModelManager;
+use crate::config::CONFIG;
+
+/// Marks a stored value as ciphertext, versioned so the scheme can change.
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 24;
+
+/// Every encrypted column, as (table, column).
+const SEALED_COLUMNS: [(&str, &str); 14] = [
+    ("ecash_payout", "notes"),
+    ("gift", "notes"),
+    ("payout_batch", "notes"),
+    ("dead_letter", "notes"),
+    ("zaps", "request"),
+    ("zaps_archive", "request"),
+    ("invoice", "payer_pubkey"),
+    ("invoice", "comment"),
+    ("invoice", "payer_data"),
+    ("invoice_archive", "payer_pubkey"),
+    ("invoice_archive", "comment"),
+    ("invoice_archive", "payer_data"),
+    ("refund", "payer_pubkey"),
+    ("xmpp_outbox", "message"),
+];
+
+/// A string stored encrypted. Derefs to the plaintext.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Sealed(String);
+
+impl Sealed {
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+
+    /// Whether a stored value is already encrypted.
+    pub fn is_encrypted(stored: &str) -> bool {
+        stored.starts_with(PREFIX)
+    }
+}
+
+impl Deref for Sealed {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Sealed {
+    fn from(s: String) -> Self {
+        Sealed(s)
+    }
+}
+
+impl From<Sealed> for String {
+    fn from(s: Sealed) -> Self {
+        s.0
+    }
+}
+
+impl fmt::Display for Sealed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Kept out of logs, these are notes and payer data.
+impl fmt::Debug for Sealed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sealed(..)")
+    }
+}
+
+impl Type<Postgres> for Sealed {
+    fn type_info() -> PgTypeInfo {
+        <String as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for Sealed {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        let stored = match CONFIG.db_encryption_key.as_deref() {
+            Some(key) => seal(key, &self.0),
+            None => self.0.clone(),
+        };
+        <String as Encode<Postgres>>::encode(stored, buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Sealed {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let stored = <&str as Decode<Postgres>>::decode(value)?;
+        if !Self::is_encrypted(stored) {
+            return Ok(Sealed(stored.to_string()));
+        }
+
+        let key = CONFIG
+            .db_encryption_key
+            .as_deref()
+            .ok_or("column is encrypted but DB_ENCRYPTION_KEY is not set")?;
+        Ok(Sealed(open(key, stored)?))
+    }
+}
+
+bindable!(Sealed);
+
+fn seal(key: &[u8; 32], plaintext: &str) -> String {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .expect("text is far below the XChaCha20-Poly1305 length limit");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    format!("{PREFIX}{}", STANDARD.encode(sealed))
+}
+
+fn open(key: &[u8; 32], stored: &str) -> Result<String> {
+    let sealed = STANDARD.decode(&stored[PREFIX.len()..])?;
+    if sealed.len() < NONCE_LEN {
+        bail!("encrypted column is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("could not decrypt column, is DB_ENCRYPTION_KEY the right key?"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// A keyed hash of a value for equality lookups on an encrypted column, or
+/// `None` without a key, when the column itself can be compared.
+pub fn blind_index(value: &str) -> Option<String> {
+    let key = CONFIG.db_encryption_key.as_deref()?;
+    Some(blind_index_with(key, value))
+}
+
+fn blind_index_with(key: &[u8; 32], value: &str) -> String {
+    // derived so the index doesn't use the encryption key directly
+    let mut engine = HmacEngine::<Sha256>::new(key);
+    engine.input(b"hermes blind index");
+    let index_key = Hmac::<Sha256>::from_engine(engine);
+
+    let mut engine = HmacEngine::<Sha256>::new(index_key.as_byte_array());
+    engine.input(value.as_bytes());
+    Hmac::<Sha256>::from_engine(engine).to_string()
+}
+
+/// Encrypts values stored before `DB_ENCRYPTION_KEY` was set, returning how
+/// many were encrypted. Safe to run again, encrypted values are skipped.
+pub async fn encrypt_existing(mm: &ModelManager) -> Result<u64> {
+    if CONFIG.db_encryption_key.is_none() {
+        bail!("DB_ENCRYPTION_KEY is not set");
+    }
+
+    let mut encrypted = 0;
+    for (table, column) in SEALED_COLUMNS {
+        let rows: Vec<(i32, String)> = sqlx::query_as(&format!(
+            "SELECT id, {column} FROM {table} WHERE {column} IS NOT NULL \
+                AND {column} NOT LIKE '{PREFIX}%'"
+        ))
+        .fetch_all(mm.db())
+        .await?;
+
+        for (id, value) in rows {
+            let mut sql = format!("UPDATE {table} SET {column} = $2");
+            // refunds are looked up by the payer key, see RefundBmc::list_claimable
+            let lookup = (table == "refund").then(|| blind_index(&value)).flatten();
+            if lookup.is_some() {
+                sql.push_str(", payer_lookup = $3");
+            }
+            sql.push_str(" WHERE id = $1");
+
+            let mut query = sqlx::query(&sql).bind(id).bind(Sealed(value));
+            if lookup.is_some() {
+                query = query.bind(lookup);
+            }
+            query.execute(mm.db()).await?;
+            encrypted += 1;
+        }
+    }
+
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn seal_round_trips() {
+        let stored = seal(&KEY, "cashuAbc");
+        assert!(Sealed::is_encrypted(&stored));
+        assert!(!stored.contains("cashuAbc"));
+        assert_eq!(open(&KEY, &stored).unwrap(), "cashuAbc");
+    }
+
+    #[test]
+    fn seal_uses_a_fresh_nonce() {
+        assert_ne!(seal(&KEY, "same"), seal(&KEY, "same"));
+    }
+
+    #[test]
+    fn open_fails_with_the_wrong_key() {
+        let stored = seal(&KEY, "cashuAbc");
+        assert!(open(&[8; 32], &stored).is_err());
+    }
+
+    #[test]
+    fn open_fails_on_tampering() {
+        let stored = seal(&KEY, "cashuAbc");
+        let mut sealed = STANDARD.decode(&stored[PREFIX.len()..]).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        let tampered = format!("{PREFIX}{}", STANDARD.encode(sealed));
+        assert!(open(&KEY, &tampered).is_err());
+
+        assert!(open(&KEY, &format!("{PREFIX}{}", STANDARD.encode([0; 8]))).is_err());
+    }
+
+    #[test]
+    fn blind_index_is_stable_per_key() {
+        let index = blind_index_with(&KEY, "npub1payer");
+        assert_eq!(index, blind_index_with(&KEY, "npub1payer"));
+        assert_eq!(index.len(), 64);
+        assert_ne!(index, blind_index_with(&KEY, "npub1other"));
+        assert_ne!(index, blind_index_with(&[8; 32], "npub1payer"));
+    }
+}
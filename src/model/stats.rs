@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+use std::fmt;
+
+use super::{invoice_state::InvoiceState, ModelManager};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::{Duration, OffsetDateTime};
+use tracing::instrument;
+
+/// Width of the buckets stats are grouped in, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// The shortest a bucket can be, for capping how many are asked for.
+    pub fn min_duration(self) -> Duration {
+        match self {
+            Granularity::Hour => Duration::hours(1),
+            Granularity::Day => Duration::days(1),
+            Granularity::Week => Duration::weeks(1),
+            Granularity::Month => Duration::days(28),
+        }
+    }
+}
+
+/// The `date_trunc` field name
+impl fmt::Display for Granularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        };
+        f.write_str(field)
+    }
+}
+
+/// What happened in one bucket. Invoices, and the zaps they were for, are
+/// bucketed by when they were created, amounts are in millisatoshis.
+#[derive(Debug, Clone, FromRow, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsBucket {
+    #[serde(with = "time::serde::rfc3339")]
+    pub start: OffsetDateTime,
+    pub invoices: i64,
+    pub invoiced_msats: i64,
+    pub settled: i64,
+    pub settled_msats: i64,
+    /// Paid zaps
+    pub zaps: i64,
+    pub zap_msats: i64,
+    pub registrations: i64,
+    /// Notifications that were dead lettered
+    pub delivery_failures: i64,
+}
+
+pub struct StatsBmc;
+
+impl StatsBmc {
+    /// Every bucket between `from` and `to`, oldest first, including empty
+    /// ones. Each table is aggregated on its own and joined on the bucket.
+    #[instrument(skip(mm))]
+    pub async fn buckets(
+        mm: &ModelManager,
+        granularity: Granularity,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+    ) -> Result<Vec<StatsBucket>> {
+        let buckets = sqlx::query_as(
+            "WITH buckets AS ( \
+                SELECT generate_series( \
+                    date_trunc($1::TEXT, $2::TIMESTAMPTZ AT TIME ZONE 'UTC'), \
+                    ($3::TIMESTAMPTZ AT TIME ZONE 'UTC') - INTERVAL '1 microsecond', \
+                    ('1 ' || $1::TEXT)::INTERVAL \
+                ) AS start \
+            ), invoices AS ( \
+                SELECT date_trunc($1::TEXT, i.created_at AT TIME ZONE 'UTC') AS start, \
+                    COUNT(*) AS invoices, \
+                    SUM(i.amount) AS invoiced_msats, \
+                    COUNT(*) FILTER (WHERE i.state = $4) AS settled, \
+                    SUM(i.amount) FILTER (WHERE i.state = $4) AS settled_msats, \
+                    COUNT(z.id) FILTER (WHERE i.state = $4) AS zaps, \
+                    SUM(i.amount) FILTER (WHERE z.id IS NOT NULL AND i.state = $4) AS zap_msats \
+                FROM ( \
+                    SELECT id, amount, state, created_at FROM invoice UNION ALL \
+                    SELECT id, amount, state, created_at FROM invoice_archive \
+                ) i LEFT JOIN ( \
+                    SELECT id FROM zaps UNION ALL SELECT id FROM zaps_archive \
+                ) z ON z.id = i.id \
+                WHERE i.created_at >= $2 AND i.created_at < $3 GROUP BY 1 \
+            ), registrations AS ( \
+                SELECT date_trunc($1::TEXT, created_at AT TIME ZONE 'UTC') AS start, \
+                    COUNT(*) AS registrations \
+                FROM app_user WHERE created_at >= $2 AND created_at < $3 GROUP BY 1 \
+            ), failures AS ( \
+                SELECT date_trunc($1::TEXT, created_at AT TIME ZONE 'UTC') AS start, \
+                    COUNT(*) AS delivery_failures \
+                FROM dead_letter WHERE created_at >= $2 AND created_at < $3 GROUP BY 1 \
+            ) \
+            SELECT b.start AT TIME ZONE 'UTC' AS start, \
+                COALESCE(i.invoices, 0) AS invoices, \
+                COALESCE(i.invoiced_msats, 0)::BIGINT AS invoiced_msats, \
+                COALESCE(i.settled, 0) AS settled, \
+                COALESCE(i.settled_msats, 0)::BIGINT AS settled_msats, \
+                COALESCE(i.zaps, 0) AS zaps, \
+                COALESCE(i.zap_msats, 0)::BIGINT AS zap_msats, \
+                COALESCE(r.registrations, 0) AS registrations, \
+                COALESCE(f.delivery_failures, 0) AS delivery_failures \
+            FROM buckets b \
+            LEFT JOIN invoices i ON i.start = b.start \
+            LEFT JOIN registrations r ON r.start = b.start \
+            LEFT JOIN failures f ON f.start = b.start \
+            ORDER BY b.start",
+        )
+        .bind(granularity.to_string())
+        .bind(from)
+        .bind(to)
+        .bind(InvoiceState::Settled)
+        .fetch_all(mm.db_read())
+        .await?;
+
+        Ok(buckets)
+    }
+}
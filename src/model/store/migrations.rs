@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use sqlx::migrate::{Migrate, Migrator};
+use tracing::info;
+
+use super::Db;
+
+/// Migrations from `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug, Default)]
+pub struct SchemaStatus {
+    pub applied: Vec<i64>,
+    pub pending: Vec<i64>,
+    /// applied to the db but not known to this binary, i.e. the db is newer
+    pub unknown: Vec<i64>,
+    /// applied with a different checksum than the embedded script
+    pub modified: Vec<i64>,
+    pub dirty: Option<i64>,
+}
+
+impl SchemaStatus {
+    pub fn is_current(&self) -> bool {
+        self.pending.is_empty()
+            && self.unknown.is_empty()
+            && self.modified.is_empty()
+            && self.dirty.is_none()
+    }
+}
+
+pub async fn status(db: &Db) -> Result<SchemaStatus> {
+    let mut conn = db.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let dirty = conn.dirty_version().await?;
+    let applied = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect::<HashMap<_, _>>();
+
+    let mut status = SchemaStatus {
+        dirty,
+        ..Default::default()
+    };
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+    {
+        match applied.get(&migration.version) {
+            Some(checksum) if *checksum == migration.checksum => {
+                status.applied.push(migration.version)
+            }
+            Some(_) => status.modified.push(migration.version),
+            None => status.pending.push(migration.version),
+        }
+    }
+    let mut unknown = applied
+        .keys()
+        .filter(|v| !MIGRATOR.iter().any(|m| m.version == **v))
+        .copied()
+        .collect::<Vec<_>>();
+    unknown.sort();
+    status.unknown = unknown;
+
+    Ok(status)
+}
+
+pub async fn up(db: &Db) -> Result<()> {
+    MIGRATOR.run(db).await?;
+    info!("Database migrated to latest version");
+    Ok(())
+}
+
+/// Reverts the most recently applied migration.
+pub async fn down(db: &Db) -> Result<()> {
+    let status = status(db).await?;
+    let mut applied = status.applied;
+    applied.sort();
+    let latest = applied
+        .pop()
+        .ok_or_else(|| anyhow!("No migrations to revert"))?;
+    let target = applied.pop().unwrap_or(0);
+
+    MIGRATOR.undo(db, target).await?;
+    info!("Reverted migration {latest}");
+    Ok(())
+}
+
+/// Refuses to continue unless the schema matches this binary exactly,
+/// applying pending migrations first if `auto_migrate` is set.
+pub async fn ensure_current(db: &Db, auto_migrate: bool) -> Result<()> {
+    let mut status = status(db).await?;
+    if auto_migrate && !status.pending.is_empty() {
+        up(db).await?;
+        status = self::status(db).await?;
+    }
+
+    if !status.is_current() {
+        return Err(anyhow!(
+            "Database schema does not match this binary, run `hermes migrate up` \
+             or set AUTO_MIGRATE: {:?}",
+            status
+        ));
+    }
+
+    Ok(())
+}
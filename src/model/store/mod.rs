@@ -1,15 +1,38 @@
 use crate::config::CONFIG;
 use anyhow::{anyhow, Result};
 
-use sqlx::postgres::PgPoolOptions;
-use sqlx::{Pool, Postgres};
+use log::LevelFilter;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Pool, Postgres};
+use std::str::FromStr;
+
+pub mod migrations;
 
 pub type Db = Pool<Postgres>;
 
 pub async fn new_db_pool() -> Result<Db> {
+    connect(&CONFIG.pg_db).await
+}
+
+/// Pool for the read replica at `DATABASE_REPLICA_URL`, if one is configured.
+pub async fn new_replica_pool() -> Result<Option<Db>> {
+    match CONFIG.pg_db_replica.as_deref() {
+        Some(url) => connect(url).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+async fn connect(url: &str) -> Result<Db> {
+    let mut options = PgConnectOptions::from_str(url)?
+        .log_slow_statements(LevelFilter::Warn, CONFIG.db_slow_query_threshold);
+    if let Some(timeout) = CONFIG.db_statement_timeout {
+        options = options.options([("statement_timeout", timeout.as_millis())]);
+    }
+
     PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&CONFIG.pg_db)
+        .max_connections(CONFIG.db_max_connections)
+        .acquire_timeout(CONFIG.db_acquire_timeout)
+        .connect_with(options)
         .await
         .map_err(|ex| anyhow!("Could not connect to database: {}", ex))
 }
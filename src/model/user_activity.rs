@@ -0,0 +1,130 @@
+#![allow(dead_code)]
+use super::{
+    invoice::{Invoice, InvoiceWithTimestamp},
+    invoice_state::InvoiceState,
+    sealed::Sealed,
+    ModelManager,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::HasFields;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// How a user's payments went over the last `days` days, amounts in
+/// millisatoshis. Invoices count towards the window they were created in.
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserActivity {
+    pub days: i32,
+    /// Paid invoices
+    pub payments: i64,
+    pub received_msats: i64,
+    /// Mean amount of a paid invoice, 0 without any
+    pub average_msats: i64,
+    /// Paid invoices whose ecash or zap receipt was dead lettered at least
+    /// once, even if a replay delivered it later
+    pub delivery_failures: i64,
+    /// `delivery_failures` out of `payments`, 0 without any
+    #[sqlx(skip)]
+    pub delivery_failure_rate: f64,
+    /// The last time the user claimed ecash or drew on their balance
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub last_claim_at: Option<OffsetDateTime>,
+}
+
+/// A received payment with what else hermes knows of it, for a wallet's
+/// activity screen.
+#[derive(Debug, Clone, FromRow)]
+pub struct PaymentRow {
+    #[sqlx(flatten)]
+    pub invoice: InvoiceWithTimestamp,
+    /// The zap request, gone once zap retention removed it
+    pub zap_request: Option<Sealed>,
+    /// How the ecash was handed out: `ecash`, `link` or `batch`, `None`
+    /// until it was
+    pub payout: Option<String>,
+    pub claimed_at: Option<OffsetDateTime>,
+}
+
+pub struct UserActivityBmc;
+
+impl UserActivityBmc {
+    #[instrument(skip(mm))]
+    pub async fn get(mm: &ModelManager, app_user_id: i32, days: i32) -> Result<UserActivity> {
+        let mut activity: UserActivity = sqlx::query_as(
+            "WITH paid AS ( \
+                SELECT id, amount FROM invoice WHERE app_user_id = $1 AND state = $3 \
+                    AND created_at > NOW() - make_interval(days => $2) \
+            ) \
+            SELECT $2 AS days, \
+                (SELECT COUNT(*) FROM paid) AS payments, \
+                (SELECT COALESCE(SUM(amount), 0)::BIGINT FROM paid) AS received_msats, \
+                (SELECT COALESCE(AVG(amount), 0)::BIGINT FROM paid) AS average_msats, \
+                (SELECT COUNT(DISTINCT d.invoice_id) FROM dead_letter d \
+                    JOIN paid p ON p.id = d.invoice_id) AS delivery_failures, \
+                GREATEST( \
+                    (SELECT MAX(claimed_at) FROM ecash_payout WHERE app_user_id = $1), \
+                    (SELECT MAX(created_at) FROM withdrawal WHERE app_user_id = $1) \
+                ) AS last_claim_at",
+        )
+        .bind(app_user_id)
+        .bind(days)
+        .bind(InvoiceState::Settled)
+        .fetch_one(mm.db_read())
+        .await?;
+
+        if activity.payments > 0 {
+            activity.delivery_failure_rate =
+                activity.delivery_failures as f64 / activity.payments as f64;
+        }
+
+        Ok(activity)
+    }
+
+    /// A user's payments, newest first, starting after `before`, the
+    /// creation time and id of the last one of the previous page.
+    #[instrument(skip(mm))]
+    pub async fn list_payments(
+        mm: &ModelManager,
+        app_user_id: i32,
+        before: Option<(OffsetDateTime, i32)>,
+        limit: i64,
+    ) -> Result<Vec<PaymentRow>> {
+        let columns: Vec<String> = Invoice::field_names()
+            .iter()
+            .map(|field| format!("i.{field}"))
+            .collect();
+        let (before_at, before_id) = before.unzip();
+        let payments = sqlx::query_as(&format!(
+            "SELECT {}, i.created_at, i.settled_at, z.request AS zap_request, \
+                CASE WHEN g.id IS NOT NULL THEN 'link' \
+                    WHEN i.payout_batch_id IS NOT NULL AND e.id IS NOT NULL THEN 'batch' \
+                    WHEN e.id IS NOT NULL THEN 'ecash' END AS payout, \
+                COALESCE(g.claimed_at, e.claimed_at) AS claimed_at \
+            FROM invoice i \
+            LEFT JOIN zaps z ON z.id = i.id \
+            LEFT JOIN gift g ON g.invoice_id = i.id \
+            LEFT JOIN LATERAL ( \
+                SELECT id, claimed_at FROM ecash_payout \
+                WHERE invoice_id = i.id OR payout_batch_id = i.payout_batch_id \
+                ORDER BY id DESC LIMIT 1 \
+            ) e ON TRUE \
+            WHERE i.app_user_id = $1 AND i.deleted_at IS NULL \
+                AND ($2::TIMESTAMPTZ IS NULL OR (i.created_at, i.id) < ($2, $3)) \
+            ORDER BY i.created_at DESC, i.id DESC LIMIT $4",
+            columns.join(", ")
+        ))
+        .bind(app_user_id)
+        .bind(before_at)
+        .bind(before_id)
+        .bind(limit)
+        .fetch_all(mm.db_read())
+        .await?;
+
+        Ok(payments)
+    }
+}
@@ -0,0 +1,48 @@
+use super::{base::DbBmc, ModelManager, Tx};
+use anyhow::Result;
+use tracing::instrument;
+
+/// Which user a wallet's key was paired with, see `POST /wallet/pair`. Keys
+/// registered through `POST /register` are paired with their first user.
+pub struct WalletPairingBmc;
+
+impl DbBmc for WalletPairingBmc {
+    const TABLE: &'static str = "wallet_pairing";
+}
+
+impl WalletPairingBmc {
+    /// Records the user created for a wallet, false if the key is already
+    /// paired with a live user. A pairing whose user was deleted is taken
+    /// over.
+    #[instrument(skip(tx))]
+    pub async fn create_in(tx: &mut Tx, pubkey: &str, app_user_id: i32) -> Result<bool> {
+        let count = sqlx::query(&format!(
+            "INSERT INTO {0} (pubkey, app_user_id) VALUES ($1, $2) \
+                ON CONFLICT (pubkey) DO UPDATE SET app_user_id = $2, created_at = NOW() \
+                WHERE {0}.app_user_id IN (SELECT id FROM app_user WHERE deleted_at IS NOT NULL)",
+            Self::TABLE
+        ))
+        .bind(pubkey)
+        .bind(app_user_id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+
+        Ok(count == 1)
+    }
+
+    /// The live user a key was paired with, if any.
+    #[instrument(skip(mm))]
+    pub async fn find_user_id(mm: &ModelManager, pubkey: &str) -> Result<Option<i32>> {
+        let id = sqlx::query_scalar(&format!(
+            "SELECT p.app_user_id FROM {} p JOIN app_user u ON u.id = p.app_user_id \
+                WHERE p.pubkey = $1 AND u.deleted_at IS NULL",
+            Self::TABLE
+        ))
+        .bind(pubkey)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,73 @@
+#![allow(dead_code)]
+use super::{
+    base::{self, DbBmc},
+    ModelManager, Tx,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::{Fields, HasFields};
+use sqlx::FromRow;
+use tracing::instrument;
+
+/// An endpoint that receives BTCPay style events for a user's invoices.
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub app_user_id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct WebhookForCreate {
+    pub app_user_id: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+pub struct WebhookBmc;
+
+impl DbBmc for WebhookBmc {
+    const TABLE: &'static str = "webhook";
+}
+
+impl WebhookBmc {
+    pub async fn create(mm: &ModelManager, webhook_c: WebhookForCreate) -> Result<i32> {
+        base::create::<Self, _>(mm, webhook_c).await
+    }
+
+    pub async fn get(mm: &ModelManager, id: i32) -> Result<Webhook> {
+        base::get::<Self, _>(mm, id).await
+    }
+
+    #[instrument(skip(mm))]
+    pub async fn list_for_user(mm: &ModelManager, app_user_id: i32) -> Result<Vec<Webhook>> {
+        let webhooks = sqlb::select()
+            .table(Self::TABLE)
+            .columns(Webhook::field_names())
+            .and_where("app_user_id", "=", app_user_id)
+            .order_by("id")
+            .fetch_all(mm.db())
+            .await?;
+
+        Ok(webhooks)
+    }
+
+    pub async fn delete(mm: &ModelManager, id: i32) -> Result<()> {
+        base::delete::<Self>(mm, id).await
+    }
+
+    /// Replaces all of a user's webhooks with one, as part of a transaction.
+    #[instrument(skip_all, fields(app_user_id = webhook_c.app_user_id))]
+    pub async fn replace_for_user_in(tx: &mut Tx, webhook_c: WebhookForCreate) -> Result<i32> {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE app_user_id = $1",
+            Self::TABLE
+        ))
+        .bind(webhook_c.app_user_id)
+        .execute(&mut **tx)
+        .await?;
+        base::create_in::<Self, _>(tx, webhook_c).await
+    }
+}
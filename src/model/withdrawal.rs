@@ -0,0 +1,37 @@
+#![allow(dead_code)]
+use super::{
+    base::{self, DbBmc},
+    ModelManager,
+};
+use anyhow::Result;
+use serde::Serialize;
+use sqlb::Fields;
+use sqlx::FromRow;
+
+/// A claim against a custodial balance, kept as the user's payout history.
+#[derive(Debug, Clone, Fields, FromRow, Serialize)]
+pub struct WithdrawalForCreate {
+    pub app_user_id: i32,
+    pub federation_id: String,
+    /// In millisatoshis, including any fee reserve
+    pub amount: i64,
+    /// `ecash` or `lightning`
+    pub kind: String,
+    pub operation_id: String,
+    pub bolt11: Option<String>,
+    /// Why it's unknown whether a lightning withdrawal went out, left for an
+    /// operator to check against the operation
+    pub error: Option<String>,
+}
+
+pub struct WithdrawalBmc;
+
+impl DbBmc for WithdrawalBmc {
+    const TABLE: &'static str = "withdrawal";
+}
+
+impl WithdrawalBmc {
+    pub async fn create(mm: &ModelManager, withdrawal_c: WithdrawalForCreate) -> Result<i32> {
+        base::create::<Self, _>(mm, withdrawal_c).await
+    }
+}
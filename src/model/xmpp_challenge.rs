@@ -0,0 +1,76 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, ModelManager};
+use anyhow::Result;
+use tracing::instrument;
+
+/// Wrong guesses allowed before a challenge has to be sent again.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// A code sent to a JID to prove the registering user receives messages
+/// there. Only the sha256 hash of the code is kept, one challenge per JID.
+pub struct XmppChallengeBmc;
+
+impl DbBmc for XmppChallengeBmc {
+    const TABLE: &'static str = "xmpp_challenge";
+}
+
+impl XmppChallengeBmc {
+    /// Starts a new challenge for `jid`, replacing an earlier one unless that
+    /// is younger than `cooldown_secs`. Returns whether it was stored, the
+    /// code should only be sent if it was.
+    #[instrument(skip(mm, code_hash))]
+    pub async fn upsert(
+        mm: &ModelManager,
+        jid: &str,
+        code_hash: &str,
+        cooldown_secs: i64,
+    ) -> Result<bool> {
+        let count = sqlx::query(&format!(
+            "INSERT INTO {0} (jid, code_hash) VALUES ($1, $2) \
+                ON CONFLICT (jid) DO UPDATE SET code_hash = EXCLUDED.code_hash, \
+                attempts = 0, created_at = NOW() \
+                WHERE {0}.created_at < NOW() - make_interval(secs => $3)",
+            Self::TABLE
+        ))
+        .bind(jid)
+        .bind(code_hash)
+        .bind(cooldown_secs)
+        .execute(mm.db())
+        .await?
+        .rows_affected();
+
+        Ok(count > 0)
+    }
+
+    /// Checks a code against the challenge for `jid`, consuming the challenge
+    /// if it matches. Every check counts as an attempt, and challenges older
+    /// than `max_age_secs` or out of attempts never match.
+    #[instrument(skip(mm, code_hash))]
+    pub async fn verify(
+        mm: &ModelManager,
+        jid: &str,
+        code_hash: &str,
+        max_age_secs: i64,
+    ) -> Result<bool> {
+        let stored: Option<String> = sqlx::query_scalar(&format!(
+            "UPDATE {} SET attempts = attempts + 1 WHERE jid = $1 AND attempts < $2 \
+                AND created_at > NOW() - make_interval(secs => $3) RETURNING code_hash",
+            Self::TABLE
+        ))
+        .bind(jid)
+        .bind(MAX_ATTEMPTS)
+        .bind(max_age_secs)
+        .fetch_optional(mm.db())
+        .await?;
+
+        if stored.as_deref() != Some(code_hash) {
+            return Ok(false);
+        }
+
+        sqlx::query(&format!("DELETE FROM {} WHERE jid = $1", Self::TABLE))
+            .bind(jid)
+            .execute(mm.db())
+            .await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(dead_code)]
+use super::{base::DbBmc, sealed::Sealed, ModelManager};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+/// Joined with `app_user` for the pubkey, which shares some column names.
+const COLUMNS: &str = "xmpp_outbox.id, xmpp_outbox.app_user_id, xmpp_outbox.jid, \
+    xmpp_outbox.message, xmpp_outbox.operation_id, xmpp_outbox.invoice_id, \
+    xmpp_outbox.created_at, xmpp_outbox.sent_at, xmpp_outbox.channel, \
+    xmpp_outbox.received_at, app_user.pubkey";
+
+/// `channel` of messages sent over XMPP.
+pub const XMPP_CHANNEL: &str = "xmpp";
+/// `channel` of messages sent as a nostr DM after their recipient stayed
+/// offline for `XMPP_PRESENCE_TIMEOUT_SECS`, or sending them over XMPP failed.
+pub const NOSTR_CHANNEL: &str = "nostr";
+
+/// An XMPP DM waiting for its recipient to come online. The message holds
+/// ecash, so it is sealed.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct XmppOutboxMessage {
+    pub id: i32,
+    pub app_user_id: i32,
+    pub jid: String,
+    pub message: Sealed,
+    pub operation_id: Option<String>,
+    pub invoice_id: Option<i32>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub sent_at: Option<OffsetDateTime>,
+    pub channel: Option<String>,
+    /// When the recipient's client confirmed it got the message, only asked
+    /// for over XMPP
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub received_at: Option<OffsetDateTime>,
+    /// The user's nostr pubkey, for falling back to a nostr DM
+    pub pubkey: String,
+}
+
+pub struct XmppOutboxBmc;
+
+impl DbBmc for XmppOutboxBmc {
+    const TABLE: &'static str = "xmpp_outbox";
+}
+
+impl XmppOutboxBmc {
+    /// Queues a message carrying the payout of `operation_id`, linked to the
+    /// invoice it paid out if there is one.
+    #[instrument(skip(mm, message))]
+    pub async fn enqueue(
+        mm: &ModelManager,
+        app_user_id: i32,
+        jid: &str,
+        message: Sealed,
+        operation_id: &str,
+    ) -> Result<i32> {
+        let (id,) = sqlx::query_as(&format!(
+            "INSERT INTO {} (app_user_id, jid, message, operation_id, invoice_id) \
+                VALUES ($1, $2, $3, $4, COALESCE(\
+                (SELECT invoice_id FROM ecash_payout WHERE operation_id = $4 LIMIT 1), \
+                (SELECT invoice_id FROM gift WHERE operation_id = $4 LIMIT 1))) \
+                RETURNING id",
+            Self::TABLE
+        ))
+        .bind(app_user_id)
+        .bind(jid)
+        .bind(message)
+        .bind(operation_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Unsent messages, oldest first.
+    #[instrument(skip(mm))]
+    pub async fn list_pending(mm: &ModelManager, limit: i64) -> Result<Vec<XmppOutboxMessage>> {
+        let messages = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM {0} JOIN app_user ON app_user.id = {0}.app_user_id \
+                WHERE {0}.sent_at IS NULL ORDER BY {0}.created_at LIMIT $1",
+            Self::TABLE
+        ))
+        .bind(limit)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Records which channel the message finally went out on.
+    #[instrument(skip(mm))]
+    pub async fn mark_sent(mm: &ModelManager, id: i32, channel: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET sent_at = NOW(), channel = $2 WHERE id = $1",
+            Self::TABLE
+        ))
+        .bind(id)
+        .bind(channel)
+        .execute(mm.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a delivery receipt, returning the message unless the id is
+    /// unknown. Only the first receipt counts.
+    #[instrument(skip(mm))]
+    pub async fn mark_received(mm: &ModelManager, id: i32) -> Result<Option<XmppOutboxMessage>> {
+        let message = sqlx::query_as(&format!(
+            "UPDATE {0} SET received_at = COALESCE({0}.received_at, NOW()) FROM app_user \
+                WHERE app_user.id = {0}.app_user_id AND {0}.id = $1 RETURNING {COLUMNS}",
+            Self::TABLE
+        ))
+        .bind(id)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(message)
+    }
+}
@@ -2,24 +2,30 @@
 
 use super::{
     base::{self, DbBmc},
-    ModelManager,
+    sealed::Sealed,
+    ModelManager, Tx,
 };
+use crate::model::invoice_state::InvoiceState;
 use anyhow::Result;
-use nostr::EventId;
+use nostr::{Event, JsonUtil};
 use serde::Serialize;
 use sqlb::Fields;
 use sqlx::FromRow;
+use tracing::instrument;
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct Zap {
     pub id: i32,
-    pub request: String,
+    pub request: Sealed,
     pub event_id: Option<String>,
+    /// The signed zap receipt as published, json
+    pub receipt: Option<String>,
 }
 
 #[derive(Debug, Clone, Fields, FromRow, Serialize)]
 pub struct ZapForUpdate {
     pub event_id: String,
+    pub receipt: String,
 }
 
 pub struct ZapBmc;
@@ -33,13 +39,19 @@ impl ZapBmc {
         base::create::<Self, _>(mm, inv_c).await
     }
 
+    pub async fn create_in(tx: &mut Tx, inv_c: Zap) -> Result<i32> {
+        base::create_in::<Self, _>(tx, inv_c).await
+    }
+
     pub async fn get(mm: &ModelManager, id: i32) -> Result<Zap> {
         base::get::<Self, _>(mm, id).await
     }
 
-    pub async fn set_event_id(mm: &ModelManager, id: i32, event_id: EventId) -> Result<()> {
+    /// Keeps the zap receipt that was published for the zap
+    pub async fn set_receipt(mm: &ModelManager, id: i32, receipt: &Event) -> Result<()> {
         let u = ZapForUpdate {
-            event_id: event_id.to_hex(),
+            event_id: receipt.id.to_hex(),
+            receipt: receipt.as_json(),
         };
         base::update::<Self, _>(mm, id, u).await?;
         Ok(())
@@ -48,4 +60,39 @@ impl ZapBmc {
     pub async fn delete(mm: &ModelManager, id: i32) -> Result<()> {
         base::delete::<Self>(mm, id).await
     }
+
+    /// Removes up to `limit` zaps of finished invoices created more than
+    /// `days` days ago, moving them to `zaps_archive` if `archive` is set.
+    /// Zaps with an unresolved dead letter are kept so their receipt can
+    /// still be replayed. Returns how many were removed.
+    #[instrument(skip(mm))]
+    pub async fn prune(mm: &ModelManager, days: i32, archive: bool, limit: i64) -> Result<u64> {
+        let sink = if archive {
+            "INSERT INTO zaps_archive (id, request, event_id, receipt) \
+                SELECT id, request, event_id, receipt FROM pruned"
+        } else {
+            "SELECT id FROM pruned"
+        };
+        let result = sqlx::query(&format!(
+            "WITH old AS ( \
+                SELECT z.id FROM {table} z JOIN invoice i ON i.id = z.id \
+                WHERE i.state <> $1 AND i.created_at < NOW() - make_interval(days => $2) \
+                    AND NOT EXISTS (SELECT 1 FROM dead_letter d \
+                        WHERE d.invoice_id = z.id AND d.resolved_at IS NULL) \
+                ORDER BY z.id LIMIT $3 \
+            ), pruned AS ( \
+                DELETE FROM {table} WHERE id IN (SELECT id FROM old) \
+                RETURNING id, request, event_id, receipt \
+            ) \
+            {sink}",
+            table = Self::TABLE,
+        ))
+        .bind(InvoiceState::Pending)
+        .bind(days)
+        .bind(limit)
+        .execute(mm.db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
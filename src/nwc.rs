@@ -0,0 +1,89 @@
+//! NIP-47 notifications for users who connect their wallet to hermes over
+//! NWC. hermes only tells the wallet about payments it received, it doesn't
+//! answer NWC requests.
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use lightning_invoice::Bolt11Invoice;
+use nostr::nips::nip04;
+use nostr::secp256k1::XOnlyPublicKey;
+use nostr::{Event, EventBuilder, Kind, Tag};
+use nostr_sdk::Client;
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{
+    compute, config::CONFIG, model::invoice::Invoice, router::handlers::nostr::AppUserRelays,
+};
+
+/// NIP-47 info event, listing what the service supports.
+const INFO_KIND: Kind = Kind::Custom(13194);
+/// NIP-47 notification event.
+const NOTIFICATION_KIND: Kind = Kind::Custom(23196);
+
+/// Announces that hermes sends `payment_received` notifications, so wallets
+/// know to subscribe to them.
+pub async fn publish_info(nostr: Client) -> Result<()> {
+    let tags = vec![Tag::parse(vec!["notifications", "payment_received"])?];
+    let event = EventBuilder::new(INFO_KIND, "", &tags).to_event(&CONFIG.nostr_sk)?;
+    let event_id = nostr.send_event(event).await?;
+    info!("Published NWC info event {event_id}");
+    Ok(())
+}
+
+/// Tells the user's NWC wallet their invoice settled, if they connected one.
+/// Failing to is only logged.
+pub async fn payment_received(
+    nostr: &Client,
+    invoice: &Invoice,
+    userrelays: &AppUserRelays,
+    preimage: Option<&str>,
+) {
+    let Some(nwc_pubkey) = &userrelays.nwc_pubkey else {
+        return;
+    };
+    if let Err(e) = send_payment_received(nostr, invoice, nwc_pubkey, preimage).await {
+        warn!(
+            "Sending NWC notification of invoice {} failed: {e:#}",
+            invoice.id
+        );
+    }
+}
+
+async fn send_payment_received(
+    nostr: &Client,
+    invoice: &Invoice,
+    nwc_pubkey: &str,
+    preimage: Option<&str>,
+) -> Result<()> {
+    let wallet = XOnlyPublicKey::from_str(nwc_pubkey)?;
+    let bolt11 = Bolt11Invoice::from_str(&invoice.bolt11)?;
+    let created_at = bolt11.duration_since_epoch().as_secs();
+
+    let content = json!({
+        "notification_type": "payment_received",
+        "notification": {
+            "type": "incoming",
+            "invoice": invoice.bolt11,
+            "payment_hash": bolt11.payment_hash().to_string(),
+            "preimage": preimage,
+            "amount": invoice.amount,
+            "fees_paid": 0,
+            "created_at": created_at,
+            "expires_at": created_at + bolt11.expiry_time().as_secs(),
+            "settled_at": OffsetDateTime::now_utc().unix_timestamp(),
+        },
+    });
+    let event = compute::run(move || -> Result<Event> {
+        let encrypted =
+            nip04::encrypt(&CONFIG.nostr_sk.secret_key()?, &wallet, content.to_string())?;
+        let tags = vec![Tag::parse(vec!["p".to_string(), wallet.to_string()])?];
+        Ok(EventBuilder::new(NOTIFICATION_KIND, encrypted, &tags).to_event(&CONFIG.nostr_sk)?)
+    })
+    .await??;
+    let event_id = nostr.send_event(event).await?;
+    info!("Sent NWC notification {event_id} of invoice {}", invoice.id);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Display names from users' kind-0 nostr profiles, for notices and admin
+//! views that read better with a name than a username. They are kept in the
+//! `nostr_profile` table, refreshed by the `profile_refresh` job and fetched
+//! on demand for users seen for the first time.
+
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::Result;
+use nostr::secp256k1::XOnlyPublicKey;
+use nostr::{Event, Filter, JsonUtil, Kind, Metadata};
+use nostr_sdk::Client;
+use tracing::{info, warn};
+
+use crate::{
+    config::CONFIG,
+    model::{nostr_profile::NostrProfileBmc, ModelManager},
+    state::AppState,
+};
+
+/// How long relays get to answer a profile lookup.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Profiles refreshed per run of the job.
+const REFRESH_BATCH: i64 = 100;
+/// Longer names are cut off, they end up in chat messages.
+const MAX_NAME_CHARS: usize = 64;
+
+/// The display name of `pubkey`, fetched from the relays if it never was.
+/// `None` if their profile has none or looking it up failed, which is only
+/// logged.
+pub async fn display_name(nostr: &Client, mm: &ModelManager, pubkey: &str) -> Option<String> {
+    match NostrProfileBmc::get(mm, pubkey).await {
+        Ok(Some(display_name)) => display_name,
+        Ok(None) => match fetch(nostr, mm, &[pubkey.to_string()]).await {
+            Ok(mut names) => names.remove(pubkey),
+            Err(e) => {
+                warn!("Fetching the nostr profile of {pubkey} failed: {e:#}");
+                None
+            }
+        },
+        Err(e) => {
+            warn!("Looking up the nostr profile of {pubkey} failed: {e:#}");
+            None
+        }
+    }
+}
+
+/// Fetches the newest profiles of `pubkeys` and stores their display names,
+/// returning those that have one. Pubkeys that aren't nostr keys, e.g. of
+/// some XMPP users, are stored without a name so they aren't tried again
+/// until the next refresh.
+pub async fn fetch(
+    nostr: &Client,
+    mm: &ModelManager,
+    pubkeys: &[String],
+) -> Result<HashMap<String, String>> {
+    let authors: Vec<XOnlyPublicKey> = pubkeys
+        .iter()
+        .filter_map(|pubkey| XOnlyPublicKey::from_str(pubkey).ok())
+        .collect();
+    let events = if authors.is_empty() {
+        vec![]
+    } else {
+        let filter = Filter::new().kind(Kind::Metadata).authors(authors);
+        nostr
+            .get_events_of(vec![filter], Some(FETCH_TIMEOUT))
+            .await?
+    };
+    let mut newest: HashMap<XOnlyPublicKey, Event> = HashMap::new();
+    for event in events {
+        if newest
+            .get(&event.pubkey)
+            .map_or(true, |known| known.created_at < event.created_at)
+        {
+            newest.insert(event.pubkey, event);
+        }
+    }
+
+    let mut names = HashMap::new();
+    for pubkey in pubkeys {
+        let name = XOnlyPublicKey::from_str(pubkey)
+            .ok()
+            .and_then(|author| newest.get(&author))
+            .and_then(|event| name_of(&event.content));
+        NostrProfileBmc::upsert(mm, pubkey, name.as_deref()).await?;
+        if let Some(name) = name {
+            names.insert(pubkey.clone(), name);
+        }
+    }
+
+    Ok(names)
+}
+
+/// The name a profile asks to be shown as, its `display_name` or else its
+/// `name`, without control characters.
+fn name_of(content: &str) -> Option<String> {
+    let metadata = Metadata::from_json(content).ok()?;
+    let name: String = metadata
+        .display_name
+        .filter(|name| !name.trim().is_empty())
+        .or(metadata.name)?
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_CHARS)
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Refetches profiles older than `PROFILE_REFRESH_SECS`, a batch at a time.
+pub async fn refresh_profiles(state: AppState) -> Result<()> {
+    let max_age = CONFIG.profile_refresh.as_secs() as i64;
+    let pubkeys = NostrProfileBmc::list_stale(&state.mm, max_age, REFRESH_BATCH).await?;
+    if pubkeys.is_empty() {
+        return Ok(());
+    }
+
+    let names = fetch(&state.nostr, &state.mm, &pubkeys).await?;
+    info!(
+        "Refreshed {} nostr profiles, {} with a display name",
+        pubkeys.len(),
+        names.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{net::IpAddr, num::NonZeroU32, sync::Arc};
+
+use arc_swap::ArcSwap;
+use governor::{DefaultKeyedRateLimiter, Quota};
+
+use crate::config::RuntimeConfig;
+
+/// Token bucket limits keyed by client ip and by the username being requested,
+/// so a single client can't spam invoices and a single user can't be targeted
+/// from many clients. Zaps are also limited by the nostr key or payer key
+/// they are sent from, so one account can't flood a user with zap receipts
+/// and DMs from many ips.
+pub struct RateLimiter {
+    limits: ArcSwap<Limits>,
+}
+
+impl RateLimiter {
+    pub fn new(runtime: &RuntimeConfig) -> Self {
+        Self {
+            limits: ArcSwap::from_pointee(Limits::new(runtime)),
+        }
+    }
+
+    /// Replaces the limits, starting every bucket over.
+    pub fn update(&self, runtime: &RuntimeConfig) {
+        self.limits.store(Arc::new(Limits::new(runtime)));
+    }
+
+    /// Returns true if the request is allowed, consuming a token from each bucket.
+    pub fn check(&self, ip: IpAddr, username: Option<&str>) -> bool {
+        self.limits.load().check(ip, username)
+    }
+
+    /// Returns true if another zap from `sender` is allowed.
+    pub fn check_zap_sender(&self, sender: &str) -> bool {
+        self.limits
+            .load()
+            .zap_sender
+            .check_key(&sender.to_string())
+            .is_ok()
+    }
+
+    /// Drops buckets that have refilled completely so the maps don't grow forever.
+    pub fn retain_recent(&self) {
+        self.limits.load().retain_recent();
+    }
+}
+
+struct Limits {
+    ip: DefaultKeyedRateLimiter<IpAddr>,
+    username: DefaultKeyedRateLimiter<String>,
+    zap_sender: DefaultKeyedRateLimiter<String>,
+}
+
+impl Limits {
+    fn new(runtime: &RuntimeConfig) -> Self {
+        let ip_quota = Quota::per_minute(
+            NonZeroU32::new(runtime.rate_limit_ip_per_minute).expect("ip limit must be > 0"),
+        );
+        let username_quota = Quota::per_minute(
+            NonZeroU32::new(runtime.rate_limit_username_per_minute)
+                .expect("username limit must be > 0"),
+        );
+        let zap_sender_quota = Quota::per_minute(
+            NonZeroU32::new(runtime.rate_limit_zap_sender_per_minute)
+                .expect("zap sender limit must be > 0"),
+        );
+
+        Self {
+            ip: governor::RateLimiter::keyed(ip_quota),
+            username: governor::RateLimiter::keyed(username_quota),
+            zap_sender: governor::RateLimiter::keyed(zap_sender_quota),
+        }
+    }
+
+    fn check(&self, ip: IpAddr, username: Option<&str>) -> bool {
+        if self.ip.check_key(&ip).is_err() {
+            return false;
+        }
+
+        match username {
+            Some(username) => self.username.check_key(&username.to_lowercase()).is_ok(),
+            None => true,
+        }
+    }
+
+    fn retain_recent(&self) {
+        self.ip.retain_recent();
+        self.username.retain_recent();
+        self.zap_sender.retain_recent();
+    }
+}
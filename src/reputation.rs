@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    audit,
+    error::ErrorCode,
+    model::{
+        ip_ban::{IpBan, IpBanBmc},
+        ModelManager,
+    },
+};
+
+/// Actor recorded in the audit log for automatic bans.
+const AUTO_BAN_ACTOR: &str = "system:reputation";
+
+/// Something an ip did that counts against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offense {
+    /// A request rejected as invalid or unauthorized, or a guessed gift link.
+    /// Rate limits aren't counted, honest payers share a busy user's bucket
+    FailedRequest,
+    /// A callback with a nostr event that isn't a valid zap request
+    InvalidZapRequest,
+    /// Any registration, so one ip can't claim names in bulk
+    Registration,
+}
+
+impl Offense {
+    /// The offense behind an error response, if it's the client's fault.
+    pub fn for_error(code: ErrorCode) -> Option<Self> {
+        match code {
+            ErrorCode::InvalidNostrEvent => Some(Offense::InvalidZapRequest),
+            ErrorCode::BadRequest
+            | ErrorCode::Unauthorized
+            | ErrorCode::NotFound
+            | ErrorCode::UserNotFound
+            | ErrorCode::InvoiceNotFound
+            | ErrorCode::AmountTooLow
+            | ErrorCode::AmountTooHigh
+            | ErrorCode::InvalidDmType
+            | ErrorCode::RegistrationFailed
+            | ErrorCode::IdempotencyKeyReused
+            | ErrorCode::GiftUnavailable => Some(Offense::FailedRequest),
+            _ => None,
+        }
+    }
+
+    fn points(&self) -> f64 {
+        match self {
+            Offense::FailedRequest => 1.0,
+            Offense::InvalidZapRequest => 5.0,
+            Offense::Registration => 10.0,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Offense::FailedRequest => "failed requests",
+            Offense::InvalidZapRequest => "invalid zap requests",
+            Offense::Registration => "registrations",
+        }
+    }
+}
+
+struct Score {
+    points: f64,
+    updated: Instant,
+}
+
+impl Score {
+    /// Points left after halving every `half_life`.
+    fn decayed(&self, half_life: Duration) -> f64 {
+        let half_lives = self.updated.elapsed().as_secs_f64() / half_life.as_secs_f64();
+        self.points * 0.5f64.powf(half_lives)
+    }
+}
+
+/// Per ip abuse scores that decay over time, and the ban list they feed.
+/// Once an ip's score reaches `threshold` it is banned for `ban_duration`.
+/// Bans are stored so they survive restarts, scores are kept in memory only.
+pub struct IpReputation {
+    threshold: Option<f64>,
+    half_life: Duration,
+    ban_duration: Duration,
+    scores: Mutex<HashMap<IpAddr, Score>>,
+    bans: Mutex<HashMap<IpAddr, IpBan>>,
+}
+
+impl IpReputation {
+    /// A zero `threshold` disables automatic bans, manual ones still apply.
+    pub async fn load(
+        mm: &ModelManager,
+        threshold: u32,
+        half_life: Duration,
+        ban_duration: Duration,
+    ) -> Result<Self> {
+        let bans = IpBanBmc::list_active(mm)
+            .await?
+            .into_iter()
+            .filter_map(|ban| Some((ban.ip.parse().ok()?, ban)))
+            .collect();
+
+        Ok(Self {
+            threshold: (threshold > 0).then_some(threshold as f64),
+            half_life,
+            ban_duration,
+            scores: Mutex::new(HashMap::new()),
+            bans: Mutex::new(bans),
+        })
+    }
+
+    pub fn banned(&self, ip: IpAddr) -> Option<IpBan> {
+        let bans = self.bans.lock().expect("bans lock poisoned");
+        bans.get(&ip).filter(|ban| ban.is_active()).cloned()
+    }
+
+    /// Adds an offense to the ip's score, banning it once the score reaches
+    /// the threshold.
+    pub async fn penalize(&self, mm: &ModelManager, ip: IpAddr, offense: Offense) -> Result<()> {
+        let Some(threshold) = self.threshold else {
+            return Ok(());
+        };
+
+        let exceeded = {
+            let mut scores = self.scores.lock().expect("scores lock poisoned");
+            let score = scores.entry(ip).or_insert(Score {
+                points: 0.0,
+                updated: Instant::now(),
+            });
+            score.points = score.decayed(self.half_life) + offense.points();
+            score.updated = Instant::now();
+            let exceeded = score.points >= threshold;
+            if exceeded {
+                scores.remove(&ip);
+            }
+            exceeded
+        };
+        if !exceeded {
+            return Ok(());
+        }
+
+        warn!(
+            "Banning {ip} for {:?} after too many {}",
+            self.ban_duration,
+            offense.as_str()
+        );
+        metrics::counter!("ip_bans_total", "created_by" => "auto").increment(1);
+        let reason = format!("Too many {}", offense.as_str());
+        self.ban(mm, ip, &reason, Some(self.ban_duration), AUTO_BAN_ACTOR)
+            .await?;
+        Ok(())
+    }
+
+    /// Bans an ip for `duration`, or until unbanned if `None`.
+    pub async fn ban(
+        &self,
+        mm: &ModelManager,
+        ip: IpAddr,
+        reason: &str,
+        duration: Option<Duration>,
+        actor: &str,
+    ) -> Result<IpBan> {
+        let expires_at = duration.map(|d| OffsetDateTime::now_utc() + d);
+        let ban = IpBanBmc::upsert(mm, &ip.to_string(), reason, expires_at, actor).await?;
+        self.bans
+            .lock()
+            .expect("bans lock poisoned")
+            .insert(ip, ban.clone());
+
+        audit::record(
+            mm,
+            actor.to_string(),
+            "ip.ban",
+            Some(&ban.ip),
+            json!({ "reason": reason, "durationSecs": duration.map(|d| d.as_secs()) }),
+        )
+        .await;
+        Ok(ban)
+    }
+
+    /// Lifts an ip's ban and forgets its score. Returns false if it wasn't
+    /// banned.
+    pub async fn unban(&self, mm: &ModelManager, ip: IpAddr, actor: &str) -> Result<bool> {
+        let removed = IpBanBmc::delete(mm, &ip.to_string()).await?;
+        self.bans.lock().expect("bans lock poisoned").remove(&ip);
+        self.scores
+            .lock()
+            .expect("scores lock poisoned")
+            .remove(&ip);
+
+        if removed {
+            audit::record(
+                mm,
+                actor.to_string(),
+                "ip.unban",
+                Some(&ip.to_string()),
+                json!({}),
+            )
+            .await;
+        }
+        Ok(removed)
+    }
+
+    /// Current scores, highest first.
+    pub fn scores(&self) -> Vec<(IpAddr, f64)> {
+        let scores = self.scores.lock().expect("scores lock poisoned");
+        let mut scores: Vec<_> = scores
+            .iter()
+            .map(|(ip, score)| (*ip, score.decayed(self.half_life)))
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+    }
+
+    /// Drops scores that have decayed away and expired bans, so the maps
+    /// don't grow forever.
+    pub fn retain_recent(&self) {
+        self.scores
+            .lock()
+            .expect("scores lock poisoned")
+            .retain(|_, score| score.decayed(self.half_life) >= 0.5);
+        self.bans
+            .lock()
+            .expect("bans lock poisoned")
+            .retain(|_, ban| ban.is_active());
+    }
+}
@@ -0,0 +1,281 @@
+//! How a user's payments have been going, for their own dashboard and, under
+//! `/admin/users/:username/activity`, for operators, and the payments
+//! themselves for a wallet's activity screen.
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use nostr::{Event, JsonUtil};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{info, instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::{
+        invoice_state::InvoiceState,
+        nostr_profile::NostrProfileBmc,
+        sealed::Sealed,
+        user_activity::{PaymentRow, UserActivity, UserActivityBmc},
+    },
+    router::handlers::{lnbits::authenticate, lnurlp::payer_data::PayerData},
+    state::AppState,
+};
+
+const DEFAULT_DAYS: i32 = 30;
+const MAX_DAYS: i32 = 366;
+
+const DEFAULT_PAYMENTS: i64 = 50;
+const MAX_PAYMENTS: i64 = 200;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityParams {
+    /// Length of the window in days, 30 if not given and at most 366
+    pub days: Option<i32>,
+}
+
+impl ActivityParams {
+    pub(crate) fn days(&self) -> i32 {
+        self.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/activity",
+    tag = "activity",
+    params(
+        ("X-Api-Key" = String, Header, description = "Key issued by an admin"),
+        ActivityParams,
+    ),
+    responses(
+        (status = 200, description = "Payments over the window", body = UserActivity),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_activity(
+    Query(params): Query<ActivityParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<UserActivity>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!("activity called for {} with {:?}", user.name, params);
+
+    let activity = UserActivityBmc::get(&state.mm, user.id, params.days()).await?;
+
+    Ok(Json(activity))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaymentsParams {
+    /// `nextCursor` of the previous page, the newest payments if not given
+    pub cursor: Option<String>,
+    /// Payments per page, 50 if not given and at most 200
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentFeed {
+    /// Newest first
+    pub payments: Vec<Payment>,
+    /// Cursor of the next page, `null` on the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Payment {
+    pub op_id: String,
+    pub payment_hash: Option<String>,
+    /// In millisatoshis
+    pub amount: i64,
+    pub state: InvoiceState,
+    pub settled: bool,
+    #[schema(value_type = String)]
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[schema(value_type = Option<String>)]
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub settled_at: Option<OffsetDateTime>,
+    /// LUD-12 comment from the payer
+    pub comment: Option<String>,
+    /// Key the payer proved the payment with, see `proofofpayer`
+    pub payer_pubkey: Option<String>,
+    /// LUD-18 payer identity
+    pub payer_data: Option<PayerData>,
+    pub zap: Option<ZapContext>,
+    /// `null` until the payment was paid out or credited
+    pub payout: Option<Payout>,
+}
+
+/// Who zapped, from the zap request.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ZapContext {
+    /// Hex pubkey of the sender, a throwaway one for anonymous zaps
+    pub sender: String,
+    /// The sender's display name, if hermes has their profile
+    pub sender_name: Option<String>,
+    /// Message sent with the zap
+    pub content: String,
+    /// Hex id of the note zapped, `null` for zaps of the profile
+    pub event_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Payout {
+    /// `ecash` sent over the user's DM channel, a claim `link`, part of a
+    /// `batch` or credited to the custodial `balance`
+    pub kind: String,
+    /// Whether a wallet claimed the ecash, always false for balances
+    pub claimed: bool,
+    #[schema(value_type = Option<String>)]
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub claimed_at: Option<OffsetDateTime>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/payments",
+    tag = "activity",
+    params(
+        ("X-Api-Key" = String, Header, description = "Key issued by an admin"),
+        PaymentsParams,
+    ),
+    responses(
+        (status = 200, description = "A page of received payments", body = PaymentFeed),
+        (status = 400, description = "Invalid cursor", body = ErrorResponse),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_payments(
+    Query(params): Query<PaymentsParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PaymentFeed>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!("payments called for {} with {:?}", user.name, params);
+
+    let before = params.cursor.as_deref().map(parse_cursor).transpose()?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAYMENTS)
+        .clamp(1, MAX_PAYMENTS);
+    let rows = UserActivityBmc::list_payments(&state.mm, user.id, before, limit).await?;
+
+    let next_cursor = match rows.last() {
+        Some(last) if rows.len() as i64 == limit => Some(format!(
+            "{}_{}",
+            last.invoice.created_at.unix_timestamp_nanos() / 1_000,
+            last.invoice.invoice.id
+        )),
+        _ => None,
+    };
+
+    let zap_requests: Vec<Option<Event>> = rows
+        .iter()
+        .map(|row| {
+            row.zap_request
+                .as_ref()
+                .and_then(|request| Event::from_json(&**request).ok())
+        })
+        .collect();
+    let senders: Vec<String> = zap_requests
+        .iter()
+        .flatten()
+        .map(|request| request.pubkey.to_string())
+        .collect();
+    let names = NostrProfileBmc::get_many(&state.mm, &senders).await?;
+
+    let payments = rows
+        .into_iter()
+        .zip(zap_requests)
+        .map(|(row, zap_request)| {
+            let zap = zap_request.map(|request| {
+                let sender = request.pubkey.to_string();
+                ZapContext {
+                    sender_name: names.get(&sender).cloned(),
+                    sender,
+                    event_id: request
+                        .tags
+                        .iter()
+                        .map(|tag| tag.as_vec())
+                        .find(|tag| tag.first().map(String::as_str) == Some("e"))
+                        .and_then(|tag| tag.into_iter().nth(1)),
+                    content: request.content,
+                }
+            });
+            payment(row, zap, user.custodial)
+        })
+        .collect();
+
+    Ok(Json(PaymentFeed {
+        payments,
+        next_cursor,
+    }))
+}
+
+fn payment(row: PaymentRow, zap: Option<ZapContext>, custodial: bool) -> Payment {
+    let PaymentRow {
+        invoice,
+        payout,
+        claimed_at,
+        ..
+    } = row;
+    let settled = invoice.invoice.state == InvoiceState::Settled;
+    let payout = match payout {
+        Some(kind) => Some(Payout {
+            kind,
+            claimed: claimed_at.is_some(),
+            claimed_at,
+        }),
+        None if settled && custodial => Some(Payout {
+            kind: "balance".to_string(),
+            claimed: false,
+            claimed_at: None,
+        }),
+        None => None,
+    };
+
+    Payment {
+        op_id: invoice.invoice.op_id,
+        payment_hash: invoice.invoice.payment_hash,
+        amount: invoice.invoice.amount,
+        state: invoice.invoice.state,
+        settled,
+        created_at: invoice.created_at,
+        settled_at: invoice.settled_at,
+        comment: invoice.invoice.comment.map(Sealed::into_inner),
+        payer_pubkey: invoice.invoice.payer_pubkey.map(Sealed::into_inner),
+        payer_data: invoice
+            .invoice
+            .payer_data
+            .and_then(|data| serde_json::from_str(&data).ok()),
+        zap,
+        payout,
+    }
+}
+
+/// The creation time in microseconds and id of the last payment of a page.
+fn parse_cursor(cursor: &str) -> Result<(OffsetDateTime, i32), AppError> {
+    let invalid = || AppError::from_code(ErrorCode::BadRequest, anyhow!("Invalid cursor"));
+    let (micros, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let micros: i128 = micros.parse().map_err(|_| invalid())?;
+    let id = id.parse().map_err(|_| invalid())?;
+    let created_at =
+        OffsetDateTime::from_unix_timestamp_nanos(micros * 1_000).map_err(|_| invalid())?;
+
+    Ok((created_at, id))
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::{
+    error::AppError,
+    model::audit_log::{AuditEntry, AuditFilter, AuditLogBmc},
+    state::AppState,
+};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListAuditParams {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ListAuditResponse {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_audit(
+    Query(params): Query<ListAuditParams>,
+    State(state): State<AppState>,
+) -> Result<Json<ListAuditResponse>, AppError> {
+    info!("admin list audit log called with {:?}", params);
+
+    let filter = AuditFilter {
+        actor: params.actor,
+        action: params.action,
+        target: params.target,
+        from: params.from,
+        to: params.to,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let (entries, total) = AuditLogBmc::list(&state.mm, &filter, limit, offset).await?;
+
+    Ok(Json(ListAuditResponse {
+        entries,
+        total,
+        limit,
+        offset,
+    }))
+}
@@ -0,0 +1,40 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::IntoResponse,
+};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    audit,
+    backup::{backup_file_name, create_backup},
+    error::AppError,
+    state::AppState,
+};
+
+#[axum_macros::debug_handler]
+pub async fn handle_backup(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    info!("admin backup called");
+    let archive = create_backup(&state.mm)?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "backup.create",
+        None,
+        json!({}),
+    )
+    .await;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", backup_file_name()),
+            ),
+        ],
+        Body::from_stream(archive),
+    ))
+}
@@ -0,0 +1,99 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::ip_ban::{IpBan, IpBanBmc},
+    state::AppState,
+};
+
+/// How many of the highest abuse scores are listed.
+const MAX_SCORES: usize = 100;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpScore {
+    pub ip: IpAddr,
+    pub score: f64,
+}
+
+#[derive(Serialize)]
+pub struct ListBansResponse {
+    pub bans: Vec<IpBan>,
+    /// Ips that aren't banned (yet), highest first
+    pub scores: Vec<IpScore>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BanParams {
+    pub ip: IpAddr,
+    pub reason: String,
+    /// Banned until unbanned if unset
+    pub duration_secs: Option<u64>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_bans(
+    State(state): State<AppState>,
+) -> Result<Json<ListBansResponse>, AppError> {
+    info!("admin list bans called");
+    let bans = IpBanBmc::list_active(&state.mm).await?;
+    let scores = state
+        .reputation
+        .scores()
+        .into_iter()
+        .take(MAX_SCORES)
+        .map(|(ip, score)| IpScore { ip, score })
+        .collect();
+
+    Ok(Json(ListBansResponse { bans, scores }))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_ban(
+    State(state): State<AppState>,
+    Json(params): Json<BanParams>,
+) -> Result<Json<IpBan>, AppError> {
+    info!("admin ban called for {}", params.ip);
+    let ban = state
+        .reputation
+        .ban(
+            &state.mm,
+            params.ip,
+            &params.reason,
+            params.duration_secs.map(Duration::from_secs),
+            &audit::admin_actor(),
+        )
+        .await?;
+
+    Ok(Json(ban))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_unban(
+    Path(ip): Path<IpAddr>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    info!("admin unban called for {ip}");
+    if !state
+        .reputation
+        .unban(&state.mm, ip, &audit::admin_actor())
+        .await?
+    {
+        return Err(AppError::from_code(
+            ErrorCode::NotFound,
+            anyhow!("{ip} is not banned"),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::{audit, error::AppError, state::AppState};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeConfigResponse {
+    pub min_sendable_msats: u64,
+    pub max_sendable_msats: u64,
+    pub nostr_relays: Vec<String>,
+    pub rate_limit_ip_per_minute: u32,
+    pub rate_limit_username_per_minute: u32,
+    pub rate_limit_zap_sender_per_minute: u32,
+    pub min_zap_msats: Option<u64>,
+    pub max_pending_invoices_per_federation: Option<u64>,
+    pub max_pending_invoices_per_user: Option<u64>,
+    pub daily_receive_limit_msats: Option<u64>,
+    pub weekly_receive_limit_msats: Option<u64>,
+    pub instance_daily_receive_limit_msats: Option<u64>,
+    pub instance_weekly_receive_limit_msats: Option<u64>,
+    pub registration_open: bool,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_reload(
+    State(state): State<AppState>,
+) -> Result<Json<RuntimeConfigResponse>, AppError> {
+    info!("admin reload called");
+    let runtime = state.reload_config().await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "config.reload",
+        None,
+        json!({}),
+    )
+    .await;
+
+    Ok(Json(RuntimeConfigResponse {
+        min_sendable_msats: runtime.min_sendable_msats,
+        max_sendable_msats: runtime.max_sendable_msats,
+        nostr_relays: runtime.nostr_relays.clone(),
+        rate_limit_ip_per_minute: runtime.rate_limit_ip_per_minute,
+        rate_limit_username_per_minute: runtime.rate_limit_username_per_minute,
+        rate_limit_zap_sender_per_minute: runtime.rate_limit_zap_sender_per_minute,
+        min_zap_msats: runtime.min_zap_msats,
+        max_pending_invoices_per_federation: runtime.max_pending_invoices_per_federation,
+        max_pending_invoices_per_user: runtime.max_pending_invoices_per_user,
+        daily_receive_limit_msats: runtime.daily_receive_limit_msats,
+        weekly_receive_limit_msats: runtime.weekly_receive_limit_msats,
+        instance_daily_receive_limit_msats: runtime.instance_daily_receive_limit_msats,
+        instance_weekly_receive_limit_msats: runtime.instance_weekly_receive_limit_msats,
+        registration_open: runtime.registration_open,
+    }))
+}
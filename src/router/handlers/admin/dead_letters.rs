@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::dead_letter::{DeadLetter, DeadLetterBmc},
+    router::handlers::lnurlp::callback::replay_dead_letter,
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ListDeadLettersParams {
+    #[serde(default)]
+    pub include_resolved: bool,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_dead_letters(
+    Query(params): Query<ListDeadLettersParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DeadLetter>>, AppError> {
+    info!("admin list dead letters called with {:?}", params);
+    let dead_letters = DeadLetterBmc::list(&state.mm, params.include_resolved).await?;
+
+    Ok(Json(dead_letters))
+}
+
+/// Retries the failed step. A failed replay is recorded on the entry and
+/// returned like a successful one, check `resolved_at` and `error`.
+#[axum_macros::debug_handler]
+pub async fn handle_replay_dead_letter(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<Json<DeadLetter>, AppError> {
+    info!("admin replay dead letter {id} called");
+    let dead_letter = DeadLetterBmc::get(&state.mm, id)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::NotFound, e))?;
+    if dead_letter.resolved_at.is_some() {
+        return Ok(Json(dead_letter));
+    }
+
+    let dead_letter = replay_dead_letter(&state, id).await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "dead_letter.replay",
+        Some(&id.to_string()),
+        json!({ "resolved": dead_letter.resolved_at.is_some() }),
+    )
+    .await;
+
+    Ok(Json(dead_letter))
+}
@@ -0,0 +1,136 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    export::{self, ExportFormat},
+    model::{
+        app_user::AppUserBmc,
+        invoice::{InvoiceBmc, InvoiceFilter},
+        invoice_state::InvoiceState,
+    },
+    router::handlers::NameOrPubkey,
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ExportUsersParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportInvoicesParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub state: Option<InvoiceState>,
+    pub federation_id: Option<String>,
+    pub username: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    /// Export soft deleted invoices instead
+    #[serde(default)]
+    pub deleted: bool,
+    /// Export archived invoices instead
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_export_users(
+    Query(params): Query<ExportUsersParams>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    info!("admin export users called with {:?}", params);
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "export.users",
+        None,
+        json!({ "format": params.format.extension() }),
+    )
+    .await;
+
+    let mm = state.mm.clone();
+    let users = export::spawn(params.format, None, |mut out| async move {
+        AppUserBmc::export(&mm, &mut out).await?;
+        out.finish().await
+    });
+
+    Ok(attachment(params.format, "users", Body::from_stream(users)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_export_invoices(
+    Query(params): Query<ExportInvoicesParams>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    info!("admin export invoices called with {:?}", params);
+
+    let app_user_id = match &params.username {
+        Some(username) => Some(
+            AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, username)
+                .await
+                .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?
+                .id,
+        ),
+        None => None,
+    };
+    let filter = InvoiceFilter {
+        state: params.state,
+        federation_id: params.federation_id.clone(),
+        app_user_id,
+        from: params.from,
+        to: params.to,
+        deleted: params.deleted,
+        archived: params.archived,
+    };
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "export.invoices",
+        None,
+        json!({ "format": params.format.extension(), "username": params.username }),
+    )
+    .await;
+
+    let mm = state.mm.clone();
+    let invoices = export::spawn(params.format, None, |mut out| async move {
+        InvoiceBmc::export(&mm, &filter, &mut out).await?;
+        out.finish().await
+    });
+
+    Ok(attachment(
+        params.format,
+        "invoices",
+        Body::from_stream(invoices),
+    ))
+}
+
+fn attachment(format: ExportFormat, name: &str, body: Body) -> impl IntoResponse {
+    let date = OffsetDateTime::now_utc().date();
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"hermes-{name}-{date}.{}\"",
+                    format.extension()
+                ),
+            ),
+        ],
+        body,
+    )
+}
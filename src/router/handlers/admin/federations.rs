@@ -0,0 +1,117 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use fedimint_core::api::InviteCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    gateways::GatewayInfo,
+    model::federation_stats::{FederationStats, FederationStatsBmc},
+    state::AppState,
+};
+
+const DEFAULT_STATS_DAYS: i32 = 30;
+const MAX_STATS_DAYS: i32 = 366;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinFederationParams {
+    pub invite_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FederationStatsParams {
+    pub federation_id: Option<String>,
+    pub days: Option<i32>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_federations(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<String>>, AppError> {
+    info!("admin list federations called");
+    let federation_ids = state
+        .federations
+        .ids()
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+
+    Ok(Json(federation_ids))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_join_federation(
+    State(state): State<AppState>,
+    Json(params): Json<JoinFederationParams>,
+) -> Result<Json<Vec<String>>, AppError> {
+    info!("admin join federation called");
+    let invite_code = InviteCode::from_str(&params.invite_code)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))?;
+
+    let federation_id = invite_code.federation_id().to_string();
+    state.federations.join(invite_code).await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "federation.join",
+        Some(&federation_id),
+        json!({}),
+    )
+    .await;
+
+    handle_list_federations(State(state)).await
+}
+
+/// A federation's gateways as of the last `gateway_refresh` run.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FederationGateways {
+    pub federation_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub refreshed_at: OffsetDateTime,
+    pub gateways: Vec<GatewayInfo>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_gateways(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<FederationGateways>>, AppError> {
+    info!("admin list gateways called");
+    let gateways = state
+        .gateways
+        .all()
+        .iter()
+        .map(|(federation_id, set)| FederationGateways {
+            federation_id: federation_id.to_string(),
+            refreshed_at: set.refreshed_at,
+            gateways: set.info(),
+        })
+        .collect();
+
+    Ok(Json(gateways))
+}
+
+/// Daily settled volume, gateway fees and note issuance, newest first.
+#[axum_macros::debug_handler]
+pub async fn handle_federation_stats(
+    Query(params): Query<FederationStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<FederationStats>>, AppError> {
+    info!("admin federation stats called with {:?}", params);
+    let days = params
+        .days
+        .unwrap_or(DEFAULT_STATS_DAYS)
+        .clamp(1, MAX_STATS_DAYS);
+
+    let stats = FederationStatsBmc::list(&state.mm, params.federation_id.as_deref(), days).await?;
+    Ok(Json(stats))
+}
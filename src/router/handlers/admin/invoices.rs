@@ -0,0 +1,159 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::{
+        app_user::AppUserBmc,
+        invoice::{Invoice, InvoiceBmc, InvoiceFilter, InvoiceWithTimestamp},
+        invoice_event::{InvoiceEvent, InvoiceEventBmc},
+        invoice_state::InvoiceState,
+    },
+    router::handlers::NameOrPubkey,
+    state::AppState,
+};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListInvoicesParams {
+    pub state: Option<InvoiceState>,
+    pub federation_id: Option<String>,
+    pub username: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    /// List soft deleted invoices instead
+    #[serde(default)]
+    pub deleted: bool,
+    /// List archived invoices instead
+    #[serde(default)]
+    pub archived: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ListInvoicesResponse {
+    pub invoices: Vec<InvoiceWithTimestamp>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_invoices(
+    Query(params): Query<ListInvoicesParams>,
+    State(state): State<AppState>,
+) -> Result<Json<ListInvoicesResponse>, AppError> {
+    info!("admin list invoices called with {:?}", params);
+
+    let app_user_id = match params.username {
+        Some(username) => Some(
+            AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+                .await
+                .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?
+                .id,
+        ),
+        None => None,
+    };
+
+    let filter = InvoiceFilter {
+        state: params.state,
+        federation_id: params.federation_id,
+        app_user_id,
+        from: params.from,
+        to: params.to,
+        deleted: params.deleted,
+        archived: params.archived,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let (invoices, total) = InvoiceBmc::list(&state.mm, &filter, limit, offset).await?;
+
+    Ok(Json(ListInvoicesResponse {
+        invoices,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+/// Everything that happened to an invoice, oldest first. `id` is the invoice
+/// id or its operation id.
+#[axum_macros::debug_handler]
+pub async fn handle_invoice_events(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<InvoiceEvent>>, AppError> {
+    info!("admin invoice events for {id} called");
+
+    let invoice = match id.parse::<i32>() {
+        Ok(id) => InvoiceBmc::get(&state.mm, id).await,
+        Err(_) => InvoiceBmc::get_by_op_id(&state.mm, &id).await,
+    }
+    .map_err(|e| AppError::from_code(ErrorCode::InvoiceNotFound, e))?;
+
+    let events = InvoiceEventBmc::list_for_invoice(&state.mm, invoice.id).await?;
+
+    Ok(Json(events))
+}
+
+/// Soft deletes an invoice, see `InvoiceBmc::delete`.
+#[axum_macros::debug_handler]
+pub async fn handle_delete_invoice(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    info!("admin delete invoice {id} called");
+    if !InvoiceBmc::delete(&state.mm, id).await? {
+        return Err(AppError::from_code(
+            ErrorCode::InvoiceNotFound,
+            anyhow::anyhow!("No live invoice with id {id}"),
+        ));
+    }
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "invoice.delete",
+        Some(&id.to_string()),
+        json!({}),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_restore_invoice(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<Json<Invoice>, AppError> {
+    info!("admin restore invoice {id} called");
+    if !InvoiceBmc::restore(&state.mm, id).await? {
+        return Err(AppError::from_code(
+            ErrorCode::InvoiceNotFound,
+            anyhow::anyhow!("No deleted invoice with id {id}"),
+        ));
+    }
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "invoice.restore",
+        Some(&id.to_string()),
+        json!({}),
+    )
+    .await;
+
+    Ok(Json(InvoiceBmc::get(&state.mm, id).await?))
+}
@@ -0,0 +1,10 @@
+use axum::{extract::State, Json};
+use tracing::info;
+
+use crate::{scheduler::JobStatus, state::AppState};
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_jobs(State(state): State<AppState>) -> Json<Vec<JobStatus>> {
+    info!("admin list jobs called");
+    Json(state.scheduler.statuses())
+}
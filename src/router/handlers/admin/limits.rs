@@ -0,0 +1,103 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::{
+        app_user::AppUserBmc,
+        receive_limit::{ReceiveLimit, ReceiveLimitBmc, ReceiveLimitForSet},
+    },
+    router::handlers::NameOrPubkey,
+    state::AppState,
+};
+
+#[axum_macros::debug_handler]
+pub async fn handle_get_receive_limit(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<ReceiveLimit>, AppError> {
+    info!("admin get receive limit called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+    let limit = ReceiveLimitBmc::get(&state.mm, user.id)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::NotFound,
+                anyhow!("{username} has the configured limits"),
+            )
+        })?;
+
+    Ok(Json(limit))
+}
+
+/// Overrides the configured receive limits for a user. Unset limits fall
+/// back to the configured ones, and `exempt` lifts them.
+#[axum_macros::debug_handler]
+pub async fn handle_set_receive_limit(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Json(params): Json<ReceiveLimitForSet>,
+) -> Result<Json<ReceiveLimit>, AppError> {
+    info!("admin set receive limit called for {}", username);
+    if params.daily_msats.is_some_and(|l| l < 0) || params.weekly_msats.is_some_and(|l| l < 0) {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Limits can't be negative"),
+        ));
+    }
+
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+    let limit = ReceiveLimitBmc::set(&state.mm, user.id, params).await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "receive_limit.set",
+        Some(&username),
+        json!({
+            "dailyMsats": limit.daily_msats,
+            "weeklyMsats": limit.weekly_msats,
+            "exempt": limit.exempt,
+        }),
+    )
+    .await;
+
+    Ok(Json(limit))
+}
+
+/// Puts a user back on the configured receive limits.
+#[axum_macros::debug_handler]
+pub async fn handle_delete_receive_limit(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    info!("admin delete receive limit called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+    if !ReceiveLimitBmc::delete(&state.mm, user.id).await? {
+        return Err(AppError::from_code(
+            ErrorCode::NotFound,
+            anyhow!("{username} has the configured limits"),
+        ));
+    }
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "receive_limit.delete",
+        Some(&username),
+        json!({}),
+    )
+    .await;
+
+    Ok(())
+}
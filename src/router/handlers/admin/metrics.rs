@@ -0,0 +1,7 @@
+use crate::telemetry::render_metrics;
+
+/// Prometheus scrape endpoint, authenticated like the rest of the admin api.
+#[axum_macros::debug_handler]
+pub async fn handle_metrics() -> String {
+    render_metrics()
+}
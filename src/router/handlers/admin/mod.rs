@@ -0,0 +1,15 @@
+pub mod audit;
+pub mod backup;
+pub mod bans;
+pub mod config;
+pub mod dead_letters;
+pub mod export;
+pub mod federations;
+pub mod invoices;
+pub mod jobs;
+pub mod limits;
+pub mod metrics;
+pub mod stats;
+pub mod telemetry;
+pub mod users;
+pub mod webhooks;
@@ -0,0 +1,70 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use crate::{
+    error::{AppError, ErrorCode},
+    model::stats::{Granularity, StatsBmc, StatsBucket},
+    state::AppState,
+};
+
+const DEFAULT_RANGE: Duration = Duration::days(30);
+const MAX_BUCKETS: u32 = 1_000;
+
+#[derive(Debug, Deserialize)]
+pub struct StatsParams {
+    #[serde(default)]
+    pub granularity: Granularity,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub granularity: Granularity,
+    #[serde(with = "time::serde::rfc3339")]
+    pub from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub to: OffsetDateTime,
+    pub buckets: Vec<StatsBucket>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_stats(
+    Query(params): Query<StatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<StatsResponse>, AppError> {
+    info!("admin stats called with {:?}", params);
+
+    let to = params.to.unwrap_or_else(OffsetDateTime::now_utc);
+    let from = params.from.unwrap_or(to - DEFAULT_RANGE);
+    if from >= to {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("from must be before to"),
+        ));
+    }
+    // a bucket may start before `from`, hence the extra one
+    if (to - from) > params.granularity.min_duration() * (MAX_BUCKETS - 1) {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("At most {MAX_BUCKETS} buckets can be returned, use a coarser granularity"),
+        ));
+    }
+
+    let buckets = StatsBmc::buckets(&state.mm, params.granularity, from, to).await?;
+
+    Ok(Json(StatsResponse {
+        granularity: params.granularity,
+        from,
+        to,
+        buckets,
+    }))
+}
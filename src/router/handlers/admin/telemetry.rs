@@ -0,0 +1,99 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    state::AppState,
+    telemetry,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracingParams {
+    /// `RUST_LOG` style directives, unchanged if not given
+    pub filter: Option<String>,
+    /// Ratio of new traces exported over OTLP, unchanged if not given
+    pub sample_ratio: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracingResponse {
+    pub filter: String,
+    pub sample_ratio: f64,
+}
+
+fn current() -> TracingResponse {
+    TracingResponse {
+        filter: telemetry::log_filter(),
+        sample_ratio: telemetry::sample_ratio(),
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_get_tracing() -> Json<TracingResponse> {
+    info!("admin get tracing called");
+    Json(current())
+}
+
+/// Changes take effect immediately and last until the next restart or
+/// `DELETE /admin/tracing`.
+#[axum_macros::debug_handler]
+pub async fn handle_set_tracing(
+    State(state): State<AppState>,
+    Json(params): Json<TracingParams>,
+) -> Result<Json<TracingResponse>, AppError> {
+    info!("admin set tracing called with {:?}", params);
+
+    // validate both before applying either
+    if let Some(ratio) = params.sample_ratio {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(AppError::from_code(
+                ErrorCode::BadRequest,
+                anyhow::anyhow!("sampleRatio must be between 0 and 1"),
+            ));
+        }
+    }
+    if let Some(filter) = params.filter.as_deref() {
+        telemetry::set_log_filter(Some(filter))
+            .map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))?;
+    }
+    if params.sample_ratio.is_some() {
+        telemetry::set_sample_ratio(params.sample_ratio)?;
+    }
+
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "tracing.update",
+        None,
+        json!({ "filter": params.filter, "sampleRatio": params.sample_ratio }),
+    )
+    .await;
+
+    Ok(Json(current()))
+}
+
+/// Goes back to `RUST_LOG` and `OTLP_SAMPLE_RATIO`.
+#[axum_macros::debug_handler]
+pub async fn handle_reset_tracing(
+    State(state): State<AppState>,
+) -> Result<Json<TracingResponse>, AppError> {
+    info!("admin reset tracing called");
+    telemetry::set_log_filter(None)?;
+    telemetry::set_sample_ratio(None)?;
+
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "tracing.reset",
+        None,
+        json!({}),
+    )
+    .await;
+
+    Ok(Json(current()))
+}
@@ -0,0 +1,162 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::{
+        app_user::{AppUser, AppUserBmc},
+        nostr_profile::NostrProfileBmc,
+        user_activity::{UserActivity, UserActivityBmc},
+    },
+    router::handlers::{activity::ActivityParams, lnbits::generate_api_key, NameOrPubkey},
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ListUsersParams {
+    /// List soft deleted users instead
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// A user along with the display name of their nostr profile, if hermes
+/// fetched one.
+#[derive(Debug, Serialize)]
+pub struct ListedUser {
+    #[serde(flatten)]
+    pub user: AppUser,
+    pub display_name: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_users(
+    Query(params): Query<ListUsersParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ListedUser>>, AppError> {
+    info!("admin list users called with {:?}", params);
+    let users = AppUserBmc::list(&state.mm, params.deleted).await?;
+    // only what's stored, the profile_refresh job fetches the rest
+    let pubkeys: Vec<String> = users.iter().map(|user| user.pubkey.clone()).collect();
+    let display_names = NostrProfileBmc::get_many(&state.mm, &pubkeys).await?;
+    let users = users
+        .into_iter()
+        .map(|user| ListedUser {
+            display_name: display_names.get(&user.pubkey).cloned(),
+            user,
+        })
+        .collect();
+
+    Ok(Json(users))
+}
+
+/// Soft deletes a user, their lightning address and NIP-05 name stop
+/// resolving but nothing they received is removed.
+#[axum_macros::debug_handler]
+pub async fn handle_delete_user(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    info!("admin delete user called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+
+    AppUserBmc::delete(&state.mm, user.id).await?;
+    state.cache.invalidate(&username);
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "user.delete",
+        Some(&username),
+        json!({}),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_restore_user(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AppUser>, AppError> {
+    info!("admin restore user called for {}", username);
+    let user = AppUserBmc::get_deleted_by_name(&state.mm, &username)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::UserNotFound,
+                anyhow::anyhow!("No deleted user named {username}"),
+            )
+        })?;
+
+    AppUserBmc::restore(&state.mm, user.id).await?;
+    state.cache.invalidate(&username);
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "user.restore",
+        Some(&username),
+        json!({}),
+    )
+    .await;
+
+    Ok(Json(user))
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    pub api_key: String,
+}
+
+/// Issues a new LNbits api key for a user, revoking the previous one. The key
+/// is only returned once, hermes keeps just its hash.
+#[axum_macros::debug_handler]
+pub async fn handle_create_api_key(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<ApiKeyResponse>, AppError> {
+    info!("admin create api key called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+
+    let (api_key, hash) = generate_api_key();
+    AppUserBmc::set_api_key_hash(&state.mm, user.id, &hash).await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "user.api_key.create",
+        Some(&username),
+        json!({}),
+    )
+    .await;
+
+    Ok(Json(ApiKeyResponse { api_key }))
+}
+
+/// The same numbers a user sees on `GET /activity`.
+#[axum_macros::debug_handler]
+pub async fn handle_user_activity(
+    Path(username): Path<String>,
+    Query(params): Query<ActivityParams>,
+    State(state): State<AppState>,
+) -> Result<Json<UserActivity>, AppError> {
+    info!(
+        "admin user activity called for {username} with {:?}",
+        params
+    );
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+
+    let activity = UserActivityBmc::get(&state.mm, user.id, params.days()).await?;
+
+    Ok(Json(activity))
+}
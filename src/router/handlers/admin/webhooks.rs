@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+use url::Url;
+
+use crate::{
+    audit,
+    error::{AppError, ErrorCode},
+    model::{
+        app_user::AppUserBmc,
+        webhook::{Webhook, WebhookBmc, WebhookForCreate},
+    },
+    router::handlers::NameOrPubkey,
+    state::AppState,
+    webhooks::random_id,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookParams {
+    pub url: Url,
+    /// Generated if not given
+    pub secret: Option<String>,
+}
+
+/// The secret is only returned when the webhook is created.
+#[derive(Serialize)]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_webhooks(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Webhook>>, AppError> {
+    info!("admin list webhooks called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+    let webhooks = WebhookBmc::list_for_user(&state.mm, user.id).await?;
+
+    Ok(Json(webhooks))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_create_webhook(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Json(params): Json<CreateWebhookParams>,
+) -> Result<Json<CreateWebhookResponse>, AppError> {
+    info!("admin create webhook called for {}", username);
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+
+    let secret = params.secret.unwrap_or_else(random_id);
+    let id = WebhookBmc::create(
+        &state.mm,
+        WebhookForCreate {
+            app_user_id: user.id,
+            url: params.url.to_string(),
+            secret: secret.clone(),
+        },
+    )
+    .await?;
+    let webhook = WebhookBmc::get(&state.mm, id).await?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "webhook.create",
+        Some(&username),
+        json!({ "webhookId": id, "url": webhook.url }),
+    )
+    .await;
+
+    Ok(Json(CreateWebhookResponse { webhook, secret }))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_delete_webhook(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    info!("admin delete webhook {id} called");
+    WebhookBmc::delete(&state.mm, id)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::NotFound, e))?;
+    audit::record(
+        &state.mm,
+        audit::admin_actor(),
+        "webhook.delete",
+        Some(&id.to_string()),
+        json!({}),
+    )
+    .await;
+
+    Ok(())
+}
@@ -0,0 +1,304 @@
+//! Custodial users keep their receipts in the federation under hermes custody
+//! and claim them, as ecash or a lightning payment, whenever they like.
+
+use std::str::FromStr;
+
+use axum::{extract::State, http::HeaderMap, Json};
+use fedimint_core::Amount;
+use fedimint_mint_client::MintClientModule;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+use utoipa::ToSchema;
+
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    federation_stats,
+    federations::{fee_reserve, nothing_sent, pay_bolt11, UnresolvedPayment},
+    model::{
+        app_user::AppUser,
+        balance::BalanceBmc,
+        withdrawal::{WithdrawalBmc, WithdrawalForCreate},
+    },
+    router::handlers::{federation_client, lnbits::authenticate, lnurlp::callback::notes_expiry},
+    state::AppState,
+};
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceResponse {
+    pub federation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimParams {
+    /// In millisatoshis, the whole balance if not given
+    pub amount: Option<u64>,
+    /// The user's current federation if not given
+    pub federation_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimResponse {
+    pub operation_id: String,
+    /// In millisatoshis
+    pub amount: u64,
+    pub notes: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawParams {
+    /// BOLT11 invoice with an amount
+    pub invoice: String,
+    /// The user's current federation if not given
+    pub federation_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawResponse {
+    pub operation_id: String,
+    /// Taken from the balance in millisatoshis, the invoice amount plus the
+    /// reserve for lightning fees
+    pub amount: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/balance",
+    tag = "balance",
+    params(("X-Api-Key" = String, Header, description = "Key issued by an admin")),
+    responses(
+        (status = 200, description = "Balance per federation", body = [BalanceResponse]),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_balance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<BalanceResponse>>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!("balance called for {}", user.name);
+
+    let balances = BalanceBmc::list_for_user(&state.mm, user.id).await?;
+
+    Ok(Json(
+        balances
+            .into_iter()
+            .map(|balance| BalanceResponse {
+                federation_id: balance.federation_id,
+                amount: balance.amount,
+            })
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/balance/claim",
+    tag = "balance",
+    params(("X-Api-Key" = String, Header, description = "Key issued by an admin")),
+    request_body = ClaimParams,
+    responses(
+        (status = 200, description = "Ecash for the claimed amount", body = ClaimResponse),
+        (status = 400, description = "Insufficient balance or unknown federation", body = ErrorResponse),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_claim(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(params): Json<ClaimParams>,
+) -> Result<Json<ClaimResponse>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!(
+        "claim called for {} with amount {:?}",
+        user.name, params.amount
+    );
+
+    let federation_id = params.federation_id.unwrap_or(user.federation_id.clone());
+    let client = federation_client(&state, &federation_id)?;
+    let amount = match params.amount {
+        Some(amount) => amount,
+        None => BalanceBmc::get(&state.mm, user.id, &federation_id).await? as u64,
+    };
+    if amount == 0 {
+        return Err(AppError::from_code(
+            ErrorCode::InsufficientBalance,
+            anyhow::anyhow!("Nothing to claim"),
+        ));
+    }
+    let amount_msats = debit(&state, &user, &federation_id, amount).await?;
+
+    let mint = client.get_first_module::<MintClientModule>();
+    let (operation_id, notes) = match mint
+        .spend_notes(
+            Amount::from_msats(amount),
+            notes_expiry(user.notes_expiry_secs),
+            (),
+        )
+        .await
+    {
+        Ok(spent) => spent,
+        Err(e) => {
+            error!("Spending claim for {} failed: {e:#}", user.name);
+            BalanceBmc::credit(&state.mm, user.id, &federation_id, amount_msats).await?;
+            return Err(e.into());
+        }
+    };
+    federation_stats::notes_issued(&state.mm, &federation_id, amount).await;
+
+    WithdrawalBmc::create(
+        &state.mm,
+        WithdrawalForCreate {
+            app_user_id: user.id,
+            federation_id,
+            amount: amount_msats,
+            kind: "ecash".to_string(),
+            operation_id: operation_id.to_string(),
+            bolt11: None,
+            error: None,
+        },
+    )
+    .await?;
+
+    Ok(Json(ClaimResponse {
+        operation_id: operation_id.to_string(),
+        amount,
+        notes: notes.to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/balance/withdraw",
+    tag = "balance",
+    params(("X-Api-Key" = String, Header, description = "Key issued by an admin")),
+    request_body = WithdrawParams,
+    responses(
+        (status = 200, description = "Invoice paid", body = WithdrawResponse),
+        (status = 400, description = "Invalid invoice, insufficient balance or unknown federation", body = ErrorResponse),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_withdraw(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(params): Json<WithdrawParams>,
+) -> Result<Json<WithdrawResponse>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!("withdraw called for {}", user.name);
+
+    let invoice = Bolt11Invoice::from_str(&params.invoice)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, anyhow::anyhow!("{e}")))?;
+    let Some(invoice_amount) = invoice.amount_milli_satoshis() else {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Invoice must have an amount"),
+        ));
+    };
+    if invoice.is_expired() {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Invoice is expired"),
+        ));
+    }
+
+    let federation_id = params.federation_id.unwrap_or(user.federation_id.clone());
+    let client = federation_client(&state, &federation_id)?;
+    let amount = invoice_amount + fee_reserve(invoice_amount);
+    let amount_msats = debit(&state, &user, &federation_id, amount).await?;
+
+    let operation_id = match pay_bolt11(&state.mm, &client, invoice).await {
+        Ok(operation_id) => operation_id,
+        Err(e) if nothing_sent(&e) => {
+            error!("Withdrawal for {} failed: {e:#}", user.name);
+            BalanceBmc::credit(&state.mm, user.id, &federation_id, amount_msats).await?;
+            return Err(e.into());
+        }
+        // the payment may have gone out, so the balance isn't given back
+        Err(e) => {
+            error!(
+                alert = true,
+                "Withdrawal for {} is unresolved, left for an operator: {e:#}", user.name
+            );
+            metrics::counter!("dead_letters_total", "channel" => "withdrawal").increment(1);
+            let operation_id = e
+                .downcast_ref::<UnresolvedPayment>()
+                .map(|unresolved| unresolved.0.to_string())
+                .unwrap_or_default();
+            WithdrawalBmc::create(
+                &state.mm,
+                WithdrawalForCreate {
+                    app_user_id: user.id,
+                    federation_id,
+                    amount: amount_msats,
+                    kind: "lightning".to_string(),
+                    operation_id,
+                    bolt11: Some(params.invoice),
+                    error: Some(format!("{e:#}")),
+                },
+            )
+            .await?;
+            return Err(e.into());
+        }
+    };
+
+    WithdrawalBmc::create(
+        &state.mm,
+        WithdrawalForCreate {
+            app_user_id: user.id,
+            federation_id,
+            amount: amount_msats,
+            kind: "lightning".to_string(),
+            operation_id: operation_id.to_string(),
+            bolt11: Some(params.invoice),
+            error: None,
+        },
+    )
+    .await?;
+
+    Ok(Json(WithdrawResponse {
+        operation_id: operation_id.to_string(),
+        amount,
+    }))
+}
+
+async fn debit(
+    state: &AppState,
+    user: &AppUser,
+    federation_id: &str,
+    amount: u64,
+) -> Result<i64, AppError> {
+    let amount_msats = msats(amount)?;
+    if !BalanceBmc::debit(&state.mm, user.id, federation_id, amount_msats).await? {
+        return Err(AppError::from_code(
+            ErrorCode::InsufficientBalance,
+            anyhow::anyhow!("Balance is less than {amount} msats"),
+        ));
+    }
+
+    Ok(amount_msats)
+}
+
+/// An amount as the database stores it, anything past `i64::MAX` would wrap
+/// negative and turn a debit into a credit.
+fn msats(amount: u64) -> Result<i64, AppError> {
+    i64::try_from(amount).map_err(|_| {
+        AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Amount {amount} is too large"),
+        )
+    })
+}
@@ -0,0 +1,386 @@
+//! Lets a wallet pull the ecash sent to its pubkey over http, for when the DM
+//! carrying it never arrived, and get notes that expired unredeemed reissued.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method},
+    Json,
+};
+use fedimint_client::{oplog::UpdateStreamOrOutcome, ClientArc};
+use fedimint_core::{core::OperationId, Amount};
+use fedimint_mint_client::{MintClientModule, OOBNotes, SpendOOBState};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{error, info, instrument};
+use utoipa::ToSchema;
+
+use crate::{
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    federation_stats,
+    federations::{nothing_sent, transfer},
+    model::{
+        app_user::AppUserBmc,
+        dead_letter::{DeadLetterBmc, DeadLetterForCreate},
+        ecash_payout::{EcashPayout, EcashPayoutBmc, EcashPayoutForCreate},
+        invoice::InvoiceBmc,
+        payout_batch::PayoutBatchBmc,
+    },
+    router::{
+        handlers::{
+            federation_client,
+            lnurlp::callback::{notes_expiry, record_payout, REISSUE_CHANNEL},
+            NoteFormat,
+        },
+        nip98,
+    },
+    state::AppState,
+};
+
+const CLAIM_PATH: &str = "/ecash/claim";
+const REISSUE_PATH: &str = "/ecash/reissue";
+/// How long to replay a spend operation's updates before assuming it is
+/// still open.
+const OPERATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingEcash {
+    pub operation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    /// `fedimint` notes or a `cashu` token
+    pub note_format: String,
+    pub notes: String,
+    pub invoice_id: Option<i32>,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub created_at: OffsetDateTime,
+}
+
+#[utoipa::path(
+    post,
+    path = "/ecash/claim",
+    tag = "ecash",
+    params(("Authorization" = String, Header, description = "NIP-98 `Nostr` auth event for this url")),
+    responses(
+        (status = 200, description = "Unclaimed ecash, now marked delivered", body = [PendingEcash]),
+        (status = 401, description = "Missing or invalid auth event", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_claim(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Vec<PendingEcash>>, AppError> {
+    let pubkey = nip98::authenticate(&headers, &Method::POST, CLAIM_PATH, &body)?;
+    info!("ecash claim called with pubkey: {pubkey}");
+
+    let payouts = EcashPayoutBmc::claim_all(
+        &state.mm,
+        &pubkey.to_string(),
+        CONFIG.notes_expiry.as_secs() as i64,
+    )
+    .await?;
+
+    // nothing left to redeliver by DM
+    for payout in &payouts {
+        if let Some(invoice_id) = payout.invoice_id {
+            let user = AppUserBmc::get(&state.mm, payout.app_user_id).await?;
+            DeadLetterBmc::resolve_for_invoice(&state.mm, invoice_id, &user.dm_type).await?;
+        }
+        if let Some(batch_id) = payout.payout_batch_id {
+            PayoutBatchBmc::mark_delivered(&state.mm, batch_id).await?;
+        }
+    }
+
+    Ok(Json(
+        payouts
+            .into_iter()
+            .map(|payout| PendingEcash {
+                operation_id: payout.operation_id,
+                amount: payout.amount,
+                note_format: payout.note_format,
+                notes: payout.notes.into(),
+                invoice_id: payout.invoice_id,
+                created_at: payout.created_at,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReissueParams {
+    /// Operation id the expired notes were sent with
+    pub operation_id: String,
+    /// Federation to reissue into, the original one if not given
+    pub federation_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReissueResponse {
+    pub operation_id: String,
+    pub federation_id: String,
+    /// In millisatoshis, less lightning fees when moved between federations
+    pub amount: u64,
+    pub notes: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/ecash/reissue",
+    tag = "ecash",
+    params(("Authorization" = String, Header, description = "NIP-98 `Nostr` auth event for this url")),
+    request_body = ReissueParams,
+    responses(
+        (status = 200, description = "New notes for the expired payout", body = ReissueResponse),
+        (status = 400, description = "Not fedimint notes or unknown federation", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid auth event", body = ErrorResponse),
+        (status = 404, description = "Unknown payout", body = ErrorResponse),
+        (status = 409, description = "Not expired, redeemed or already reissued", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_reissue(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<ReissueResponse>, AppError> {
+    let pubkey = nip98::authenticate(&headers, &Method::POST, REISSUE_PATH, &body)?;
+    let params: ReissueParams = nip98::parse_body(&body)?;
+    info!(
+        "ecash reissue called with pubkey: {pubkey}, operation: {}",
+        params.operation_id
+    );
+
+    let payout =
+        EcashPayoutBmc::get_for_pubkey(&state.mm, &pubkey.to_string(), &params.operation_id)
+            .await?
+            .ok_or_else(|| {
+                AppError::from_code(ErrorCode::NotFound, anyhow!("Payout does not exist"))
+            })?;
+    if payout.note_format != NoteFormat::Fedimint.to_string() {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Only fedimint notes can be reissued"),
+        ));
+    }
+
+    let source_id = source_federation(&state, &payout).await?;
+    let target_id = params.federation_id.unwrap_or(source_id.clone());
+    let source = federation_client(&state, &source_id)?;
+    let target = federation_client(&state, &target_id)?;
+
+    let mint = source.get_first_module::<MintClientModule>();
+    let spend = mint
+        .subscribe_spend_notes(payout.operation_id.parse()?)
+        .await?;
+    match operation_outcome(spend).await {
+        Some(SpendOOBState::Refunded | SpendOOBState::UserCanceledSuccess) => {}
+        Some(SpendOOBState::Success | SpendOOBState::UserCanceledFailure) => {
+            return Err(AppError::from_code(
+                ErrorCode::ReissueUnavailable,
+                anyhow!("The notes were already redeemed"),
+            ))
+        }
+        _ => {
+            return Err(AppError::from_code(
+                ErrorCode::ReissueUnavailable,
+                anyhow!("The notes haven't expired yet"),
+            ))
+        }
+    }
+
+    if !EcashPayoutBmc::start_reissue(&state.mm, payout.id).await? {
+        return Err(AppError::from_code(
+            ErrorCode::ReissueUnavailable,
+            anyhow!("The payout was already reissued"),
+        ));
+    }
+
+    // the payout stays marked reissued once funds left the source federation
+    let amount = payout.amount as u64;
+    let amount = if source_id == target_id {
+        amount
+    } else {
+        match transfer(&state.mm, &source, &target, amount).await {
+            Ok(received) => received,
+            Err(e) if nothing_sent(&e) => {
+                error!("Moving payout {} to {target_id} failed: {e:#}", payout.id);
+                EcashPayoutBmc::undo_reissue(&state.mm, payout.id).await?;
+                return Err(e.into());
+            }
+            Err(e) => return Err(hand_to_operator(&state, &payout, &target_id, None, e).await),
+        }
+    };
+    let (operation_id, notes) =
+        match reissue_notes(&state, &payout, &target_id, &target, amount).await {
+            Ok(reissued) => reissued,
+            Err(e) if source_id == target_id => {
+                error!(
+                    alert = true,
+                    "Reissuing payout {} into {target_id} failed: {e:#}", payout.id
+                );
+                EcashPayoutBmc::undo_reissue(&state.mm, payout.id).await?;
+                return Err(e.into());
+            }
+            Err(e) => {
+                let received = Some(amount);
+                return Err(hand_to_operator(&state, &payout, &target_id, received, e).await);
+            }
+        };
+    info!("Reissued payout {} into {target_id}", payout.id);
+
+    Ok(Json(ReissueResponse {
+        operation_id: operation_id.to_string(),
+        federation_id: target_id,
+        amount,
+        notes: notes.to_string(),
+    }))
+}
+
+/// Spends fresh notes for a payout in `target_id` and records them as a new
+/// payout the wallet can also claim.
+async fn reissue_notes(
+    state: &AppState,
+    payout: &EcashPayout,
+    target_id: &str,
+    target: &ClientArc,
+    amount: u64,
+) -> anyhow::Result<(OperationId, OOBNotes)> {
+    let user = AppUserBmc::get(&state.mm, payout.app_user_id).await?;
+    let (operation_id, notes) = target
+        .get_first_module::<MintClientModule>()
+        .spend_notes(
+            Amount::from_msats(amount),
+            notes_expiry(user.notes_expiry_secs),
+            (),
+        )
+        .await?;
+    federation_stats::notes_issued(&state.mm, target_id, amount).await;
+
+    record_payout(
+        &state.mm,
+        EcashPayoutForCreate {
+            app_user_id: payout.app_user_id,
+            invoice_id: payout.invoice_id,
+            payout_batch_id: payout.payout_batch_id,
+            operation_id: operation_id.to_string(),
+            amount: amount as i64,
+            note_format: NoteFormat::Fedimint.to_string(),
+            notes: notes.to_string().into(),
+        },
+    )
+    .await;
+
+    Ok((operation_id, notes))
+}
+
+/// Records a reissue that failed after its funds left the source federation,
+/// so it can't be retried into paying out twice, and dead letters it for an
+/// operator if it was an invoice's payout. `received` is what arrived in the
+/// target federation, `None` if it's unknown whether the transfer went
+/// through.
+async fn hand_to_operator(
+    state: &AppState,
+    payout: &EcashPayout,
+    target_id: &str,
+    received: Option<u64>,
+    e: anyhow::Error,
+) -> AppError {
+    error!(
+        alert = true,
+        "Reissuing payout {} into {target_id} failed after moving its funds: {e:#}", payout.id
+    );
+    let received_msats = received.map(|msats| msats as i64);
+    if let Err(e) =
+        EcashPayoutBmc::mark_reissue_stuck(&state.mm, payout.id, target_id, received_msats).await
+    {
+        error!(
+            "Recording stuck reissue of payout {} failed: {e:#}",
+            payout.id
+        );
+    }
+    if let Some(invoice_id) = payout.invoice_id {
+        metrics::counter!("dead_letters_total", "channel" => REISSUE_CHANNEL).increment(1);
+        let dead_letter = DeadLetterForCreate {
+            invoice_id,
+            channel: REISSUE_CHANNEL.to_string(),
+            operation_id: Some(payout.operation_id.clone()),
+            notes: None,
+            error: format!("{e:#}"),
+        };
+        if let Err(e) = DeadLetterBmc::create(&state.mm, dead_letter).await {
+            error!(
+                "Dead lettering reissue of payout {} failed: {e:#}",
+                payout.id
+            );
+        }
+    }
+
+    AppError::from_code(
+        ErrorCode::ReissueUnavailable,
+        anyhow!("The reissue didn't finish and was handed to an operator"),
+    )
+}
+
+/// Finishes a reissue dead letter: once the transfer is known to have
+/// arrived, the notes are spent in the target federation for the wallet to
+/// claim.
+pub(crate) async fn replay_reissue(state: &AppState, operation_id: &str) -> anyhow::Result<()> {
+    let payout = EcashPayoutBmc::get_by_operation_id(&state.mm, operation_id).await?;
+    let Some(target_id) = payout.reissue_federation_id.clone() else {
+        return Ok(());
+    };
+    let Some(received) = payout.reissue_msats else {
+        bail!(
+            "Unknown whether payout {}'s transfer to {target_id} arrived, set reissue_msats once checked",
+            payout.id
+        );
+    };
+    let target = federation_client(state, &target_id).map_err(|e| e.error)?;
+    reissue_notes(state, &payout, &target_id, &target, received as u64).await?;
+    EcashPayoutBmc::clear_reissue_stuck(&state.mm, payout.id).await?;
+    info!("Reissued stuck payout {} into {target_id}", payout.id);
+
+    Ok(())
+}
+
+/// The federation a payout's notes were spent from.
+async fn source_federation(state: &AppState, payout: &EcashPayout) -> anyhow::Result<String> {
+    if let Some(invoice_id) = payout.invoice_id {
+        return Ok(InvoiceBmc::get(&state.mm, invoice_id).await?.federation_id);
+    }
+    if let Some(batch_id) = payout.payout_batch_id {
+        return Ok(PayoutBatchBmc::get(&state.mm, batch_id)
+            .await?
+            .federation_id);
+    }
+    Err(anyhow!("Payout {} has no invoice or batch", payout.id))
+}
+
+/// The final state of a spend, or `None` if it is still open.
+async fn operation_outcome(
+    subscription: UpdateStreamOrOutcome<SpendOOBState>,
+) -> Option<SpendOOBState> {
+    let mut stream = subscription.into_stream();
+    let mut last = None;
+    let drained = tokio::time::timeout(OPERATION_TIMEOUT, async {
+        while let Some(op_state) = stream.next().await {
+            last = Some(op_state);
+        }
+    })
+    .await;
+
+    drained.ok().and(last)
+}
@@ -0,0 +1,72 @@
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::{
+    error::{AppError, ErrorCode},
+    events::InvoiceUpdate,
+    model::invoice::InvoiceBmc,
+    router::handlers::lnurlp::verify::token_matches,
+    state::AppState,
+};
+
+pub mod sse;
+pub mod ws;
+
+/// Token of the verify url of the operation streamed over SSE.
+#[derive(Debug, Deserialize)]
+pub struct TokenParams {
+    pub token: Option<String>,
+}
+
+/// Which invoice updates a push subscriber wants to receive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceSubscriptionParams {
+    pub operation_id: Option<String>,
+    /// Token of the operation's verify url, required with `operation_id`
+    pub token: Option<String>,
+    pub username: Option<String>,
+}
+
+impl InvoiceSubscriptionParams {
+    pub fn is_empty(&self) -> bool {
+        self.operation_id.is_none() && self.username.is_none()
+    }
+
+    pub fn matches(&self, update: &InvoiceUpdate) -> bool {
+        self.operation_id
+            .as_ref()
+            .map_or(true, |op_id| *op_id == update.operation_id)
+            && self
+                .username
+                .as_ref()
+                .map_or(true, |name| *name == update.username)
+    }
+
+    /// A subscription to a single operation is done once it reaches a terminal state.
+    pub fn is_finished_by(&self, update: &InvoiceUpdate) -> bool {
+        self.operation_id.is_some() && update.state.is_terminal()
+    }
+}
+
+/// Checks that `token` is the one of the operation's verify url. A wrong
+/// token looks the same as an unknown invoice.
+pub(super) async fn authorize_operation(
+    state: &AppState,
+    op_id: &str,
+    token: Option<&str>,
+) -> Result<(), AppError> {
+    let not_found = || {
+        AppError::from_code(
+            ErrorCode::InvoiceNotFound,
+            anyhow!("No invoice found with op_id: {op_id}"),
+        )
+    };
+    let invoice = InvoiceBmc::get_by_op_id(&state.mm, op_id)
+        .await
+        .map_err(|_| not_found())?;
+    match token {
+        Some(token) if token_matches(invoice.app_user_id, op_id, token) => Ok(()),
+        _ => Err(not_found()),
+    }
+}
@@ -0,0 +1,69 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::{error::AppError, state::AppState};
+
+use super::{authorize_operation, ws::current_state, InvoiceSubscriptionParams, TokenParams};
+
+#[axum_macros::debug_handler]
+pub async fn handle_sse(
+    Path(operation_id): Path<String>,
+    Query(query): Query<TokenParams>,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    info!("sse called with operation_id: {}", operation_id);
+    authorize_operation(&state, &operation_id, query.token.as_deref()).await?;
+    let params = InvoiceSubscriptionParams {
+        operation_id: Some(operation_id),
+        token: query.token,
+        username: None,
+    };
+
+    // subscribe before reading the current state so no transition is missed
+    let rx = state.invoice_events.subscribe();
+    let initial = current_state(&state, &params).await;
+
+    // the stream ends on shutdown, an open one would hold up graceful shutdown
+    let shutdown = state.shutdown.clone();
+    let events = stream::unfold(
+        (rx, initial, params, shutdown, false),
+        |(mut rx, mut pending, params, shutdown, done)| async move {
+            if done {
+                return None;
+            }
+
+            let update = match pending.take() {
+                Some(update) => update,
+                None => loop {
+                    let received = tokio::select! {
+                        _ = shutdown.cancelled() => return None,
+                        received = rx.recv() => received,
+                    };
+                    match received {
+                        Ok(update) if params.matches(&update) => break update,
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(n)) => warn!("sse subscriber lagged by {n} updates"),
+                        Err(RecvError::Closed) => return None,
+                    }
+                },
+            };
+
+            let done = params.is_finished_by(&update);
+            let event = Event::default()
+                .event("invoice")
+                .json_data(&update)
+                .expect("serializing update can't fail");
+
+            Some((Ok(event), (rx, pending, params, shutdown, done)))
+        },
+    );
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,112 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::HeaderMap,
+    response::Response,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::{
+    error::{AppError, ErrorCode},
+    events::InvoiceUpdate,
+    model::{app_user::AppUserBmc, invoice::InvoiceBmc},
+    router::handlers::lnbits::authenticate,
+    state::AppState,
+};
+
+use super::{authorize_operation, InvoiceSubscriptionParams};
+
+#[axum_macros::debug_handler]
+pub async fn handle_ws(
+    ws: WebSocketUpgrade,
+    Query(params): Query<InvoiceSubscriptionParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info!(
+        "ws called with operation_id: {:?}, username: {:?}",
+        params.operation_id, params.username
+    );
+    if params.is_empty() {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("operationId or username is required"),
+        ));
+    }
+    if let Some(op_id) = &params.operation_id {
+        authorize_operation(&state, op_id, params.token.as_deref()).await?;
+    }
+    // every invoice of a user is only streamed to the user themselves
+    if let Some(username) = &params.username {
+        let user = authenticate(&state, &headers).await?;
+        if user.name != *username {
+            return Err(AppError::from_code(
+                ErrorCode::Unauthorized,
+                anyhow!("The api key is not for {username}"),
+            ));
+        }
+    }
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, params)))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState, params: InvoiceSubscriptionParams) {
+    // subscribe before reading the current state so no transition is missed
+    let mut rx = state.invoice_events.subscribe();
+
+    if let Some(update) = current_state(&state, &params).await {
+        if send_update(&mut socket, &update).await.is_err() || params.is_finished_by(&update) {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            // an open socket would hold up graceful shutdown
+            _ = state.shutdown.cancelled() => break,
+            update = rx.recv() => match update {
+                Ok(update) if params.matches(&update) => {
+                    if send_update(&mut socket, &update).await.is_err()
+                        || params.is_finished_by(&update)
+                    {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => warn!("ws subscriber lagged by {n} updates"),
+                Err(RecvError::Closed) => break,
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+
+    let _ = socket.close().await;
+}
+
+/// Looks up the invoice state when subscribing to a single operation.
+pub(super) async fn current_state(
+    state: &AppState,
+    params: &InvoiceSubscriptionParams,
+) -> Option<InvoiceUpdate> {
+    let op_id = params.operation_id.as_ref()?;
+    let invoice = InvoiceBmc::get_by_op_id(&state.mm, op_id).await.ok()?;
+    let user = AppUserBmc::get(&state.mm, invoice.app_user_id).await.ok()?;
+
+    Some(InvoiceUpdate {
+        operation_id: invoice.op_id,
+        username: user.name,
+        state: invoice.state,
+    })
+}
+
+async fn send_update(socket: &mut WebSocket, update: &InvoiceUpdate) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(update).expect("serializing update can't fail");
+    socket.send(Message::Text(json)).await
+}
@@ -0,0 +1,77 @@
+//! One-time claim links for users with the `link` note format, for recipients
+//! without a DM channel they can keep ecash in.
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::prelude::rand::rngs::OsRng;
+use nostr::prelude::rand::RngCore;
+use serde::Serialize;
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+use crate::{
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::gift::GiftBmc,
+    state::AppState,
+};
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GiftResponse {
+    pub operation_id: String,
+    /// In millisatoshis
+    pub amount: i64,
+    pub notes: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/gift/{token}",
+    tag = "gift",
+    params(("token" = String, Path, description = "Token from the claim link")),
+    responses(
+        (status = 200, description = "The gift's ecash, the link no longer works", body = GiftResponse),
+        (status = 410, description = "Unknown, already claimed or expired", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_claim_gift(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<GiftResponse>, AppError> {
+    let default_expiry = CONFIG.notes_expiry.as_secs() as i64;
+    let gift = GiftBmc::claim(&state.mm, &hash_token(&token), default_expiry)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::GiftUnavailable,
+                anyhow::anyhow!("Gift was already claimed or has expired"),
+            )
+        })?;
+    info!("Gift for invoice {} claimed", gift.invoice_id);
+
+    Ok(Json(GiftResponse {
+        operation_id: gift.operation_id,
+        amount: gift.amount,
+        notes: gift.notes.into(),
+    }))
+}
+
+/// Generates a new claim token, returning it along with the hash to store.
+pub fn generate_token() -> (String, String) {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    let token = hex::encode(token);
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::hash(token.as_bytes()).to_string()
+}
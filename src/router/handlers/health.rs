@@ -0,0 +1,99 @@
+use axum::{extract::State, http::StatusCode, Json};
+use nostr_sdk::RelayStatus;
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::state::AppState;
+
+#[derive(Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub healthy: bool,
+    pub detail: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    pub ready: bool,
+    pub database: ComponentHealth,
+    pub federations: ComponentHealth,
+    pub nostr: ComponentHealth,
+}
+
+/// Liveness only tells the orchestrator the process is serving requests.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "Serving requests", body = String))
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_live() -> &'static str {
+    "OK"
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready", body = ReadinessResponse),
+        (status = 503, description = "A dependency is unhealthy", body = ReadinessResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let database = match state.mm.ping().await {
+        Ok(_) => ComponentHealth {
+            healthy: true,
+            detail: "connected".to_string(),
+        },
+        // the error can name hosts and users, it's only for the logs
+        Err(e) => {
+            warn!("Readiness database check failed: {e:#}");
+            ComponentHealth {
+                healthy: false,
+                detail: "unavailable".to_string(),
+            }
+        }
+    };
+
+    let federation_count = state.federations.len();
+    let federations = ComponentHealth {
+        healthy: !state.federations.is_empty(),
+        detail: format!("{federation_count} federation(s) connected"),
+    };
+
+    let relays = state.nostr.relays().await;
+    let mut connected = 0;
+    for relay in relays.values() {
+        if relay.status().await == RelayStatus::Connected {
+            connected += 1;
+        }
+    }
+    let nostr = ComponentHealth {
+        healthy: connected > 0,
+        detail: format!("{connected}/{} relay(s) connected", relays.len()),
+    };
+
+    let ready = database.healthy && federations.healthy && nostr.healthy;
+    if !ready {
+        warn!("readiness check failed");
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(ReadinessResponse {
+            ready,
+            database,
+            federations,
+            nostr,
+        }),
+    )
+}
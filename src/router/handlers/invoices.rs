@@ -0,0 +1,248 @@
+//! Finding an invoice from what a wallet or support has at hand: its payment
+//! hash or the BOLT11 string itself, or many at once for a wallet catching
+//! up after being offline.
+
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+use url::Url;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::{
+        app_user::AppUserBmc,
+        invoice::{Invoice, InvoiceBmc},
+        invoice_state::InvoiceState,
+    },
+    router::{
+        handlers::lnurlp::verify::{token_matches, verify_url},
+        public_url::public_base_url,
+    },
+    state::AppState,
+};
+
+/// Invoices one status query may ask for.
+const MAX_STATUS_QUERIES: usize = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceLookupParams {
+    /// Hex payment hash
+    pub payment_hash: Option<String>,
+    /// BOLT11 invoice, with or without a `lightning:` prefix
+    pub bolt11: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceLookupResponse {
+    pub payment_hash: String,
+    /// In millisatoshis
+    pub amount: i64,
+    pub state: InvoiceState,
+    pub settled: bool,
+    /// LUD-21 verify url to poll, only given to whoever has the invoice
+    /// itself since it names the recipient
+    pub verify: Option<Url>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/invoices/lookup",
+    tag = "invoices",
+    params(InvoiceLookupParams),
+    responses(
+        (status = 200, description = "The invoice's status", body = InvoiceLookupResponse),
+        (status = 400, description = "Neither or both of paymentHash and bolt11, or an invalid bolt11", body = ErrorResponse),
+        (status = 404, description = "Not an invoice of this server", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_lookup(
+    Query(params): Query<InvoiceLookupParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<InvoiceLookupResponse>, AppError> {
+    info!("invoice lookup called with {:?}", params);
+
+    let (invoice, with_bolt11) = match (params.payment_hash, params.bolt11) {
+        (Some(payment_hash), None) => {
+            let payment_hash = payment_hash.to_lowercase();
+            let invoice = InvoiceBmc::find_by_payment_hash(&state.mm, &payment_hash).await?;
+            (invoice, false)
+        }
+        (None, Some(bolt11)) => {
+            let bolt11 = bolt11.trim();
+            let bolt11 = bolt11
+                .strip_prefix("lightning:")
+                .or_else(|| bolt11.strip_prefix("LIGHTNING:"))
+                .unwrap_or(bolt11);
+            let bolt11 = Bolt11Invoice::from_str(bolt11)
+                .map_err(|e| AppError::from_code(ErrorCode::BadRequest, anyhow!("{e}")))?;
+            // stored as hermes encoded it, which is the canonical encoding
+            let invoice = InvoiceBmc::find_by_bolt11(&state.mm, &bolt11.to_string()).await?;
+            (invoice, true)
+        }
+        _ => {
+            return Err(AppError::from_code(
+                ErrorCode::BadRequest,
+                anyhow!("Pass exactly one of paymentHash and bolt11"),
+            ))
+        }
+    };
+    let invoice = invoice.ok_or_else(|| {
+        AppError::from_code(
+            ErrorCode::InvoiceNotFound,
+            anyhow!("No invoice found for the lookup"),
+        )
+    })?;
+
+    let verify = if with_bolt11 {
+        let user = AppUserBmc::get(&state.mm, invoice.app_user_id).await?;
+        let url = verify_url(
+            &public_base_url(&headers),
+            &user.name,
+            invoice.app_user_id,
+            &invoice.op_id,
+        );
+        Some(url.parse()?)
+    } else {
+        None
+    };
+
+    Ok(Json(InvoiceLookupResponse {
+        payment_hash: payment_hash(&invoice)?,
+        amount: invoice.amount,
+        state: invoice.state,
+        settled: invoice.state == InvoiceState::Settled,
+        verify,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatusParams {
+    /// Invoices by the operation id and token of their verify url
+    #[serde(default)]
+    pub operations: Vec<VerifyOperation>,
+    /// Invoices by hex payment hash
+    #[serde(default)]
+    pub payment_hashes: Vec<String>,
+}
+
+/// The last two path segments of a verify url.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyOperation {
+    pub op_id: String,
+    pub token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatusResponse {
+    /// Every invoice found, unknown ones are left out
+    pub invoices: Vec<InvoiceStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceStatus {
+    /// Only given for invoices asked for by operation
+    pub op_id: Option<String>,
+    pub payment_hash: String,
+    /// In millisatoshis
+    pub amount: i64,
+    pub state: InvoiceState,
+    pub settled: bool,
+    /// Hex preimage of a settled invoice asked for by operation, if hermes
+    /// recorded it. Verify fetches a missing one from the federation.
+    pub preimage: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/invoices/status",
+    tag = "invoices",
+    request_body = InvoiceStatusParams,
+    responses(
+        (status = 200, description = "The status of each invoice found", body = InvoiceStatusResponse),
+        (status = 400, description = "More than 100 invoices asked for", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_status(
+    State(state): State<AppState>,
+    Json(params): Json<InvoiceStatusParams>,
+) -> Result<Json<InvoiceStatusResponse>, AppError> {
+    let count = params.operations.len() + params.payment_hashes.len();
+    info!("invoice status called for {count} invoices");
+    if count > MAX_STATUS_QUERIES {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("At most {MAX_STATUS_QUERIES} invoices can be asked for at once"),
+        ));
+    }
+
+    let tokens: HashMap<String, String> = params
+        .operations
+        .into_iter()
+        .map(|op| (op.op_id, op.token))
+        .collect();
+    let op_ids: Vec<String> = tokens.keys().cloned().collect();
+    let payment_hashes: Vec<String> = params
+        .payment_hashes
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect();
+    let invoices = InvoiceBmc::find_many(&state.mm, &op_ids, &payment_hashes).await?;
+
+    let mut statuses = Vec::with_capacity(invoices.len());
+    for invoice in invoices {
+        // a wrong token looks the same as an unknown invoice, like on verify
+        let by_op = tokens
+            .get(&invoice.op_id)
+            .is_some_and(|token| token_matches(invoice.app_user_id, &invoice.op_id, token));
+        let by_hash = invoice
+            .payment_hash
+            .as_ref()
+            .is_some_and(|h| payment_hashes.contains(h));
+        if !by_op && !by_hash {
+            continue;
+        }
+
+        let settled = invoice.state == InvoiceState::Settled;
+        statuses.push(InvoiceStatus {
+            payment_hash: payment_hash(&invoice)?,
+            op_id: by_op.then(|| invoice.op_id.clone()),
+            amount: invoice.amount,
+            state: invoice.state,
+            settled,
+            preimage: invoice.preimage.filter(|_| by_op && settled),
+        });
+    }
+
+    Ok(Json(InvoiceStatusResponse { invoices: statuses }))
+}
+
+fn payment_hash(invoice: &Invoice) -> Result<String, AppError> {
+    match &invoice.payment_hash {
+        Some(payment_hash) => Ok(payment_hash.clone()),
+        // invoices from before payment hashes were stored
+        None => Ok(Bolt11Invoice::from_str(&invoice.bolt11)?
+            .payment_hash()
+            .to_string()),
+    }
+}
@@ -0,0 +1,245 @@
+//! A minimal LNbits compatible wallet api so point of sale apps and other
+//! LNbits integrations can create and check invoices for a hermes user.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::prelude::rand::rngs::OsRng;
+use nostr::prelude::rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::{
+        app_user::{AppUser, AppUserBmc},
+        app_user_relays::AppUserRelaysBmc,
+        balance::BalanceBmc,
+        invoice::InvoiceBmc,
+        invoice_state::InvoiceState,
+        sealed::Sealed,
+    },
+    router::handlers::lnurlp::{
+        callback::{issue_invoice, PayerData},
+        payer_data,
+    },
+    state::AppState,
+};
+
+const API_KEY: &str = "x-api-key";
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreatePaymentParams {
+    pub out: bool,
+    /// Amount in sats
+    pub amount: u64,
+    #[serde(default)]
+    pub memo: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatePaymentResponse {
+    pub payment_hash: String,
+    pub payment_request: String,
+    pub checking_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PaymentStatus {
+    pub paid: bool,
+    pub pending: bool,
+    pub details: PaymentDetails,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PaymentDetails {
+    pub checking_id: String,
+    pub payment_hash: String,
+    pub bolt11: String,
+    /// Amount in msats
+    pub amount: i64,
+    pub status: InvoiceState,
+    /// LUD-12 comment from the payer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Key the payer proved the payment with, see `proofofpayer`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payer_pubkey: Option<String>,
+    /// LUD-18 payer identity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payer_data: Option<payer_data::PayerData>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WalletDetails {
+    pub id: String,
+    pub name: String,
+    /// Balance in msats
+    pub balance: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/payments",
+    tag = "lnbits",
+    params(("X-Api-Key" = String, Header, description = "Key issued by an admin")),
+    request_body = CreatePaymentParams,
+    responses(
+        (status = 201, description = "Invoice created", body = CreatePaymentResponse),
+        (status = 400, description = "Outgoing payment or amount out of range", body = ErrorResponse),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_create_payment(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(params): Json<CreatePaymentParams>,
+) -> Result<(StatusCode, Json<CreatePaymentResponse>), AppError> {
+    let user = authenticate(&state, &headers).await?;
+    info!("lnbits create payment called for {}", user.name);
+
+    if params.out {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Outgoing payments are not supported"),
+        ));
+    }
+
+    let userrelays = AppUserRelaysBmc::get_by_id(&state.mm, user.id).await?;
+    let issued = issue_invoice(
+        &state,
+        userrelays,
+        params.amount.saturating_mul(1_000),
+        params.memo,
+        None,
+        PayerData::default(),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatePaymentResponse {
+            checking_id: issued.payment_hash.clone(),
+            payment_hash: issued.payment_hash,
+            payment_request: issued.bolt11,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/payments/{payment_hash}",
+    tag = "lnbits",
+    params(
+        ("payment_hash" = String, Path, description = "Payment hash of the invoice"),
+        ("X-Api-Key" = String, Header, description = "Key issued by an admin"),
+    ),
+    responses(
+        (status = 200, description = "Payment status", body = PaymentStatus),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+        (status = 404, description = "Unknown payment", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all, fields(payment_hash = %payment_hash))]
+pub async fn handle_check_payment(
+    Path(payment_hash): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PaymentStatus>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+
+    let invoice = InvoiceBmc::get_by_payment_hash(&state.mm, user.id, &payment_hash)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::InvoiceNotFound,
+                anyhow::anyhow!("Payment does not exist"),
+            )
+        })?;
+
+    Ok(Json(PaymentStatus {
+        paid: invoice.state == InvoiceState::Settled,
+        pending: invoice.state == InvoiceState::Pending,
+        details: PaymentDetails {
+            checking_id: payment_hash.clone(),
+            payment_hash,
+            bolt11: invoice.bolt11,
+            amount: invoice.amount,
+            status: invoice.state,
+            comment: invoice.comment.map(Sealed::into_inner),
+            payer_pubkey: invoice.payer_pubkey.map(Sealed::into_inner),
+            payer_data: invoice
+                .payer_data
+                .and_then(|data| serde_json::from_str(&data).ok()),
+        },
+    }))
+}
+
+/// Received funds are forwarded to the user as ecash, so the balance is
+/// zero unless the user is custodial.
+#[utoipa::path(
+    get,
+    path = "/api/v1/wallet",
+    tag = "lnbits",
+    params(("X-Api-Key" = String, Header, description = "Key issued by an admin")),
+    responses(
+        (status = 200, description = "Wallet details", body = WalletDetails),
+        (status = 401, description = "Invalid api key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all)]
+pub async fn handle_wallet(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<WalletDetails>, AppError> {
+    let user = authenticate(&state, &headers).await?;
+    let balance = BalanceBmc::get(&state.mm, user.id, &user.federation_id).await?;
+
+    Ok(Json(WalletDetails {
+        id: user.id.to_string(),
+        name: user.name,
+        balance: balance as u64,
+    }))
+}
+
+/// Generates a new api key, returning it along with the hash to store.
+pub fn generate_api_key() -> (String, String) {
+    let mut key = [0u8; 16];
+    OsRng.fill_bytes(&mut key);
+    let key = hex::encode(key);
+    let hash = hash_api_key(&key);
+    (key, hash)
+}
+
+fn hash_api_key(key: &str) -> String {
+    Sha256::hash(key.as_bytes()).to_string()
+}
+
+pub(crate) async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<AppUser, AppError> {
+    let key = headers
+        .get(API_KEY)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    let user = if key.is_empty() {
+        None
+    } else {
+        AppUserBmc::get_by_api_key_hash(&state.mm, &hash_api_key(key)).await?
+    };
+
+    user.ok_or_else(|| {
+        AppError::from_code(ErrorCode::Unauthorized, anyhow::anyhow!("Invalid api key"))
+    })
+}
@@ -1,49 +1,79 @@
-use std::{str::FromStr, time::Duration};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Result;
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{Path, Query, RawQuery, State},
+    http::HeaderMap,
     Json,
 };
-use fedimint_client::{oplog::UpdateStreamOrOutcome, ClientArc};
-use fedimint_core::{config::FederationId, core::OperationId, task::spawn, Amount};
+use fedimint_client::ClientArc;
+use fedimint_core::{config::FederationId, core::OperationId, Amount};
 use fedimint_ln_client::{LightningClientModule, LnReceiveState};
 use fedimint_mint_client::{MintClientModule, OOBNotes};
 use futures::StreamExt;
-use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
-use nostr::bitcoin::hashes::sha256::Hash as Sha256;
-use nostr::hashes::Hash;
-use nostr::key::{Secp256k1, SecretKey};
-use nostr::prelude::rand::rngs::OsRng;
-use nostr::prelude::rand::RngCore;
-use nostr::secp256k1::XOnlyPublicKey;
-use nostr::{Event, EventBuilder, JsonUtil, Kind};
-use nostr_sdk::Client;
+use lightning_invoice::Bolt11Invoice;
+use nostr::secp256k1::{PublicKey, XOnlyPublicKey};
+use nostr::{Event, EventBuilder, JsonUtil, Kind, Tag};
+use nostr_sdk::{Client, Options};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tracing::{error, info};
-use url::Url;
-use xmpp::{parsers::message::MessageType, Jid};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, info_span, instrument, warn, Instrument, Span};
+use url::{Host, Url};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::model::zap::{Zap, ZapBmc};
+use crate::cashu::{self, CashuMint};
+use crate::compute;
+use crate::federation_stats;
+use crate::federations;
+use crate::invoice_log;
+use crate::model::balance::BalanceBmc;
+use crate::model::dead_letter::{DeadLetter, DeadLetterBmc, DeadLetterForCreate};
+use crate::model::ecash_payout::{EcashPayoutBmc, EcashPayoutForCreate};
+use crate::model::gift::{GiftBmc, GiftForCreate};
+use crate::model::invoice_event::InvoiceEventSource;
+use crate::model::is_not_found;
+use crate::model::payout_batch::PayoutBatchBmc;
+use crate::model::receive_limit::ReceiveLimitBmc;
+use crate::model::refund::{RefundBmc, RefundForCreate};
+use crate::model::sealed::Sealed;
+use crate::model::xmpp_outbox::{XmppOutboxBmc, NOSTR_CHANNEL, XMPP_CHANNEL};
+use crate::model::zap::ZapBmc;
 use crate::model::{invoice_state::InvoiceState, ModelManager};
+use crate::nwc;
+use crate::profiles;
+use crate::write_buffer;
 use crate::{
-    config::CONFIG,
-    error::AppError,
+    config::{CONFIG, RUNTIME_CONFIG},
+    error::{AppError, ErrorCode, ErrorResponse},
+    events::InvoiceUpdate,
     model::{
         app_user_relays::AppUserRelaysBmc,
-        invoice::{InvoiceBmc, InvoiceForCreate},
+        invoice::{Invoice, InvoiceBmc, InvoiceForCreate},
+        is_unique_violation,
+    },
+    router::{
+        handlers::{ecash::replay_reissue, gift, nostr::AppUserRelays, NameOrPubkey, NoteFormat},
+        middleware::current_request_id,
+        public_url::public_base_url,
     },
-    router::handlers::{nostr::AppUserRelays, NameOrPubkey},
     state::AppState,
-    utils::{create_xmpp_client, empty_string_as_none},
+    subscriptions::Admission,
+    supervisor::{supervise, RestartPolicy},
+    utils::{empty_string_as_none, resolve_public},
+    xmpp_client,
 };
 
-use super::LnurlStatus;
+use super::{comment, forward, payer_data, verify, well_known, LnurlStatus};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
 #[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
 pub struct LnurlCallbackParams {
     pub amount: u64, // User specified amount in MilliSatoshi
     #[serde(default, deserialize_with = "empty_string_as_none")]
@@ -53,183 +83,943 @@ pub struct LnurlCallbackParams {
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub proofofpayer: Option<String>, // Optional ephemeral secp256k1 public key generated by payer
     #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub payerdata: Option<String>, // Optional LUD-18 payer identity, as json
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     pub nostr: Option<String>, // Optional zap request
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LnurlCallbackSuccessAction {
     pub tag: String,
     pub message: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LnurlCallbackResponse {
     pub status: LnurlStatus,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
     pub pr: String, // BOLT11 invoice
-    pub verify: Url,
+    /// Not given for users whose invoices come from their own node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<Url>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub success_action: Option<LnurlCallbackSuccessAction>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub routes: Option<Vec<String>>,
 }
 
-const MIN_AMOUNT: u64 = 1000;
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
 
+#[utoipa::path(
+    get,
+    path = "/lnurlp/{username}/callback",
+    tag = "lnurlp",
+    params(
+        ("username" = String, Path, description = "Lightning address user"),
+        ("idempotency-key" = Option<String>, Header, description = "Retries with the same key return the same invoice"),
+        LnurlCallbackParams,
+    ),
+    responses(
+        (status = 200, description = "LUD-06 invoice", body = LnurlCallbackResponse),
+        (status = 400, description = "Amount out of range, invalid zap request or payer data", body = ErrorResponse),
+        (status = 403, description = "The user's or instance's receive limit would be exceeded", body = ErrorResponse),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+        (status = 409, description = "Idempotency key reused for another amount", body = ErrorResponse),
+        (status = 429, description = "Too many unpaid invoices for the user or federation, or zaps from the sender", body = ErrorResponse),
+        (status = 502, description = "The user's own node didn't return a usable invoice", body = ErrorResponse),
+        (status = 503, description = "Overloaded or shutting down", body = ErrorResponse),
+    )
+)]
 #[axum_macros::debug_handler]
+#[instrument(skip_all, fields(username = %username))]
 pub async fn handle_callback(
     Path(username): Path<String>,
     Query(params): Query<LnurlCallbackParams>,
+    RawQuery(query): RawQuery,
     State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Result<Json<LnurlCallbackResponse>, AppError> {
     info!("callback called with username: {}", username);
-    if params.amount < MIN_AMOUNT {
-        return Err(AppError {
-            error: anyhow::anyhow!("Amount < MIN_AMOUNT"),
-            status: StatusCode::BAD_REQUEST,
-        });
+    // verify nostr param is a signed zap request
+    let zap_request = params
+        .nostr
+        .as_deref()
+        .map(|n| {
+            Event::from_json(n)
+                .ok()
+                .filter(|e| e.kind == Kind::ZapRequest && e.verify().is_ok())
+                .ok_or_else(|| {
+                    AppError::from_code(
+                        ErrorCode::InvalidNostrEvent,
+                        anyhow::anyhow!("Invalid nostr event"),
+                    )
+                })
+        })
+        .transpose()?;
+
+    let nip05relays = match state.cache.callback.get(&username) {
+        Some(cached) => {
+            metrics::counter!("user_cache_lookups_total", "cache" => "callback", "result" => "hit")
+                .increment(1);
+            cached
+        }
+        None => {
+            metrics::counter!("user_cache_lookups_total", "cache" => "callback", "result" => "miss")
+                .increment(1);
+            // a failing database isn't the payer's fault, only a missing user is
+            let nip05relays = AppUserRelaysBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+                .await
+                .map_err(|e| {
+                    let code = if is_not_found(&e) {
+                        ErrorCode::UserNotFound
+                    } else {
+                        ErrorCode::Internal
+                    };
+                    AppError::from_code(code, e)
+                })?;
+            state.cache.callback.insert(&username, nip05relays.clone());
+            nip05relays
+        }
+    };
+    if let Some(target) = nip05relays.forward_to.as_deref() {
+        let response = forward::callback(target, &query.unwrap_or_default(), params.amount)
+            .await
+            .map_err(|e| AppError::from_code(ErrorCode::NodeUnavailable, e))?;
+        return Ok(Json(response));
     }
 
-    // verify nostr param is a zap request
-    if params
-        .nostr
-        .as_ref()
-        .is_some_and(|n| Event::from_json(n).is_ok_and(|e| e.kind == Kind::ZapRequest))
+    // lets the payer claim a refund if the payment can't be delivered
+    let payer_pubkey = params
+        .proofofpayer
+        .map(|key| {
+            PublicKey::from_str(&key)
+                .map(|key| key.to_string())
+                .map_err(|e| {
+                    AppError::from_code(
+                        ErrorCode::BadRequest,
+                        anyhow::anyhow!("Invalid proofofpayer key: {e}"),
+                    )
+                })
+        })
+        .transpose()?;
+
+    // wallets retrying a request get the invoice they were already given,
+    // without it counting against the sender's rate limit again
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+        .or_else(|| params.nonce.clone())
+        .filter(|k| k.len() <= 255);
+    if let Some(key) = idempotency_key.as_ref() {
+        if let Some(existing) =
+            InvoiceBmc::get_by_idempotency_key(&state.mm, nip05relays.app_user_id, key).await?
+        {
+            let issued = reuse_invoice(existing, params.amount)?;
+            return Ok(Json(callback_response(
+                &public_base_url(&headers),
+                &username,
+                nip05relays.app_user_id,
+                &issued.op_id,
+                issued.bolt11,
+            )?));
+        }
+    }
+
+    check_zap_sender(
+        &state,
+        zap_request.as_ref(),
+        payer_pubkey.as_deref(),
+        params.amount,
+    )?;
+
+    let comment = comment::moderate(params.comment, &username).await?;
+    let payer_data = payer_data::parse(params.payerdata)?;
+
+    let app_user_id = nip05relays.app_user_id;
+    let issued = issue_invoice(
+        &state,
+        nip05relays,
+        params.amount,
+        // fedimint 0.2 can only put a plain description on the invoice
+        well_known::payment_description(&username),
+        idempotency_key,
+        PayerData {
+            zap_request: params.nostr,
+            pubkey: payer_pubkey,
+            comment,
+            payer_data,
+        },
+    )
+    .await?;
+
+    Ok(Json(callback_response(
+        &public_base_url(&headers),
+        &username,
+        app_user_id,
+        &issued.op_id,
+        issued.bolt11,
+    )?))
+}
+
+fn callback_response(
+    base_url: &str,
+    username: &str,
+    app_user_id: i32,
+    op_id: &str,
+    pr: String,
+) -> Result<LnurlCallbackResponse> {
+    let verify_url = verify::verify_url(base_url, username, app_user_id, op_id);
+
+    Ok(LnurlCallbackResponse {
+        pr,
+        success_action: None,
+        status: LnurlStatus::Ok,
+        reason: None,
+        verify: Some(verify_url.parse()?),
+        routes: Some(vec![]),
+    })
+}
+
+/// What the payer sent along with a payment request.
+#[derive(Debug, Default)]
+pub(crate) struct PayerData {
+    pub zap_request: Option<String>,
+    /// Key the payment is refundable to
+    pub pubkey: Option<String>,
+    pub comment: Option<String>,
+    /// LUD-18 payer identity, as json
+    pub payer_data: Option<String>,
+}
+
+/// An invoice handed out to a payer.
+pub(crate) struct IssuedInvoice {
+    pub op_id: String,
+    pub bolt11: String,
+    pub payment_hash: String,
+}
+
+/// Keeps one nostr account or payer key from flooding a user with tiny zaps
+/// and the receipts and DMs that come with them: zaps below `MIN_ZAP_MSATS`
+/// are refused, and payments naming a sender are rate limited per sender.
+fn check_zap_sender(
+    state: &AppState,
+    zap_request: Option<&Event>,
+    payer_pubkey: Option<&str>,
+    amount: u64,
+) -> Result<(), AppError> {
+    if zap_request.is_some() {
+        if let Some(min) = RUNTIME_CONFIG
+            .load()
+            .min_zap_msats
+            .filter(|min| amount < *min)
+        {
+            return Err(AppError::from_code(
+                ErrorCode::AmountTooLow,
+                anyhow::anyhow!("Zaps must be at least {min} msats"),
+            ));
+        }
+    }
+
+    let sender = zap_request
+        .map(|request| request.pubkey.to_string())
+        .or(payer_pubkey.map(|key| key.to_string()));
+    if let Some(sender) = sender {
+        if !state.rate_limiter.check_zap_sender(&sender) {
+            warn!("rate limited zaps from {sender}");
+            metrics::counter!("zaps_throttled_total").increment(1);
+            return Err(AppError::from_code(
+                ErrorCode::RateLimited,
+                anyhow::anyhow!("Too many zaps from this sender, try again later"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuses new invoices while the federation or user already has as many
+/// unpaid ones as allowed, so a flood of callbacks can't pile up invoices.
+async fn check_pending_caps(state: &AppState, nip05relays: &AppUserRelays) -> Result<(), AppError> {
+    let runtime = RUNTIME_CONFIG.load();
+    if runtime.max_pending_invoices_per_federation.is_none()
+        && runtime.max_pending_invoices_per_user.is_none()
     {
-        return Err(AppError {
-            error: anyhow::anyhow!("Invalid nostr event"),
-            status: StatusCode::BAD_REQUEST,
-        });
+        return Ok(());
+    }
+
+    let (federation, user) = InvoiceBmc::count_pending(
+        &state.mm,
+        &nip05relays.federation_id,
+        nip05relays.app_user_id,
+    )
+    .await?;
+
+    let exceeded = |count: i64, max: Option<u64>| max.is_some_and(|max| count as u64 >= max);
+    if exceeded(federation, runtime.max_pending_invoices_per_federation) {
+        metrics::counter!("pending_invoice_cap_hits_total", "scope" => "federation").increment(1);
+        return Err(AppError::from_code(
+            ErrorCode::TooManyPendingInvoices,
+            anyhow::anyhow!("Too many unpaid invoices in this federation, try again later"),
+        ));
+    }
+    if exceeded(user, runtime.max_pending_invoices_per_user) {
+        metrics::counter!("pending_invoice_cap_hits_total", "scope" => "user").increment(1);
+        return Err(AppError::from_code(
+            ErrorCode::TooManyPendingInvoices,
+            anyhow::anyhow!("Too many unpaid invoices for this user, try again later"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Refuses invoices that would take the user or the instance past their
+/// daily or weekly receive limits. An operator's override replaces the
+/// configured user limits, or lifts them for exempt users.
+async fn check_receive_limits(
+    state: &AppState,
+    app_user_id: i32,
+    amount: u64,
+) -> Result<(), AppError> {
+    let runtime = RUNTIME_CONFIG.load();
+    let limit_override = ReceiveLimitBmc::get(&state.mm, app_user_id).await?;
+    let (daily, weekly) = match &limit_override {
+        Some(o) if o.exempt => (None, None),
+        Some(o) => (
+            o.daily_msats
+                .map(|l| l as u64)
+                .or(runtime.daily_receive_limit_msats),
+            o.weekly_msats
+                .map(|l| l as u64)
+                .or(runtime.weekly_receive_limit_msats),
+        ),
+        None => (
+            runtime.daily_receive_limit_msats,
+            runtime.weekly_receive_limit_msats,
+        ),
+    };
+
+    let limits = [
+        (daily, "user", "daily"),
+        (weekly, "user", "weekly"),
+        (
+            runtime.instance_daily_receive_limit_msats,
+            "instance",
+            "daily",
+        ),
+        (
+            runtime.instance_weekly_receive_limit_msats,
+            "instance",
+            "weekly",
+        ),
+    ];
+    if limits.iter().all(|(limit, _, _)| limit.is_none()) {
+        return Ok(());
+    }
+
+    let volume = ReceiveLimitBmc::received_volume(&state.mm, app_user_id).await?;
+    let received = [
+        volume.user_daily,
+        volume.user_weekly,
+        volume.instance_daily,
+        volume.instance_weekly,
+    ];
+    for ((limit, scope, period), received) in limits.into_iter().zip(received) {
+        if limit.is_some_and(|limit| received as u64 + amount > limit) {
+            metrics::counter!("receive_limit_hits_total", "scope" => scope, "period" => period)
+                .increment(1);
+            return Err(AppError::from_code(
+                ErrorCode::ReceiveLimitExceeded,
+                anyhow::anyhow!(
+                    "The {period} receive limit for this {scope} was reached, try a smaller amount or later"
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates an invoice for a user, stores it and starts watching it for
+/// payment. Amounts are in millisatoshis and must be within the sendable
+/// range. With an idempotency key, a repeated request gets back the
+/// invoice that was already issued. A payer key makes the invoice refundable
+/// to its holder if the payment can't be delivered.
+pub(crate) async fn issue_invoice(
+    state: &AppState,
+    nip05relays: AppUserRelays,
+    amount: u64,
+    description: String,
+    idempotency_key: Option<String>,
+    payer: PayerData,
+) -> Result<IssuedInvoice, AppError> {
+    if state.shutdown.is_cancelled() {
+        return Err(AppError::from_code(
+            ErrorCode::ShuttingDown,
+            anyhow::anyhow!("Server is shutting down"),
+        ));
+    }
+
+    let runtime = RUNTIME_CONFIG.load();
+    if amount < runtime.min_sendable_msats {
+        return Err(AppError::from_code(
+            ErrorCode::AmountTooLow,
+            anyhow::anyhow!("Amount < minSendable"),
+        ));
+    }
+    if amount > runtime.max_sendable_msats {
+        return Err(AppError::from_code(
+            ErrorCode::AmountTooHigh,
+            anyhow::anyhow!("Amount > maxSendable"),
+        ));
+    }
+
+    if let Some(key) = idempotency_key.as_ref() {
+        if let Some(existing) =
+            InvoiceBmc::get_by_idempotency_key(&state.mm, nip05relays.app_user_id, key).await?
+        {
+            return reuse_invoice(existing, amount);
+        }
     }
 
-    let nip05relays = AppUserRelaysBmc::get_by(&state.mm, NameOrPubkey::Name, &username).await?;
     let federation_id = FederationId::from_str(&nip05relays.federation_id).map_err(|e| {
-        AppError::new(
-            StatusCode::BAD_REQUEST,
+        AppError::from_code(
+            ErrorCode::FederationUnavailable,
             anyhow::anyhow!("Invalid federation_id: {}", e),
         )
     })?;
 
-    let locked_clients = state.fm.clients.lock().await.clone();
-    let client = locked_clients.get(&federation_id).ok_or_else(|| {
-        AppError::new(
-            StatusCode::BAD_REQUEST,
+    check_pending_caps(state, &nip05relays).await?;
+    check_receive_limits(state, nip05relays.app_user_id, amount).await?;
+
+    // reserve a subscription slot before minting anything
+    let admission = state.subscriptions.admit().ok_or_else(|| {
+        AppError::from_code(
+            ErrorCode::Overloaded,
+            anyhow::anyhow!("Too many pending invoices, try again later"),
+        )
+    })?;
+
+    let client = state.federations.get(&federation_id).ok_or_else(|| {
+        AppError::from_code(
+            ErrorCode::FederationUnavailable,
             anyhow::anyhow!("FederationId not found in multimint map"),
         )
     })?;
 
     let ln = client.get_first_module::<LightningClientModule>();
 
+    // the gateway the client routes the invoice through, for accounting, only
+    // looked up here until the gateway_refresh job first ran
+    let gateway = match state.gateways.active(&federation_id) {
+        Some(gateway) => Some(gateway),
+        None => ln.select_active_gateway().await.ok(),
+    };
+
     let (op_id, pr) = ln
-        .create_bolt11_invoice(
-            Amount {
-                msats: params.amount,
-            },
-            "test invoice".to_string(), // todo set description hash properly
-            None,
-            (),
-        )
+        .create_bolt11_invoice(Amount { msats: amount }, description, None, ())
+        .instrument(info_span!("create_bolt11_invoice", federation_id = %federation_id))
         .await?;
 
-    // insert invoice into db for later verification
-    let id = InvoiceBmc::create(
-        &state.mm,
-        InvoiceForCreate {
-            op_id: op_id.to_string(),
-            federation_id: nip05relays.federation_id.clone(),
-            app_user_id: nip05relays.app_user_id,
-            amount: params.amount as i64,
-            bolt11: pr.to_string(),
-        },
-    )
-    .await?;
+    // insert invoice into db for later verification, along with its zap
+    // request so neither is left without the other
+    let invoice_c = InvoiceForCreate {
+        op_id: op_id.to_string(),
+        federation_id: nip05relays.federation_id.clone(),
+        app_user_id: nip05relays.app_user_id,
+        amount: amount as i64,
+        bolt11: pr.to_string(),
+        payment_hash: Some(pr.payment_hash().to_string()),
+        request_id: current_request_id(),
+        idempotency_key: idempotency_key.clone(),
+        payer_pubkey: payer.pubkey.map(Sealed::from),
+        comment: payer.comment.map(Sealed::from),
+        payer_data: payer.payer_data.map(Sealed::from),
+        gateway_id: gateway.as_ref().map(|g| g.gateway_id.to_string()),
+        gateway_fee_msats: gateway.as_ref().map(|g| {
+            let fees = g.fees;
+            (fees.base_msat as u64 + amount * fees.proportional_millionths as u64 / 1_000_000)
+                as i64
+        }),
+    };
+    let created = if write_buffer::enabled() {
+        write_buffer::create_invoice(invoice_c, payer.zap_request).await
+    } else {
+        create_invoice(&state.mm, invoice_c, payer.zap_request).await
+    };
+    let id = match created {
+        Ok(id) => id,
+        // a concurrent request with the same key won the race
+        Err(e) if is_unique_violation(&e) => {
+            let key = idempotency_key.unwrap_or_default();
+            let existing =
+                InvoiceBmc::get_by_idempotency_key(&state.mm, nip05relays.app_user_id, &key)
+                    .await?
+                    .ok_or(e)?;
+            return reuse_invoice(existing, amount);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    invoice_log::record(&state.mm, id, "created", None).await;
 
-    // save nostr zap request
-    if let Some(request) = params.nostr {
-        ZapBmc::create(
-            &state.mm,
-            Zap {
-                id,
-                request,
-                event_id: None,
-            },
-        )
-        .await?;
-    }
+    state.invoice_events.publish(InvoiceUpdate {
+        operation_id: op_id.to_string(),
+        username: nip05relays.name.clone(),
+        state: InvoiceState::Pending,
+    });
 
-    // create subscription to operation
-    let subscription = ln
-        .subscribe_ln_receive(op_id)
-        .await
-        .expect("subscribing to a just created operation can't fail");
+    let expires_at = pr.timestamp().checked_add(pr.expiry_time());
+    spawn_invoice_subscription(state.clone(), id, op_id, expires_at, nip05relays, admission).await;
 
-    spawn_invoice_subscription(state, id, nip05relays, subscription).await;
+    Ok(IssuedInvoice {
+        op_id: op_id.to_string(),
+        bolt11: pr.to_string(),
+        payment_hash: pr.payment_hash().to_string(),
+    })
+}
 
-    let verify_url = format!(
-        "http://{}:{}/lnurlp/{}/verify/{}",
-        CONFIG.domain, CONFIG.port, username, op_id
-    );
+/// Creates an invoice and its zap request in a transaction of their own.
+async fn create_invoice(
+    mm: &ModelManager,
+    invoice_c: InvoiceForCreate,
+    zap_request: Option<String>,
+) -> Result<i32> {
+    let mut tx = mm.begin().await?;
+    let id = InvoiceBmc::create_with_zap_in(&mut tx, invoice_c, zap_request).await?;
+    tx.commit().await?;
+    Ok(id)
+}
 
-    let res = LnurlCallbackResponse {
-        pr: pr.to_string(),
-        success_action: None,
-        status: LnurlStatus::Ok,
-        reason: None,
-        verify: verify_url.parse()?,
-        routes: Some(vec![]),
-    };
+fn reuse_invoice(existing: Invoice, amount: u64) -> Result<IssuedInvoice, AppError> {
+    if existing.amount != amount as i64 {
+        return Err(AppError::from_code(
+            ErrorCode::IdempotencyKeyReused,
+            anyhow::anyhow!("Idempotency key was already used for a different amount"),
+        ));
+    }
 
-    Ok(Json(res))
+    info!(
+        "returning existing invoice {} for idempotency key",
+        existing.id
+    );
+    Ok(IssuedInvoice {
+        op_id: existing.op_id,
+        bolt11: existing.bolt11,
+        payment_hash: existing.payment_hash.unwrap_or_default(),
+    })
 }
 
+/// Restarts for a subscription that errored or panicked, e.g. while the
+/// federation was briefly unreachable.
+const SUBSCRIPTION_RESTART: RestartPolicy = RestartPolicy::OnFailure {
+    max_restarts: 5,
+    backoff: Duration::from_secs(1),
+};
+
 pub(crate) async fn spawn_invoice_subscription(
     state: AppState,
     id: i32,
+    op_id: OperationId,
+    expires_at: Option<SystemTime>,
     userrelays: AppUserRelays,
-    subscription: UpdateStreamOrOutcome<LnReceiveState>,
+    admission: Admission,
 ) {
-    spawn("waiting for invoice being paid", async move {
-        let locked_clients = state.fm.clients.lock().await;
-        let client = locked_clients
-            .get(&FederationId::from_str(&userrelays.federation_id).unwrap())
-            .unwrap();
-        let nostr = state.nostr.clone();
-        let mut stream = subscription.into_stream();
-        while let Some(op_state) = stream.next().await {
-            match op_state {
-                LnReceiveState::Canceled { reason } => {
-                    error!("Payment canceled, reason: {:?}", reason);
-                    InvoiceBmc::set_state(&state.mm, id, InvoiceState::Cancelled)
-                        .await
-                        .expect("settling invoice can't fail");
-                    break;
+    // parented to the request span when called from the callback
+    let span = info_span!(
+        "invoice_subscription",
+        invoice_id = id,
+        op_id = %op_id,
+        username = %userrelays.name,
+        federation_id = %userrelays.federation_id
+    );
+    let tasks = state.tasks.clone();
+    tasks.spawn(
+        async move {
+            let stopped = state.subscriptions.watch(id, expires_at);
+            let permit = tokio::select! {
+                permit = admission.ready() => permit,
+                _ = state.shutdown.cancelled() => {
+                    info!("Shutting down, queued invoice {id} stays pending");
+                    return;
                 }
-                LnReceiveState::Claimed => {
-                    info!("Payment claimed");
-                    let invoice = InvoiceBmc::set_state(&state.mm, id, InvoiceState::Settled)
-                        .await
-                        .expect("settling invoice can't fail");
-                    notify_user(
-                        client,
-                        &nostr,
-                        &state.mm,
-                        id,
-                        invoice.amount as u64,
-                        userrelays.clone(),
-                    )
-                    .await
-                    .expect("notifying user can't fail");
-                    break;
+                _ = stopped.cancelled() => {
+                    info!("Invoice {id} no longer needs watching");
+                    return;
                 }
-                _ => {}
+            };
+            state.subscriptions.record_metrics();
+
+            // already reported by supervise, the invoice stays pending for reconciliation
+            let _ = supervise("invoice_subscription", SUBSCRIPTION_RESTART, || {
+                watch_invoice(
+                    state.clone(),
+                    id,
+                    op_id,
+                    userrelays.clone(),
+                    stopped.clone(),
+                )
+            })
+            .await;
+
+            state.subscriptions.unwatch(id);
+            drop(permit);
+            state.subscriptions.record_metrics();
+        }
+        .instrument(span),
+    );
+}
+
+/// Follows an invoice's fedimint operation until it is claimed or canceled.
+async fn watch_invoice(
+    state: AppState,
+    id: i32,
+    op_id: OperationId,
+    userrelays: AppUserRelays,
+    stopped: CancellationToken,
+) -> Result<()> {
+    let federation_id = FederationId::from_str(&userrelays.federation_id)
+        .map_err(|e| anyhow::anyhow!("invalid federation id: {e:?}"))?;
+    let client = state
+        .federations
+        .get(&federation_id)
+        .ok_or_else(|| anyhow::anyhow!("federation {federation_id} is not connected"))?;
+    let ln = client.get_first_module::<LightningClientModule>();
+    let mut stream = ln
+        .subscribe_ln_receive(op_id)
+        .instrument(info_span!("subscribe_ln_receive"))
+        .await?
+        .into_stream();
+
+    // the gateway paid into the contract, a cancel after this means the payer paid
+    let mut funded = false;
+    loop {
+        // only stop between updates so a claimed payment always finishes notifying
+        let op_state = tokio::select! {
+            op_state = stream.next() => op_state,
+            _ = state.shutdown.cancelled() => {
+                info!("Shutting down, invoice {id} stays pending");
+                return Ok(());
+            }
+            _ = stopped.cancelled() => {
+                info!("Invoice {id} no longer needs watching");
+                return Ok(());
+            }
+        };
+        let Some(op_state) = op_state else {
+            return Ok(());
+        };
+        state.subscriptions.touch(id);
+        let final_state = match op_state {
+            LnReceiveState::Canceled { reason } => {
+                error!("Payment canceled, reason: {:?}", reason);
+                InvoiceState::Cancelled
+            }
+            LnReceiveState::Claimed => {
+                info!("Payment claimed");
+                InvoiceState::Settled
             }
+            LnReceiveState::Funded => {
+                funded = true;
+                continue;
+            }
+            _ => continue,
+        };
+        finish_invoice(
+            &state,
+            &client,
+            id,
+            &userrelays,
+            final_state,
+            InvoiceEventSource::Subscription,
+        )
+        .await?;
+        if final_state == InvoiceState::Cancelled && funded {
+            record_refund(&state.mm, id, REFUND_CANCELED_AFTER_FUNDING).await?;
         }
+        return Ok(());
+    }
+}
+
+/// Moves a pending invoice to its final state and, if it was paid, pays the
+/// user out. Does nothing if the invoice was already finished, so the
+/// subscription and the reconciliation job can't both notify. Every step is
+/// recorded in the invoice's events as coming from `source`.
+pub(crate) async fn finish_invoice(
+    state: &AppState,
+    client: &ClientArc,
+    id: i32,
+    userrelays: &AppUserRelays,
+    final_state: InvoiceState,
+    source: InvoiceEventSource,
+) -> Result<()> {
+    let Some(invoice) =
+        InvoiceBmc::transition(&state.mm, id, InvoiceState::Pending, final_state, source).await?
+    else {
+        info!("Invoice {id} was already finished");
+        return Ok(());
+    };
+
+    state.invoice_events.publish(InvoiceUpdate {
+        operation_id: invoice.op_id.clone(),
+        username: userrelays.name.clone(),
+        state: invoice.state,
     });
+
+    if invoice.state != InvoiceState::Settled {
+        return Ok(());
+    }
+    federation_stats::settled(&state.mm, &invoice.federation_id, invoice.amount as u64).await;
+    let preimage = record_preimage(&state.mm, client, &invoice).await;
+    // the notice looks up a display name, which mustn't hold up the payout
+    let (notice_state, notice_invoice, notice_user) =
+        (state.clone(), invoice.clone(), userrelays.clone());
+    state.tasks.spawn(
+        async move { post_room_notice(&notice_state, &notice_invoice, &notice_user).await }
+            .instrument(Span::current()),
+    );
+    nwc::payment_received(&state.nostr, &invoice, userrelays, preimage.as_deref()).await;
+
+    invoice_log::with_source(source, pay_user(state, client, &invoice, userrelays)).await
+}
+
+/// Stores the preimage of a settled invoice for verify responses, returning
+/// it if the federation has it. Best effort, verify fetches it again if it's
+/// missing.
+pub(crate) async fn record_preimage(
+    mm: &ModelManager,
+    client: &ClientArc,
+    invoice: &Invoice,
+) -> Option<String> {
+    let payment_hash = match &invoice.payment_hash {
+        Some(payment_hash) => payment_hash.clone(),
+        None => match Bolt11Invoice::from_str(&invoice.bolt11) {
+            Ok(bolt11) => bolt11.payment_hash().to_string(),
+            Err(e) => {
+                warn!("Could not parse bolt11 of invoice {}: {e}", invoice.id);
+                return None;
+            }
+        },
+    };
+
+    let preimage = match federations::receive_preimage(client, &payment_hash).await {
+        Ok(preimage) => preimage?,
+        Err(e) => {
+            warn!("Could not fetch preimage of invoice {}: {e:#}", invoice.id);
+            return None;
+        }
+    };
+    if let Err(e) = InvoiceBmc::set_preimage(mm, invoice.id, &preimage).await {
+        warn!("Could not record preimage of invoice {}: {e:#}", invoice.id);
+    }
+
+    Some(preimage)
+}
+
+/// Tells the user's notify room about a settled payment, without any ecash,
+/// naming them by their nostr display name if they have one. Failing to is
+/// only logged.
+async fn post_room_notice(state: &AppState, invoice: &Invoice, userrelays: &AppUserRelays) {
+    let Some(room) = &userrelays.notify_room else {
+        return;
+    };
+    let address = format!("{}@{}", userrelays.name, CONFIG.domain);
+    let recipient = match profiles::display_name(&state.nostr, &state.mm, &userrelays.pubkey).await
+    {
+        Some(display_name) => format!("{display_name} ({address})"),
+        None => address,
+    };
+    let notice = format!("{recipient} received {} sats", invoice.amount / 1_000);
+    if let Err(e) = xmpp_client::send_groupchat(room, &notice) {
+        warn!(
+            "Posting a notice of invoice {} to {room} failed: {e:#}",
+            invoice.id
+        );
+    }
+}
+
+/// Hands a settled invoice's payment to the user the way they chose.
+async fn pay_user(
+    state: &AppState,
+    client: &ClientArc,
+    invoice: &Invoice,
+    userrelays: &AppUserRelays,
+) -> Result<()> {
+    if userrelays.custodial {
+        credit_balance(&state.nostr, &state.mm, invoice, userrelays).await?;
+    } else if userrelays.batch_payouts {
+        batch_payout(&state.nostr, &state.mm, invoice, userrelays).await?;
+    } else {
+        notify_user(
+            client,
+            &state.nostr,
+            &state.mm,
+            invoice.id,
+            invoice.amount as u64,
+            userrelays.clone(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Keeps a settled invoice in custody as part of the user's balance, to be
+/// claimed whenever they like.
+async fn credit_balance(
+    nostr: &Client,
+    mm: &ModelManager,
+    invoice: &Invoice,
+    userrelays: &AppUserRelays,
+) -> Result<()> {
+    if let Err(e) = BalanceBmc::credit(
+        mm,
+        userrelays.app_user_id,
+        &invoice.federation_id,
+        invoice.amount,
+    )
+    .await
+    {
+        // replaying spends the invoice on its own
+        return dead_letter(mm, invoice.id, SPEND_NOTES_CHANNEL, None, e).await;
+    }
+    invoice_log::record(mm, invoice.id, "credited", None).await;
+
+    publish_zap_receipt(nostr, mm, invoice.id).await
+}
+
+/// Holds a settled invoice for the payout batching job instead of spending
+/// it right away. The zap receipt doesn't wait for the payout.
+async fn batch_payout(
+    nostr: &Client,
+    mm: &ModelManager,
+    invoice: &Invoice,
+    userrelays: &AppUserRelays,
+) -> Result<()> {
+    if let Err(e) = PayoutBatchBmc::add_invoice(
+        mm,
+        userrelays.app_user_id,
+        &invoice.federation_id,
+        invoice.id,
+        invoice.amount,
+    )
+    .await
+    {
+        // replaying spends the invoice on its own
+        return dead_letter(mm, invoice.id, SPEND_NOTES_CHANNEL, None, e).await;
+    }
+    invoice_log::record(mm, invoice.id, "batched", None).await;
+
+    publish_zap_receipt(nostr, mm, invoice.id).await
+}
+
+/// How long a user has to redeem spent notes before they go back to hermes,
+/// `NOTES_EXPIRY_SECS` unless the user chose their own.
+pub(crate) fn notes_expiry(notes_expiry_secs: Option<i32>) -> Duration {
+    notes_expiry_secs.map_or(CONFIG.notes_expiry, |secs| Duration::from_secs(secs as u64))
+}
+
+/// Dead letter channel for a failure before any ecash was spent.
+pub(crate) const SPEND_NOTES_CHANNEL: &str = "spend_notes";
+/// Dead letter channel for a paid Cashu mint quote whose tokens weren't minted.
+const CASHU_MINT_CHANNEL: &str = "cashu_mint";
+/// Dead letter channel for a zap receipt that failed to publish.
+const ZAP_CHANNEL: &str = "zap";
+/// Dead letter channel for a reissue whose funds left the source federation.
+pub(crate) const REISSUE_CHANNEL: &str = "reissue";
+
+/// How long each relay gets to accept a zap receipt.
+const ZAP_RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Relays of a zap request beyond this many are ignored.
+const MAX_ZAP_REQUEST_RELAYS: usize = 10;
+/// Zap request relays kept connected between receipts, the least recently
+/// asked for are dropped beyond this.
+const MAX_POOLED_ZAP_RELAYS: usize = 50;
+
+/// Connections to the relays zap requests ask for that hermes doesn't use
+/// itself, shared by every receipt.
+static ZAP_RELAY_CLIENT: OnceLock<Client> = OnceLock::new();
+/// Relays in `ZAP_RELAY_CLIENT`, most recently asked for last.
+static ZAP_RELAY_ORDER: Mutex<Vec<PooledRelay>> = Mutex::new(Vec::new());
+/// Held while relays are added to or removed from `ZAP_RELAY_CLIENT`, so one
+/// receipt can't drop a relay another just pinned.
+static ZAP_RELAY_UPDATE: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+/// How long queueing an XMPP DM may take before it is sent over nostr instead.
+const XMPP_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Refund reason for a payment whose ecash couldn't be issued to the user.
+const REFUND_UNDELIVERABLE: &str = "undeliverable";
+/// Refund reason for a receive that was canceled after being funded.
+const REFUND_CANCELED_AFTER_FUNDING: &str = "canceled_after_funding";
+
+/// The ecash a user receives for a paid invoice.
+#[derive(Debug, Clone)]
+pub(crate) enum Payout {
+    Fedimint(OOBNotes),
+    Cashu(String),
+    /// Claim url for a gift holding the notes
+    Link(String),
+}
+
+impl Payout {
+    fn parse(note_format: &str, s: &str) -> Result<Self> {
+        match note_format {
+            "cashu" => Ok(Payout::Cashu(s.to_string())),
+            "link" => Ok(Payout::Link(s.to_string())),
+            _ => Ok(Payout::Fedimint(s.parse()?)),
+        }
+    }
+
+    fn note_format(&self) -> NoteFormat {
+        match self {
+            Payout::Fedimint(_) => NoteFormat::Fedimint,
+            Payout::Cashu(_) => NoteFormat::Cashu,
+            Payout::Link(_) => NoteFormat::Link,
+        }
+    }
+
+    fn message(&self, operation_id: OperationId, amount: u64, comment: Option<&str>) -> String {
+        let mut message = match self {
+            Payout::Fedimint(notes) => json!({
+                "operationId": operation_id,
+                "amount": amount,
+                "notes": notes.to_string(),
+            }),
+            Payout::Cashu(token) => json!({
+                "operationId": operation_id,
+                "amount": amount,
+                "token": token,
+            }),
+            Payout::Link(url) => json!({
+                "operationId": operation_id,
+                "amount": amount,
+                "link": url,
+            }),
+        };
+        if let Some(comment) = comment {
+            message["comment"] = json!(comment);
+        }
+        message.to_string()
+    }
+}
+
+impl fmt::Display for Payout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Payout::Fedimint(notes) => write!(f, "{notes}"),
+            Payout::Cashu(token) => write!(f, "{token}"),
+            Payout::Link(url) => write!(f, "{url}"),
+        }
+    }
 }
 
+/// Spends the invoice amount into ecash and delivers it, dead lettering any
+/// step that fails so it can be replayed later.
+#[instrument(skip_all, fields(invoice_id = id, dm_type = %app_user_relays.dm_type))]
 async fn notify_user(
     client: &ClientArc,
     nostr: &Client,
@@ -237,47 +1027,631 @@ async fn notify_user(
     id: i32,
     amount: u64,
     app_user_relays: AppUserRelays,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<()> {
+    if app_user_relays.note_format == NoteFormat::Cashu.to_string() {
+        return notify_user_cashu(client, nostr, mm, id, amount, app_user_relays).await;
+    }
+
     let mint = client.get_first_module::<MintClientModule>();
-    let (operation_id, notes) = mint
-        .spend_notes(Amount::from_msats(amount), Duration::from_secs(604800), ())
-        .await?;
-    match app_user_relays.dm_type.as_str() {
-        "nostr" => send_nostr_dm(nostr, &app_user_relays, operation_id, amount, notes).await,
-        "xmpp" => send_xmpp_msg(&app_user_relays, operation_id, amount, notes).await,
-        _ => Err(anyhow::anyhow!("Unsupported dm_type")),
-    }?;
+    let (operation_id, notes) = match mint
+        .spend_notes(
+            Amount::from_msats(amount),
+            notes_expiry(app_user_relays.notes_expiry_secs),
+            (),
+        )
+        .instrument(info_span!("spend_notes"))
+        .await
+    {
+        Ok(spent) => spent,
+        Err(e) => return dead_letter(mm, id, SPEND_NOTES_CHANNEL, None, e).await,
+    };
+    federation_stats::notes_issued(mm, &client.federation_id().to_string(), amount).await;
 
-    // Send zap if needed
-    if let Ok(zap) = ZapBmc::get(&mm, id).await {
-        let request = Event::from_json(zap.request)?;
-        let event = create_zap_event(request, amount)?;
+    let payout = if app_user_relays.note_format == NoteFormat::Link.to_string() {
+        match create_gift(mm, &app_user_relays, id, amount, operation_id, &notes).await {
+            Ok(url) => Payout::Link(url),
+            Err(e) => {
+                // the notes are spent, hand them over rather than lose them
+                error!("Creating gift for invoice {id} failed, sending notes: {e:#}");
+                Payout::Fedimint(notes)
+            }
+        }
+    } else {
+        Payout::Fedimint(notes)
+    };
 
-        let event_id = nostr.send_event(event).await?;
-        info!("Broadcasted zap {event_id}!");
+    deliver_payout(
+        nostr,
+        mm,
+        id,
+        amount,
+        &app_user_relays,
+        operation_id,
+        payout,
+    )
+    .await
+}
+
+/// Stores spent notes behind a new one-time claim token and returns the
+/// claim url.
+async fn create_gift(
+    mm: &ModelManager,
+    app_user_relays: &AppUserRelays,
+    id: i32,
+    amount: u64,
+    operation_id: OperationId,
+    notes: &OOBNotes,
+) -> Result<String> {
+    let (token, token_hash) = gift::generate_token();
+    GiftBmc::create(
+        mm,
+        GiftForCreate {
+            app_user_id: app_user_relays.app_user_id,
+            invoice_id: id,
+            token_hash,
+            operation_id: operation_id.to_string(),
+            amount: amount as i64,
+            notes: notes.to_string().into(),
+        },
+    )
+    .await?;
 
-        ZapBmc::set_event_id(&mm, id, event_id).await?;
+    Ok(format!(
+        "{}/gift/{token}",
+        public_base_url(&HeaderMap::new())
+    ))
+}
+
+/// Pays a Cashu mint quote from the federation and mints a token for it.
+async fn notify_user_cashu(
+    client: &ClientArc,
+    nostr: &Client,
+    mm: &ModelManager,
+    id: i32,
+    amount: u64,
+    app_user_relays: AppUserRelays,
+) -> Result<()> {
+    let cashu = CashuMint::from_config()?;
+    let sats = CashuMint::quote_amount(amount);
+    let paid = async {
+        let quote = cashu.request_quote(sats).await?;
+        let operation_id = cashu::pay_quote(mm, client, &quote, sats).await?;
+        Ok::<_, anyhow::Error>((quote, operation_id))
+    };
+    let (quote, operation_id) = match paid.await {
+        Ok(paid) => paid,
+        Err(e) => return dead_letter(mm, id, SPEND_NOTES_CHANNEL, None, e).await,
+    };
+
+    let token = match cashu.mint_token(&quote.quote, sats).await {
+        Ok(token) => token,
+        Err(e) => {
+            return dead_letter(
+                mm,
+                id,
+                CASHU_MINT_CHANNEL,
+                Some((operation_id, &quote.quote)),
+                e,
+            )
+            .await
+        }
+    };
+
+    deliver_payout(
+        nostr,
+        mm,
+        id,
+        sats * 1_000,
+        &app_user_relays,
+        operation_id,
+        Payout::Cashu(token),
+    )
+    .await
+}
+
+/// Sends spent ecash to the user and then the zap receipt, dead lettering
+/// either if it fails.
+async fn deliver_payout(
+    nostr: &Client,
+    mm: &ModelManager,
+    id: i32,
+    amount: u64,
+    app_user_relays: &AppUserRelays,
+    operation_id: OperationId,
+    payout: Payout,
+) -> Result<()> {
+    let serialized = {
+        let payout = payout.clone();
+        compute::run(move || payout.to_string()).await?
+    };
+
+    // a gift link is already claimable, and only its token hash is stored
+    if !matches!(payout, Payout::Link(_)) {
+        record_payout(
+            mm,
+            EcashPayoutForCreate {
+                app_user_id: app_user_relays.app_user_id,
+                invoice_id: Some(id),
+                payout_batch_id: None,
+                operation_id: operation_id.to_string(),
+                amount: amount as i64,
+                note_format: payout.note_format().to_string(),
+                notes: serialized.clone().into(),
+            },
+        )
+        .await;
     }
 
-    Ok(())
+    // a missing comment shouldn't hold up the ecash
+    let comment = InvoiceBmc::get(mm, id).await.ok().and_then(|i| i.comment);
+    let channel = match send_payout(
+        nostr,
+        mm,
+        app_user_relays,
+        operation_id,
+        amount,
+        &payout,
+        comment.as_deref(),
+    )
+    .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            return dead_letter(
+                mm,
+                id,
+                &app_user_relays.dm_type,
+                Some((operation_id, &serialized)),
+                e,
+            )
+            .await
+        }
+    };
+    let detail = Some(format!("{} over {channel}", payout.note_format()));
+    invoice_log::record(mm, id, "delivered", detail).await;
+
+    publish_zap_receipt(nostr, mm, id).await
 }
 
-async fn send_nostr_dm(
+/// Keeps spent ecash so the user's wallet can fetch it if the DM never
+/// arrives. Failing to is only logged, the DM is still sent.
+pub(crate) async fn record_payout(mm: &ModelManager, payout_c: EcashPayoutForCreate) {
+    let operation_id = payout_c.operation_id.clone();
+    if let Err(e) = EcashPayoutBmc::create(mm, payout_c).await {
+        error!("Recording payout {operation_id} failed: {e:#}");
+    }
+}
+
+/// DMs the payout over the user's dm types in turn, returning the channel
+/// it went out on.
+pub(crate) async fn send_payout(
     nostr: &Client,
+    mm: &ModelManager,
     app_user_relays: &AppUserRelays,
     operation_id: OperationId,
     amount: u64,
-    notes: OOBNotes,
+    payout: &Payout,
+    comment: Option<&str>,
+) -> Result<&'static str> {
+    let message = {
+        let payout = payout.clone();
+        let comment = comment.map(str::to_string);
+        compute::run(move || payout.message(operation_id, amount, comment.as_deref())).await?
+    };
+    let mut result = Err(anyhow::anyhow!("Unsupported dm_type"));
+    for dm_type in dm_types(app_user_relays) {
+        result = match dm_type {
+            "nostr" => send_nostr_dm(nostr, app_user_relays, message.clone())
+                .await
+                .map(|()| NOSTR_CHANNEL),
+            "xmpp" => {
+                send_xmpp_msg(nostr, mm, app_user_relays, operation_id, message.clone()).await
+            }
+            _ => Err(anyhow::anyhow!("Unsupported dm_type {dm_type}")),
+        };
+        match &result {
+            Ok(_) => break,
+            Err(e) => warn!("Sending {operation_id} over {dm_type} failed: {e:#}"),
+        }
+    }
+    result
+}
+
+/// The user's dm type followed by their fallbacks, in the order they are
+/// tried.
+fn dm_types(app_user_relays: &AppUserRelays) -> impl Iterator<Item = &str> {
+    std::iter::once(app_user_relays.dm_type.as_str())
+        .chain(app_user_relays.fallback_dm_types.iter().map(|t| t.as_str()))
+}
+
+/// Sends a zap receipt if the invoice was for a zap, dead lettering a failure.
+async fn publish_zap_receipt(nostr: &Client, mm: &ModelManager, id: i32) -> Result<()> {
+    match send_zap_receipt(nostr, mm, id).await {
+        Ok(()) => Ok(()),
+        Err(e) => dead_letter(mm, id, ZAP_CHANNEL, None, e).await,
+    }
+}
+
+async fn send_zap_receipt(nostr: &Client, mm: &ModelManager, id: i32) -> Result<()> {
+    if let Ok(zap) = ZapBmc::get(mm, id).await {
+        let request = Event::from_json(&*zap.request)?;
+        let invoice = InvoiceBmc::get(mm, id).await?;
+        let event = {
+            let request = request.clone();
+            compute::run(move || create_zap_event(request, &invoice)).await??
+        };
+
+        let accepted = broadcast_zap_receipt(nostr, &request, &event)
+            .instrument(info_span!("send_zap_receipt"))
+            .await?;
+        info!("Broadcasted zap {} to {accepted} relays!", event.id);
+
+        ZapBmc::set_receipt(mm, id, &event).await?;
+        invoice_log::record(mm, id, "zap_receipt_sent", Some(event.id.to_string())).await;
+    }
+
+    Ok(())
+}
+
+/// Publishes a zap receipt to hermes's relays and the ones the zap request
+/// asks for, all at once with a timeout each. Request relays hermes isn't
+/// connected to go through a shared pool of their own, and only if they're
+/// public. Fails if no relay took it, returns how many did.
+async fn broadcast_zap_receipt(nostr: &Client, request: &Event, event: &Event) -> Result<usize> {
+    let pool: Vec<Url> = nostr.relays().await.into_keys().collect();
+    let mut extra = Vec::new();
+    for url in zap_request_relays(request) {
+        if extra.len() == MAX_ZAP_REQUEST_RELAYS {
+            break;
+        }
+        if pool.contains(&url) {
+            continue;
+        }
+        if is_public_relay(&url).await {
+            extra.push(url);
+        } else {
+            warn!("Skipping non-public zap request relay {url}");
+        }
+    }
+    let extra_client = if extra.is_empty() {
+        None
+    } else {
+        Some(zap_relay_client(&extra).await?)
+    };
+
+    let targets = pool.iter().map(|url| (nostr, url)).chain(
+        extra_client
+            .iter()
+            .flat_map(|lease| extra.iter().map(move |url| (lease.client, url))),
+    );
+    let results = futures::future::join_all(targets.map(|(client, url)| async move {
+        let sent = tokio::time::timeout(
+            ZAP_RELAY_TIMEOUT,
+            client.send_event_to(url.clone(), event.clone()),
+        )
+        .await;
+        (url, sent)
+    }))
+    .await;
+
+    let mut accepted = 0;
+    for (url, sent) in results {
+        let result = match sent {
+            Ok(Ok(_)) => {
+                accepted += 1;
+                "accepted"
+            }
+            Ok(Err(e)) => {
+                warn!("Relay {url} refused zap receipt {}: {e}", event.id);
+                "failed"
+            }
+            Err(_) => {
+                warn!("Relay {url} timed out on zap receipt {}", event.id);
+                "timeout"
+            }
+        };
+        metrics::counter!("zap_receipt_relays_total", "result" => result).increment(1);
+    }
+    if accepted == 0 {
+        return Err(anyhow::anyhow!(
+            "No relay accepted zap receipt {}",
+            event.id
+        ));
+    }
+    Ok(accepted)
+}
+
+/// A zap request relay in the shared pool and how many receipts are being
+/// sent to it right now.
+#[derive(Debug)]
+struct PooledRelay {
+    url: Url,
+    users: usize,
+}
+
+/// The shared client for zap request relays, with `relays` pinned in its pool
+/// until the lease is dropped.
+struct ZapRelayLease {
+    client: &'static Client,
+    relays: Vec<Url>,
+}
+
+impl Drop for ZapRelayLease {
+    fn drop(&mut self) {
+        let mut pooled = ZAP_RELAY_ORDER.lock().expect("zap relay order poisoned");
+        for relay in pooled.iter_mut() {
+            if self.relays.contains(&relay.url) {
+                relay.users = relay.users.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// The shared client for zap request relays, connected to `relays`. Relays
+/// beyond `MAX_POOLED_ZAP_RELAYS` are dropped, least recently used first,
+/// unless a receipt is still being sent to them.
+async fn zap_relay_client(relays: &[Url]) -> Result<ZapRelayLease> {
+    let client = ZAP_RELAY_CLIENT.get_or_init(|| {
+        Client::with_opts(&CONFIG.nostr_sk, Options::new().proxy(CONFIG.socks_proxy))
+    });
+    let _update = ZAP_RELAY_UPDATE
+        .get_or_init(|| tokio::sync::Mutex::new(()))
+        .lock()
+        .await;
+    let evicted = {
+        let mut pooled = ZAP_RELAY_ORDER.lock().expect("zap relay order poisoned");
+        pin_relays(&mut pooled, relays)
+    };
+    // unpins the relays again if connecting fails
+    let lease = ZapRelayLease {
+        client,
+        relays: relays.to_vec(),
+    };
+    for url in evicted {
+        if let Err(e) = client.remove_relay(url.as_str()).await {
+            warn!("Dropping zap request relay {url} failed: {e}");
+        }
+    }
+    for url in relays {
+        client.add_relay(url.as_str()).await?;
+    }
+    // only connects the relays that aren't yet
+    client.connect().await;
+
+    Ok(lease)
+}
+
+/// Moves `relays` to the back of the pool with one more user each, and takes
+/// the least recently used relays nobody is using out of it until it's back
+/// to `MAX_POOLED_ZAP_RELAYS`. Returns the relays taken out.
+fn pin_relays(pooled: &mut Vec<PooledRelay>, relays: &[Url]) -> Vec<Url> {
+    for url in relays {
+        let users = match pooled.iter().position(|relay| &relay.url == url) {
+            Some(i) => pooled.remove(i).users,
+            None => 0,
+        };
+        pooled.push(PooledRelay {
+            url: url.clone(),
+            users: users + 1,
+        });
+    }
+
+    let mut excess = pooled.len().saturating_sub(MAX_POOLED_ZAP_RELAYS);
+    let mut evicted = Vec::new();
+    pooled.retain(|relay| {
+        if excess == 0 || relay.users > 0 {
+            return true;
+        }
+        excess -= 1;
+        evicted.push(relay.url.clone());
+        false
+    });
+    evicted
+}
+
+/// Whether a relay's host only resolves to public addresses, so zap requests
+/// can't point hermes at its own network. Behind a proxy names are left for
+/// the proxy to resolve, looking them up here would leak them outside of it
+/// and the address checked might not be the one the proxy connects to.
+async fn is_public_relay(url: &Url) -> bool {
+    let Some(host) = url.host() else {
+        return false;
+    };
+    if let Host::Domain(domain) = host {
+        if CONFIG.socks_proxy.is_some() {
+            return true;
+        }
+        if domain.ends_with(".onion") {
+            return false;
+        }
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    resolve_public(host, port).await.is_ok()
+}
+
+/// The websocket relays listed in a zap request's `relays` tag, once each.
+fn zap_request_relays(request: &Event) -> Vec<Url> {
+    let mut relays: Vec<Url> = request
+        .tags
+        .iter()
+        .map(|tag| tag.as_vec())
+        .filter(|tag| tag.first().map(String::as_str) == Some("relays"))
+        .flat_map(|tag| tag.into_iter().skip(1))
+        .filter_map(|relay| Url::parse(&relay).ok())
+        .filter(|url| matches!(url.scheme(), "ws" | "wss"))
+        .collect();
+    relays.sort();
+    relays.dedup();
+    relays
+}
+
+/// Records a failed settlement step. The invoice is already settled so the
+/// failure isn't returned, retrying the whole settlement would do nothing.
+async fn dead_letter(
+    mm: &ModelManager,
+    invoice_id: i32,
+    channel: &str,
+    payload: Option<(OperationId, &str)>,
+    e: anyhow::Error,
+) -> Result<()> {
+    error!(
+        alert = true,
+        "Settling invoice {invoice_id} failed at {channel}, dead lettered: {e:#}"
+    );
+    metrics::counter!("dead_letters_total", "channel" => channel.to_string()).increment(1);
+
+    DeadLetterBmc::create(
+        mm,
+        DeadLetterForCreate {
+            invoice_id,
+            channel: channel.to_string(),
+            operation_id: payload.map(|(operation_id, _)| operation_id.to_string()),
+            notes: payload.map(|(_, notes)| notes.to_string().into()),
+            error: format!("{e:#}"),
+        },
+    )
+    .await?;
+    invoice_log::record(mm, invoice_id, "dead_lettered", Some(channel.to_string())).await;
+
+    // nothing was spent, so we still hold the payment
+    if channel == SPEND_NOTES_CHANNEL {
+        record_refund(mm, invoice_id, REFUND_UNDELIVERABLE).await?;
+    }
+
+    Ok(())
+}
+
+/// Makes a payment the user never received refundable to the payer, if they
+/// gave a key to claim it with.
+async fn record_refund(mm: &ModelManager, invoice_id: i32, reason: &str) -> Result<()> {
+    let invoice = InvoiceBmc::get(mm, invoice_id).await?;
+    let Some(payer_pubkey) = invoice.payer_pubkey else {
+        return Ok(());
+    };
+
+    RefundBmc::open(
+        mm,
+        RefundForCreate {
+            invoice_id,
+            payer_pubkey: payer_pubkey.into(),
+            amount: invoice.amount,
+            reason: reason.to_string(),
+        },
+    )
+    .await?;
+    invoice_log::record(mm, invoice_id, "refundable", Some(reason.to_string())).await;
+    info!("Invoice {invoice_id} is refundable to the payer: {reason}");
+
+    Ok(())
+}
+
+/// Retries a dead lettered settlement from the step that failed.
+pub(crate) async fn replay_dead_letter(state: &AppState, id: i32) -> Result<DeadLetter> {
+    invoice_log::with_source(InvoiceEventSource::Admin, replay(state, id)).await
+}
+
+async fn replay(state: &AppState, id: i32) -> Result<DeadLetter> {
+    let dead_letter = DeadLetterBmc::get(&state.mm, id).await?;
+    if dead_letter.resolved_at.is_some() {
+        return Err(anyhow::anyhow!("Dead letter {id} was already replayed"));
+    }
+    let invoice = InvoiceBmc::get(&state.mm, dead_letter.invoice_id).await?;
+    let userrelays = AppUserRelaysBmc::get_by_id(&state.mm, invoice.app_user_id).await?;
+    let amount = invoice.amount as u64;
+
+    let replayed = match (
+        dead_letter.channel.as_str(),
+        dead_letter.operation_id,
+        dead_letter.notes,
+    ) {
+        (ZAP_CHANNEL, _, _) => send_zap_receipt(&state.nostr, &state.mm, invoice.id).await,
+        (REISSUE_CHANNEL, Some(operation_id), _) => replay_reissue(state, &operation_id).await,
+        // the quote is paid, mint it and deliver, new failures get their own entry
+        (CASHU_MINT_CHANNEL, Some(operation_id), Some(quote)) => {
+            let cashu = CashuMint::from_config()?;
+            let sats = CashuMint::quote_amount(amount);
+            match cashu.mint_token(&quote, sats).await {
+                Ok(token) => {
+                    deliver_payout(
+                        &state.nostr,
+                        &state.mm,
+                        invoice.id,
+                        sats * 1_000,
+                        &userrelays,
+                        operation_id.parse()?,
+                        Payout::Cashu(token),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        // the ecash was spent but never delivered, send the same again
+        (_, Some(operation_id), Some(notes)) => {
+            let payout = Payout::parse(&userrelays.note_format, &notes)?;
+            match send_payout(
+                &state.nostr,
+                &state.mm,
+                &userrelays,
+                operation_id.parse()?,
+                amount,
+                &payout,
+                invoice.comment.as_deref(),
+            )
+            .await
+            {
+                Ok(_) => publish_zap_receipt(&state.nostr, &state.mm, invoice.id).await,
+                Err(e) => Err(e),
+            }
+        }
+        // nothing was spent yet, start over, new failures get their own entry
+        _ => {
+            if !RefundBmc::void(&state.mm, invoice.id).await? {
+                info!("Invoice {} was refunded to the payer instead", invoice.id);
+                return DeadLetterBmc::mark_resolved(&state.mm, id).await;
+            }
+            let client = FederationId::from_str(&invoice.federation_id)
+                .ok()
+                .and_then(|federation_id| state.federations.get(&federation_id))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("federation {} is not connected", invoice.federation_id)
+                })?;
+            notify_user(
+                &client,
+                &state.nostr,
+                &state.mm,
+                invoice.id,
+                amount,
+                userrelays,
+            )
+            .await
+        }
+    };
+
+    match replayed {
+        Ok(()) => {
+            info!("Replayed dead letter {id}");
+            let detail = Some(dead_letter.channel.clone());
+            invoice_log::record(&state.mm, invoice.id, "replayed", detail).await;
+            DeadLetterBmc::mark_resolved(&state.mm, id).await
+        }
+        Err(e) => DeadLetterBmc::record_failure(&state.mm, id, &format!("{e:#}")).await,
+    }
+}
+
+#[instrument(skip_all, fields(pubkey = %app_user_relays.pubkey))]
+async fn send_nostr_dm(
+    nostr: &Client,
+    app_user_relays: &AppUserRelays,
+    message: String,
 ) -> Result<()> {
     let dm = nostr
         .send_direct_msg(
-            XOnlyPublicKey::from_str(&app_user_relays.pubkey).unwrap(),
-            json!({
-                "operationId": operation_id,
-                "amount": amount,
-                "notes": notes.to_string(),
-            })
-            .to_string(),
+            XOnlyPublicKey::from_str(&app_user_relays.pubkey)?,
+            message,
             None,
         )
         .await?;
@@ -286,66 +1660,161 @@ async fn send_nostr_dm(
     Ok(())
 }
 
-// TODO: add xmpp to registration
+/// Sends the message over XMPP, or as a nostr DM if that fails or takes
+/// longer than `XMPP_SEND_TIMEOUT` and the user's pubkey is a valid nostr
+/// key. Returns the channel it went out on.
+#[instrument(skip_all, fields(name = %app_user_relays.name))]
 async fn send_xmpp_msg(
+    nostr: &Client,
+    mm: &ModelManager,
     app_user_relays: &AppUserRelays,
     operation_id: OperationId,
-    amount: u64,
-    notes: OOBNotes,
+    message: String,
+) -> Result<&'static str> {
+    let mut outbox_id = None;
+    let queued = tokio::time::timeout(
+        XMPP_SEND_TIMEOUT,
+        queue_xmpp_msg(mm, app_user_relays, operation_id, &message, &mut outbox_id),
+    )
+    .await
+    .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out after {XMPP_SEND_TIMEOUT:?}")));
+    let Err(e) = queued else {
+        return Ok(XMPP_CHANNEL);
+    };
+    if XOnlyPublicKey::from_str(&app_user_relays.pubkey).is_err() {
+        return Err(e);
+    }
+
+    warn!("XMPP delivery of {operation_id} failed, sending it over nostr: {e:#}");
+    send_nostr_dm(nostr, app_user_relays, message).await?;
+    metrics::counter!("xmpp_nostr_fallback_total").increment(1);
+    // the ecash is out, so only logged, but the outbox job would send it again
+    if let Some(id) = outbox_id {
+        if let Err(e) = XmppOutboxBmc::mark_sent(mm, id, NOSTR_CHANNEL).await {
+            error!("Recording outbox message {id} as sent over nostr failed: {e:#}");
+        }
+    }
+
+    Ok(NOSTR_CHANNEL)
+}
+
+/// Queues the message in the XMPP outbox, sending it right away if the
+/// recipient is online. The `xmpp_outbox` job sends the rest once they are.
+/// `outbox_id` is set once the message is queued.
+async fn queue_xmpp_msg(
+    mm: &ModelManager,
+    app_user_relays: &AppUserRelays,
+    operation_id: OperationId,
+    message: &str,
+    outbox_id: &mut Option<i32>,
 ) -> Result<()> {
-    let mut xmpp_client = create_xmpp_client()?;
-    let recipient = xmpp::BareJid::new(&format!(
-        "{}@{}",
-        app_user_relays.name, CONFIG.xmpp_chat_server
-    ))?;
-
-    xmpp_client
-        .send_message(
-            Jid::Bare(recipient),
-            MessageType::Chat,
-            "en",
-            &json!({
-                "operationId": operation_id,
-                "amount": amount,
-                "notes": notes.to_string(),
-            })
-            .to_string(),
-        )
-        .await;
+    // users registered before JIDs were verified only have their name
+    let jid = match &app_user_relays.jid {
+        Some(jid) => jid.clone(),
+        None => format!("{}@{}", app_user_relays.name, CONFIG.xmpp_chat_server),
+    };
+    let id = XmppOutboxBmc::enqueue(
+        mm,
+        app_user_relays.app_user_id,
+        &jid,
+        message.to_string().into(),
+        &operation_id.to_string(),
+    )
+    .await?;
+    *outbox_id = Some(id);
+    if xmpp_client::is_online(&jid) {
+        xmpp_client::send_message(&jid, message, Some(id))?;
+        XmppOutboxBmc::mark_sent(mm, id, XMPP_CHANNEL).await?;
+    } else if xmpp_client::enabled() {
+        xmpp_client::subscribe(&jid)?;
+    }
 
     Ok(())
 }
 
-/// Creates a nostr zap event with a fake invoice
-fn create_zap_event(request: Event, amt_msats: u64) -> Result<Event> {
-    let preimage = &mut [0u8; 32];
-    OsRng.fill_bytes(preimage);
-    let invoice_hash = Sha256::hash(preimage);
+/// Creates a nostr zap receipt for the invoice that paid the zap, committing
+/// to the request the way NIP-57 has clients check it: the request as the
+/// `description`, its `p`, `e` and `a` tags copied and its sender as `P`. The
+/// preimage is only included once it's known.
+fn create_zap_event(request: Event, invoice: &Invoice) -> Result<Event> {
+    let mut tags = vec![
+        Tag::Bolt11(invoice.bolt11.clone()),
+        Tag::Description(request.as_json()),
+        Tag::parse(vec!["P".to_string(), request.pubkey.to_string()])?,
+    ];
+    if let Some(preimage) = &invoice.preimage {
+        tags.push(Tag::Preimage(preimage.clone()));
+    }
+    tags.extend(
+        request
+            .tags
+            .iter()
+            .filter(|tag| {
+                matches!(
+                    tag.as_vec().first().map(String::as_str),
+                    Some("p" | "e" | "a")
+                )
+            })
+            .cloned(),
+    );
+
+    let event = EventBuilder::new(Kind::ZapReceipt, "", &tags).to_event(&CONFIG.nostr_sk)?;
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr::{EventBuilder, Keys, Tag};
 
-    let payment_secret = &mut [0u8; 32];
-    OsRng.fill_bytes(payment_secret);
+    use super::*;
 
-    let priv_key_bytes = &mut [0u8; 32];
-    OsRng.fill_bytes(priv_key_bytes);
-    let private_key = SecretKey::from_slice(priv_key_bytes)?;
+    #[test]
+    fn zap_request_relays_are_websockets_once_each() {
+        let tags = vec![
+            Tag::parse(vec![
+                "relays",
+                "wss://relay.example.com",
+                "https://relay.example.com",
+                "not a url",
+                "wss://relay.example.com",
+                "ws://other.example.com",
+            ])
+            .unwrap(),
+            Tag::parse(vec!["relay", "wss://ignored.example.com"]).unwrap(),
+        ];
+        let request = EventBuilder::new(Kind::ZapRequest, "", &tags)
+            .to_event(&Keys::generate())
+            .unwrap();
 
-    let desc_hash = Sha256::hash(request.as_json().as_bytes());
+        let relays: Vec<String> = zap_request_relays(&request)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            relays,
+            ["ws://other.example.com/", "wss://relay.example.com/"]
+        );
+    }
 
-    let fake_invoice = InvoiceBuilder::new(Currency::Bitcoin)
-        .amount_milli_satoshis(amt_msats)
-        .description_hash(desc_hash)
-        .current_timestamp()
-        .payment_hash(invoice_hash)
-        .payment_secret(PaymentSecret(*payment_secret))
-        .min_final_cltv_expiry_delta(144)
-        .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &private_key))?;
+    fn relay(i: usize) -> Url {
+        Url::parse(&format!("wss://relay{i}.example.com")).unwrap()
+    }
 
-    let event = EventBuilder::new_zap_receipt(
-        fake_invoice.to_string(),
-        Some(hex::encode(preimage)),
-        request,
-    )
-    .to_event(&CONFIG.nostr_sk)?;
+    #[test]
+    fn pinned_relays_are_not_evicted() {
+        let mut pooled = Vec::new();
+        let first: Vec<Url> = (0..MAX_POOLED_ZAP_RELAYS).map(relay).collect();
+        assert!(pin_relays(&mut pooled, &first).is_empty());
+        // everything but the first relay is done with
+        for pooled_relay in pooled.iter_mut().skip(1) {
+            pooled_relay.users = 0;
+        }
 
-    Ok(event)
+        let evicted = pin_relays(&mut pooled, &[relay(100), relay(101)]);
+        assert_eq!(evicted, [relay(1), relay(2)]);
+        assert_eq!(pooled.len(), MAX_POOLED_ZAP_RELAYS);
+        assert_eq!(pooled[0].url, relay(0));
+        assert_eq!(pooled[0].users, 1);
+    }
 }
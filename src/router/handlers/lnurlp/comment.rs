@@ -0,0 +1,115 @@
+//! LUD-12 comments payers attach to a payment. They are cleaned up and
+//! checked against the deny list and moderation service before they are
+//! stored and sent to the user with their ecash.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{instrument, warn};
+use url::Url;
+
+use crate::{
+    config::CONFIG,
+    error::{AppError, ErrorCode},
+    utils::http_client_builder,
+};
+
+const MODERATION_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    allow: bool,
+    /// Replaces the comment, e.g. with words masked
+    comment: Option<String>,
+}
+
+/// The comment to keep for a payment to `username`, if any. Comments longer
+/// than `COMMENT_ALLOWED` or matching `COMMENT_DENY_PATTERNS` are rejected, as
+/// are those the moderation service denies. If the moderation service can't
+/// be reached the comment is dropped rather than holding up the payment.
+#[instrument(skip_all, fields(username = %username))]
+pub(crate) async fn moderate(
+    comment: Option<String>,
+    username: &str,
+) -> Result<Option<String>, AppError> {
+    let Some(comment) = comment else {
+        return Ok(None);
+    };
+
+    let allowed = CONFIG.comment_allowed as usize;
+    if comment.chars().count() > allowed {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Comment is longer than commentAllowed ({allowed})"),
+        ));
+    }
+
+    let comment = sanitize(&comment);
+    if comment.is_empty() {
+        return Ok(None);
+    }
+
+    if CONFIG
+        .comment_deny_patterns
+        .iter()
+        .any(|p| p.is_match(&comment))
+    {
+        metrics::counter!("comments_rejected_total", "by" => "deny_list").increment(1);
+        return Err(rejected());
+    }
+
+    let Some(url) = CONFIG.comment_moderation_url.as_ref() else {
+        return Ok(Some(comment));
+    };
+    match check(url, &comment, username).await {
+        Ok(response) if response.allow => {
+            let comment = response.comment.map_or(comment, |c| sanitize(&c));
+            Ok((!comment.is_empty()).then_some(comment))
+        }
+        Ok(_) => {
+            metrics::counter!("comments_rejected_total", "by" => "moderation").increment(1);
+            Err(rejected())
+        }
+        Err(e) => {
+            warn!("Comment moderation failed, dropping the comment: {e:#}");
+            Ok(None)
+        }
+    }
+}
+
+/// Drops control and bidi override characters, which could hide or reorder
+/// text in the user's client, and surrounding whitespace.
+fn sanitize(comment: &str) -> String {
+    comment
+        .chars()
+        .filter(|c| {
+            !c.is_control() && !matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+async fn check(url: &Url, comment: &str, username: &str) -> Result<ModerationResponse> {
+    let response = http_client_builder()?
+        .timeout(MODERATION_TIMEOUT)
+        .build()?
+        .post(url.clone())
+        .json(&json!({ "comment": comment, "username": username }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response)
+}
+
+fn rejected() -> AppError {
+    AppError::from_code(
+        ErrorCode::CommentRejected,
+        anyhow!("Comment was rejected, try again without it"),
+    )
+}
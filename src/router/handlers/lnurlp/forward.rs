@@ -0,0 +1,139 @@
+//! Users with their own lightning node can have hermes act as a plain
+//! lightning address front for it: pay requests and invoices come from their
+//! node's LNURL and no fedimint receive is involved.
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use fedimint_core::Amount;
+use lightning_invoice::Bolt11Invoice;
+use nostr::secp256k1::XOnlyPublicKey;
+use serde::Deserialize;
+use tracing::instrument;
+use url::Url;
+
+use super::{
+    callback::LnurlCallbackResponse, lnurl::decode_lnurl, payer_data::PayerDataSpec,
+    well_known::LnurlWellKnownResponse, LnurlStatus, LnurlType,
+};
+use crate::{config::CONFIG, utils::http_client_builder};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PayRequest {
+    callback: Url,
+    max_sendable: u64,
+    min_sendable: u64,
+    metadata: String,
+    comment_allowed: Option<i32>,
+    #[serde(default)]
+    allows_nostr: bool,
+    nostr_pubkey: Option<XOnlyPublicKey>,
+    payer_data: Option<PayerDataSpec>,
+}
+
+#[derive(Deserialize)]
+struct PayResponse {
+    pr: String,
+    verify: Option<Url>,
+    #[serde(default)]
+    routes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    reason: String,
+}
+
+/// The pay request url for a lightning address, LNURL or https url.
+pub(crate) fn pay_url(target: &str) -> Result<Url> {
+    let url = match target.split_once('@') {
+        Some((user, domain)) if !target.contains("://") => {
+            let scheme = if domain.ends_with(".onion") {
+                "http"
+            } else {
+                "https"
+            };
+            format!("{scheme}://{domain}/.well-known/lnurlp/{user}").parse()?
+        }
+        _ if target.starts_with("https://") => target.parse()?,
+        _ => decode_lnurl(target)?,
+    };
+
+    if url.host_str() == Some(CONFIG.domain.as_str()) {
+        bail!("Can't forward to a lightning address on this server");
+    }
+    Ok(url)
+}
+
+/// The user's node's pay request, with its callback replaced by ours so
+/// wallets keep talking to hermes.
+#[instrument(skip_all)]
+pub(crate) async fn well_known(target: &str, callback: Url) -> Result<LnurlWellKnownResponse> {
+    let pay_request: PayRequest = fetch(pay_url(target)?).await?;
+
+    Ok(LnurlWellKnownResponse {
+        callback,
+        max_sendable: Amount::from_msats(pay_request.max_sendable),
+        min_sendable: Amount::from_msats(pay_request.min_sendable),
+        metadata: pay_request.metadata,
+        comment_allowed: pay_request.comment_allowed,
+        tag: LnurlType::PayRequest,
+        status: LnurlStatus::Ok,
+        nostr_pubkey: pay_request.nostr_pubkey,
+        allows_nostr: pay_request.allows_nostr,
+        payer_data: pay_request.payer_data,
+    })
+}
+
+/// Passes the payer's callback query on to the user's node and returns its
+/// invoice, after checking it is for the requested amount.
+#[instrument(skip_all, fields(amount = amount))]
+pub(crate) async fn callback(
+    target: &str,
+    query: &str,
+    amount: u64,
+) -> Result<LnurlCallbackResponse> {
+    let pay_request: PayRequest = fetch(pay_url(target)?).await?;
+    let mut callback = pay_request.callback;
+    callback
+        .query_pairs_mut()
+        .extend_pairs(url::form_urlencoded::parse(query.as_bytes()));
+
+    let response: PayResponse = fetch(callback).await?;
+    let invoice = Bolt11Invoice::from_str(&response.pr).map_err(|e| anyhow!("{e}"))?;
+    ensure!(
+        invoice.amount_milli_satoshis() == Some(amount),
+        "Node returned an invoice for {:?} msats instead of {amount}",
+        invoice.amount_milli_satoshis()
+    );
+
+    Ok(LnurlCallbackResponse {
+        status: LnurlStatus::Ok,
+        reason: None,
+        pr: response.pr,
+        verify: response.verify,
+        success_action: None,
+        routes: Some(response.routes),
+    })
+}
+
+/// Fetches a LUD-06 response, turning an `ERROR` status into an error.
+async fn fetch<T: for<'de> Deserialize<'de>>(url: Url) -> Result<T> {
+    let body: serde_json::Value = http_client_builder()?
+        .timeout(TIMEOUT)
+        .build()?
+        .get(url)
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if body["status"].as_str() == Some("ERROR") {
+        let error: ErrorResponse = serde_json::from_value(body)?;
+        bail!("Node returned an error: {}", error.reason);
+    }
+    Ok(serde_json::from_value(body)?)
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use nostr::bitcoin::bech32::{self, FromBase32, ToBase32, Variant};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use url::Url;
+use utoipa::{IntoParams, ToSchema};
+
+use super::well_known::{handle_well_known, LnurlWellKnownResponse};
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::app_user::AppUserBmc,
+    router::{handlers::NameOrPubkey, public_url::public_base_url},
+    state::AppState,
+};
+
+const LNURL_HRP: &str = "lnurl";
+/// LUD-17 schemes and the LNURL subprotocol they stand for
+const LUD17_SCHEMES: [&str; 4] = ["lnurlp", "lnurlw", "lnurlc", "keyauth"];
+const WELL_KNOWN_PREFIX: &str = "/.well-known/lnurlp/";
+
+/// Every way of pointing a wallet at a user's pay request.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlResponse {
+    /// LUD-01 bech32 LNURL, uppercase for compact QR codes
+    pub lnurl: String,
+    /// The LNURL as a `lightning:` uri, for deep links and codes phone
+    /// cameras open in a wallet
+    pub uri: String,
+    /// LUD-17 `lnurlp://` uri
+    pub lud17: String,
+    /// LUD-16 lightning address
+    pub lightning_address: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ResolveParams {
+    /// A bech32 LNURL or LUD-17 uri, optionally with a `lightning:` prefix
+    pub lnurl: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/lnurlp/{username}/lnurl",
+    tag = "lnurlp",
+    params(("username" = String, Path, description = "Lightning address user")),
+    responses(
+        (status = 200, description = "LNURL encodings of the user's pay request", body = LnurlResponse),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_lnurl(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<LnurlResponse>, AppError> {
+    info!("lnurl called with username: {}", username);
+    Ok(Json(user_lnurls(&state, &headers, &username).await?))
+}
+
+/// The LNURL encodings of a user's pay request, as reached through `headers`.
+pub(crate) async fn user_lnurls(
+    state: &AppState,
+    headers: &HeaderMap,
+    username: &str,
+) -> Result<LnurlResponse, AppError> {
+    if state.cache.app_users.get(username).is_none() {
+        let app_user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, username)
+            .await
+            .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+        state.cache.app_users.insert(username, app_user);
+    }
+
+    let url: Url = format!("{}{WELL_KNOWN_PREFIX}{username}", public_base_url(headers)).parse()?;
+    let host = url.host_str().unwrap_or_default();
+
+    let lnurl = encode_lnurl(&url)?;
+    Ok(LnurlResponse {
+        uri: format!("lightning:{lnurl}"),
+        lnurl,
+        lud17: to_lud17(&url, "lnurlp")?,
+        lightning_address: format!("{username}@{host}"),
+    })
+}
+
+/// Serves the pay request an LNURL points to, as long as it is one of ours.
+#[utoipa::path(
+    get,
+    path = "/lnurlp/resolve",
+    tag = "lnurlp",
+    params(ResolveParams),
+    responses(
+        (status = 200, description = "LUD-06 pay request", body = LnurlWellKnownResponse),
+        (status = 400, description = "Not an LNURL for a user on this server", body = ErrorResponse),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_resolve(
+    Query(params): Query<ResolveParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<LnurlWellKnownResponse>, AppError> {
+    info!("resolve called with lnurl: {}", params.lnurl);
+    let url =
+        decode_lnurl(&params.lnurl).map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))?;
+
+    let ours: Url = public_base_url(&headers).parse()?;
+    let username = url
+        .path()
+        .strip_prefix(WELL_KNOWN_PREFIX)
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+        .filter(|_| url.host_str() == ours.host_str())
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::BadRequest,
+                anyhow!("Not a pay request on this server"),
+            )
+        })?;
+
+    handle_well_known(Path(username.to_string()), State(state), headers).await
+}
+
+/// LUD-01 bech32 encoding of a url.
+pub fn encode_lnurl(url: &Url) -> Result<String> {
+    let lnurl = bech32::encode(
+        LNURL_HRP,
+        url.as_str().as_bytes().to_base32(),
+        Variant::Bech32,
+    )?;
+    Ok(lnurl.to_uppercase())
+}
+
+/// Replaces the scheme with a LUD-17 one, e.g. `lnurlp://`.
+pub fn to_lud17(url: &Url, scheme: &str) -> Result<String> {
+    let (_, rest) = url
+        .as_str()
+        .split_once("://")
+        .ok_or_else(|| anyhow!("Url has no scheme: {url}"))?;
+    Ok(format!("{scheme}://{rest}"))
+}
+
+/// Decodes a bech32 LNURL or a LUD-17 uri into the https (or, for onion
+/// services, http) url it stands for.
+pub fn decode_lnurl(lnurl: &str) -> Result<Url> {
+    let lnurl = lnurl.trim();
+    let lnurl = lnurl
+        .get(..10)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("lightning:"))
+        .map_or(lnurl, |_| &lnurl[10..]);
+
+    if let Some((scheme, rest)) = lnurl.split_once("://") {
+        let scheme = scheme.to_lowercase();
+        if !LUD17_SCHEMES.contains(&scheme.as_str()) {
+            bail!("Unsupported LNURL scheme: {scheme}");
+        }
+        let url: Url = format!("https://{rest}").parse()?;
+        if url.host_str().is_some_and(|h| h.ends_with(".onion")) {
+            return Ok(format!("http://{rest}").parse()?);
+        }
+        return Ok(url);
+    }
+
+    let (hrp, data, _) = bech32::decode(lnurl)?;
+    if hrp != LNURL_HRP {
+        bail!("Expected an lnurl, got {hrp}");
+    }
+    let url = String::from_utf8(Vec::<u8>::from_base32(&data)?)?;
+
+    Ok(url.parse()?)
+}
@@ -1,16 +1,22 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 pub mod callback;
+pub mod comment;
+pub mod forward;
+pub mod lnurl;
+pub mod payer_data;
+pub mod qr;
 pub mod verify;
 pub mod well_known;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum LnurlType {
     PayRequest,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum LnurlStatus {
     Ok,
@@ -0,0 +1,108 @@
+//! LUD-18 payer identity: what a payer may tell the recipient about
+//! themselves, advertised on the pay request and checked on the callback
+//! before it is stored with the invoice.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use nostr::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::{AppError, ErrorCode};
+
+/// Longest `payerdata` accepted, in bytes of json.
+const MAX_PAYER_DATA_LEN: usize = 1024;
+/// Longest name, identifier or email accepted, in characters.
+const MAX_FIELD_LEN: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PayerDataField {
+    pub mandatory: bool,
+}
+
+/// The payer data a pay request asks for, all of it optional here.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct PayerDataSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<PayerDataField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<PayerDataField>,
+}
+
+/// What a payer sent in `payerdata`. Fields that weren't asked for, like
+/// `auth`, are dropped.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct PayerData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Hex secp256k1 public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    /// Usually the payer's own lightning address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+pub(crate) fn spec() -> PayerDataSpec {
+    let optional = || Some(PayerDataField { mandatory: false });
+    PayerDataSpec {
+        name: optional(),
+        pubkey: optional(),
+        identifier: optional(),
+        email: optional(),
+    }
+}
+
+/// Checks a callback's `payerdata` and returns the json to store, without
+/// the fields that weren't asked for. `None` if nothing was sent.
+pub(crate) fn parse(payer_data: Option<String>) -> Result<Option<String>, AppError> {
+    let Some(payer_data) = payer_data else {
+        return Ok(None);
+    };
+    if payer_data.len() > MAX_PAYER_DATA_LEN {
+        return Err(invalid(format!(
+            "payerdata is longer than {MAX_PAYER_DATA_LEN} bytes"
+        )));
+    }
+
+    let payer_data: PayerData = serde_json::from_str(&payer_data)
+        .map_err(|e| invalid(format!("Invalid payerdata: {e}")))?;
+    for (field, value) in [
+        ("name", &payer_data.name),
+        ("identifier", &payer_data.identifier),
+        ("email", &payer_data.email),
+    ] {
+        if value
+            .as_ref()
+            .is_some_and(|v| v.chars().count() > MAX_FIELD_LEN)
+        {
+            return Err(invalid(format!(
+                "payerdata {field} is longer than {MAX_FIELD_LEN} characters"
+            )));
+        }
+    }
+    if let Some(pubkey) = payer_data.pubkey.as_deref() {
+        PublicKey::from_str(pubkey)
+            .map_err(|e| invalid(format!("Invalid payerdata pubkey: {e}")))?;
+    }
+
+    if payer_data.name.is_none()
+        && payer_data.pubkey.is_none()
+        && payer_data.identifier.is_none()
+        && payer_data.email.is_none()
+    {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(&payer_data)?))
+}
+
+fn invalid(message: String) -> AppError {
+    AppError::from_code(ErrorCode::BadRequest, anyhow!(message))
+}
@@ -0,0 +1,111 @@
+use std::io::Cursor;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use super::lnurl::user_lnurls;
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    state::AppState,
+};
+
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+/// What the code encodes.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrContent {
+    /// Bech32 LNURL, understood by the most wallets
+    #[default]
+    Lnurl,
+    /// The LNURL as a `lightning:` uri
+    Uri,
+    /// `user@domain`
+    Address,
+    /// LUD-17 `lnurlp://` uri
+    Lud17,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrParams {
+    #[serde(default)]
+    pub format: QrFormat,
+    #[serde(default)]
+    pub content: QrContent,
+    /// Minimum width and height in pixels, at most 1024
+    pub size: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/lnurlp/{username}/qr",
+    tag = "lnurlp",
+    params(("username" = String, Path, description = "Lightning address user"), QrParams),
+    responses(
+        (status = 200, description = "QR code image", content_type = ["image/svg+xml", "image/png"]),
+        (status = 400, description = "Size out of range", body = ErrorResponse),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_qr(
+    Path(username): Path<String>,
+    Query(params): Query<QrParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info!("qr called with username: {}, {:?}", username, params);
+    let size = params.size.unwrap_or(DEFAULT_SIZE);
+    if size == 0 || size > MAX_SIZE {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("size must be between 1 and {MAX_SIZE}"),
+        ));
+    }
+
+    let lnurls = user_lnurls(&state, &headers, &username).await?;
+    let data = match params.content {
+        QrContent::Lnurl => lnurls.lnurl,
+        QrContent::Uri => lnurls.uri,
+        QrContent::Address => lnurls.lightning_address,
+        QrContent::Lud17 => lnurls.lud17,
+    };
+    let code = QrCode::new(data.as_bytes())?;
+
+    let response = match params.format {
+        QrFormat::Svg => {
+            let image = code
+                .render::<svg::Color>()
+                .min_dimensions(size, size)
+                .build();
+            ([(CONTENT_TYPE, "image/svg+xml")], image).into_response()
+        }
+        QrFormat::Png => {
+            let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+            let mut png = Vec::new();
+            DynamicImage::ImageLuma8(image)
+                .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+            ([(CONTENT_TYPE, "image/png")], png).into_response()
+        }
+    };
+
+    Ok(response)
+}
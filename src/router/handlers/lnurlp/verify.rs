@@ -1,28 +1,64 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
 use axum::{
     extract::{Path, State},
     Json,
 };
-
+use fedimint_client::derivable_secret::ChildId;
+use fedimint_core::config::FederationId;
+use nostr::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::{Hash, HashEngine};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use subtle::ConstantTimeEq;
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+use zeroize::Zeroizing;
 
 use crate::model::invoice_state::InvoiceState;
-use crate::{error::AppError, model::invoice::InvoiceBmc, state::AppState};
+use crate::{
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::invoice::{Invoice, InvoiceBmc},
+    state::AppState,
+};
 
-use super::LnurlStatus;
+use super::{callback::record_preimage, LnurlStatus};
 
-#[derive(Serialize, Deserialize)]
+/// Child of `SECRET_KEY` the verify token key is derived from.
+const VERIFY_KEY_CHILD_ID: ChildId = ChildId(0x7665_7269_6679);
+/// Bytes of the HMAC kept in the url, plenty against guessing.
+const TOKEN_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LnurlVerifyResponse {
     pub status: LnurlStatus,
     pub settled: bool,
-    pub preimage: String,
+    /// Hex preimage, `null` until the invoice settled
+    pub preimage: Option<String>,
     pub pr: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/lnurlp/{username}/verify/{op_id}/{token}",
+    tag = "lnurlp",
+    params(
+        ("username" = String, Path, description = "Lightning address user"),
+        ("op_id" = String, Path, description = "Operation id from the callback's verify url"),
+        ("token" = String, Path, description = "Token from the callback's verify url"),
+    ),
+    responses(
+        (status = 200, description = "LUD-21 payment status", body = LnurlVerifyResponse),
+        (status = 404, description = "Unknown invoice", body = ErrorResponse),
+    )
+)]
 #[axum_macros::debug_handler]
+#[instrument(skip_all, fields(username = %username, op_id = %op_id))]
 pub async fn handle_verify(
-    Path((username, op_id)): Path<(String, String)>,
+    Path((username, op_id, token)): Path<(String, String, String)>,
     State(state): State<AppState>,
 ) -> Result<Json<LnurlVerifyResponse>, AppError> {
     info!(
@@ -31,14 +67,127 @@ pub async fn handle_verify(
     );
 
     // Use the operation id to look up the invoice
-    let invoice = InvoiceBmc::get_by_op_id(&state.mm, &op_id).await?;
+    let invoice = InvoiceBmc::get_by_op_id(&state.mm, &op_id)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::InvoiceNotFound, e))?;
+    // a wrong token looks the same as an unknown invoice
+    if !token_matches(invoice.app_user_id, &op_id, &token) {
+        return Err(AppError::from_code(
+            ErrorCode::InvoiceNotFound,
+            anyhow!("No invoice found with op_id: {op_id}"),
+        ));
+    }
+
+    Ok(Json(verify_response(&state, invoice).await))
+}
+
+/// The verify url from before it carried a token, only answered for the
+/// invoices handed out with it until they expire.
+#[axum_macros::debug_handler]
+#[instrument(skip_all, fields(username = %username, op_id = %op_id))]
+pub async fn handle_legacy_verify(
+    Path((username, op_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Result<Json<LnurlVerifyResponse>, AppError> {
+    info!(
+        "legacy verify called with username: {}, op_id: {}",
+        username, op_id
+    );
+
+    let invoice = InvoiceBmc::get_legacy_verify(&state.mm, &op_id)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::InvoiceNotFound,
+                anyhow!("No invoice found with op_id: {op_id}"),
+            )
+        })?;
+
+    Ok(Json(verify_response(&state, invoice).await))
+}
+
+async fn verify_response(state: &AppState, invoice: Invoice) -> LnurlVerifyResponse {
+    let settled = invoice.state == InvoiceState::Settled;
+    let preimage = match invoice.preimage.clone() {
+        Some(preimage) => Some(preimage),
+        // recording it when settling is best effort, try again
+        None if settled => fetch_preimage(state, &invoice).await,
+        None => None,
+    };
 
-    let verify_response = LnurlVerifyResponse {
+    LnurlVerifyResponse {
         status: LnurlStatus::Ok,
-        settled: invoice.state == InvoiceState::Settled,
-        preimage: "".to_string(), // TODO: figure out how to get the preimage from fedimint client
+        settled,
+        preimage,
         pr: invoice.bolt11,
-    };
+    }
+}
+
+/// Asks the invoice's federation for the preimage of a settled invoice and
+/// stores it for next time.
+async fn fetch_preimage(state: &AppState, invoice: &Invoice) -> Option<String> {
+    let federation_id = FederationId::from_str(&invoice.federation_id).ok()?;
+    let client = state.federations.get(&federation_id)?;
+    record_preimage(&state.mm, &client, invoice).await
+}
+
+/// The LUD-21 verify url of an invoice.
+pub(crate) fn verify_url(base_url: &str, username: &str, app_user_id: i32, op_id: &str) -> String {
+    let token = verify_token(app_user_id, op_id);
+    format!("{base_url}/lnurlp/{username}/verify/{op_id}/{token}")
+}
+
+/// Whether `token` is the one of the invoice's verify url, compared in
+/// constant time.
+pub(crate) fn token_matches(app_user_id: i32, op_id: &str, token: &str) -> bool {
+    same_token(&verify_token(app_user_id, op_id), token)
+}
+
+fn same_token(expected: &str, token: &str) -> bool {
+    bool::from(expected.as_bytes().ct_eq(token.as_bytes()))
+}
+
+/// The token that makes a verify url unguessable, an HMAC over the invoice's
+/// operation id and user with a key derived from `SECRET_KEY`.
+fn verify_token(app_user_id: i32, op_id: &str) -> String {
+    let key: Zeroizing<[u8; 32]> = Zeroizing::new(
+        CONFIG
+            .root_secret
+            .child_key(VERIFY_KEY_CHILD_ID)
+            .to_random_bytes(),
+    );
+    keyed_token(key.as_slice(), app_user_id, op_id)
+}
+
+fn keyed_token(key: &[u8], app_user_id: i32, op_id: &str) -> String {
+    let mut engine = HmacEngine::<Sha256>::new(key);
+    engine.input(format!("{app_user_id}:{op_id}").as_bytes());
+    let hmac = Hmac::<Sha256>::from_engine(engine);
+    hex::encode(&hmac.as_byte_array()[..TOKEN_LEN])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3; 32];
+
+    #[test]
+    fn token_is_bound_to_key_user_and_operation() {
+        let token = keyed_token(&KEY, 1, "op");
+        assert_eq!(token.len(), TOKEN_LEN * 2);
+        assert_eq!(token, keyed_token(&KEY, 1, "op"));
+        assert_ne!(token, keyed_token(&KEY, 2, "op"));
+        assert_ne!(token, keyed_token(&KEY, 1, "other"));
+        assert_ne!(token, keyed_token(&[4; 32], 1, "op"));
+    }
 
-    Ok(Json(verify_response))
+    #[test]
+    fn only_the_exact_token_matches() {
+        let token = keyed_token(&KEY, 1, "op");
+        assert!(same_token(&token, &token));
+        assert!(!same_token(&token, &token[1..]));
+        assert!(!same_token(&token, &token.to_uppercase()));
+        assert!(!same_token(&token, ""));
+    }
 }
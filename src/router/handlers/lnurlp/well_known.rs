@@ -1,10 +1,13 @@
-use super::{LnurlStatus, LnurlType};
-use crate::config::CONFIG;
-use crate::error::AppError;
+use super::payer_data::{self, PayerDataSpec};
+use super::{forward, LnurlStatus, LnurlType};
+use crate::config::{CONFIG, RUNTIME_CONFIG};
+use crate::error::{AppError, ErrorCode, ErrorResponse};
 use crate::model::app_user::AppUserBmc;
 use crate::router::handlers::NameOrPubkey;
+use crate::router::public_url::public_base_url;
 use crate::state::AppState;
 use axum::extract::{Path, State};
+use axum::http::HeaderMap;
 use axum::Json;
 use fedimint_core::Amount;
 use nostr::prelude::XOnlyPublicKey;
@@ -12,6 +15,7 @@ use serde::ser::{SerializeTuple, Serializer};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use url::Url;
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MetadataType {
@@ -22,6 +26,18 @@ pub enum MetadataType {
     TextIdentifier,
 }
 
+impl MetadataType {
+    fn mime_type(&self) -> &'static str {
+        match self {
+            MetadataType::TextPlain => "text/plain",
+            MetadataType::ImagePngBase64 => "image/png;base64",
+            MetadataType::ImageJpegBase64 => "image/jpeg;base64",
+            MetadataType::TextEmail => "text/email",
+            MetadataType::TextIdentifier => "text/identifier",
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct MetadataEntry {
     pub metadata_type: MetadataType,
@@ -34,17 +50,42 @@ impl Serialize for MetadataEntry {
         S: Serializer,
     {
         let mut tup = serializer.serialize_tuple(2)?;
-        tup.serialize_element(&format!("{:?}", self.metadata_type))?;
+        tup.serialize_element(self.metadata_type.mime_type())?;
         tup.serialize_element(&self.content)?;
         tup.end()
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// The text payers are shown for a user's address.
+pub fn payment_description(username: &str) -> String {
+    format!("Pay to {username}@{}", CONFIG.domain)
+}
+
+/// LUD-06 metadata for a user's address, the json array of entries payer
+/// wallets display.
+pub fn metadata(username: &str) -> String {
+    let entries = [
+        MetadataEntry {
+            metadata_type: MetadataType::TextPlain,
+            content: payment_description(username),
+        },
+        MetadataEntry {
+            metadata_type: MetadataType::TextIdentifier,
+            content: format!("{username}@{}", CONFIG.domain),
+        },
+    ];
+    serde_json::to_string(&entries).expect("metadata entries serialize")
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LnurlWellKnownResponse {
     pub callback: Url,
+    /// In millisatoshis
+    #[schema(value_type = u64)]
     pub max_sendable: Amount,
+    /// In millisatoshis
+    #[schema(value_type = u64)]
     pub min_sendable: Amount,
     pub metadata: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -52,29 +93,69 @@ pub struct LnurlWellKnownResponse {
     pub tag: LnurlType,
     pub status: LnurlStatus,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
     pub nostr_pubkey: Option<XOnlyPublicKey>,
     pub allows_nostr: bool,
+    /// LUD-18 payer identity the payer may send as `payerdata`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payer_data: Option<PayerDataSpec>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/.well-known/lnurlp/{username}",
+    tag = "lnurlp",
+    params(("username" = String, Path, description = "Lightning address user")),
+    responses(
+        (status = 200, description = "LUD-06 pay request", body = LnurlWellKnownResponse),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+        (status = 502, description = "The user's own node is unreachable", body = ErrorResponse),
+    )
+)]
 #[axum_macros::debug_handler]
 pub async fn handle_well_known(
     Path(username): Path<String>,
     State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Result<Json<LnurlWellKnownResponse>, AppError> {
     // see if username exists in nostr.json
     info!("well_known called with username: {}", username);
-    let _app_user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username).await?;
+    let app_user = match state.cache.app_users.get(&username) {
+        Some(app_user) => app_user,
+        None => {
+            let app_user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &username)
+                .await
+                .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+            state.cache.app_users.insert(&username, app_user.clone());
+            app_user
+        }
+    };
+
+    let base_url = public_base_url(&headers);
+    let callback: Url = format!("{base_url}/lnurlp/{username}/callback").parse()?;
+    if let Some(target) = app_user.forward_to.as_deref() {
+        let res = forward::well_known(target, callback)
+            .await
+            .map_err(|e| AppError::from_code(ErrorCode::NodeUnavailable, e))?;
+        return Ok(Json(res));
+    }
 
+    let runtime = RUNTIME_CONFIG.load();
     let res = LnurlWellKnownResponse {
-        callback: format!("http://{}/lnurlp/{}/callback", CONFIG.domain, username).parse()?,
-        max_sendable: Amount { msats: 100000 },
-        min_sendable: Amount { msats: 1000 },
-        metadata: "test metadata".to_string(),
-        comment_allowed: None,
+        callback,
+        max_sendable: Amount {
+            msats: runtime.max_sendable_msats,
+        },
+        min_sendable: Amount {
+            msats: runtime.min_sendable_msats,
+        },
+        metadata: metadata(&username),
+        comment_allowed: (CONFIG.comment_allowed > 0).then_some(CONFIG.comment_allowed as i32),
         tag: LnurlType::PayRequest,
         status: LnurlStatus::Ok,
         nostr_pubkey: Some(CONFIG.nostr_sk.public_key()),
         allows_nostr: true,
+        payer_data: Some(payer_data::spec()),
     };
 
     Ok(Json(res))
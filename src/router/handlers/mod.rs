@@ -1,9 +1,46 @@
-use std::{fmt, fs::read_to_string};
+use std::{fmt, fs::read_to_string, str::FromStr};
 
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+use crate::{
+    error::{AppError, ErrorCode},
+    state::AppState,
+};
+
+pub mod activity;
+pub mod admin;
+pub mod balance;
+pub mod ecash;
+pub mod events;
+pub mod gift;
+pub mod health;
+pub mod invoices;
+pub mod lnbits;
 pub mod lnurlp;
 pub mod nostr;
+pub mod refunds;
+pub mod status;
+pub mod wallet;
+
+/// The connected client for a federation id, for handlers where the payer or
+/// user names the federation.
+pub(crate) fn federation_client(
+    state: &AppState,
+    federation_id: &str,
+) -> Result<ClientArc, AppError> {
+    FederationId::from_str(federation_id)
+        .ok()
+        .and_then(|id| state.federations.get(&id))
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::FederationUnavailable,
+                anyhow::anyhow!("federation {federation_id} is not connected"),
+            )
+        })
+}
 
 #[axum_macros::debug_handler]
 pub async fn handle_readme() -> String {
@@ -16,13 +53,35 @@ pub enum NameOrPubkey {
     Pubkey,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum SupportedDmType {
     Nostr,
     Xmpp,
 }
 
+/// How a user receives the ecash for a paid invoice.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteFormat {
+    #[default]
+    Fedimint,
+    /// Melted into `CASHU_MINT_URL` and sent as a Cashu token
+    Cashu,
+    /// Fedimint notes behind a one-time claim link, only the link is sent
+    Link,
+}
+
+impl fmt::Display for NoteFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NoteFormat::Fedimint => write!(f, "fedimint"),
+            NoteFormat::Cashu => write!(f, "cashu"),
+            NoteFormat::Link => write!(f, "link"),
+        }
+    }
+}
+
 impl fmt::Display for SupportedDmType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 pub mod register;
+pub mod verify;
 pub mod well_known;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +10,29 @@ pub struct AppUserRelays {
     pub pubkey: String,
     pub name: String,
     pub dm_type: String,
+    pub note_format: String,
     pub federation_id: String,
+    /// Receipts are held and paid out together by the payout batching job
+    #[serde(default)]
+    pub batch_payouts: bool,
+    /// Receipts stay in hermes custody until the user claims them
+    #[serde(default)]
+    pub custodial: bool,
+    /// Lightning address or LNURL of the user's own node that payments go to
+    pub forward_to: Option<String>,
+    /// Overrides `NOTES_EXPIRY_SECS` for this user
+    pub notes_expiry_secs: Option<i32>,
+    /// Verified XMPP address DMs go to
+    #[serde(default)]
+    pub jid: Option<String>,
+    /// XMPP groupchat that gets a notice of every settled payment
+    #[serde(default)]
+    pub notify_room: Option<String>,
+    /// Tried in order when delivering over `dm_type` fails
+    #[serde(default)]
+    pub fallback_dm_types: Vec<String>,
+    /// NWC wallet that gets NIP-47 notifications of settled payments
+    #[serde(default)]
+    pub nwc_pubkey: Option<String>,
     pub relays: Vec<String>,
 }
@@ -1,56 +1,276 @@
+use std::str::FromStr;
+
 use anyhow::anyhow;
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{extract::State, Json};
 use fedimint_core::config::FederationId;
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::prelude::rand::{rngs::OsRng, Rng};
+use nostr::secp256k1::XOnlyPublicKey;
 use serde::Deserialize;
+use serde_json::json;
 use tracing::info;
+use utoipa::ToSchema;
 
 use crate::{
-    config::CONFIG,
-    error::AppError,
-    model::app_user_relays::{AppUserRelaysBmc, AppUserRelaysForCreate},
+    audit,
+    config::{CONFIG, RUNTIME_CONFIG},
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::{
+        app_user::AppUserBmc,
+        app_user_relays::{AppUserRelaysBmc, AppUserRelaysForCreate},
+        xmpp_challenge::XmppChallengeBmc,
+        ModelManager,
+    },
+    router::handlers::lnurlp::forward,
     state::AppState,
+    xmpp_client,
 };
 
-use crate::router::SupportedDmType;
+use crate::router::{NoteFormat, SupportedDmType};
+
+/// An hour, enough to come online and redeem.
+const MIN_NOTES_EXPIRY_SECS: u32 = 60 * 60;
+/// 30 days, past that unredeemed notes are better reclaimed.
+const MAX_NOTES_EXPIRY_SECS: u32 = 30 * 24 * 60 * 60;
+/// Longest name the app_user table holds.
+const MAX_NAME_LEN: usize = 20;
+/// How many free alternatives a `NAME_TAKEN` error offers.
+const NAME_SUGGESTIONS: usize = 3;
+/// How long an XMPP verification code can be used for.
+const XMPP_CODE_MAX_AGE_SECS: i64 = 10 * 60;
+/// A minute between codes sent to the same JID, so hermes can't be used to
+/// flood someone with messages.
+const XMPP_CODE_COOLDOWN_SECS: i64 = 60;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct UserParams {
     pub pubkey: String,
     pub name: String,
     pub dm_type: SupportedDmType,
+    #[serde(default)]
+    pub note_format: NoteFormat,
+    #[schema(value_type = String)]
     pub federation_id: FederationId,
+    /// Collect receipts into a scheduled combined payout instead of one per payment
+    #[serde(default)]
+    pub batch_payouts: bool,
+    /// Keep receipts as a balance to claim on demand instead of sending them
+    #[serde(default)]
+    pub custodial: bool,
+    /// Lightning address or LNURL of your own node, invoices are fetched from
+    /// it instead of the federation
+    pub forward_to: Option<String>,
+    /// Seconds you have to redeem sent notes before hermes reclaims them,
+    /// the server's default if not given
+    pub notes_expiry_secs: Option<u32>,
     pub relays: Option<Vec<String>>,
+    /// XMPP address DMs go to, required for XMPP
+    pub jid: Option<String>,
+    /// The code `POST /register/xmpp` sent to `jid`
+    pub jid_code: Option<String>,
+    /// XMPP groupchat (MUC) hermes joins to post a notice of every payment,
+    /// e.g. a team's treasury room. The ecash still goes to you.
+    pub notify_room: Option<String>,
+    /// DM types tried in order when delivering over `dm_type` fails, e.g.
+    /// `["nostr"]` for an XMPP user
+    #[serde(default)]
+    pub fallback_dm_types: Vec<SupportedDmType>,
+    /// Hex pubkey of your NWC wallet, sent a NIP-47 `payment_received`
+    /// notification whenever one of your invoices settles
+    pub nwc_pubkey: Option<String>,
 }
 
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct XmppChallengeParams {
+    pub jid: String,
+}
+
+/// Sends a verification code to an XMPP address, registering with it needs
+/// the code as `jid_code`.
+#[utoipa::path(
+    post,
+    path = "/register/xmpp",
+    tag = "nostr",
+    request_body = XmppChallengeParams,
+    responses(
+        (status = 200, description = "Code sent, or an earlier one is still fresh", body = bool),
+        (status = 400, description = "Invalid JID", body = ErrorResponse),
+        (status = 403, description = "Registration is closed", body = ErrorResponse),
+    )
+)]
+pub async fn handle_xmpp_challenge(
+    State(state): State<AppState>,
+    Json(params): Json<XmppChallengeParams>,
+) -> Result<Json<bool>, AppError> {
+    info!("xmpp challenge called for jid: {}", params.jid);
+
+    if !RUNTIME_CONFIG.load().registration_open {
+        return Err(AppError::from_code(
+            ErrorCode::RegistrationClosed,
+            anyhow!("This server isn't accepting new registrations"),
+        ));
+    }
+
+    let jid = parse_jid(&params.jid)?;
+    let code = format!("{:06}", OsRng.gen_range(0..1_000_000));
+    let stored =
+        XmppChallengeBmc::upsert(&state.mm, &jid, &hash_code(&code), XMPP_CODE_COOLDOWN_SECS)
+            .await?;
+    if stored {
+        let message = format!("Your hermes verification code is {code}");
+        xmpp_client::send_message(&jid, &message, None)?;
+        // so DMs can wait for them to be online
+        xmpp_client::subscribe(&jid)?;
+    }
+
+    Ok(Json(true))
+}
+
+fn parse_jid(jid: &str) -> Result<String, AppError> {
+    let jid = xmpp_client::parse_bare(jid)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, anyhow!("Invalid JID: {e}")))?;
+    Ok(jid.to_string())
+}
+
+fn hash_code(code: &str) -> String {
+    Sha256::hash(code.as_bytes()).to_string()
+}
+
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "nostr",
+    request_body = UserParams,
+    responses(
+        (status = 200, description = "Registered", body = bool),
+        (status = 400, description = "Invalid registration or unknown federation", body = ErrorResponse),
+        (status = 403, description = "Registration is closed", body = ErrorResponse),
+        (status = 409, description = "Name is taken, with free alternatives in `suggestions`", body = ErrorResponse),
+    )
+)]
 #[axum_macros::debug_handler]
 pub async fn handle_register(
     State(state): State<AppState>,
     Json(params): Json<UserParams>,
 ) -> Result<Json<bool>, AppError> {
     info!("register called with pubkey: {:?}", params.pubkey);
+    register_user(&state, params).await?;
+
+    Ok(Json(true))
+}
+
+/// Validates and registers a user.
+async fn register_user(state: &AppState, params: UserParams) -> Result<(), AppError> {
+    let (user_c, details) = prepare_registration(state, params).await?;
+    let name = user_c.name.clone();
+    let actor = audit::user_actor(&user_c.pubkey);
+
+    match AppUserRelaysBmc::register(&state.mm, user_c).await {
+        Ok(_) => {
+            state.cache.invalidate(&name);
+            audit::record(&state.mm, actor, "user.register", Some(&name), details).await;
+            Ok(())
+        }
+        Err(e) => Err(registration_error(&state.mm, &name, e).await),
+    }
+}
+
+/// Validates a registration, returning the user to create and the details to
+/// audit once it is. Shared by `POST /register` and wallet pairing, which
+/// creates the user in its own transaction.
+pub(crate) async fn prepare_registration(
+    state: &AppState,
+    params: UserParams,
+) -> Result<(AppUserRelaysForCreate, serde_json::Value), AppError> {
+    if !RUNTIME_CONFIG.load().registration_open {
+        return Err(AppError::from_code(
+            ErrorCode::RegistrationClosed,
+            anyhow!("This server isn't accepting new registrations"),
+        ));
+    }
 
     // Check if the federationId is in the multimint map
-    if !state
-        .fm
-        .clients
-        .lock()
-        .await
-        .contains_key(&params.federation_id)
-    {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
+    if !state.federations.contains(&params.federation_id) {
+        return Err(AppError::from_code(
+            ErrorCode::FederationUnavailable,
             anyhow!("FederationId not found in multimint map"),
         ));
     }
 
+    if params.note_format == NoteFormat::Cashu && CONFIG.cashu_mint_url.is_none() {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Cashu delivery is not enabled on this server"),
+        ));
+    }
+
+    if params.batch_payouts && params.note_format != NoteFormat::Fedimint {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Batched payouts are only available for fedimint notes"),
+        ));
+    }
+
+    if params.custodial && (params.batch_payouts || params.note_format != NoteFormat::Fedimint) {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!("Custodial balances are claimed as fedimint notes and can't be batched"),
+        ));
+    }
+
+    if params
+        .notes_expiry_secs
+        .is_some_and(|secs| !(MIN_NOTES_EXPIRY_SECS..=MAX_NOTES_EXPIRY_SECS).contains(&secs))
+    {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow!(
+                "notes_expiry_secs must be between {MIN_NOTES_EXPIRY_SECS} and {MAX_NOTES_EXPIRY_SECS}"
+            ),
+        ));
+    }
+
+    if let Some(target) = params.forward_to.as_deref() {
+        forward::pay_url(target).map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))?;
+    }
+
+    let mut dm_types = vec![&params.dm_type];
+    for fallback in &params.fallback_dm_types {
+        if dm_types.contains(&fallback) {
+            return Err(AppError::from_code(
+                ErrorCode::InvalidDmType,
+                anyhow!("{fallback} is already tried before"),
+            ));
+        }
+        dm_types.push(fallback);
+    }
+
+    let jid = if dm_types.contains(&&SupportedDmType::Xmpp) {
+        let jid = params.jid.as_deref().ok_or_else(|| {
+            AppError::from_code(ErrorCode::InvalidDmType, anyhow!("XMPP requires a jid"))
+        })?;
+        Some(parse_jid(jid)?)
+    } else {
+        None
+    };
+
+    let notify_room = params.notify_room.as_deref().map(parse_jid).transpose()?;
+
+    if let Some(nwc_pubkey) = &params.nwc_pubkey {
+        XOnlyPublicKey::from_str(nwc_pubkey).map_err(|e| {
+            AppError::from_code(ErrorCode::BadRequest, anyhow!("Invalid nwc_pubkey: {e}"))
+        })?;
+    }
+
     let relays = match params.dm_type {
         SupportedDmType::Nostr => params
             .relays
-            .unwrap_or_else(|| vec![CONFIG.default_relay.clone()]),
+            .unwrap_or_else(|| RUNTIME_CONFIG.load().nostr_relays.clone()),
         SupportedDmType::Xmpp => {
             if params.relays.clone().is_some_and(|r| r.len() != 1) {
-                return Err(AppError::new(
-                    StatusCode::BAD_REQUEST,
+                return Err(AppError::from_code(
+                    ErrorCode::InvalidDmType,
                     anyhow!("XMPP requires exactly one chat server"),
                 ));
             } else {
@@ -61,19 +281,102 @@ pub async fn handle_register(
         }
     };
 
+    let name = params.name;
+    if !AppUserBmc::taken_names(&state.mm, &[name.clone()])
+        .await?
+        .is_empty()
+    {
+        return Err(name_taken(&state.mm, &name).await);
+    }
+
+    // last check, a verified code is used up
+    if let Some(jid) = &jid {
+        let verified = match params.jid_code.as_deref() {
+            Some(code) => {
+                XmppChallengeBmc::verify(&state.mm, jid, &hash_code(code), XMPP_CODE_MAX_AGE_SECS)
+                    .await?
+            }
+            None => false,
+        };
+        if !verified {
+            return Err(AppError::from_code(
+                ErrorCode::InvalidDmType,
+                anyhow!("Wrong or expired jid_code, request one from /register/xmpp"),
+            ));
+        }
+    }
+
+    let details = json!({
+        "federationId": params.federation_id.to_string(),
+        "dmType": params.dm_type.to_string(),
+        "fallbackDmTypes": params.fallback_dm_types.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+        "noteFormat": params.note_format.to_string(),
+        "batchPayouts": params.batch_payouts,
+        "custodial": params.custodial,
+        "forwarded": params.forward_to.is_some(),
+        "nwc": params.nwc_pubkey.is_some(),
+    });
     let nip05relays_c = AppUserRelaysForCreate {
         pubkey: params.pubkey,
         federation_id: params.federation_id.to_string(),
-        name: params.name,
+        name: name.clone(),
         dm_type: params.dm_type.to_string(),
+        note_format: params.note_format.to_string(),
+        batch_payouts: params.batch_payouts,
+        custodial: params.custodial,
+        forward_to: params.forward_to,
+        notes_expiry_secs: params.notes_expiry_secs.map(|secs| secs as i32),
+        jid,
+        notify_room,
+        fallback_dm_types: params
+            .fallback_dm_types
+            .iter()
+            .map(|dm_type| dm_type.to_string())
+            .collect(),
+        nwc_pubkey: params.nwc_pubkey,
         relays,
     };
 
-    match AppUserRelaysBmc::register(&state.mm, nip05relays_c).await {
-        Ok(_) => Ok(Json(true)),
-        Err(e) => Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            anyhow!("Error registering nip05relays {:?}", e),
-        )),
+    Ok((nip05relays_c, details))
+}
+
+/// The error for a failed insert of a registration.
+pub(crate) async fn registration_error(
+    mm: &ModelManager,
+    name: &str,
+    e: anyhow::Error,
+) -> AppError {
+    // lost a race with a concurrent registration of the same name
+    if AppUserBmc::is_name_conflict(&e) {
+        return name_taken(mm, name).await;
     }
+    AppError::from_code(
+        ErrorCode::RegistrationFailed,
+        anyhow!("Error registering nip05relays {:?}", e),
+    )
+}
+
+/// A `NAME_TAKEN` error offering up to `NAME_SUGGESTIONS` free names built by
+/// appending digits, trimmed to fit `MAX_NAME_LEN`.
+async fn name_taken(mm: &ModelManager, name: &str) -> AppError {
+    let candidates: Vec<String> = (1..=9)
+        .map(|i| {
+            let base: String = name.chars().take(MAX_NAME_LEN - 1).collect();
+            format!("{base}{i}")
+        })
+        .collect();
+    let taken = AppUserBmc::taken_names(mm, &candidates)
+        .await
+        .unwrap_or_else(|_| candidates.iter().map(|c| c.to_lowercase()).collect());
+    let suggestions = candidates
+        .into_iter()
+        .filter(|c| !taken.contains(&c.to_lowercase()))
+        .take(NAME_SUGGESTIONS)
+        .collect();
+
+    AppError::from_code(
+        ErrorCode::NameTaken,
+        anyhow!("The name {name} is already taken"),
+    )
+    .with_suggestions(suggestions)
 }
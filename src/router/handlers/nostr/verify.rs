@@ -0,0 +1,106 @@
+//! Checking a single NIP-05 identity against hermes's own records, so
+//! clients verifying many of them don't need a nostr.json for each.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::Response,
+};
+use nostr::prelude::XOnlyPublicKey;
+use nostr::{Event, EventBuilder, Kind, Tag};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    compute,
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::app_user::AppUserBmc,
+    state::AppState,
+};
+
+use super::well_known::cached_json;
+
+/// NIP-78 app data, replaceable per identity by its `d` tag.
+const ATTESTATION_KIND: Kind = Kind::Custom(30078);
+
+#[derive(Deserialize, Serialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Nip05VerifyParams {
+    /// The identity to check, e.g. `alice@example.com`
+    pub nip05: String,
+    /// Hex pubkey it should belong to
+    pub pubkey: String,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Nip05Attestation {
+    pub nip05: String,
+    pub pubkey: String,
+    /// Whether `nip05` belongs to `pubkey`
+    pub valid: bool,
+    /// The same, as an event signed with hermes's nostr key, the
+    /// `nostrPubkey` of its LNURL pay responses
+    #[schema(value_type = Object)]
+    pub attestation: Event,
+}
+
+#[utoipa::path(
+    get,
+    path = "/nip05/verify",
+    tag = "nostr",
+    params(Nip05VerifyParams),
+    responses(
+        (status = 200, description = "Signed result of the check", body = Nip05Attestation),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 400, description = "Invalid pubkey, or not an identity on this domain", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_nip05_verify(
+    Query(params): Query<Nip05VerifyParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info!("nip05 verify called with {params:?}");
+    let pubkey = XOnlyPublicKey::from_str(&params.pubkey)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, anyhow!("Invalid pubkey: {e}")))?;
+    let name = match params.nip05.rsplit_once('@') {
+        Some((name, domain)) if domain.eq_ignore_ascii_case(&CONFIG.domain) => name,
+        _ => {
+            return Err(AppError::from_code(
+                ErrorCode::BadRequest,
+                anyhow!("Only identities @{} can be verified here", CONFIG.domain),
+            ))
+        }
+    };
+
+    let nip05 = format!("{name}@{}", CONFIG.domain);
+    let pubkey = pubkey.to_string();
+    let valid = AppUserBmc::find_pubkey(&state.mm, name).await? == Some(pubkey.clone());
+
+    let content = json!({ "nip05": nip05, "pubkey": pubkey, "valid": valid });
+    let tags = vec![
+        Tag::parse(vec!["d".to_string(), format!("nip05:{nip05}")])?,
+        Tag::parse(vec!["p".to_string(), pubkey.clone()])?,
+    ];
+    let builder = EventBuilder::new(ATTESTATION_KIND, content.to_string(), &tags);
+    let attestation = compute::run(move || builder.to_event(&CONFIG.nostr_sk)).await??;
+
+    // a new signature every time, only the result decides the ETag
+    cached_json(
+        &headers,
+        &content,
+        &Nip05Attestation {
+            nip05,
+            pubkey,
+            valid,
+            attestation,
+        },
+    )
+}
@@ -2,28 +2,42 @@ use std::collections::HashMap;
 
 use axum::{
     extract::{Query, State},
+    http::{
+        header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
     Json,
 };
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
 use nostr::prelude::XOnlyPublicKey;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use tracing::info;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    error::AppError, model::app_user_relays::AppUserRelaysBmc, router::handlers::NameOrPubkey,
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::app_user_relays::AppUserRelaysBmc,
+    router::handlers::NameOrPubkey,
     state::AppState,
 };
 
 use super::AppUserRelays;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UserWellKnownParams {
     pub name: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct UserWellKnown {
+    #[schema(value_type = HashMap<String, String>)]
     pub names: HashMap<String, XOnlyPublicKey>,
+    #[schema(value_type = HashMap<String, Vec<String>>)]
     pub relays: HashMap<XOnlyPublicKey, Vec<String>>,
 }
 
@@ -34,25 +48,83 @@ impl UserWellKnown {
             app_user_relays.name,
             XOnlyPublicKey::from_str(&app_user_relays.pubkey).unwrap(),
         );
+        // XMPP users' relays are their chat server, not a hint
+        let hints: Vec<String> = app_user_relays
+            .relays
+            .into_iter()
+            .filter(|relay| relay.starts_with("wss://") || relay.starts_with("ws://"))
+            .collect();
         let mut relays = HashMap::new();
-        relays.insert(
-            XOnlyPublicKey::from_str(&app_user_relays.pubkey).unwrap(),
-            app_user_relays.relays,
-        );
+        if !hints.is_empty() {
+            relays.insert(
+                XOnlyPublicKey::from_str(&app_user_relays.pubkey).unwrap(),
+                hints,
+            );
+        }
         Self { names, relays }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/.well-known/nostr.json",
+    tag = "nostr",
+    params(UserWellKnownParams),
+    responses(
+        (status = 200, description = "NIP-05 names and relays", body = UserWellKnown),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 404, description = "Unknown user", body = ErrorResponse),
+    )
+)]
 #[axum_macros::debug_handler]
 pub async fn handle_nip05_well_known(
     Query(params): Query<UserWellKnownParams>,
     State(state): State<AppState>,
-) -> Result<Json<UserWellKnown>, AppError> {
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     info!("nip05_well_known called with name: {:?}", params.name);
-    let app_user_relays =
-        AppUserRelaysBmc::get_by(&state.mm, NameOrPubkey::Name, &params.name).await?;
+    let app_user_relays = match state.cache.nip05.get(&params.name) {
+        Some(cached) => cached,
+        None => {
+            let app_user_relays =
+                AppUserRelaysBmc::get_by(&state.mm, NameOrPubkey::Name, &params.name)
+                    .await
+                    .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?;
+            state
+                .cache
+                .nip05
+                .insert(&params.name, app_user_relays.clone());
+            app_user_relays
+        }
+    };
 
     let nip05_well_known = UserWellKnown::from_db(app_user_relays);
 
-    Ok(Json(nip05_well_known))
+    cached_json(&headers, &nip05_well_known, &nip05_well_known)
+}
+
+/// Answers with `body` along with caching headers, or an empty 304 if the
+/// request's `If-None-Match` matches the ETag of `version`. Clients verifying
+/// often can revalidate instead of fetching again.
+pub(super) fn cached_json<T: Serialize>(
+    headers: &HeaderMap,
+    version: &impl Serialize,
+    body: &T,
+) -> Result<Response, AppError> {
+    let etag = format!("\"{}\"", Sha256::hash(&serde_json::to_vec(version)?));
+    let cache_control = format!("public, max-age={}", CONFIG.cache_ttl.as_secs());
+    let caching = [(ETAG, etag.clone()), (CACHE_CONTROL, cache_control)];
+    let unchanged = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim().trim_start_matches("W/") == etag)
+        });
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, caching).into_response());
+    }
+
+    Ok((caching, Json(body)).into_response())
 }
@@ -0,0 +1,195 @@
+//! Payers who passed a `proofofpayer` key to the callback can claim back
+//! payments that couldn't be delivered, by signing a refund invoice with it.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use fedimint_core::config::FederationId;
+use lightning_invoice::Bolt11Invoice;
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{error, info, instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppError, ErrorCode, ErrorResponse},
+    federations::{fee_reserve, pay_bolt11},
+    model::{
+        dead_letter::DeadLetterBmc,
+        invoice::InvoiceBmc,
+        refund::{Refund, RefundBmc},
+    },
+    router::handlers::lnurlp::callback::SPEND_NOTES_CHANNEL,
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListRefundsParams {
+    /// The `proofofpayer` key given in the callback
+    pub payer: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RefundResponse {
+    pub id: i32,
+    /// In millisatoshis
+    pub amount: i64,
+    /// The most a refund invoice may ask for, in millisatoshis
+    pub max_refund: i64,
+    pub reason: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub created_at: OffsetDateTime,
+    pub claimed: bool,
+    pub operation_id: Option<String>,
+}
+
+impl From<Refund> for RefundResponse {
+    fn from(refund: Refund) -> Self {
+        Self {
+            id: refund.id,
+            amount: refund.amount,
+            max_refund: max_refund(refund.amount),
+            reason: refund.reason,
+            created_at: refund.created_at,
+            claimed: refund.claimed_at.is_some(),
+            operation_id: refund.operation_id,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ClaimRefundParams {
+    /// BOLT11 invoice for at most `maxRefund`
+    pub invoice: String,
+    /// Hex compact ECDSA signature of sha256(invoice) by the payer key
+    pub signature: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/refunds",
+    tag = "refunds",
+    params(ListRefundsParams),
+    responses(
+        (status = 200, description = "Refunds the payer can claim", body = [RefundResponse]),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_list_refunds(
+    Query(params): Query<ListRefundsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RefundResponse>>, AppError> {
+    info!("list refunds called with {:?}", params);
+    let payer = PublicKey::from_str(&params.payer)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))?;
+    let refunds = RefundBmc::list_claimable(&state.mm, &payer.to_string()).await?;
+
+    Ok(Json(refunds.into_iter().map(Into::into).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/refunds/{id}/claim",
+    tag = "refunds",
+    params(("id" = i32, Path, description = "Refund id")),
+    request_body = ClaimRefundParams,
+    responses(
+        (status = 200, description = "Refund paid", body = RefundResponse),
+        (status = 400, description = "Invalid refund invoice", body = ErrorResponse),
+        (status = 401, description = "Signature doesn't match the payer key", body = ErrorResponse),
+        (status = 404, description = "Unknown refund", body = ErrorResponse),
+        (status = 409, description = "Already claimed or delivered", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+#[instrument(skip_all, fields(refund_id = id))]
+pub async fn handle_claim_refund(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(params): Json<ClaimRefundParams>,
+) -> Result<Json<RefundResponse>, AppError> {
+    info!("claim refund {id} called");
+    let refund = RefundBmc::get(&state.mm, id)
+        .await
+        .map_err(|e| AppError::from_code(ErrorCode::NotFound, e))?;
+
+    verify_signature(&refund.payer_pubkey, &params.invoice, &params.signature)
+        .map_err(|e| AppError::from_code(ErrorCode::Unauthorized, e))?;
+
+    let invoice = Bolt11Invoice::from_str(&params.invoice)
+        .map_err(|e| AppError::from_code(ErrorCode::BadRequest, anyhow::anyhow!("{e}")))?;
+    let max = max_refund(refund.amount);
+    if !invoice
+        .amount_milli_satoshis()
+        .is_some_and(|amount| amount as i64 <= max)
+    {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Refund invoice must be for at most {max} msats"),
+        ));
+    }
+    if invoice.is_expired() {
+        return Err(AppError::from_code(
+            ErrorCode::BadRequest,
+            anyhow::anyhow!("Refund invoice is expired"),
+        ));
+    }
+
+    let paid_invoice = InvoiceBmc::get(&state.mm, refund.invoice_id).await?;
+    let client = FederationId::from_str(&paid_invoice.federation_id)
+        .ok()
+        .and_then(|federation_id| state.federations.get(&federation_id))
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::FederationUnavailable,
+                anyhow::anyhow!("federation {} is not connected", paid_invoice.federation_id),
+            )
+        })?;
+
+    let refund = RefundBmc::claim(&state.mm, id, &params.invoice)
+        .await?
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::RefundUnavailable,
+                anyhow::anyhow!("Refund was already claimed or delivered"),
+            )
+        })?;
+
+    let operation_id = match pay_bolt11(&state.mm, &client, invoice).await {
+        Ok(operation_id) => operation_id.to_string(),
+        Err(e) => {
+            error!("Paying refund {id} failed: {e:#}");
+            RefundBmc::unclaim(&state.mm, id).await?;
+            return Err(e.into());
+        }
+    };
+    RefundBmc::set_operation_id(&state.mm, id, &operation_id).await?;
+    // the payment can no longer be replayed to the user
+    DeadLetterBmc::resolve_for_invoice(&state.mm, refund.invoice_id, SPEND_NOTES_CHANNEL).await?;
+    info!("Refunded invoice {} to the payer", refund.invoice_id);
+
+    Ok(Json(RefundBmc::get(&state.mm, id).await?.into()))
+}
+
+fn max_refund(amount: i64) -> i64 {
+    // held back for the lightning fees of paying the refund
+    (amount - fee_reserve(amount as u64) as i64).max(0)
+}
+
+fn verify_signature(payer_pubkey: &str, invoice: &str, signature: &str) -> anyhow::Result<()> {
+    let key = PublicKey::from_str(payer_pubkey)?;
+    let signature = Signature::from_compact(&hex::decode(signature)?)?;
+    let message = Message::from_slice(Sha256::hash(invoice.as_bytes()).as_ref())?;
+    Secp256k1::verification_only().verify_ecdsa(&message, &signature, &key)?;
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! What this instance supports, for wallets to feature-detect it before
+//! registering a user.
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{CONFIG, RUNTIME_CONFIG},
+    router::handlers::{NoteFormat, SupportedDmType},
+    state::AppState,
+};
+
+/// LNURL documents implemented: bech32 lnurls, payRequest, comments,
+/// lightning addresses, raw lnurlp urls, payer identity and verify.
+const LUDS: [u16; 7] = [1, 6, 12, 16, 17, 18, 21];
+/// NIPs implemented: DMs, NIP-05 identifiers, zaps and http auth.
+const NIPS: [u16; 4] = [4, 5, 57, 98];
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResponse {
+    pub version: String,
+    pub domain: String,
+    pub luds: Vec<u16>,
+    pub nips: Vec<u16>,
+    /// Whether `POST /register` accepts new users
+    pub registration_open: bool,
+    /// Ways a user can receive their ecash
+    pub note_formats: Vec<NoteFormat>,
+    pub dm_types: Vec<SupportedDmType>,
+    /// Federations users can register with, only listed if the operator
+    /// chose to publish them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub federations: Option<Vec<String>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "status",
+    responses((status = 200, description = "What this instance supports", body = StatusResponse))
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_status(State(state): State<AppState>) -> Json<StatusResponse> {
+    let mut note_formats = vec![NoteFormat::Fedimint, NoteFormat::Link];
+    if CONFIG.cashu_mint_url.is_some() {
+        note_formats.push(NoteFormat::Cashu);
+    }
+
+    let federations = CONFIG.status_show_federations.then(|| {
+        state
+            .federations
+            .ids()
+            .iter()
+            .map(|id| id.to_string())
+            .collect()
+    });
+
+    Json(StatusResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        domain: CONFIG.domain.clone(),
+        luds: LUDS.to_vec(),
+        nips: NIPS.to_vec(),
+        registration_open: RUNTIME_CONFIG.load().registration_open,
+        note_formats,
+        dm_types: vec![SupportedDmType::Nostr, SupportedDmType::Xmpp],
+        federations,
+    })
+}
@@ -0,0 +1,314 @@
+//! Pairing a mobile wallet with hermes in one call: the wallet proves its
+//! nostr key, gets a lightning address, an api key and a signed push
+//! endpoint, and can rotate the credentials later the same way.
+
+use anyhow::anyhow;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method},
+    Json,
+};
+use fedimint_core::config::FederationId;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+use url::{Host, Url};
+use utoipa::ToSchema;
+
+use crate::{
+    audit,
+    config::CONFIG,
+    error::{AppError, ErrorCode, ErrorResponse},
+    model::{
+        app_user::{AppUser, AppUserBmc},
+        app_user_relays::AppUserRelaysBmc,
+        wallet_pairing::WalletPairingBmc,
+        webhook::{WebhookBmc, WebhookForCreate},
+        ModelManager, Tx,
+    },
+    router::{
+        handlers::{
+            lnbits::generate_api_key,
+            nostr::register::{prepare_registration, registration_error, UserParams},
+            NameOrPubkey,
+        },
+        nip98, NoteFormat, SupportedDmType,
+    },
+    state::AppState,
+    utils::resolve_public,
+    webhooks::random_id,
+};
+
+const PAIR_PATH: &str = "/wallet/pair";
+const ROTATE_PATH: &str = "/wallet/rotate";
+
+/// Hex characters of the pubkey an assigned name starts with, more are
+/// taken while it's in use.
+const ASSIGNED_NAME_MIN_LEN: usize = 8;
+/// Longest name the app_user table holds.
+const MAX_NAME_LEN: usize = 20;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairParams {
+    /// Federation payments are received in
+    #[schema(value_type = String)]
+    pub federation_id: FederationId,
+    /// Name of the lightning address, assigned from the pubkey if not given
+    pub name: Option<String>,
+    /// Where settled payments are posted, as signed webhooks
+    pub push_url: Option<Url>,
+    /// Relays ecash DMs are sent over, the server's if not given
+    pub relays: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RotateParams {
+    /// Replaces the push endpoint, which otherwise keeps its url and gets a
+    /// new secret
+    pub push_url: Option<Url>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairResponse {
+    pub name: String,
+    pub lightning_address: String,
+    pub federation_id: String,
+    /// `X-Api-Key` for the balance and LNbits endpoints, only shown once
+    pub api_key: String,
+    /// Key of the HMAC-SHA256 in the `BTCPay-Sig` header of pushes, only
+    /// shown once
+    pub push_secret: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/pair",
+    tag = "wallet",
+    params(("Authorization" = String, Header, description = "NIP-98 `Nostr <base64 event>`, signed by the wallet's key")),
+    request_body = PairParams,
+    responses(
+        (status = 200, description = "Paired, with the credentials", body = PairResponse),
+        (status = 400, description = "Invalid registration, unknown federation or non-public push url", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid authorization", body = ErrorResponse),
+        (status = 403, description = "Registration is closed", body = ErrorResponse),
+        (status = 409, description = "Already paired, or the name is taken", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_pair(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<PairResponse>, AppError> {
+    let pubkey = nip98::authenticate(&headers, &Method::POST, PAIR_PATH, &body)?.to_string();
+    let params: PairParams = nip98::parse_body(&body)?;
+    info!("wallet pair called by {pubkey}");
+
+    if let Some(push_url) = &params.push_url {
+        check_push_url(push_url).await?;
+    }
+    let name = match params.name {
+        Some(name) => name,
+        None => assign_name(&state.mm, &pubkey).await?,
+    };
+    let (user_c, details) = prepare_registration(
+        &state,
+        UserParams {
+            pubkey: pubkey.clone(),
+            name: name.clone(),
+            dm_type: SupportedDmType::Nostr,
+            note_format: NoteFormat::default(),
+            federation_id: params.federation_id,
+            batch_payouts: false,
+            custodial: false,
+            forward_to: None,
+            notes_expiry_secs: None,
+            relays: params.relays,
+            jid: None,
+            jid_code: None,
+            notify_room: None,
+            fallback_dm_types: vec![],
+            nwc_pubkey: None,
+        },
+    )
+    .await?;
+
+    // the user, the pairing and the credentials are created together, a key
+    // pairing twice at once is stopped by the pairing's primary key
+    let mut tx = state.mm.begin().await?;
+    let user_id = match AppUserRelaysBmc::register_in(&mut tx, user_c).await {
+        Ok(id) => id,
+        Err(e) => return Err(registration_error(&state.mm, &name, e).await),
+    };
+    if !WalletPairingBmc::create_in(&mut tx, &pubkey, user_id).await? {
+        return Err(AppError::from_code(
+            ErrorCode::AlreadyPaired,
+            anyhow!("This key is already paired, rotate its credentials instead"),
+        ));
+    }
+    let (api_key, push_secret) = issue_credentials(&mut tx, user_id, params.push_url).await?;
+    tx.commit().await?;
+
+    state.cache.invalidate(&name);
+    audit::record(
+        &state.mm,
+        audit::user_actor(&pubkey),
+        "user.register",
+        Some(&name),
+        details,
+    )
+    .await;
+    audit::record(
+        &state.mm,
+        audit::user_actor(&pubkey),
+        "wallet.pair",
+        Some(&name),
+        json!({ "push": push_secret.is_some() }),
+    )
+    .await;
+
+    let user = AppUserBmc::get(&state.mm, user_id).await?;
+    Ok(Json(pair_response(user, api_key, push_secret)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/rotate",
+    tag = "wallet",
+    params(("Authorization" = String, Header, description = "NIP-98 `Nostr <base64 event>`, signed by the wallet's key")),
+    request_body = RotateParams,
+    responses(
+        (status = 200, description = "New credentials, the old ones stop working", body = PairResponse),
+        (status = 400, description = "Non-public push url", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid authorization", body = ErrorResponse),
+        (status = 404, description = "No user with this key", body = ErrorResponse),
+    )
+)]
+#[axum_macros::debug_handler]
+pub async fn handle_rotate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<PairResponse>, AppError> {
+    let pubkey = nip98::authenticate(&headers, &Method::POST, ROTATE_PATH, &body)?.to_string();
+    let params: RotateParams = nip98::parse_body(&body)?;
+    info!("wallet rotate called by {pubkey}");
+
+    // users registered through `POST /register` rotate too
+    let user = match WalletPairingBmc::find_user_id(&state.mm, &pubkey).await? {
+        Some(id) => AppUserBmc::get(&state.mm, id).await?,
+        None => AppUserBmc::get_by(&state.mm, NameOrPubkey::Pubkey, &pubkey)
+            .await
+            .map_err(|e| AppError::from_code(ErrorCode::UserNotFound, e))?,
+    };
+
+    // the push endpoint keeps its url unless a new one is given
+    let push_url = match params.push_url {
+        Some(url) => Some(url),
+        None => WebhookBmc::list_for_user(&state.mm, user.id)
+            .await?
+            .first()
+            .map(|webhook| webhook.url.parse())
+            .transpose()?,
+    };
+    if let Some(push_url) = &push_url {
+        check_push_url(push_url).await?;
+    }
+    let mut tx = state.mm.begin().await?;
+    let (api_key, push_secret) = issue_credentials(&mut tx, user.id, push_url).await?;
+    tx.commit().await?;
+    audit::record(
+        &state.mm,
+        audit::user_actor(&pubkey),
+        "wallet.rotate",
+        Some(&user.name),
+        json!({ "push": push_secret.is_some() }),
+    )
+    .await;
+
+    Ok(Json(pair_response(user, api_key, push_secret)))
+}
+
+/// Replaces the user's api key and, given a push url, their webhooks with
+/// one for it under a new secret, in `tx` so a failure leaves the old
+/// credentials working.
+async fn issue_credentials(
+    tx: &mut Tx,
+    user_id: i32,
+    push_url: Option<Url>,
+) -> Result<(String, Option<String>), AppError> {
+    let (api_key, hash) = generate_api_key();
+    AppUserBmc::set_api_key_hash_in(tx, user_id, &hash).await?;
+
+    let Some(push_url) = push_url else {
+        return Ok((api_key, None));
+    };
+    let secret = random_id();
+    WebhookBmc::replace_for_user_in(
+        tx,
+        WebhookForCreate {
+            app_user_id: user_id,
+            url: push_url.to_string(),
+            secret: secret.clone(),
+        },
+    )
+    .await?;
+
+    Ok((api_key, Some(secret)))
+}
+
+/// Push urls come from anyone holding a key, so they must be https and only
+/// reach public addresses. Names resolve at `SOCKS_PROXY` when there is one.
+async fn check_push_url(url: &Url) -> Result<(), AppError> {
+    let invalid = |e: anyhow::Error| AppError::from_code(ErrorCode::BadRequest, e);
+    if url.scheme() != "https" {
+        return Err(invalid(anyhow!("Push url must be https")));
+    }
+    let host = url
+        .host()
+        .ok_or_else(|| invalid(anyhow!("Push url has no host")))?;
+    if CONFIG.socks_proxy.is_some() && matches!(host, Host::Domain(_)) {
+        return Ok(());
+    }
+    resolve_public(
+        &host.to_string(),
+        url.port_or_known_default().unwrap_or(443),
+    )
+    .await
+    .map_err(|e| invalid(e.context("Push url must be public")))?;
+
+    Ok(())
+}
+
+fn pair_response(user: AppUser, api_key: String, push_secret: Option<String>) -> PairResponse {
+    PairResponse {
+        lightning_address: format!("{}@{}", user.name, CONFIG.domain),
+        name: user.name,
+        federation_id: user.federation_id,
+        api_key,
+        push_secret,
+    }
+}
+
+/// The shortest free prefix of the pubkey, from `ASSIGNED_NAME_MIN_LEN`
+/// characters up to the longest name there is room for.
+async fn assign_name(mm: &ModelManager, pubkey: &str) -> Result<String, AppError> {
+    let candidates: Vec<String> = (ASSIGNED_NAME_MIN_LEN..=MAX_NAME_LEN)
+        .map(|len| pubkey.chars().take(len).collect())
+        .collect();
+    let taken = AppUserBmc::taken_names(mm, &candidates).await?;
+
+    candidates
+        .into_iter()
+        .find(|c| !taken.contains(&c.to_lowercase()))
+        .ok_or_else(|| {
+            AppError::from_code(
+                ErrorCode::NameTaken,
+                anyhow!("No free name could be assigned, choose one"),
+            )
+        })
+}
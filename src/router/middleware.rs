@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, Extension, Path, Request, State},
+    http::{header::AUTHORIZATION, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use subtle::ConstantTimeEq;
+use tracing::{info_span, warn, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    error::{AppError, ErrorCode},
+    reputation::Offense,
+    router::proxy,
+    state::AppState,
+};
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+    static ADMIN_KEY_ID: String;
+}
+
+/// The id of the request currently being handled, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// A short id of the admin key the current request was made with, if any.
+pub fn current_admin_key_id() -> Option<String> {
+    ADMIN_KEY_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The address a request came from, see [`client_ip`].
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Works out the real client address behind trusted proxies for rate
+/// limiting, bans and logs, and strips forwarded headers from anyone else so
+/// only a trusted proxy can choose the urls we hand out.
+pub async fn client_ip(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let ip = proxy::client_ip(addr.ip(), request.headers());
+    if !proxy::is_trusted_proxy(addr.ip()) {
+        proxy::strip_forwarded_headers(request.headers_mut());
+    }
+    request.extensions_mut().insert(ClientIp(ip));
+
+    next.run(request).await
+}
+
+/// Reuses a sane incoming `X-Request-Id` or generates one, makes it available to
+/// handlers and log spans, and echoes it back in the response.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|h| h.to_str().ok())
+        .filter(|h| !h.is_empty() && h.len() <= 64 && h.chars().all(|c| c.is_ascii_graphic()))
+        .map(|h| h.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let client_ip = request.extensions().get::<ClientIp>().map(|ip| ip.0);
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        client_ip = ?client_ip
+    );
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    response
+}
+
+/// Rejects requests once the client ip or the requested username runs out of tokens.
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let username = params.get("username").map(|u| u.as_str());
+    if !state.rate_limiter.check(ip, username) {
+        warn!("rate limited {ip} for username {:?}", username);
+        return Err(AppError::from_code(
+            ErrorCode::RateLimited,
+            anyhow!("Rate limit exceeded"),
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Turns away banned ips and scores failed requests and registrations, so
+/// ips that keep making them get banned. Admin and health endpoints are
+/// exempt.
+pub async fn ip_reputation(
+    State(state): State<AppState>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let path = request.uri().path();
+    if path.starts_with("/admin") || path.starts_with("/health") {
+        return Ok(next.run(request).await);
+    }
+
+    if state.reputation.banned(ip).is_some() {
+        return Err(AppError::from_code(
+            ErrorCode::Banned,
+            anyhow!("Too many bad requests from this address, try again later"),
+        ));
+    }
+
+    let is_registration = request.method() == Method::POST && path == "/register";
+    let response = next.run(request).await;
+
+    let offense = match response.extensions().get::<ErrorCode>() {
+        Some(code) => Offense::for_error(*code),
+        None if is_registration && response.status().is_success() => Some(Offense::Registration),
+        None => None,
+    };
+    if let Some(offense) = offense {
+        if let Err(e) = state.reputation.penalize(&state.mm, ip, offense).await {
+            warn!("Could not ban {ip}: {e:#}");
+        }
+    }
+
+    Ok(response)
+}
+
+/// Compares against every configured admin key in constant time.
+pub fn is_admin_key(provided: &str) -> bool {
+    // check every key so timing doesn't reveal which one matched
+    let authorized = CONFIG.admin_api_keys.iter().fold(false, |acc, key| {
+        acc | bool::from(key.as_bytes().ct_eq(provided.as_bytes()))
+    });
+
+    !provided.is_empty() && authorized
+}
+
+/// Requires a `Authorization: Bearer <key>` header matching one of the configured admin keys.
+/// The start of the key's hash identifies it in the audit log.
+pub async fn admin_auth(request: Request, next: Next) -> Result<Response, AppError> {
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !is_admin_key(provided) {
+        warn!("unauthorized admin request to {}", request.uri().path());
+        return Err(AppError::from_code(
+            ErrorCode::Unauthorized,
+            anyhow!("Invalid admin api key"),
+        ));
+    }
+
+    let key_id = admin_key_id(provided);
+    Ok(ADMIN_KEY_ID.scope(key_id, next.run(request)).await)
+}
+
+/// Runs `f` on behalf of the admin holding `key`, for admin calls that don't
+/// come through [`admin_auth`].
+pub async fn as_admin<F: Future>(key: &str, f: F) -> F::Output {
+    ADMIN_KEY_ID.scope(admin_key_id(key), f).await
+}
+
+fn admin_key_id(key: &str) -> String {
+    Sha256::hash(key.as_bytes()).to_string()[..8].to_string()
+}
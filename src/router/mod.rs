@@ -1,23 +1,29 @@
+use std::str::FromStr;
+
 use anyhow::Result;
 use axum::{
-    routing::{get, post},
+    http::{HeaderName, HeaderValue, Method},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, post},
     Router,
 };
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 pub mod handlers;
+pub mod middleware;
+pub mod nip98;
+pub mod openapi;
+pub mod proxy;
+pub mod public_url;
 
 use handlers::*;
+use openapi::ApiDoc;
 
-use crate::state::AppState;
+use crate::{config::CONFIG, state::AppState};
 
 pub async fn create_router(state: AppState) -> Result<Router> {
-    let app = Router::new()
-        .route("/", get(handle_readme))
-        .route("/health", get(|| async { "OK" }))
-        .route("/register", post(nostr::register::handle_register))
-        .route(
-            "/.well-known/nostr.json",
-            get(nostr::well_known::handle_nip05_well_known),
-        )
+    let lnurlp_routes = Router::new()
         .route(
             "/.well-known/lnurlp/:username",
             get(lnurlp::well_known::handle_well_known),
@@ -26,11 +32,180 @@ pub async fn create_router(state: AppState) -> Result<Router> {
             "/lnurlp/:username/callback",
             get(lnurlp::callback::handle_callback),
         )
+        .route("/lnurlp/:username/lnurl", get(lnurlp::lnurl::handle_lnurl))
+        .route("/lnurlp/:username/qr", get(lnurlp::qr::handle_qr))
+        .route("/lnurlp/resolve", get(lnurlp::lnurl::handle_resolve))
         .route(
-            "/lnurlp/:username/verify/:op_id",
+            "/lnurlp/:username/verify/:op_id/:token",
             get(lnurlp::verify::handle_verify),
         )
+        .route(
+            "/lnurlp/:username/verify/:op_id",
+            get(lnurlp::verify::handle_legacy_verify),
+        )
+        .route("/ecash/claim", post(ecash::handle_claim))
+        .route("/ecash/reissue", post(ecash::handle_reissue))
+        .route("/gift/:token", get(gift::handle_claim_gift))
+        .route("/refunds", get(refunds::handle_list_refunds))
+        .route("/invoices/lookup", get(invoices::handle_lookup))
+        .route("/invoices/status", post(invoices::handle_status))
+        .route("/refunds/:id/claim", post(refunds::handle_claim_refund))
+        .route_layer(from_fn_with_state(state.clone(), middleware::rate_limit));
+
+    let lnbits_routes = Router::new()
+        .route("/api/v1/payments", post(lnbits::handle_create_payment))
+        .route(
+            "/api/v1/payments/:payment_hash",
+            get(lnbits::handle_check_payment),
+        )
+        .route("/api/v1/wallet", get(lnbits::handle_wallet))
+        .route("/balance", get(balance::handle_balance))
+        .route("/balance/claim", post(balance::handle_claim))
+        .route("/balance/withdraw", post(balance::handle_withdraw))
+        .route("/activity", get(activity::handle_activity))
+        .route("/payments", get(activity::handle_payments))
+        .route_layer(from_fn_with_state(state.clone(), middleware::rate_limit));
+
+    let admin_routes = Router::new()
+        .route(
+            "/federations",
+            get(admin::federations::handle_list_federations)
+                .post(admin::federations::handle_join_federation),
+        )
+        .route(
+            "/federations/stats",
+            get(admin::federations::handle_federation_stats),
+        )
+        .route(
+            "/federations/gateways",
+            get(admin::federations::handle_list_gateways),
+        )
+        .route("/invoices", get(admin::invoices::handle_list_invoices))
+        .route(
+            "/invoices/:id",
+            delete(admin::invoices::handle_delete_invoice),
+        )
+        .route(
+            "/invoices/:id/restore",
+            post(admin::invoices::handle_restore_invoice),
+        )
+        .route(
+            "/invoices/:id/events",
+            get(admin::invoices::handle_invoice_events),
+        )
+        .route("/users", get(admin::users::handle_list_users))
+        .route("/users/:username", delete(admin::users::handle_delete_user))
+        .route(
+            "/users/:username/restore",
+            post(admin::users::handle_restore_user),
+        )
+        .route(
+            "/users/:username/api-key",
+            post(admin::users::handle_create_api_key),
+        )
+        .route(
+            "/users/:username/activity",
+            get(admin::users::handle_user_activity),
+        )
+        .route(
+            "/users/:username/receive-limit",
+            get(admin::limits::handle_get_receive_limit)
+                .put(admin::limits::handle_set_receive_limit)
+                .delete(admin::limits::handle_delete_receive_limit),
+        )
+        .route(
+            "/users/:username/webhooks",
+            get(admin::webhooks::handle_list_webhooks).post(admin::webhooks::handle_create_webhook),
+        )
+        .route(
+            "/webhooks/:id",
+            delete(admin::webhooks::handle_delete_webhook),
+        )
+        .route("/jobs", get(admin::jobs::handle_list_jobs))
+        .route("/audit", get(admin::audit::handle_list_audit))
+        .route(
+            "/bans",
+            get(admin::bans::handle_list_bans).post(admin::bans::handle_ban),
+        )
+        .route("/bans/:ip", delete(admin::bans::handle_unban))
+        .route(
+            "/dead-letters",
+            get(admin::dead_letters::handle_list_dead_letters),
+        )
+        .route(
+            "/dead-letters/:id/replay",
+            post(admin::dead_letters::handle_replay_dead_letter),
+        )
+        .route("/metrics", get(admin::metrics::handle_metrics))
+        .route("/stats", get(admin::stats::handle_stats))
+        .route("/backup", get(admin::backup::handle_backup))
+        .route("/export/users", get(admin::export::handle_export_users))
+        .route(
+            "/export/invoices",
+            get(admin::export::handle_export_invoices),
+        )
+        .route("/reload", post(admin::config::handle_reload))
+        .route(
+            "/tracing",
+            get(admin::telemetry::handle_get_tracing)
+                .put(admin::telemetry::handle_set_tracing)
+                .delete(admin::telemetry::handle_reset_tracing),
+        )
+        .route_layer(from_fn(middleware::admin_auth));
+
+    let app = Router::new()
+        .route("/", get(handle_readme))
+        .merge(SwaggerUi::new("/swagger-ui").url("/openapi.json", ApiDoc::openapi()))
+        .route("/health", get(|| async { "OK" }))
+        .route("/health/live", get(health::handle_live))
+        .route("/health/ready", get(health::handle_ready))
+        .route("/status", get(status::handle_status))
+        .route("/register", post(nostr::register::handle_register))
+        .route("/wallet/pair", post(wallet::handle_pair))
+        .route("/wallet/rotate", post(wallet::handle_rotate))
+        .route(
+            "/register/xmpp",
+            post(nostr::register::handle_xmpp_challenge),
+        )
+        .route("/ws", get(events::ws::handle_ws))
+        .route("/events/:operation_id", get(events::sse::handle_sse))
+        .route(
+            "/.well-known/nostr.json",
+            get(nostr::well_known::handle_nip05_well_known),
+        )
+        .route("/nip05/verify", get(nostr::verify::handle_nip05_verify))
+        .merge(lnurlp_routes)
+        .merge(lnbits_routes)
+        .nest("/admin", admin_routes)
+        .layer(from_fn_with_state(state.clone(), middleware::ip_reputation))
+        .layer(cors_layer()?)
+        .layer(from_fn(middleware::request_id))
+        .layer(from_fn(middleware::client_ip))
         .with_state(state);
 
     Ok(app)
 }
+
+fn cors_layer() -> Result<CorsLayer> {
+    let origins = if CONFIG.cors_allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = CONFIG
+            .cors_allowed_origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o))
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let headers = CONFIG
+        .cors_allowed_headers
+        .iter()
+        .map(|h| HeaderName::from_str(h))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers(headers))
+}
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{anyhow, bail, ensure};
+use axum::http::{header::AUTHORIZATION, HeaderMap, Method};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::Hash;
+use nostr::{secp256k1::XOnlyPublicKey, Event, EventId, JsonUtil, Kind, Timestamp};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::{AppError, ErrorCode},
+    router::public_url::public_base_url,
+};
+
+/// How far an auth event's timestamp may be from ours.
+const MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+lazy_static::lazy_static! {
+    /// Ids of the auth events accepted within the skew window, with when
+    /// they were, so a seen header can't be replayed
+    static ref USED_EVENTS: Mutex<HashMap<EventId, u64>> = Mutex::new(HashMap::new());
+}
+
+/// Authenticates a request by its NIP-98 `Authorization: Nostr <event>`
+/// header, returning the key that signed it. The event must be for `path` on
+/// the url the client reached us on, and `method`, carry the hash of `body`
+/// for methods with one, and can only be used once.
+pub fn authenticate(
+    headers: &HeaderMap,
+    method: &Method,
+    path: &str,
+    body: &[u8],
+) -> Result<XOnlyPublicKey, AppError> {
+    let url = format!("{}{path}", public_base_url(headers));
+    verify(headers, method, &url, body).map_err(|e| AppError::from_code(ErrorCode::Unauthorized, e))
+}
+
+/// Parses the JSON body of a request authenticated with its raw bytes.
+pub fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, AppError> {
+    serde_json::from_slice(body).map_err(|e| AppError::from_code(ErrorCode::BadRequest, e))
+}
+
+fn verify(
+    headers: &HeaderMap,
+    method: &Method,
+    url: &str,
+    body: &[u8],
+) -> anyhow::Result<XOnlyPublicKey> {
+    let encoded = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Nostr "))
+        .ok_or_else(|| anyhow!("Missing Nostr authorization"))?;
+    let event = Event::from_json(STANDARD.decode(encoded.trim())?)?;
+    event.verify()?;
+
+    ensure!(event.kind == Kind::HttpAuth, "Not an http auth event");
+    let now = Timestamp::now().as_u64();
+    if event.created_at.as_u64().abs_diff(now) > MAX_CLOCK_SKEW_SECS {
+        bail!("Auth event is too old or in the future");
+    }
+
+    let tag = |name: &str| {
+        event
+            .tags
+            .iter()
+            .map(|t| t.as_vec())
+            .find(|t| t.first().is_some_and(|n| n == name))
+            .and_then(|t| t.get(1).cloned())
+    };
+    ensure!(
+        tag("u").as_deref() == Some(url),
+        "Auth event is for another url"
+    );
+    ensure!(
+        tag("method").is_some_and(|m| m.eq_ignore_ascii_case(method.as_str())),
+        "Auth event is for another method"
+    );
+    if matches!(*method, Method::POST | Method::PUT | Method::PATCH) {
+        let payload = hex::encode(Sha256::hash(body).as_byte_array());
+        ensure!(
+            tag("payload").is_some_and(|p| p.eq_ignore_ascii_case(&payload)),
+            "Auth event is for another body"
+        );
+    }
+
+    let mut used = USED_EVENTS.lock().expect("nip98 lock poisoned");
+    mark_used(&mut used, event.id, now)?;
+    Ok(event.pubkey)
+}
+
+/// Records an accepted event, failing if it was used before. Events older
+/// than the skew window are forgotten, they're rejected as too old anyway.
+fn mark_used(used: &mut HashMap<EventId, u64>, id: EventId, now: u64) -> anyhow::Result<()> {
+    used.retain(|_, created_at| now.saturating_sub(*created_at) <= 2 * MAX_CLOCK_SKEW_SECS);
+    ensure!(
+        used.insert(id, now).is_none(),
+        "Auth event was already used"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use nostr::{EventBuilder, Keys, Tag};
+
+    use super::*;
+
+    const URL: &str = "https://hermes.example.com/wallet/pair";
+    const BODY: &[u8] = br#"{"federationId":"abc"}"#;
+
+    fn auth_event(keys: &Keys, kind: Kind, url: &str, method: &str, body: Option<&[u8]>) -> Event {
+        let mut tags = vec![
+            Tag::parse(vec!["u", url]).unwrap(),
+            Tag::parse(vec!["method", method]).unwrap(),
+        ];
+        if let Some(body) = body {
+            let payload = hex::encode(Sha256::hash(body).as_byte_array());
+            tags.push(Tag::parse(vec!["payload".to_string(), payload]).unwrap());
+        }
+        EventBuilder::new(kind, "", &tags).to_event(keys).unwrap()
+    }
+
+    fn headers(event: &Event) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Nostr {}", STANDARD.encode(event.as_json()));
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_a_signed_event_once() {
+        let keys = Keys::generate();
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "POST", Some(BODY));
+        let headers = headers(&event);
+
+        let pubkey = verify(&headers, &Method::POST, URL, BODY).unwrap();
+        assert_eq!(pubkey, keys.public_key());
+        assert!(verify(&headers, &Method::POST, URL, BODY).is_err());
+    }
+
+    #[test]
+    fn rejects_another_body() {
+        let keys = Keys::generate();
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "POST", Some(BODY));
+        assert!(verify(&headers(&event), &Method::POST, URL, b"{}").is_err());
+
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "POST", None);
+        assert!(verify(&headers(&event), &Method::POST, URL, BODY).is_err());
+    }
+
+    #[test]
+    fn needs_no_payload_without_a_body() {
+        let keys = Keys::generate();
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "GET", None);
+        assert!(verify(&headers(&event), &Method::GET, URL, &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_another_url_method_or_kind() {
+        let keys = Keys::generate();
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "POST", Some(BODY));
+        let other_url = "https://hermes.example.com/wallet/rotate";
+        assert!(verify(&headers(&event), &Method::POST, other_url, BODY).is_err());
+
+        let event = auth_event(&keys, Kind::HttpAuth, URL, "PUT", Some(BODY));
+        assert!(verify(&headers(&event), &Method::POST, URL, BODY).is_err());
+
+        let event = auth_event(&keys, Kind::TextNote, URL, "POST", Some(BODY));
+        assert!(verify(&headers(&event), &Method::POST, URL, BODY).is_err());
+    }
+
+    #[test]
+    fn rejects_a_missing_or_malformed_header() {
+        assert!(verify(&HeaderMap::new(), &Method::POST, URL, BODY).is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Nostr not-base64"));
+        assert!(verify(&headers, &Method::POST, URL, BODY).is_err());
+    }
+
+    #[test]
+    fn forgets_events_past_the_skew_window() {
+        let mut used = HashMap::new();
+        let id = EventId::all_zeros();
+        mark_used(&mut used, id, 1_000).unwrap();
+        assert!(mark_used(&mut used, id, 1_000 + MAX_CLOCK_SKEW_SECS).is_err());
+        assert!(mark_used(&mut used, id, 1_000 + 4 * MAX_CLOCK_SKEW_SECS).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use utoipa::OpenApi;
+
+use crate::{
+    error::{ErrorCode, ErrorResponse},
+    model::{invoice_state::InvoiceState, user_activity::UserActivity},
+    router::handlers::{
+        activity, balance, ecash, gift, health, invoices, lnbits,
+        lnurlp::{self, callback, lnurl, payer_data, qr, verify, well_known},
+        nostr::{self, register},
+        refunds, status, wallet, NoteFormat, SupportedDmType,
+    },
+};
+
+/// The public api, served at `/openapi.json` with a Swagger UI at
+/// `/swagger-ui`. Admin endpoints are described by `proto/admin.proto`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "hermes", description = "Lightning address server for fedimint users"),
+    paths(
+        well_known::handle_well_known,
+        callback::handle_callback,
+        verify::handle_verify,
+        lnurl::handle_lnurl,
+        lnurl::handle_resolve,
+        qr::handle_qr,
+        nostr::well_known::handle_nip05_well_known,
+        nostr::verify::handle_nip05_verify,
+        register::handle_register,
+        register::handle_xmpp_challenge,
+        wallet::handle_pair,
+        wallet::handle_rotate,
+        ecash::handle_claim,
+        ecash::handle_reissue,
+        gift::handle_claim_gift,
+        refunds::handle_list_refunds,
+        refunds::handle_claim_refund,
+        invoices::handle_lookup,
+        invoices::handle_status,
+        lnbits::handle_create_payment,
+        lnbits::handle_check_payment,
+        lnbits::handle_wallet,
+        balance::handle_balance,
+        balance::handle_claim,
+        balance::handle_withdraw,
+        activity::handle_activity,
+        activity::handle_payments,
+        health::handle_live,
+        health::handle_ready,
+        status::handle_status,
+    ),
+    components(schemas(
+        ErrorCode,
+        ErrorResponse,
+        InvoiceState,
+        NoteFormat,
+        SupportedDmType,
+        UserActivity,
+        activity::PaymentFeed,
+        activity::Payment,
+        activity::ZapContext,
+        activity::Payout,
+        lnurlp::LnurlStatus,
+        lnurlp::LnurlType,
+        well_known::LnurlWellKnownResponse,
+        payer_data::PayerDataSpec,
+        payer_data::PayerDataField,
+        payer_data::PayerData,
+        callback::LnurlCallbackResponse,
+        callback::LnurlCallbackSuccessAction,
+        verify::LnurlVerifyResponse,
+        lnurl::LnurlResponse,
+        qr::QrFormat,
+        qr::QrContent,
+        nostr::well_known::UserWellKnown,
+        nostr::verify::Nip05Attestation,
+        register::UserParams,
+        register::XmppChallengeParams,
+        wallet::PairParams,
+        wallet::RotateParams,
+        wallet::PairResponse,
+        ecash::PendingEcash,
+        ecash::ReissueParams,
+        ecash::ReissueResponse,
+        gift::GiftResponse,
+        refunds::RefundResponse,
+        refunds::ClaimRefundParams,
+        invoices::InvoiceLookupResponse,
+        invoices::InvoiceStatusParams,
+        invoices::VerifyOperation,
+        invoices::InvoiceStatusResponse,
+        invoices::InvoiceStatus,
+        lnbits::CreatePaymentParams,
+        lnbits::CreatePaymentResponse,
+        lnbits::PaymentStatus,
+        lnbits::PaymentDetails,
+        lnbits::WalletDetails,
+        balance::BalanceResponse,
+        balance::ClaimParams,
+        balance::ClaimResponse,
+        balance::WithdrawParams,
+        balance::WithdrawResponse,
+        health::ReadinessResponse,
+        health::ComponentHealth,
+        status::StatusResponse,
+    )),
+    tags(
+        (name = "lnurlp", description = "LUD-06 lightning address payments"),
+        (name = "nostr", description = "Registration and NIP-05"),
+        (name = "wallet", description = "Pairing mobile wallets in one call"),
+        (name = "ecash", description = "Pulling ecash that wasn't received by DM"),
+        (name = "gift", description = "One-time ecash claim links"),
+        (name = "refunds", description = "Refunds of undeliverable payments"),
+        (name = "invoices", description = "Finding invoices by payment hash, bolt11 or verify url"),
+        (name = "lnbits", description = "LNbits compatible wallet api"),
+        (name = "balance", description = "Custodial balances claimed on demand"),
+        (name = "activity", description = "A user's payments over time, and each of them"),
+        (name = "health", description = "Liveness and readiness"),
+        (name = "status", description = "Supported features, for feature detection"),
+    )
+)]
+pub struct ApiDoc;
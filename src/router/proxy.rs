@@ -0,0 +1,152 @@
+//! Requests arriving through a reverse proxy carry the client's address and
+//! the scheme and host it used in `X-Forwarded-*` headers. They are only
+//! believed from proxies in `TRUSTED_PROXIES`, or from anyone with
+//! `TRUST_FORWARDED_HEADERS`, and stripped from everything else so handlers
+//! can't be fooled by a client setting them itself.
+
+use std::net::IpAddr;
+
+use axum::http::HeaderMap;
+
+use crate::config::CONFIG;
+
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+pub const X_FORWARDED_HOST: &str = "x-forwarded-host";
+pub const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Whether forwarded headers from `peer` are believed.
+pub fn is_trusted_proxy(peer: IpAddr) -> bool {
+    CONFIG.trust_forwarded_headers || is_listed_proxy(peer)
+}
+
+fn is_listed_proxy(ip: IpAddr) -> bool {
+    CONFIG.trusted_proxies.iter().any(|n| n.contains(&ip))
+}
+
+/// The address of the client behind any trusted proxies. `X-Forwarded-For`
+/// is read from the right, each proxy appending the address it got the
+/// request from, and the first address that isn't a listed proxy is the
+/// client. With only `TRUST_FORWARDED_HEADERS` that is the last one, so a
+/// client can't pick its address by sending its own `X-Forwarded-For`.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !is_trusted_proxy(peer) {
+        return peer;
+    }
+    forwarded_client(peer, headers, is_listed_proxy)
+}
+
+/// Walks `X-Forwarded-For` from the right past the proxies `is_proxy` lists.
+fn forwarded_client(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    is_proxy: impl Fn(IpAddr) -> bool,
+) -> IpAddr {
+    let forwarded_for = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for hop in forwarded_for.into_iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !is_proxy(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Drops forwarded headers a client may have set itself.
+pub fn strip_forwarded_headers(headers: &mut HeaderMap) {
+    for name in [X_FORWARDED_FOR, X_FORWARDED_HOST, X_FORWARDED_PROTO] {
+        headers.remove(name);
+    }
+}
+
+/// The first value of a forwarded header, the one set by the proxy closest to
+/// the client.
+pub fn forwarded_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "10.0.0.1";
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn client(values: &[&str], is_proxy: impl Fn(IpAddr) -> bool) -> String {
+        forwarded_client(PEER.parse().unwrap(), &forwarded_for(values), is_proxy).to_string()
+    }
+
+    fn private(ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => ip.is_private(),
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    #[test]
+    fn takes_the_address_before_the_listed_proxies() {
+        assert_eq!(client(&["203.0.113.7, 10.0.0.2"], private), "203.0.113.7");
+        assert_eq!(
+            client(&["203.0.113.7", "10.0.0.2, 10.0.0.3"], private),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn ignores_what_the_client_prepended() {
+        assert_eq!(
+            client(&["1.2.3.4, 203.0.113.7, 10.0.0.2"], private),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn takes_the_last_address_without_listed_proxies() {
+        assert_eq!(client(&["1.2.3.4, 203.0.113.7"], |_| false), "203.0.113.7");
+    }
+
+    #[test]
+    fn stops_at_garbage_and_falls_back_to_the_peer() {
+        assert_eq!(client(&["203.0.113.7, unknown"], private), PEER);
+        assert_eq!(client(&[], private), PEER);
+        assert_eq!(client(&["2001:db8::1"], private), "2001:db8::1");
+    }
+
+    #[test]
+    fn reads_the_first_forwarded_value() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_HOST,
+            " a.example.com , b.example.com".parse().unwrap(),
+        );
+        assert_eq!(
+            forwarded_header(&headers, X_FORWARDED_HOST),
+            Some("a.example.com")
+        );
+
+        headers.insert(X_FORWARDED_PROTO, " , https".parse().unwrap());
+        assert_eq!(forwarded_header(&headers, X_FORWARDED_PROTO), None);
+    }
+}
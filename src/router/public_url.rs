@@ -0,0 +1,53 @@
+use axum::http::{header::HOST, HeaderMap};
+
+use crate::config::CONFIG;
+use crate::router::proxy::{forwarded_header, X_FORWARDED_HOST, X_FORWARDED_PROTO};
+
+/// The base url clients reached us on, without a trailing slash, for building
+/// the callback and verify urls we hand out. Requests to `ONION_DOMAIN` stay
+/// on the onion, otherwise `PUBLIC_URL` wins, then `X-Forwarded-*` headers
+/// from a trusted proxy, then the `Host` header if it is one of our domains.
+/// Anything else falls back to `DOMAIN`, with `PORT` unless a proxy told us
+/// the scheme. Forwarded headers from untrusted clients were already
+/// stripped by the `client_ip` middleware.
+pub fn public_base_url(headers: &HeaderMap) -> String {
+    let forwarded_host = forwarded_header(headers, X_FORWARDED_HOST);
+    let host = forwarded_host.or(header(headers, HOST.as_str()));
+    let hostname = host.and_then(|h| h.split(':').next()).unwrap_or_default();
+
+    if CONFIG.onion_domain.as_deref() == Some(hostname) {
+        return format!("http://{hostname}");
+    }
+
+    if let Some(url) = CONFIG.public_url.as_ref() {
+        return url.as_str().trim_end_matches('/').to_string();
+    }
+
+    let forwarded_proto = forwarded_header(headers, X_FORWARDED_PROTO)
+        .filter(|proto| *proto == "http" || *proto == "https");
+    if let Some(host) = forwarded_host {
+        return format!("{}://{host}", forwarded_proto.unwrap_or("http"));
+    }
+
+    let default_scheme = if CONFIG.acme_domains.is_empty() {
+        "http"
+    } else {
+        "https"
+    };
+    let scheme = forwarded_proto.unwrap_or(default_scheme);
+    match host {
+        Some(host)
+            if hostname == CONFIG.domain || CONFIG.acme_domains.iter().any(|d| d == hostname) =>
+        {
+            format!("{scheme}://{host}")
+        }
+        // behind a proxy our own port isn't the one clients use
+        _ if forwarded_proto.is_some() => format!("{scheme}://{}", CONFIG.domain),
+        _ if CONFIG.acme_domains.is_empty() => format!("http://{}:{}", CONFIG.domain, CONFIG.port),
+        _ => format!("https://{}", CONFIG.acme_domains[0]),
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|h| h.to_str().ok())
+}
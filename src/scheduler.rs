@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use nostr::prelude::rand::{thread_rng, Rng};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{info, info_span, Instrument};
+
+use crate::{
+    config::JobConfig,
+    state::AppState,
+    supervisor::{supervise, RestartPolicy},
+};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub interval_secs: u64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_run_at: Option<OffsetDateTime>,
+    pub last_duration_ms: Option<u128>,
+    pub last_error: Option<String>,
+    pub runs: u64,
+    pub failures: u64,
+}
+
+/// Runs background jobs on an interval (plus jitter) until shutdown. Each job
+/// is configured with `JOB_<NAME>_ENABLED`, `JOB_<NAME>_INTERVAL_SECS` and
+/// `JOB_<NAME>_JITTER_SECS`.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    statuses: Arc<Mutex<BTreeMap<&'static str, JobStatus>>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F, Fut>(
+        &self,
+        state: &AppState,
+        name: &'static str,
+        default_interval: Duration,
+        job: F,
+    ) -> Result<()>
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let config = JobConfig::load(name, default_interval)?;
+        self.statuses.lock().unwrap().insert(
+            name,
+            JobStatus {
+                name,
+                enabled: config.enabled,
+                interval_secs: config.interval.as_secs(),
+                last_run_at: None,
+                last_duration_ms: None,
+                last_error: None,
+                runs: 0,
+                failures: 0,
+            },
+        );
+        if !config.enabled {
+            info!("Job {name} is disabled");
+            return Ok(());
+        }
+
+        let state = state.clone();
+        let statuses = self.statuses.clone();
+        state.tasks.clone().spawn(async move {
+            loop {
+                let jitter = if config.jitter.is_zero() {
+                    Duration::ZERO
+                } else {
+                    thread_rng().gen_range(Duration::ZERO..config.jitter)
+                };
+                tokio::select! {
+                    _ = tokio::time::sleep(config.interval + jitter) => {}
+                    _ = state.shutdown.cancelled() => break,
+                }
+
+                let started = Instant::now();
+                let result = supervise(name, RestartPolicy::Never, || job(state.clone()))
+                    .instrument(info_span!("job", name))
+                    .await;
+
+                let mut statuses = statuses.lock().unwrap();
+                let status = statuses.get_mut(name).expect("registered above");
+                status.last_run_at = Some(OffsetDateTime::now_utc());
+                status.last_duration_ms = Some(started.elapsed().as_millis());
+                status.runs += 1;
+                match result {
+                    Ok(_) => status.last_error = None,
+                    Err(e) => {
+                        status.failures += 1;
+                        status.last_error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.statuses.lock().unwrap().values().cloned().collect()
+    }
+}
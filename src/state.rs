@@ -1,26 +1,116 @@
+use std::sync::Arc;
+
 use multimint::MultiMint;
-use nostr_sdk::Client;
+use nostr_sdk::{Client, Options};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::info;
 
-use crate::{config, model::ModelManager};
+use crate::{
+    cache::UserCache,
+    config::{self, RuntimeConfig, RUNTIME_CONFIG},
+    events::InvoiceEvents,
+    federations::FederationRegistry,
+    gateways::GatewayCache,
+    model::ModelManager,
+    rate_limit::RateLimiter,
+    reputation::IpReputation,
+    scheduler::Scheduler,
+    subscriptions::SubscriptionManager,
+};
 
 use anyhow::Result;
 use config::CONFIG;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub fm: MultiMint,
+    pub federations: FederationRegistry,
+    /// Each federation's gateways, refreshed by the `gateway_refresh` job
+    pub gateways: GatewayCache,
     pub mm: ModelManager,
     pub nostr: Client,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub reputation: Arc<IpReputation>,
+    /// Tracks invoice subscriptions and jobs so shutdown can wait for them
+    pub tasks: TaskTracker,
+    /// Cancelled once the server starts shutting down
+    pub shutdown: CancellationToken,
+    pub invoice_events: InvoiceEvents,
+    pub scheduler: Scheduler,
+    pub cache: Arc<UserCache>,
+    pub subscriptions: SubscriptionManager,
 }
 
 impl AppState {
     pub async fn new() -> Result<Self> {
-        let fm = MultiMint::new(CONFIG.fm_db_path.clone()).await?;
+        let runtime = RUNTIME_CONFIG.load();
+        let federations =
+            FederationRegistry::new(MultiMint::new(CONFIG.fm_db_path.clone()).await?).await;
         let mm = ModelManager::new().await?;
-        let nostr = nostr_sdk::Client::new(&CONFIG.nostr_sk);
-        nostr.add_relay(CONFIG.default_relay.as_str()).await?;
+        mm.ensure_schema(CONFIG.auto_migrate).await?;
+        let nostr = Client::with_opts(&CONFIG.nostr_sk, Options::new().proxy(CONFIG.socks_proxy));
+        for relay in runtime.nostr_relays.iter() {
+            nostr.add_relay(relay.as_str()).await?;
+        }
         nostr.connect().await;
+        let rate_limiter = Arc::new(RateLimiter::new(&runtime));
+        let reputation = Arc::new(
+            IpReputation::load(
+                &mm,
+                CONFIG.ip_ban_threshold,
+                CONFIG.ip_ban_half_life,
+                CONFIG.ip_ban_duration,
+            )
+            .await?,
+        );
+
+        Ok(Self {
+            federations,
+            gateways: GatewayCache::new(),
+            mm,
+            nostr,
+            rate_limiter,
+            reputation,
+            tasks: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            invoice_events: InvoiceEvents::new(),
+            scheduler: Scheduler::new(),
+            cache: Arc::new(UserCache::new(CONFIG.cache_ttl, CONFIG.callback_cache_ttl)),
+            subscriptions: SubscriptionManager::new(
+                CONFIG.subscription_max_active,
+                CONFIG.subscription_max_queued,
+                CONFIG.subscription_overflow,
+                CONFIG.subscription_max_memory_mb.map(|mb| mb * 1024 * 1024),
+            ),
+        })
+    }
+
+    /// Re-reads `RuntimeConfig` and applies it without touching pending invoice subscriptions.
+    pub async fn reload_config(&self) -> Result<Arc<RuntimeConfig>> {
+        let old = RUNTIME_CONFIG.load_full();
+        let new = Arc::new(RuntimeConfig::load()?);
+
+        if old.rate_limit_ip_per_minute != new.rate_limit_ip_per_minute
+            || old.rate_limit_username_per_minute != new.rate_limit_username_per_minute
+            || old.rate_limit_zap_sender_per_minute != new.rate_limit_zap_sender_per_minute
+        {
+            self.rate_limiter.update(&new);
+        }
+
+        for relay in new.nostr_relays.iter() {
+            if !old.nostr_relays.contains(relay) {
+                self.nostr.add_relay(relay.as_str()).await?;
+                self.nostr.connect_relay(relay.as_str()).await?;
+            }
+        }
+        for relay in old.nostr_relays.iter() {
+            if !new.nostr_relays.contains(relay) {
+                self.nostr.remove_relay(relay.as_str()).await?;
+            }
+        }
+
+        RUNTIME_CONFIG.store(new.clone());
+        info!("Reloaded runtime config: {:?}", new);
 
-        Ok(Self { fm, mm, nostr })
+        Ok(new)
     }
 }
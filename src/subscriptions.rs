@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime},
+};
+
+use lightning_invoice::Bolt11Invoice;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// What to do with a new invoice once every subscription slot is taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait for a free slot, up to `SUBSCRIPTION_MAX_QUEUED` waiting invoices
+    Queue,
+    /// Reject the callback before an invoice is created
+    Shed,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "queue" => Ok(Self::Queue),
+            "shed" => Ok(Self::Shed),
+            _ => Err(format!(
+                "unknown overflow policy '{s}', expected queue or shed"
+            )),
+        }
+    }
+}
+
+/// Bounds how many invoice subscriptions run at once so invoice spam can't
+/// spawn an unbounded number of tasks.
+#[derive(Clone)]
+pub struct SubscriptionManager {
+    slots: Arc<Semaphore>,
+    max_active: usize,
+    max_queued: usize,
+    queued: Arc<AtomicUsize>,
+    policy: OverflowPolicy,
+    /// Resident memory above which streams of expired invoices are dropped
+    max_memory_bytes: Option<u64>,
+    /// Lets an invoice's subscription be stopped early, keyed by invoice id
+    watched: Arc<Mutex<HashMap<i32, Watched>>>,
+}
+
+/// A subscription that's running or waiting for a slot.
+struct Watched {
+    stop: CancellationToken,
+    /// BOLT11 expiry of the invoice, `None` if it couldn't be read
+    expires_at: Option<SystemTime>,
+    /// Last update from its stream, or when it was started
+    last_active: Instant,
+}
+
+impl Watched {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A reserved place for one subscription, either running or waiting to run.
+pub enum Admission {
+    Active(OwnedSemaphorePermit),
+    Queued(QueuedSlot),
+}
+
+pub struct QueuedSlot {
+    manager: SubscriptionManager,
+}
+
+impl Drop for QueuedSlot {
+    fn drop(&mut self) {
+        self.manager.queued.fetch_sub(1, Ordering::SeqCst);
+        self.manager.record_metrics();
+    }
+}
+
+impl Admission {
+    /// Waits until the subscription may run. The returned permit frees the
+    /// slot when dropped.
+    pub async fn ready(self) -> OwnedSemaphorePermit {
+        match self {
+            Admission::Active(permit) => permit,
+            Admission::Queued(slot) => {
+                let permit = slot
+                    .manager
+                    .slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("subscription semaphore is never closed");
+                drop(slot);
+                permit
+            }
+        }
+    }
+}
+
+impl SubscriptionManager {
+    pub fn new(
+        max_active: usize,
+        max_queued: usize,
+        policy: OverflowPolicy,
+        max_memory_bytes: Option<u64>,
+    ) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_active)),
+            max_active,
+            max_queued,
+            queued: Arc::new(AtomicUsize::new(0)),
+            policy,
+            max_memory_bytes,
+            watched: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reserves room for a new invoice's subscription, or `None` if the
+    /// invoice should be refused. With every slot taken, the stream of an
+    /// expired invoice is stopped to make room first.
+    pub fn admit(&self) -> Option<Admission> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            self.record_metrics();
+            return Some(Admission::Active(permit));
+        }
+
+        // its slot frees up as soon as the stream notices, wait for that
+        // regardless of the policy and queue length
+        if self.evict_expired(1) > 0 {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            self.record_metrics();
+            return Some(Admission::Queued(QueuedSlot {
+                manager: self.clone(),
+            }));
+        }
+
+        if self.policy == OverflowPolicy::Queue {
+            if self.queued.fetch_add(1, Ordering::SeqCst) < self.max_queued {
+                self.record_metrics();
+                return Some(Admission::Queued(QueuedSlot {
+                    manager: self.clone(),
+                }));
+            }
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        metrics::counter!("invoice_subscriptions_shed_total").increment(1);
+        None
+    }
+
+    /// Reserves room for an invoice that already exists, e.g. one resumed on
+    /// startup. These are never shed, only queued.
+    pub fn admit_existing(&self) -> Admission {
+        match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => Admission::Active(permit),
+            Err(_) => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+                Admission::Queued(QueuedSlot {
+                    manager: self.clone(),
+                })
+            }
+        }
+    }
+
+    /// Registers a running subscription, the token fires if it should stop.
+    pub fn watch(&self, invoice_id: i32, expires_at: Option<SystemTime>) -> CancellationToken {
+        let stop = CancellationToken::new();
+        self.watched.lock().unwrap().insert(
+            invoice_id,
+            Watched {
+                stop: stop.clone(),
+                expires_at,
+                last_active: Instant::now(),
+            },
+        );
+        stop
+    }
+
+    /// Notes an update from the invoice's stream, streams that haven't had
+    /// one for longest are stopped first.
+    pub fn touch(&self, invoice_id: i32) {
+        if let Some(watched) = self.watched.lock().unwrap().get_mut(&invoice_id) {
+            watched.last_active = Instant::now();
+        }
+    }
+
+    pub fn unwatch(&self, invoice_id: i32) {
+        self.watched.lock().unwrap().remove(&invoice_id);
+    }
+
+    /// Stops the subscription for an invoice that no longer needs watching.
+    pub fn cancel(&self, invoice_id: i32) {
+        if let Some(watched) = self.watched.lock().unwrap().remove(&invoice_id) {
+            watched.stop.cancel();
+        }
+    }
+
+    /// Stops up to `max` streams of invoices past their expiry, least
+    /// recently active first. The invoices stay pending until the expiry job
+    /// or reconciliation gets to them.
+    pub fn evict_expired(&self, max: usize) -> usize {
+        let now = SystemTime::now();
+        let mut watched = self.watched.lock().unwrap();
+        let mut expired: Vec<(i32, Instant)> = watched
+            .iter()
+            .filter(|(_, w)| w.is_expired(now))
+            .map(|(id, w)| (*id, w.last_active))
+            .collect();
+        expired.sort_by_key(|(_, last_active)| *last_active);
+
+        let mut evicted = 0;
+        for (invoice_id, _) in expired.into_iter().take(max) {
+            if let Some(w) = watched.remove(&invoice_id) {
+                w.stop.cancel();
+                evicted += 1;
+            }
+        }
+        drop(watched);
+
+        if evicted > 0 {
+            info!("Stopped {evicted} subscription(s) of expired invoices");
+            metrics::counter!("invoice_subscriptions_evicted_total").increment(evicted as u64);
+        }
+        evicted
+    }
+
+    /// Records stream and memory numbers and, over `SUBSCRIPTION_MAX_MEMORY_MB`,
+    /// stops every stream of an expired invoice.
+    pub fn trim(&self) {
+        self.record_metrics();
+        let Some(resident) = resident_memory_bytes() else {
+            return;
+        };
+        metrics::gauge!("process_resident_memory_bytes").set(resident as f64);
+
+        match self.max_memory_bytes {
+            Some(max) if resident > max => {
+                let evicted = self.evict_expired(usize::MAX);
+                let mib = resident / (1024 * 1024);
+                warn!("Using {mib} MiB, stopped {evicted} expired subscription(s)");
+                self.record_metrics();
+            }
+            _ => {}
+        }
+    }
+
+    /// Subscriptions registered, running or waiting, and how many of them
+    /// are for invoices already past their expiry.
+    pub fn streams(&self) -> (usize, usize) {
+        let now = SystemTime::now();
+        let watched = self.watched.lock().unwrap();
+        let expired = watched.values().filter(|w| w.is_expired(now)).count();
+        (watched.len(), expired)
+    }
+
+    pub fn active(&self) -> usize {
+        self.max_active - self.slots.available_permits()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn record_metrics(&self) {
+        metrics::gauge!("invoice_subscriptions_active").set(self.active() as f64);
+        metrics::gauge!("invoice_subscriptions_queued").set(self.queued() as f64);
+        metrics::gauge!("invoice_subscriptions_max_active").set(self.max_active as f64);
+        let (streams, expired) = self.streams();
+        metrics::gauge!("invoice_subscriptions_streams").set(streams as f64);
+        metrics::gauge!("invoice_subscriptions_expired_streams").set(expired as f64);
+    }
+}
+
+/// When a BOLT11 invoice expires, `None` if it can't be parsed.
+pub fn bolt11_expiry(bolt11: &str) -> Option<SystemTime> {
+    let invoice = Bolt11Invoice::from_str(bolt11).ok()?;
+    invoice.timestamp().checked_add(invoice.expiry_time())
+}
+
+/// Resident set size of the process, only known on Linux.
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
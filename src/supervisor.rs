@@ -0,0 +1,81 @@
+use std::{any::Any, future::Future, panic::AssertUnwindSafe, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::FutureExt;
+use tokio_util::task::TaskTracker;
+use tracing::{error, warn, Instrument, Span};
+
+/// What to do when a supervised task returns an error or panics.
+#[derive(Debug, Clone, Copy)]
+pub enum RestartPolicy {
+    /// Report the failure and give up
+    Never,
+    /// Run the task again, waiting `backoff` before the first restart and
+    /// doubling it for every restart after that
+    OnFailure {
+        max_restarts: u32,
+        backoff: Duration,
+    },
+}
+
+/// Runs `task` until it succeeds, capturing errors and panics and restarting
+/// according to `policy`. Failures are counted in
+/// `supervised_task_failures_total` and the final one is logged as an alert.
+pub async fn supervise<F, Fut>(name: &'static str, policy: RestartPolicy, mut task: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut restarts = 0;
+    loop {
+        let e = match AssertUnwindSafe(task()).catch_unwind().await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => e,
+            Err(panic) => anyhow!("panicked: {}", panic_message(panic.as_ref())),
+        };
+        metrics::counter!("supervised_task_failures_total", "task" => name).increment(1);
+
+        match policy {
+            RestartPolicy::OnFailure {
+                max_restarts,
+                backoff,
+            } if restarts < max_restarts => {
+                warn!(task = name, restarts, "Task failed, restarting: {e:#}");
+                tokio::time::sleep(backoff * 2u32.saturating_pow(restarts)).await;
+                restarts += 1;
+            }
+            _ => {
+                error!(task = name, alert = true, "Task failed: {e:#}");
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Spawns a supervised task on `tasks` so shutdown waits for it, in the
+/// current span.
+pub fn spawn_supervised<F, Fut>(
+    tasks: &TaskTracker,
+    name: &'static str,
+    policy: RestartPolicy,
+    task: F,
+) where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    tasks.spawn(
+        async move {
+            // already reported by supervise
+            let _ = supervise(name, policy, task).await;
+        }
+        .instrument(Span::current()),
+    );
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
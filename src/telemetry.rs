@@ -0,0 +1,332 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use anyhow::{anyhow, Result};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use opentelemetry::{
+    trace::{Link, SamplingResult, SpanKind, TraceId},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    runtime,
+    trace::{self, Sampler, ShouldSample},
+    Resource,
+};
+use sentry::integrations::tracing::EventFilter;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    warn, Level, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    alerts::{self, AlertLayer},
+    config::CONFIG,
+};
+
+static METRICS: OnceLock<PrometheusHandle> = OnceLock::new();
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+/// `f64` bits of the ratio of new traces exported, see `RuntimeRatioSampler`
+static SAMPLE_RATIO: AtomicU64 = AtomicU64::new(0);
+
+/// Spans under this target are model calls, timed by `DbTimingLayer`.
+const MODEL_TARGET: &str = "hermes::model";
+const DB_QUERY_DURATION: &str = "db_query_duration_seconds";
+/// Most invoice creations should fit in the first few, LNURL wallets give up
+/// on a callback after a few seconds.
+const DB_QUERY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, the event's fields at the top level and
+    /// every enclosing span with its fields (`request_id`, `username`,
+    /// `federation_id`, `op_id`, ...) under `spans`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format '{s}', expected text or json")),
+        }
+    }
+}
+
+/// Starts reporting panics to Sentry if `SENTRY_DSN` is set, errors are
+/// reported through the layer added by `init_tracing`. Queued reports are
+/// sent when the guard is dropped, so keep it until exit.
+pub fn init_error_reporting() -> Option<sentry::ClientInitGuard> {
+    let dsn = CONFIG.sentry_dsn.as_deref()?;
+    Some(sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            environment: CONFIG.sentry_environment.clone().map(Into::into),
+            sample_rate: CONFIG.sentry_sample_rate,
+            attach_stacktrace: true,
+            ..Default::default()
+        },
+    )))
+}
+
+/// Sets up the fmt subscriber in `LOG_FORMAT`, the alert layer if an alert
+/// destination is configured, a Sentry layer if `SENTRY_DSN` is set and, if
+/// `OTLP_ENDPOINT` is configured, an OpenTelemetry layer exporting spans over
+/// OTLP. The filter and sample ratio can be changed later with
+/// `set_log_filter` and `set_sample_ratio`.
+pub fn init_tracing() -> Result<()> {
+    let (filter, filter_handle) = reload::Layer::new(default_log_filter());
+    let _ = LOG_FILTER.set(filter_handle);
+    SAMPLE_RATIO.store(CONFIG.otlp_sample_ratio.to_bits(), Ordering::Relaxed);
+
+    let otel_layer = match CONFIG.otlp_endpoint.as_ref() {
+        Some(endpoint) => {
+            let sampler = Sampler::ParentBased(Box::new(RuntimeRatioSampler));
+            let tracer =
+                opentelemetry_otlp::new_pipeline()
+                    .tracing()
+                    .with_exporter(
+                        opentelemetry_otlp::new_exporter()
+                            .tonic()
+                            .with_endpoint(endpoint),
+                    )
+                    .with_trace_config(trace::config().with_sampler(sampler).with_resource(
+                        Resource::new(vec![KeyValue::new("service.name", "hermes")]),
+                    ))
+                    .install_batch(runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    let (text_layer, json_layer) = match CONFIG.log_format {
+        LogFormat::Text => (Some(tracing_subscriber::fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+        ),
+    };
+
+    // errors become Sentry events, with the info and warn lines before them as breadcrumbs
+    let sentry_layer = CONFIG.sentry_dsn.is_some().then(|| {
+        sentry::integrations::tracing::layer().event_filter(|metadata| match *metadata.level() {
+            Level::ERROR => EventFilter::Event,
+            Level::WARN | Level::INFO => EventFilter::Breadcrumb,
+            _ => EventFilter::Ignore,
+        })
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text_layer)
+        .with(json_layer)
+        .with(otel_layer)
+        .with(DbTimingLayer)
+        .with(sentry_layer)
+        .with(alerts::enabled().then_some(AlertLayer))
+        .init();
+
+    Ok(())
+}
+
+/// `RUST_LOG`, or `info` if it isn't set or valid.
+fn default_log_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+/// The directives currently filtering logs and spans, in `RUST_LOG` syntax.
+pub fn log_filter() -> String {
+    LOG_FILTER
+        .get()
+        .and_then(|handle| handle.with_current(|filter| filter.to_string()).ok())
+        .unwrap_or_default()
+}
+
+/// Replaces the log filter with `directives` in `RUST_LOG` syntax, e.g.
+/// `info,hermes::router::handlers::lnurlp=debug`, or `RUST_LOG` if `None`.
+pub fn set_log_filter(directives: Option<&str>) -> Result<()> {
+    let filter = match directives {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => default_log_filter(),
+    };
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| anyhow!("tracing is not initialized"))?;
+    handle.reload(filter)?;
+    Ok(())
+}
+
+/// The ratio of new traces exported over OTLP, traces started elsewhere
+/// follow their parent.
+pub fn sample_ratio() -> f64 {
+    f64::from_bits(SAMPLE_RATIO.load(Ordering::Relaxed))
+}
+
+/// Sets the ratio of new traces exported, `OTLP_SAMPLE_RATIO` if `None`.
+pub fn set_sample_ratio(ratio: Option<f64>) -> Result<()> {
+    let ratio = ratio.unwrap_or(CONFIG.otlp_sample_ratio);
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(anyhow!("sample ratio must be between 0 and 1"));
+    }
+    SAMPLE_RATIO.store(ratio.to_bits(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Samples new traces by trace id at the current `sample_ratio`, which the
+/// SDK's own samplers fix at startup.
+#[derive(Debug, Clone)]
+struct RuntimeRatioSampler;
+
+impl ShouldSample for RuntimeRatioSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&opentelemetry::Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        Sampler::TraceIdRatioBased(sample_ratio()).should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+        )
+    }
+}
+
+/// Flushes any spans still buffered in the exporter.
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Installs the Prometheus recorder backing the `metrics` macros.
+pub fn init_metrics() -> Result<()> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(DB_QUERY_DURATION.to_string()),
+            &DB_QUERY_BUCKETS,
+        )?
+        .install_recorder()?;
+    let _ = METRICS.set(handle);
+    Ok(())
+}
+
+/// Renders all recorded metrics in the Prometheus text format.
+pub fn render_metrics() -> String {
+    METRICS.get().map(|h| h.render()).unwrap_or_default()
+}
+
+/// Times every model call, i.e. each BMC method and the base helpers they
+/// share, from the time its span is created until it closes, so waiting for
+/// a pooled connection is included. Durations go into the
+/// `db_query_duration_seconds` histogram labelled `query`, e.g.
+/// `invoice::get_by_op_id`, and calls taking `DB_SLOW_QUERY_MS` or longer are
+/// logged unless they are part of a call that already is.
+struct DbTimingLayer;
+
+struct DbCall {
+    start: Instant,
+    /// The `table` field of the base helpers, which are shared by every model
+    table: Option<String>,
+}
+
+impl<S> Layer<S> for DbTimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.metadata().target().starts_with(MODEL_TARGET) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut table = TableField(None);
+        attrs.record(&mut table);
+        span.extensions_mut().insert(DbCall {
+            start: Instant::now(),
+            table: table.0,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(call) = span.extensions_mut().remove::<DbCall>() else {
+            return;
+        };
+        let elapsed = call.start.elapsed();
+
+        let model = call.table.unwrap_or_else(|| {
+            let target = span.metadata().target();
+            target
+                .strip_prefix(MODEL_TARGET)
+                .unwrap_or(target)
+                .trim_start_matches("::")
+                .to_string()
+        });
+        let query = format!("{model}::{}", span.name());
+        metrics::histogram!(DB_QUERY_DURATION, "query" => query.clone())
+            .record(elapsed.as_secs_f64());
+
+        let nested = span
+            .parent()
+            .is_some_and(|parent| parent.metadata().target().starts_with(MODEL_TARGET));
+        if elapsed >= CONFIG.db_slow_query_threshold && !nested {
+            metrics::counter!("db_slow_queries_total", "query" => query.clone()).increment(1);
+            warn!(
+                target: "hermes::db",
+                query = %query,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "Slow database call"
+            );
+        }
+    }
+}
+
+struct TableField(Option<String>);
+
+impl Visit for TableField {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "table" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
@@ -0,0 +1,47 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::Router;
+use futures::StreamExt;
+use rustls_acme::{caches::DirCache, AcmeConfig};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::config::CONFIG;
+
+/// Serves the app over TLS with certificates provisioned and renewed from
+/// Let's Encrypt for `ACME_DOMAINS`, using the TLS-ALPN-01 challenge.
+pub async fn serve(app: Router, shutdown: CancellationToken) -> Result<()> {
+    let mut acme = AcmeConfig::new(CONFIG.acme_domains.clone())
+        .contact(CONFIG.acme_contacts.iter().map(|c| format!("mailto:{c}")))
+        .cache_option(CONFIG.acme_cache_dir.clone().map(DirCache::new))
+        .directory_lets_encrypt(CONFIG.acme_production)
+        .state();
+    let acceptor = acme.axum_acceptor(acme.default_rustls_config());
+
+    tokio::spawn(async move {
+        while let Some(event) = acme.next().await {
+            match event {
+                Ok(ok) => info!("acme event: {:?}", ok),
+                Err(e) => error!("acme error: {:?}", e),
+            }
+        }
+    });
+
+    let handle = axum_server::Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+        shutdown_handle.graceful_shutdown(Some(CONFIG.shutdown_timeout));
+    });
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], CONFIG.tls_port));
+    info!("Listening with TLS on {}", CONFIG.tls_port);
+    axum_server::bind(addr)
+        .handle(handle)
+        .acceptor(acceptor)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+
+    Ok(())
+}
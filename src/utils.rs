@@ -1,9 +1,12 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
 
 use crate::config::CONFIG;
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
 use serde::{de, Deserialize, Deserializer};
-use xmpp::Agent;
 
 pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
 where
@@ -18,13 +21,114 @@ where
     }
 }
 
-// TODO: XMPP client doesn't implement Clone, so we can't use it in AppState which is annoying
-pub fn create_xmpp_client() -> Result<Agent> {
-    let jid = xmpp::BareJid::new(&format!(
-        "{}@{}",
-        CONFIG.xmpp_username, CONFIG.xmpp_chat_server
-    ))?;
-    let client = xmpp::ClientBuilder::new(jid, &CONFIG.xmpp_password).build();
+/// A builder for outbound HTTP clients, routed through `SOCKS_PROXY` if set.
+pub fn http_client_builder() -> Result<reqwest::ClientBuilder> {
+    let builder = reqwest::Client::builder();
+    match CONFIG.socks_proxy {
+        // socks5h so names, including onion addresses, resolve at the proxy
+        Some(proxy) => Ok(builder.proxy(reqwest::Proxy::all(format!("socks5h://{proxy}"))?)),
+        None => Ok(builder),
+    }
+}
+
+/// The addresses `host` resolves to, failing unless every one of them is
+/// publicly routable, so urls users hand hermes can't point it at its own
+/// network.
+pub async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    ensure!(!addrs.is_empty(), "{host} does not resolve");
+    if let Some(addr) = addrs.iter().find(|addr| !is_global(addr.ip())) {
+        return Err(anyhow!(
+            "{host} resolves to non-public address {}",
+            addr.ip()
+        ));
+    }
+    Ok(addrs)
+}
+
+/// Whether an address is publicly routable: not loopback, private, link
+/// local, shared, reserved, documentation or multicast.
+fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_global_v4(ip),
+            None => is_global_v6(ip),
+        },
+    }
+}
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // shared address space, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
 
-    Ok(client)
+fn is_global_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // NAT64 and IPv4 compatible addresses hide an IPv4 one
+        || (first == 0x64 && ip.segments()[1] == 0xff9b)
+        || ip.segments()[..6] == [0; 6])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_global() {
+        for ip in [
+            "1.1.1.1",
+            "203.0.114.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_global(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.1.2.3",
+            "198.18.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::a00:1",
+            "2001:db8::1",
+        ] {
+            assert!(!is_global(ip.parse().unwrap()), "{ip}");
+        }
+    }
 }
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use nostr::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use nostr::bitcoin::hashes::sha256::Hash as Sha256;
+use nostr::hashes::{Hash, HashEngine};
+use nostr::prelude::rand::rngs::OsRng;
+use nostr::prelude::rand::RngCore;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::{
+    events::InvoiceUpdate,
+    model::{
+        app_user::AppUserBmc,
+        invoice_state::InvoiceState,
+        webhook::{Webhook, WebhookBmc},
+    },
+    router::handlers::NameOrPubkey,
+    state::AppState,
+    supervisor::{spawn_supervised, RestartPolicy},
+    utils::http_client_builder,
+};
+
+/// Header carrying the HMAC-SHA256 of the body, keyed with the webhook secret.
+const SIGNATURE_HEADER: &str = "BTCPay-Sig";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const DELIVERY_RETRY: RestartPolicy = RestartPolicy::OnFailure {
+    max_restarts: 5,
+    backoff: Duration::from_secs(2),
+};
+
+/// Turns invoice updates into BTCPay Server compatible webhook events and
+/// delivers them to the user's registered webhooks until shutdown.
+pub async fn dispatch(state: AppState) -> Result<()> {
+    let mut updates = state.invoice_events.subscribe();
+    // a redirect could lead a checked push url back into our own network
+    let http = http_client_builder()?
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    loop {
+        let update = tokio::select! {
+            _ = state.shutdown.cancelled() => return Ok(()),
+            update = updates.recv() => update,
+        };
+
+        match update {
+            Ok(update) => {
+                if let Err(e) = enqueue(&state, &http, update).await {
+                    warn!("Could not queue webhook events: {e:#}");
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Webhook dispatcher missed {missed} invoice update(s)")
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn enqueue(state: &AppState, http: &reqwest::Client, update: InvoiceUpdate) -> Result<()> {
+    let Some(event_type) = event_type(update.state) else {
+        return Ok(());
+    };
+
+    let user = AppUserBmc::get_by(&state.mm, NameOrPubkey::Name, &update.username).await?;
+    for webhook in WebhookBmc::list_for_user(&state.mm, user.id).await? {
+        let payload = event_payload(&webhook, event_type, &update);
+        let http = http.clone();
+        spawn_supervised(
+            &state.tasks,
+            "webhook_delivery",
+            DELIVERY_RETRY,
+            move || deliver(http.clone(), webhook.clone(), payload.clone()),
+        );
+    }
+
+    Ok(())
+}
+
+/// The BTCPay event for an invoice state, if there is one.
+fn event_type(state: InvoiceState) -> Option<&'static str> {
+    match state {
+        InvoiceState::Pending => Some("InvoiceCreated"),
+        InvoiceState::Settled => Some("InvoiceSettled"),
+        InvoiceState::Expired => Some("InvoiceExpired"),
+        InvoiceState::Cancelled => None,
+    }
+}
+
+fn event_payload(webhook: &Webhook, event_type: &str, update: &InvoiceUpdate) -> Value {
+    let mut payload = json!({
+        "deliveryId": random_id(),
+        "webhookId": webhook.id.to_string(),
+        "isRedelivery": false,
+        "type": event_type,
+        "timestamp": OffsetDateTime::now_utc().unix_timestamp(),
+        "storeId": update.username,
+        "invoiceId": update.operation_id,
+    });
+    payload["originalDeliveryId"] = payload["deliveryId"].clone();
+
+    match update.state {
+        InvoiceState::Settled => {
+            payload["manuallyMarked"] = json!(false);
+            payload["overPaid"] = json!(false);
+        }
+        InvoiceState::Expired => payload["partiallyPaid"] = json!(false),
+        _ => {}
+    }
+
+    payload
+}
+
+async fn deliver(http: reqwest::Client, webhook: Webhook, payload: Value) -> Result<()> {
+    let body = payload.to_string();
+    http.post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, &body))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!(
+        "Delivered {} for invoice {} to webhook {}",
+        payload["type"], payload["invoiceId"], webhook.id
+    );
+    Ok(())
+}
+
+/// `sha256=<hex hmac>` as BTCPay Server sends it.
+fn sign(secret: &str, body: &str) -> String {
+    let mut engine = HmacEngine::<Sha256>::new(secret.as_bytes());
+    engine.input(body.as_bytes());
+    format!("sha256={}", Hmac::<Sha256>::from_engine(engine))
+}
+
+/// A random hex id, used for delivery ids and generated secrets.
+pub fn random_id() -> String {
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    hex::encode(id)
+}
@@ -0,0 +1,228 @@
+//! Optional write-behind buffer for the inserts every zap makes, so a busy
+//! live event doesn't need a transaction per invoice and per step.
+//!
+//! Invoices are group committed: the callback still waits for its row, but
+//! everything queued meanwhile shares one transaction, so none is lost.
+//! Invoice events are only queued, so a crash loses at most
+//! `WRITE_BUFFER_MAX_ROWS` of them, the last `WRITE_BUFFER_FLUSH_MS` worth.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{info, warn};
+
+use crate::{
+    config::CONFIG,
+    model::{
+        invoice::{InvoiceBmc, InvoiceForCreate},
+        invoice_event::{InvoiceEventBmc, InvoiceEventForCreate},
+        ModelManager, Tx,
+    },
+    state::AppState,
+};
+
+/// How long the callback waits for its invoice to be committed.
+const INVOICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static BUFFER: OnceLock<Buffer> = OnceLock::new();
+
+struct PendingInvoice {
+    invoice: InvoiceForCreate,
+    zap_request: Option<String>,
+    reply: oneshot::Sender<Result<i32>>,
+}
+
+type Receivers = (
+    mpsc::Receiver<PendingInvoice>,
+    mpsc::Receiver<InvoiceEventForCreate>,
+);
+
+struct Buffer {
+    invoices: mpsc::Sender<PendingInvoice>,
+    events: mpsc::Sender<InvoiceEventForCreate>,
+    receivers: Mutex<Receivers>,
+    /// Set once `run` drained the buffer for shutdown, later writes go
+    /// straight to the database
+    closed: AtomicBool,
+}
+
+fn buffer() -> &'static Buffer {
+    BUFFER.get_or_init(|| {
+        let (invoices, invoice_rx) = mpsc::channel(CONFIG.write_buffer_max_rows);
+        let (events, event_rx) = mpsc::channel(CONFIG.write_buffer_max_rows);
+        Buffer {
+            invoices,
+            events,
+            receivers: Mutex::new((invoice_rx, event_rx)),
+            closed: AtomicBool::new(false),
+        }
+    })
+}
+
+/// Whether writes should go through the buffer.
+pub fn enabled() -> bool {
+    CONFIG.write_buffer && !buffer().closed.load(Ordering::Acquire)
+}
+
+/// Creates an invoice, and its zap request if it is for a zap, in the next
+/// group commit.
+pub async fn create_invoice(invoice: InvoiceForCreate, zap_request: Option<String>) -> Result<i32> {
+    let (reply, created) = oneshot::channel();
+    let pending = PendingInvoice {
+        invoice,
+        zap_request,
+        reply,
+    };
+    tokio::time::timeout(INVOICE_TIMEOUT, async {
+        buffer()
+            .invoices
+            .send(pending)
+            .await
+            .map_err(|_| anyhow!("Write buffer is closed"))?;
+        created
+            .await
+            .map_err(|_| anyhow!("Write buffer dropped the invoice"))?
+    })
+    .await
+    .map_err(|_| anyhow!("Write buffer did not commit the invoice in time"))?
+}
+
+/// Queues an invoice event, handing it back if the buffer is full so the
+/// caller can insert it directly.
+pub fn push_event(event_c: InvoiceEventForCreate) -> Result<(), InvoiceEventForCreate> {
+    buffer()
+        .events
+        .try_send(event_c)
+        .map_err(|e| e.into_inner())
+}
+
+/// Commits queued invoices as they come in and flushes invoice events every
+/// `WRITE_BUFFER_FLUSH_MS`, until shutdown, when both are drained.
+pub async fn run(state: AppState) -> Result<()> {
+    let buffer = buffer();
+    let mut receivers = buffer
+        .receivers
+        .try_lock()
+        .map_err(|_| anyhow!("Write buffer is already running"))?;
+    let (invoices, events) = &mut *receivers;
+    let mut flush = tokio::time::interval(CONFIG.write_buffer_flush);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            Some(first) = invoices.recv() => {
+                let mut batch = vec![first];
+                while batch.len() < CONFIG.write_buffer_max_rows {
+                    match invoices.try_recv() {
+                        Ok(pending) => batch.push(pending),
+                        Err(_) => break,
+                    }
+                }
+                commit_invoices(&state.mm, batch).await;
+            }
+            _ = flush.tick() => flush_events(&state.mm, events).await,
+        }
+    }
+
+    buffer.closed.store(true, Ordering::Release);
+    let mut batch = Vec::new();
+    while let Ok(pending) = invoices.try_recv() {
+        batch.push(pending);
+    }
+    if !batch.is_empty() {
+        commit_invoices(&state.mm, batch).await;
+    }
+    flush_events(&state.mm, events).await;
+    info!("Write buffer drained");
+
+    Ok(())
+}
+
+/// Inserts a batch of invoices in one transaction. Each is inserted under a
+/// savepoint, so one that fails, like a reused idempotency key, is rolled
+/// back on its own and only fails its own callback. Replies are only sent
+/// once the commit succeeded.
+async fn commit_invoices(mm: &ModelManager, batch: Vec<PendingInvoice>) {
+    let size = batch.len() as f64;
+    metrics::histogram!("write_buffer_batch_size", "table" => "invoice").record(size);
+    let mut tx = match mm.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            let e = format!("{e:#}");
+            for pending in batch {
+                let _ = pending
+                    .reply
+                    .send(Err(anyhow!("Starting invoice batch failed: {e}")));
+            }
+            return;
+        }
+    };
+
+    let mut created = Vec::with_capacity(batch.len());
+    for pending in batch {
+        let result = match savepoint(&mut tx, "SAVEPOINT buffered_invoice").await {
+            Ok(()) => {
+                InvoiceBmc::create_with_zap_in(&mut tx, pending.invoice, pending.zap_request).await
+            }
+            Err(e) => Err(e),
+        };
+        let done = match result {
+            Ok(_) => "RELEASE SAVEPOINT buffered_invoice",
+            Err(_) => "ROLLBACK TO SAVEPOINT buffered_invoice",
+        };
+        // without the savepoint the transaction is aborted, fail everything
+        if let Err(e) = savepoint(&mut tx, done).await {
+            let e = format!("{e:#}");
+            warn!("Buffered invoice batch failed: {e}");
+            let _ = pending
+                .reply
+                .send(Err(anyhow!("Invoice batch failed: {e}")));
+            for (reply, _) in created {
+                let _ = reply.send(Err(anyhow!("Invoice batch failed: {e}")));
+            }
+            return;
+        }
+        created.push((pending.reply, result));
+    }
+
+    if let Err(e) = tx.commit().await {
+        let e = format!("{e:#}");
+        warn!("Committing {} buffered invoices failed: {e}", created.len());
+        for (reply, _) in created {
+            let _ = reply.send(Err(anyhow!("Committing invoice batch failed: {e}")));
+        }
+        return;
+    }
+    for (reply, result) in created {
+        let _ = reply.send(result);
+    }
+}
+
+async fn savepoint(tx: &mut Tx, sql: &str) -> Result<()> {
+    sqlx::query(sql).execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// Inserts every queued invoice event, at most `WRITE_BUFFER_MAX_ROWS`.
+async fn flush_events(mm: &ModelManager, events: &mut mpsc::Receiver<InvoiceEventForCreate>) {
+    let mut batch = Vec::new();
+    while let Ok(event_c) = events.try_recv() {
+        batch.push(event_c);
+    }
+    if batch.is_empty() {
+        return;
+    }
+
+    let count = batch.len();
+    metrics::histogram!("write_buffer_batch_size", "table" => "invoice_event").record(count as f64);
+    if let Err(e) = InvoiceEventBmc::create_many(mm, batch).await {
+        warn!("Could not record {count} buffered invoice events: {e:#}");
+    }
+}
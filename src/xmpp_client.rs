@@ -0,0 +1,328 @@
+//! The one XMPP connection hermes keeps open, as `XMPP_USERNAME` or as a
+//! component on `XMPP_COMPONENT_DOMAIN`. Messages are handed to it through a
+//! channel, and it tracks which contacts are online so DMs can wait for
+//! their recipient, see `jobs::xmpp_outbox`, and records the delivery
+//! receipts clients send back.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio_xmpp::{AsyncClient, Component, Event};
+use tracing::{debug, info, warn};
+use xmpp_parsers::{
+    message::{Body, Message, MessageType},
+    muc::Muc,
+    presence::{Presence, Type as PresenceType},
+    receipts::{Received, Request as ReceiptRequest},
+    BareJid, Element, Jid,
+};
+
+use crate::{
+    config::CONFIG,
+    invoice_log,
+    model::{xmpp_outbox::XmppOutboxBmc, ModelManager},
+    state::AppState,
+};
+
+const QUEUE_SIZE: usize = 1000;
+/// Ids of messages sent from the outbox, followed by the outbox id.
+const RECEIPT_ID_PREFIX: &str = "hermes-outbox-";
+/// What hermes is called in groupchats.
+const ROOM_NICK: &str = "hermes";
+
+static CONNECTION: OnceLock<Connection> = OnceLock::new();
+
+struct Connection {
+    outgoing: mpsc::Sender<Element>,
+    /// Taken by `run` for as long as it is connected
+    incoming: tokio::sync::Mutex<mpsc::Receiver<Element>>,
+    /// Online resources by bare JID
+    online: Mutex<HashMap<String, HashSet<String>>>,
+    /// JIDs asked for their presence since connecting
+    subscribed: Mutex<HashSet<String>>,
+    /// Groupchats joined since connecting
+    rooms: Mutex<HashSet<String>>,
+}
+
+impl Connection {
+    /// Forgets what the server only knows for a session.
+    fn reset(&self) {
+        self.online.lock().unwrap().clear();
+        self.subscribed.lock().unwrap().clear();
+        self.rooms.lock().unwrap().clear();
+    }
+}
+
+fn connection() -> &'static Connection {
+    CONNECTION.get_or_init(|| {
+        let (outgoing, incoming) = mpsc::channel(QUEUE_SIZE);
+        Connection {
+            outgoing,
+            incoming: tokio::sync::Mutex::new(incoming),
+            online: Mutex::new(HashMap::new()),
+            subscribed: Mutex::new(HashSet::new()),
+            rooms: Mutex::new(HashSet::new()),
+        }
+    })
+}
+
+/// A JID in its bare form, e.g. without a `/resource`.
+pub fn parse_bare(jid: &str) -> Result<BareJid> {
+    Ok(BareJid::from(Jid::from_str(jid)?))
+}
+
+/// Queues a chat message for the connection. It is sent once connected, so
+/// this only fails on a bad JID or a full queue. Messages with an `id` ask
+/// for a XEP-0184 delivery receipt, which `run` records on the outbox
+/// message of that id.
+pub fn send_message(jid: &str, body: &str, id: Option<i32>) -> Result<()> {
+    let mut message = Message::new(Some(Jid::Bare(parse_bare(jid)?)));
+    message.type_ = MessageType::Chat;
+    message.bodies.insert(String::new(), Body(body.to_string()));
+    if let Some(id) = id {
+        message.id = Some(format!("{RECEIPT_ID_PREFIX}{id}"));
+        message.payloads.push(ReceiptRequest.into());
+    }
+    send(message.into())
+}
+
+/// Whether hermes connects to XMPP at all. It can't go through
+/// `SOCKS_PROXY`, so with a proxy it only does if `XMPP_WITHOUT_PROXY` allows
+/// connecting directly.
+pub fn enabled() -> bool {
+    CONFIG.socks_proxy.is_none() || CONFIG.xmpp_without_proxy
+}
+
+/// Whether any resource of `jid` is online. Only known once they accepted
+/// the presence subscription `subscribe` asks for.
+pub fn is_online(jid: &str) -> bool {
+    let Ok(jid) = parse_bare(jid) else {
+        return false;
+    };
+    connection()
+        .online
+        .lock()
+        .unwrap()
+        .get(&jid.to_string())
+        .is_some_and(|resources| !resources.is_empty())
+}
+
+/// Asks `jid` to share their presence, once per connection.
+pub fn subscribe(jid: &str) -> Result<()> {
+    let jid = parse_bare(jid)?;
+    if !connection()
+        .subscribed
+        .lock()
+        .unwrap()
+        .insert(jid.to_string())
+    {
+        return Ok(());
+    }
+    send(
+        Presence::new(PresenceType::Subscribe)
+            .with_to(Jid::Bare(jid))
+            .into(),
+    )
+}
+
+/// Posts to a groupchat, joining it first if needed.
+pub fn send_groupchat(room: &str, body: &str) -> Result<()> {
+    let room = parse_bare(room)?;
+    join_room(&room)?;
+    let mut message = Message::new(Some(Jid::Bare(room)));
+    message.type_ = MessageType::Groupchat;
+    message.bodies.insert(String::new(), Body(body.to_string()));
+    send(message.into())
+}
+
+/// Joins a groupchat as `ROOM_NICK`, once per connection.
+fn join_room(room: &BareJid) -> Result<()> {
+    if !connection().rooms.lock().unwrap().insert(room.to_string()) {
+        return Ok(());
+    }
+    let occupant = Jid::from_str(&format!("{room}/{ROOM_NICK}"))?;
+    let mut presence = Presence::new(PresenceType::None).with_to(occupant);
+    presence.payloads.push(Muc::new().into());
+    send(presence.into())
+}
+
+fn send(stanza: Element) -> Result<()> {
+    connection()
+        .outgoing
+        .try_send(stanza)
+        .map_err(|e| anyhow!("XMPP queue: {e}"))
+}
+
+/// Keeps the connection up until shutdown. Connected as a client it
+/// reconnects by itself, as a component it fails and is restarted.
+pub async fn run(state: AppState) -> Result<()> {
+    let conn = connection();
+    let mut outgoing = conn
+        .incoming
+        .try_lock()
+        .map_err(|_| anyhow!("XMPP connection is already running"))?;
+
+    let result = match &CONFIG.xmpp_component_domain {
+        Some(domain) => run_component(&state, conn, &mut outgoing, domain).await,
+        None => run_client(&state, conn, &mut outgoing).await,
+    };
+    conn.reset();
+    result
+}
+
+/// Logs in as `XMPP_USERNAME` on `XMPP_CHAT_SERVER`.
+async fn run_client(
+    state: &AppState,
+    conn: &Connection,
+    outgoing: &mut mpsc::Receiver<Element>,
+) -> Result<()> {
+    let jid = parse_bare(&format!(
+        "{}@{}",
+        CONFIG.xmpp_username, CONFIG.xmpp_chat_server
+    ))?;
+    let mut client = AsyncClient::new(jid, CONFIG.xmpp_password.to_string());
+    client.set_reconnect(true);
+    let mut connected = false;
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => return Ok(()),
+            Some(stanza) = outgoing.recv(), if connected => {
+                client.send_stanza(stanza).await?;
+            }
+            event = client.next() => match event {
+                Some(Event::Online { bound_jid, .. }) => {
+                    info!("XMPP connected as {bound_jid}");
+                    connected = true;
+                    client.send_stanza(Presence::new(PresenceType::None).into()).await?;
+                }
+                Some(Event::Disconnected(e)) => {
+                    warn!("XMPP disconnected: {e}");
+                    connected = false;
+                    conn.reset();
+                }
+                Some(Event::Stanza(stanza)) => {
+                    if let Some(reply) = handle_incoming(state, conn, stanza).await {
+                        client.send_stanza(reply).await?;
+                    }
+                }
+                None => return Err(anyhow!("XMPP connection closed")),
+            }
+        }
+    }
+}
+
+/// Connects to `XMPP_COMPONENT_HOST` as a XEP-0114 component for `domain`.
+/// The server routes everything for the domain to hermes without a roster
+/// or resource binding, so stanzas carry the domain as their sender.
+async fn run_component(
+    state: &AppState,
+    conn: &Connection,
+    outgoing: &mut mpsc::Receiver<Element>,
+    domain: &str,
+) -> Result<()> {
+    let secret = CONFIG
+        .xmpp_component_secret
+        .as_deref()
+        .ok_or_else(|| anyhow!("XMPP_COMPONENT_SECRET is not set"))?;
+    let mut component = Component::new(
+        domain,
+        secret,
+        &CONFIG.xmpp_component_host,
+        CONFIG.xmpp_component_port,
+    )
+    .await?;
+    info!("XMPP component connected for {domain}");
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => return Ok(()),
+            Some(mut stanza) = outgoing.recv() => {
+                stanza.set_attr("from", domain);
+                component.send_stanza(stanza).await?;
+            }
+            stanza = component.next() => match stanza {
+                Some(stanza) => {
+                    if let Some(mut reply) = handle_incoming(state, conn, stanza).await {
+                        reply.set_attr("from", domain);
+                        component.send_stanza(reply).await?;
+                    }
+                }
+                None => return Err(anyhow!("XMPP component connection closed")),
+            }
+        }
+    }
+}
+
+/// Records delivery receipts and tracks presence, returning a reply to send
+/// if there is one.
+async fn handle_incoming(state: &AppState, conn: &Connection, stanza: Element) -> Option<Element> {
+    if stanza.name() == "message" {
+        if let Some(id) = received_receipt(stanza) {
+            record_receipt(&state.mm, id).await;
+        }
+        return None;
+    }
+    handle_presence(conn, stanza)
+}
+
+/// The outbox id a XEP-0184 receipt is for, if the message is one.
+fn received_receipt(stanza: Element) -> Option<i32> {
+    let message = Message::try_from(stanza).ok()?;
+    message.payloads.into_iter().find_map(|payload| {
+        let received = Received::try_from(payload).ok()?;
+        received.id.strip_prefix(RECEIPT_ID_PREFIX)?.parse().ok()
+    })
+}
+
+/// The recipient's client confirmed it got the message. Invoices it paid
+/// out get an `xmpp_received` event.
+async fn record_receipt(mm: &ModelManager, id: i32) {
+    match XmppOutboxBmc::mark_received(mm, id).await {
+        Ok(Some(message)) => {
+            metrics::counter!("xmpp_receipts_total").increment(1);
+            if let Some(invoice_id) = message.invoice_id {
+                invoice_log::record(mm, invoice_id, "xmpp_received", Some(message.jid)).await;
+            }
+        }
+        Ok(None) => debug!("XMPP receipt for unknown outbox message {id}"),
+        Err(e) => warn!("Recording XMPP receipt for outbox message {id} failed: {e:#}"),
+    }
+}
+
+/// Tracks contacts' presence and accepts their subscriptions, so they can
+/// see hermes is online too.
+fn handle_presence(conn: &Connection, stanza: Element) -> Option<Element> {
+    let presence = Presence::try_from(stanza).ok()?;
+    let from = presence.from?;
+    let resource = from
+        .to_string()
+        .split_once('/')
+        .map(|(_, resource)| resource.to_string())
+        .unwrap_or_default();
+    let bare = BareJid::from(from.clone()).to_string();
+    debug!("XMPP presence {:?} from {bare}", presence.type_);
+
+    let mut online = conn.online.lock().unwrap();
+    match presence.type_ {
+        PresenceType::None => {
+            online.entry(bare).or_default().insert(resource);
+        }
+        PresenceType::Unavailable => {
+            if let Some(resources) = online.get_mut(&bare) {
+                resources.remove(&resource);
+            }
+        }
+        PresenceType::Subscribe => {
+            return Some(Presence::new(PresenceType::Subscribed).with_to(from).into());
+        }
+        _ => {}
+    }
+    None
+}